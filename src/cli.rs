@@ -0,0 +1,233 @@
+use wasmrun::exec::Value;
+
+use std::fmt;
+
+pub const USAGE: &str = "\
+Usage: wasmrun [options] <file> [-- <program args>...]
+
+Arguments after `--` are passed to the program as WASI command line arguments, after the module
+file.
+
+Options:
+    --invoke <fn>    Call the exported function <fn> instead of `_start`
+    --arg <value>    Pass an argument to the called function, e.g. `i32:5` or `f64:1.5`. Can be
+                     repeated, arguments are passed in order.
+    --wasi           Make WASI functions available to the module";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    pub file: String,
+    pub invoke: Option<String>,
+    pub args: Vec<Value>,
+    pub wasi: bool,
+    /// Arguments after `--`
+    pub program_args: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ArgsError {
+    /// The module file is not given
+    MissingFile,
+    /// A flag that takes a value is the last argument
+    MissingValue {
+        flag: String,
+    },
+    UnknownFlag {
+        flag: String,
+    },
+    /// A function argument is not in `<type>:<value>` form, or the value can't be parsed
+    InvalidArg {
+        arg: String,
+    },
+    /// More than one module file is given
+    UnexpectedArg {
+        arg: String,
+    },
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgsError::MissingFile => write!(f, "missing module file"),
+            ArgsError::MissingValue { flag } => write!(f, "missing value for {}", flag),
+            ArgsError::UnknownFlag { flag } => write!(f, "unknown option {}", flag),
+            ArgsError::InvalidArg { arg } => write!(
+                f,
+                "invalid argument {:?}, expected <type>:<value> (e.g. i32:5)",
+                arg
+            ),
+            ArgsError::UnexpectedArg { arg } => write!(f, "unexpected argument {:?}", arg),
+        }
+    }
+}
+
+/// Parse command line arguments, excluding the program name.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Args, ArgsError> {
+    let mut args = args.into_iter();
+    let mut file = None;
+    let mut invoke = None;
+    let mut fn_args = vec![];
+    let mut wasi = false;
+    let mut program_args = vec![];
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--" => {
+                program_args.extend(args.by_ref());
+            }
+            "--invoke" => {
+                invoke = Some(flag_value(&mut args, &arg)?);
+            }
+            "--arg" => {
+                let value = flag_value(&mut args, &arg)?;
+                fn_args.push(parse_value(&value)?);
+            }
+            "--wasi" => {
+                wasi = true;
+            }
+            _ if arg.starts_with("--") => {
+                return Err(ArgsError::UnknownFlag { flag: arg });
+            }
+            _ => {
+                if file.is_some() {
+                    return Err(ArgsError::UnexpectedArg { arg });
+                }
+                file = Some(arg);
+            }
+        }
+    }
+
+    Ok(Args {
+        file: file.ok_or(ArgsError::MissingFile)?,
+        invoke,
+        args: fn_args,
+        wasi,
+        program_args,
+    })
+}
+
+fn flag_value<I: Iterator<Item = String>>(args: &mut I, flag: &str) -> Result<String, ArgsError> {
+    args.next().ok_or_else(|| ArgsError::MissingValue {
+        flag: flag.to_owned(),
+    })
+}
+
+// Parse a typed function argument like `i32:5`
+fn parse_value(arg: &str) -> Result<Value, ArgsError> {
+    let invalid = || ArgsError::InvalidArg {
+        arg: arg.to_owned(),
+    };
+
+    let (ty, value) = match arg.find(':') {
+        Some(colon) => (&arg[..colon], &arg[colon + 1..]),
+        None => return Err(invalid()),
+    };
+
+    match ty {
+        "i32" => value.parse().map(Value::I32).map_err(|_| invalid()),
+        "i64" => value.parse().map(Value::I64).map_err(|_| invalid()),
+        "f32" => value.parse().map(Value::F32).map_err(|_| invalid()),
+        "f64" => value.parse().map(Value::F64).map_err(|_| invalid()),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| (*arg).to_owned()).collect()
+}
+
+#[test]
+fn parse_file() {
+    assert_eq!(
+        parse_args(args(&["test.wasm"])),
+        Ok(Args {
+            file: "test.wasm".to_owned(),
+            ..Default::default()
+        })
+    );
+}
+
+#[test]
+fn parse_invoke_args() {
+    assert_eq!(
+        parse_args(args(&[
+            "--invoke",
+            "add",
+            "--arg",
+            "i32:40",
+            "test.wasm",
+            "--arg",
+            "i64:-2",
+            "--arg",
+            "f32:1.5",
+            "--arg",
+            "f64:2",
+            "--wasi"
+        ])),
+        Ok(Args {
+            file: "test.wasm".to_owned(),
+            invoke: Some("add".to_owned()),
+            args: vec![
+                Value::I32(40),
+                Value::I64(-2),
+                Value::F32(1.5),
+                Value::F64(2.0)
+            ],
+            wasi: true,
+            ..Default::default()
+        })
+    );
+}
+
+#[test]
+fn parse_program_args() {
+    assert_eq!(
+        parse_args(args(&[
+            "--wasi",
+            "test.wasm",
+            "--",
+            "-v",
+            "--invoke",
+            "x.txt"
+        ])),
+        Ok(Args {
+            file: "test.wasm".to_owned(),
+            wasi: true,
+            program_args: args(&["-v", "--invoke", "x.txt"]),
+            ..Default::default()
+        })
+    );
+    assert_eq!(parse_args(args(&["--", "a"])), Err(ArgsError::MissingFile));
+}
+
+#[test]
+fn parse_args_errors() {
+    assert_eq!(parse_args(args(&[])), Err(ArgsError::MissingFile));
+    assert_eq!(
+        parse_args(args(&["test.wasm", "--invoke"])),
+        Err(ArgsError::MissingValue {
+            flag: "--invoke".to_owned()
+        })
+    );
+    assert_eq!(
+        parse_args(args(&["test.wasm", "--foo"])),
+        Err(ArgsError::UnknownFlag {
+            flag: "--foo".to_owned()
+        })
+    );
+    assert_eq!(
+        parse_args(args(&["a.wasm", "b.wasm"])),
+        Err(ArgsError::UnexpectedArg {
+            arg: "b.wasm".to_owned()
+        })
+    );
+    for arg in &["5", "i32:x", "i32:4294967296", "v128:0", "i32"] {
+        assert_eq!(
+            parse_args(args(&["test.wasm", "--arg", arg])),
+            Err(ArgsError::InvalidArg {
+                arg: (*arg).to_owned()
+            })
+        );
+    }
+}
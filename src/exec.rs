@@ -1,36 +1,59 @@
 mod const_expr;
 mod frame;
+mod linker;
 mod stack;
 mod store;
+mod trap;
 mod value;
+pub mod wasi;
+
+#[cfg(test)]
+mod tests;
 
 use const_expr::ConstExpr;
 use frame::FrameStack;
+pub use linker::{instantiate_modules, Extern, LinkError, Linker};
 use stack::Stack;
-use store::{Global, ModuleIdx, Store};
+use store::{DataAddr, ElemAddr, Func, Global, GlobalAddr, MemAddr, Ref, Store, TableAddr};
+pub use store::{FuncAddr, ModuleIdx};
+pub use trap::{FrameInfo, Trap, TrapKind};
+pub use value::Value;
+use value::{canonicalize_f32, canonicalize_f64};
 
 use crate::parser;
-use crate::parser::{Export, FuncIdx, FuncType, ImportDesc, Instruction, MemArg};
-
-use std::mem::replace;
+use crate::parser::{
+    DataMode, ElemMode, ElemType, Export, ExportDesc, FuncIdx, FuncType, GlobalIdx, ImportDesc,
+    Instruction, MemArg, MemIdx, Names, TypeIdx, ValType,
+};
+
+use log::trace;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt;
 use std::rc::Rc;
 
-type Addr = u32;
-
 const PAGE_SIZE: usize = 65536;
 
-#[derive(Default)]
+// Max. number of pages of a memory, makes the memory size 4 GiB
+const MAX_PAGES: u32 = 65536;
+
+const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+
+#[derive(Default, Clone)]
 pub struct Module {
     pub types: Vec<FuncType>,
-    pub func_addrs: Vec<Addr>,
-    pub table_addrs: Vec<Addr>,
-    pub mem_addrs: Vec<Addr>,
-    pub global_addrs: Vec<Addr>,
+    pub func_addrs: Vec<FuncAddr>,
+    pub table_addrs: Vec<TableAddr>,
+    pub mem_addrs: Vec<MemAddr>,
+    pub global_addrs: Vec<GlobalAddr>,
+    pub data_addrs: Vec<DataAddr>,
+    pub elem_addrs: Vec<ElemAddr>,
     pub exports: Vec<Export>,
     pub start: Option<FuncIdx>,
+    pub names: Names,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum BlockType {
     // A block in a function
     Block,
@@ -40,18 +63,121 @@ enum BlockType {
     Function,
 }
 
-#[derive(Default)]
+// A block in execution
+#[derive(Debug, Clone)]
+struct BlockIp {
+    ty: BlockType,
+    instrs: Rc<[Instruction]>,
+    // Index of the next instruction to execute in `instrs`
+    ip: u32,
+}
+
+// Where execution continues when branching to a label
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LabelTarget {
+    // After the end of the block. Branching to the label of a function returns from the function.
+    End,
+    // Beginning of the loop
+    Start,
+}
+
+// A control label. Each block in `Runtime::ip` has a label at the same index in `Runtime::labels`.
+#[derive(Debug, Clone, Copy)]
+struct Label {
+    target: LabelTarget,
+    // Height of the operand stack when entering the block, without the block's parameters
+    stack_height: usize,
+    // Number of values kept on the stack when branching to the label
+    arity: usize,
+}
+
+/// Result of executing a single instruction with `Runtime::step`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    /// The function has more instructions to execute
+    Continue,
+    /// The function reached its end. The function frame is popped and the results are on the stack.
+    Finished,
+}
+
+/// An instruction about to be executed, passed to `Runtime::trace_hook`.
+#[derive(Debug)]
+pub struct TraceEvent<'a> {
+    pub module_idx: ModuleIdx,
+    pub func_idx: FuncIdx,
+    /// Index of the instruction in its block
+    pub ip: u32,
+    pub instr: &'a Instruction,
+    /// Number of values on the stack, including values of the caller functions
+    pub stack_depth: usize,
+}
+
+pub type TraceHook = Box<dyn FnMut(&TraceEvent)>;
+
+/// Execution state of a `Runtime`, including the store and the call stack. See
+/// `Runtime::snapshot`.
+#[derive(Clone)]
+pub struct RuntimeSnapshot {
+    store: Store,
+    stack: Stack,
+    frames: FrameStack,
+    modules: Vec<Module>,
+    ip: Vec<BlockIp>,
+    labels: Vec<Label>,
+}
+
+/// Result of `Runtime::run_until_break`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakResult {
+    /// Execution stopped before the instruction at a breakpoint
+    Breakpoint,
+    /// The function reached its end. The function frame is popped and the results are on the stack.
+    Finished,
+}
+
 pub struct Runtime {
     store: Store,
     stack: Stack,
     frames: FrameStack,
     modules: Vec<Module>,
 
+    /// Max. number of wasm function frames. Calls from wasm recurse in the interpreter, so this
+    /// also bounds the native stack usage.
+    pub max_call_depth: usize,
+
+    // Remaining fuel. Each executed instruction consumes one unit of fuel. `None` when fuel
+    // metering is disabled, which is the default.
+    fuel: Option<u64>,
+
+    // Breakpoints as (module index, function index, instruction offset in the function body)
+    breakpoints: HashSet<(ModuleIdx, FuncIdx, u32)>,
+
+    /// Called before executing each instruction
+    pub trace_hook: Option<TraceHook>,
+
     // Instruction pointer. Currently we don't need to make this a part of `Runtime`, but at some
     // point we'll have debugging commands and we want to be able to stop at any point in execution
     // and then continue. For that we need to store the current point in program permanently, and I
     // think this is a good place for that.
-    ip: Vec<(BlockType, Rc<[Instruction]>, u32)>,
+    ip: Vec<BlockIp>,
+    labels: Vec<Label>,
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Runtime {
+            store: Default::default(),
+            stack: Default::default(),
+            frames: Default::default(),
+            modules: Default::default(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            fuel: None,
+            breakpoints: Default::default(),
+            trace_hook: None,
+            ip: Default::default(),
+            labels: Default::default(),
+        }
+    }
 }
 
 impl Runtime {
@@ -63,36 +189,538 @@ impl Runtime {
         self.modules[idx].start
     }
 
-    // Move on to the next instruction in the current function. Depending on the current block type
-    // this may jump forwards or backwards.
-    fn next_instr(&mut self) {
-        let mut ip = replace(&mut self.ip, vec![]);
-
-        if let Some((block_ty, current_block, block_ip)) = ip.pop() {
-            if (block_ip + 1) as usize >= current_block.len() {
-                match block_ty {
-                    BlockType::Function => {
-                        // End of the function, the function frame will be popped by `call`.
-                        ip.push((block_ty, current_block, block_ip + 1));
-                    }
-                    BlockType::Block => {
-                        // End of the block, which is already popped.
-                    }
-                    BlockType::Loop => {
-                        // End of loop, jump to beginning.
-                        ip.push((block_ty, current_block, 0));
-                    }
-                }
-            } else {
-                ip.push((block_ty, current_block, block_ip + 1));
+    /// Add fuel to bound execution. After fuel is added for the first time, executing an
+    /// instruction when the runtime is out of fuel traps with `OutOfFuel`.
+    pub fn add_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(self.fuel.unwrap_or(0).saturating_add(fuel));
+    }
+
+    /// Remaining fuel, or `None` if fuel metering is not enabled
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    pub fn get_export(&self, module_idx: ModuleIdx, name: &str) -> Option<&Export> {
+        self.modules[module_idx]
+            .exports
+            .iter()
+            .find(|export| export.nm == name)
+    }
+
+    pub fn get_exported_func(&self, module_idx: ModuleIdx, name: &str) -> Option<FuncIdx> {
+        match self.get_export(module_idx, name)?.desc {
+            ExportDesc::Func(fun_idx) => Some(fun_idx),
+            _ => None,
+        }
+    }
+
+    pub fn get_exported_global(&self, module_idx: ModuleIdx, name: &str) -> Option<GlobalIdx> {
+        match self.get_export(module_idx, name)?.desc {
+            ExportDesc::Global(global_idx) => Some(global_idx),
+            _ => None,
+        }
+    }
+
+    /// Read `len` bytes at `addr` in the module's memory. Fails with `MemoryOutOfBounds` when the
+    /// range is out of bounds, or the module doesn't have a memory.
+    pub fn read_memory(&self, module_idx: ModuleIdx, addr: u32, len: u32) -> Result<&[u8], Trap> {
+        let mem = &self.store.mems[self.module_mem_addr(module_idx)?].data;
+        let end_addr = u64::from(addr) + u64::from(len);
+        if end_addr > mem.len() as u64 {
+            return Err(TrapKind::MemoryOutOfBounds.into());
+        }
+        Ok(&mem[addr as usize..end_addr as usize])
+    }
+
+    /// Write `bytes` at `addr` in the module's memory. Fails with `MemoryOutOfBounds` when the
+    /// range is out of bounds, or the module doesn't have a memory. Nothing is written on failure.
+    pub fn write_memory(
+        &mut self,
+        module_idx: ModuleIdx,
+        addr: u32,
+        bytes: &[u8],
+    ) -> Result<(), Trap> {
+        let mem_addr = self.module_mem_addr(module_idx)?;
+        let mem = &mut self.store.mems[mem_addr].data;
+        let end_addr = u64::from(addr) + bytes.len() as u64;
+        if end_addr > mem.len() as u64 {
+            return Err(TrapKind::MemoryOutOfBounds.into());
+        }
+        mem[addr as usize..end_addr as usize].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    // Address of the module's memory
+    fn module_mem_addr(&self, module_idx: ModuleIdx) -> Result<MemAddr, Trap> {
+        match self.modules[module_idx].mem_addrs.first() {
+            Some(mem_addr) => Ok(*mem_addr),
+            None => Err(TrapKind::MemoryOutOfBounds.into()),
+        }
+    }
+
+    /// Call the exported function `fn_name` of the module with the given arguments and return the
+    /// function's results.
+    pub fn invoke(
+        &mut self,
+        module_idx: ModuleIdx,
+        fn_name: &str,
+        args: &[Value],
+    ) -> Result<Vec<Value>, Trap> {
+        let fun_idx = self
+            .get_exported_func(module_idx, fn_name)
+            .ok_or_else(|| TrapKind::UnknownExport(fn_name.to_owned()))?;
+
+        let fun_addr = self.modules[module_idx].func_addrs[fun_idx.0 as usize];
+        let fun_ty = self.func_type(fun_addr);
+        if args.len() != fun_ty.args.len()
+            || !args
+                .iter()
+                .zip(fun_ty.args.iter())
+                .all(|(arg, ty)| value_has_type(arg, ty))
+        {
+            return Err(TrapKind::ArgumentTypeMismatch.into());
+        }
+
+        for arg in args {
+            self.stack.push_value(*arg);
+        }
+        call(self, module_idx, fun_idx)
+    }
+
+    /// Run a module as a program: call the module's start function if it has one, then the
+    /// exported function `entry` with `args`, or `_start` when `entry` is not given. Calling
+    /// `_start` is skipped if the module doesn't export it. Returns results of the entry function.
+    pub fn run_program(
+        &mut self,
+        module_idx: ModuleIdx,
+        entry: Option<&str>,
+        args: &[Value],
+    ) -> Result<Vec<Value>, Trap> {
+        if let Some(start_idx) = self.get_module_start(module_idx) {
+            call(self, module_idx, start_idx)?;
+        }
+
+        match entry {
+            Some(entry) => self.invoke(module_idx, entry, args),
+            None if self.get_exported_func(module_idx, "_start").is_some() => {
+                self.invoke(module_idx, "_start", args)
+            }
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Value of the exported global `name`, or `None` if the module doesn't export a global with
+    /// the name.
+    pub fn get_global(&self, module_idx: ModuleIdx, name: &str) -> Option<Value> {
+        let global_idx = self.get_exported_global(module_idx, name)?;
+        let global_addr = self.modules[module_idx].global_addrs[global_idx.0 as usize];
+        Some(self.store.globals[global_addr].value)
+    }
+
+    /// Set the value of the exported global `name`. Fails when the global is not exported, is
+    /// immutable, or the value has a different type than the global.
+    pub fn set_global(
+        &mut self,
+        module_idx: ModuleIdx,
+        name: &str,
+        value: Value,
+    ) -> Result<(), Trap> {
+        let global_idx = self
+            .get_exported_global(module_idx, name)
+            .ok_or_else(|| TrapKind::UnknownExport(name.to_owned()))?;
+
+        let global_addr = self.modules[module_idx].global_addrs[global_idx.0 as usize];
+        let global = &mut self.store.globals[global_addr];
+        if !global.mutable {
+            return Err(TrapKind::ImmutableGlobal.into());
+        }
+        if !value_has_type(&value, &global.value.ty()) {
+            return Err(TrapKind::ArgumentTypeMismatch.into());
+        }
+
+        global.value = value;
+        Ok(())
+    }
+
+    /// Prepare a call to a wasm function with the arguments on the stack, without executing any of
+    /// its instructions. Use `step` to execute the function.
+    pub fn start_call(&mut self, module_idx: ModuleIdx, fun_idx: FuncIdx) -> Result<(), Trap> {
+        let fun_addr = self.modules[module_idx].func_addrs[fun_idx.0 as usize];
+        enter_wasm_func(self, fun_addr)
+    }
+
+    /// Execute one instruction of the current function. Calls made by the instruction are executed
+    /// to completion.
+    pub fn step(&mut self) -> Result<StepResult, Trap> {
+        let result = step(self);
+        if !matches!(result, Ok(StepResult::Continue)) {
+            leave_wasm_func(self);
+        }
+        result
+    }
+
+    /// Add a breakpoint before the instruction at `instr_offset` in the body of the function.
+    /// Offsets are indices of the top-level instructions of the function body, instructions
+    /// nested in blocks can't be used as breakpoints.
+    pub fn add_breakpoint(&mut self, module_idx: ModuleIdx, fun_idx: FuncIdx, instr_offset: u32) {
+        self.breakpoints.insert((module_idx, fun_idx, instr_offset));
+    }
+
+    pub fn remove_breakpoint(
+        &mut self,
+        module_idx: ModuleIdx,
+        fun_idx: FuncIdx,
+        instr_offset: u32,
+    ) {
+        self.breakpoints
+            .remove(&(module_idx, fun_idx, instr_offset));
+    }
+
+    /// Execute the current function until it reaches an instruction with a breakpoint, or until
+    /// the end of the function. Executes at least one instruction, so that execution can be
+    /// continued after stopping at a breakpoint. Breakpoints in functions called by the current
+    /// function are not checked.
+    pub fn run_until_break(&mut self) -> Result<BreakResult, Trap> {
+        loop {
+            if let StepResult::Finished = self.step()? {
+                return Ok(BreakResult::Finished);
+            }
+            if self.at_breakpoint() {
+                return Ok(BreakResult::Breakpoint);
             }
         }
+    }
+
+    /// Capture the execution state: the store (memories, tables, globals, ...), modules, and the
+    /// call stack. Fuel, breakpoints, and the trace hook are not a part of the snapshot.
+    pub fn snapshot(&self) -> RuntimeSnapshot {
+        RuntimeSnapshot {
+            store: self.store.clone(),
+            stack: self.stack.clone(),
+            frames: self.frames.clone(),
+            modules: self.modules.clone(),
+            ip: self.ip.clone(),
+            labels: self.labels.clone(),
+        }
+    }
 
+    /// Roll back the execution state to a snapshot. When the snapshot is taken in the middle of a
+    /// function, execution can be continued with `step` or `run_until_break`.
+    pub fn restore(&mut self, snapshot: RuntimeSnapshot) {
+        let RuntimeSnapshot {
+            store,
+            stack,
+            frames,
+            modules,
+            ip,
+            labels,
+        } = snapshot;
+        self.store = store;
+        self.stack = stack;
+        self.frames = frames;
+        self.modules = modules;
         self.ip = ip;
+        self.labels = labels;
+    }
+
+    /// Reset the runtime to a snapshot, e.g. one taken right after allocating the modules: clear
+    /// the call stack, and restore memories, tables, globals, and segments to their contents in
+    /// the snapshot. Unlike `restore`, buffers are reused and the snapshot can be used again.
+    /// Modules and items allocated after taking the snapshot stay allocated and are not changed.
+    /// Start functions are not called again; `run_program` calls the start function of the module
+    /// it runs.
+    pub fn reset(&mut self, snapshot: &RuntimeSnapshot) {
+        self.stack.clear();
+        self.frames.clear();
+        self.ip.clear();
+        self.labels.clear();
+        self.store.reset(&snapshot.store);
+    }
+
+    /// Wasm functions on the call stack, innermost first
+    pub fn backtrace(&self) -> Vec<FrameInfo> {
+        // Instruction offsets of the functions, outermost first
+        let mut instr_offsets: Vec<u32> = vec![];
+        // Offset of the first instruction of the current block in its function body
+        let mut block_offset = 0;
+        for (i, block) in self.ip.iter().enumerate() {
+            if let BlockType::Function = block.ty {
+                block_offset = 0;
+                instr_offsets.push(0);
+            }
+
+            // Instruction pointer of a block is bumped when entering a nested block
+            let nested_block = match self.ip.get(i + 1) {
+                Some(
+                    nested @ BlockIp {
+                        ty: BlockType::Block | BlockType::Loop,
+                        ..
+                    },
+                ) => Some(nested),
+                _ => None,
+            };
+            let ip = if nested_block.is_some() {
+                block.ip - 1
+            } else {
+                block.ip
+            } as usize;
+
+            let instr_offset = block_offset + instrs_len(&block.instrs[..ip]);
+            if let Some(offset) = instr_offsets.last_mut() {
+                *offset = instr_offset;
+            }
+
+            // Instructions of the `else` branch follow the instructions of the `then` branch
+            block_offset = instr_offset + 1;
+            if let (Some(nested), Some(Instruction::If(if_))) = (nested_block, block.instrs.get(ip))
+            {
+                if Rc::ptr_eq(&nested.instrs, &if_.else_instrs) {
+                    block_offset += instrs_len(&if_.then_instrs);
+                }
+            }
+        }
+
+        self.frames
+            .iter()
+            .zip(instr_offsets)
+            .rev()
+            .map(|(frame, instr_offset)| FrameInfo {
+                module_idx: frame.module(),
+                func_idx: frame.fun(),
+                func_name: self.modules[frame.module()]
+                    .names
+                    .fun_name(frame.fun())
+                    .map(str::to_owned),
+                instr_offset,
+            })
+            .collect()
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        match self.ip.last() {
+            Some(BlockIp {
+                ty: BlockType::Function,
+                ip,
+                ..
+            }) => {
+                let frame = self.frames.current();
+                self.breakpoints
+                    .contains(&(frame.module(), frame.fun(), *ip))
+            }
+            _ => false,
+        }
+    }
+
+    // Address of the current module's memory `mem_idx`
+    fn current_mem_addr(&self, mem_idx: MemIdx) -> MemAddr {
+        let current_module = self.frames.current().module();
+        self.modules[current_module].mem_addrs[mem_idx as usize]
+    }
+
+    // Pop an address or length operand for the current module's memory `mem_idx`. The operand is
+    // an i64 for memory64 memories, and i32 otherwise.
+    fn pop_addr(&mut self, mem_idx: MemIdx) -> Result<u64, TrapKind> {
+        if self.store.mems[self.current_mem_addr(mem_idx)].memory64 {
+            Ok(self.stack.pop_i64()? as u64)
+        } else {
+            Ok(u64::from(self.stack.pop_i32()? as u32))
+        }
+    }
+
+    // Read `N` bytes at the effective address `addr + memarg.offset` of the current module's
+    // memory `memarg.mem`.
+    fn load<const N: usize>(&self, addr: u64, memarg: &MemArg) -> Result<[u8; N], TrapKind> {
+        // Compute in `u128` so that the effective address can't wrap around
+        let addr = u128::from(addr) + u128::from(memarg.offset);
+        let end_addr = addr + N as u128;
+
+        let mem = &self.store.mems[self.current_mem_addr(memarg.mem)].data;
+        if end_addr > mem.len() as u128 {
+            return Err(TrapKind::MemoryOutOfBounds);
+        }
+        let (addr, end_addr) = (addr as usize, end_addr as usize);
+
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(&mem[addr..end_addr]);
+        Ok(bytes)
+    }
+
+    // Write `bytes` to the effective address `addr + memarg.offset` of the current module's memory
+    // `memarg.mem`.
+    fn store<const N: usize>(
+        &mut self,
+        bytes: [u8; N],
+        addr: u64,
+        memarg: &MemArg,
+    ) -> Result<(), TrapKind> {
+        // Compute in `u128` so that the effective address can't wrap around
+        let addr = u128::from(addr) + u128::from(memarg.offset);
+        let end_addr = addr + N as u128;
+
+        let mem_addr = self.current_mem_addr(memarg.mem);
+        let mem = &mut self.store.mems[mem_addr].data;
+        if end_addr > mem.len() as u128 {
+            return Err(TrapKind::MemoryOutOfBounds);
+        }
+        let (addr, end_addr) = (addr as usize, end_addr as usize);
+
+        mem[addr..end_addr].copy_from_slice(&bytes);
+        Ok(())
+    }
+
+    // Move on to the next instruction in the current block. Blocks that reach their end are popped
+    // by `exec`.
+    fn next_instr(&mut self) {
+        if let Some(block) = self.ip.last_mut() {
+            block.ip += 1;
+        }
+    }
+
+    fn func_type(&self, fun_addr: FuncAddr) -> &FuncType {
+        match &self.store.funcs[fun_addr] {
+            Func::Wasm(func) => &self.modules[func.module_idx].types[func.fun.ty.0 as usize],
+            Func::Host(func) => &func.ty,
+        }
+    }
+
+    // Branch to the label `lbl_idx`. Blocks are exited, loops are continued from the beginning, and
+    // branching to the function block returns from the function. Values pushed in the target block
+    // are dropped, except for the label's arity.
+    //
+    // Instruction pointers of parent blocks are bumped when entering a block, so no need to update
+    // them here. Exited blocks are popped by `finish_blocks`.
+    fn br(&mut self, lbl_idx: u32) -> Result<(), TrapKind> {
+        // Blocks of the callers are not visible to the current function
+        if lbl_idx > self.function_label_idx() {
+            return Err(TrapKind::StackUnderflow);
+        }
+
+        for _ in 0..lbl_idx {
+            self.ip.pop();
+            self.labels.pop();
+        }
+
+        let (block, label) = match (self.ip.last_mut(), self.labels.last()) {
+            (Some(block), Some(label)) => (block, label),
+            _ => return Err(TrapKind::StackUnderflow),
+        };
+
+        self.stack.unwind(label.stack_height, label.arity);
+
+        block.ip = match label.target {
+            LabelTarget::End => block.instrs.len() as u32,
+            LabelTarget::Start => 0,
+        };
+        Ok(())
+    }
+
+    // Index of the current function's label, relative to the innermost label
+    fn function_label_idx(&self) -> u32 {
+        self.ip
+            .iter()
+            .rev()
+            .position(|block| block.ty == BlockType::Function)
+            .expect("function_label_idx: no function to execute") as u32
+    }
+
+    fn push_block(&mut self, block: BlockIp, label: Label) {
+        self.ip.push(block);
+        self.labels.push(label);
+    }
+
+    fn pop_block(&mut self) {
+        self.ip.pop();
+        self.labels.pop();
+    }
+
+    // Enter a block of the current function with the given type. Parameters of the block are
+    // already on the stack and stay there for the block's instructions.
+    fn enter_block(
+        &mut self,
+        ty: BlockType,
+        block_ty: &parser::BlockType,
+        instrs: &Rc<[Instruction]>,
+    ) {
+        let (n_params, n_results) = match block_ty {
+            parser::BlockType::Empty => (0, 0),
+            parser::BlockType::ValType(_) => (0, 1),
+            parser::BlockType::TypeIdx(type_idx) => {
+                let current_module = self.frames.current().module();
+                let fun_ty = &self.modules[current_module].types[type_idx.0 as usize];
+                (fun_ty.args.len(), fun_ty.ret.len())
+            }
+        };
+        // Branching to a loop continues with the loop's parameters, branching to a block exits it
+        // with its results
+        let (target, arity) = match ty {
+            BlockType::Block => (LabelTarget::End, n_results),
+            BlockType::Loop => (LabelTarget::Start, n_params),
+            BlockType::Function => panic!("enter_block: function block"),
+        };
+        self.push_block(
+            BlockIp {
+                ty,
+                instrs: instrs.clone(),
+                ip: 0,
+            },
+            Label {
+                target,
+                stack_height: self.stack.len() - n_params,
+                arity,
+            },
+        );
+    }
+}
+
+// Number of instructions in `instrs`, including the instructions of nested blocks
+fn instrs_len(instrs: &[Instruction]) -> u32 {
+    instrs
+        .iter()
+        .map(|instr| match instr {
+            Instruction::Block(block) | Instruction::Loop(block) => 1 + instrs_len(&block.instrs),
+            Instruction::If(if_) => 1 + instrs_len(&if_.then_instrs) + instrs_len(&if_.else_instrs),
+            _ => 1,
+        })
+        .sum()
+}
+
+/// Errors when allocating a module with `allocate_module`
+#[derive(Debug, PartialEq, Eq)]
+pub enum InstantiationError {
+    /// An import is not defined in the linker
+    UnresolvedImport { module: String, name: String },
+    /// An import is defined in the linker, but with a different kind or an incompatible type
+    ImportTypeMismatch { module: String, name: String },
+    /// A global initializer or a segment offset is not a constant expression of the right type.
+    /// Only happens in modules that are not validated.
+    InvalidConstExpr,
+    /// An active element or data segment does not fit into its table or memory
+    Trap(TrapKind),
+    /// A table or memory of the module is too large to allocate
+    OutOfMemory,
+}
+
+impl fmt::Display for InstantiationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstantiationError::UnresolvedImport { module, name } => {
+                write!(f, "unknown import {}.{}", module, name)
+            }
+            InstantiationError::ImportTypeMismatch { module, name } => {
+                write!(f, "incompatible import type {}.{}", module, name)
+            }
+            InstantiationError::InvalidConstExpr => write!(f, "constant expression required"),
+            InstantiationError::Trap(kind) => write!(f, "{}", kind),
+            InstantiationError::OutOfMemory => write!(f, "out of memory"),
+        }
     }
 }
 
-pub fn allocate_module(rt: &mut Runtime, parsed_module: parser::Module) -> ModuleIdx {
+pub fn allocate_module(
+    rt: &mut Runtime,
+    linker: &Linker,
+    parsed_module: parser::Module,
+) -> Result<ModuleIdx, InstantiationError> {
     // https://webassembly.github.io/spec/core/exec/modules.html
 
     let parser::Module {
@@ -101,80 +729,199 @@ pub fn allocate_module(rt: &mut Runtime, parsed_module: parser::Module) -> Modul
         tables,
         mem_addrs,
         globals,
-        elems,    // TODO
-        data,     // TODO
-        names: _, // used for debugging
+        elems,
+        data,
+        names,
         start,
         imports,
         exports,
-        datacount: _, // used for efficient validation when bulk memory ops are used
+        datacount: _, // checked against the data segments by the parser, used in validation
     } = parsed_module;
 
     let module_idx = rt.modules.len();
 
-    let mut inst = Module::default();
-    inst.types = types;
-    inst.exports = exports;
+    let mut inst = Module {
+        types,
+        exports,
+        names,
+        ..Default::default()
+    };
 
-    // Allocate imported functions
-    // TODO: allocate other imported stuff (tables, memories, globals)
-    // TODO: not sure how to resolve imports yet
+    // Resolve imports
     for import in imports {
-        match import.desc {
-            ImportDesc::Func(_) => {
-                // FIXME
-                inst.func_addrs.push(u32::MAX);
+        let unresolved = || InstantiationError::UnresolvedImport {
+            module: import.module.clone(),
+            name: import.name.clone(),
+        };
+        let mismatch = || InstantiationError::ImportTypeMismatch {
+            module: import.module.clone(),
+            name: import.name.clone(),
+        };
+        let ext = linker
+            .get(&import.module, &import.name)
+            .ok_or_else(unresolved)?;
+        match &import.desc {
+            ImportDesc::Func(type_idx) => {
+                let fun_addr = match ext {
+                    Extern::Func(fun_addr) => fun_addr,
+                    _ => return Err(mismatch()),
+                };
+                if rt.func_type(fun_addr) != &inst.types[type_idx.0 as usize] {
+                    return Err(mismatch());
+                }
+                inst.func_addrs.push(fun_addr);
+            }
+            ImportDesc::Global(global_ty) => {
+                let global_addr = match ext {
+                    Extern::Global(global_addr) => global_addr,
+                    _ => return Err(mismatch()),
+                };
+                let global = &rt.store.globals[global_addr];
+                let mutable = global_ty.mut_ == parser::types::Mutability::Var;
+                if !value_has_type(&global.value, &global_ty.ty) || global.mutable != mutable {
+                    return Err(mismatch());
+                }
+                inst.global_addrs.push(global_addr);
+            }
+            ImportDesc::MemType(limits) => {
+                let mem_addr = match ext {
+                    Extern::Mem(mem_addr) => mem_addr,
+                    _ => return Err(mismatch()),
+                };
+                let mem = &rt.store.mems[mem_addr];
+                let size = (mem.data.len() / PAGE_SIZE) as u64;
+                if mem.memory64 != limits.memory64 || !limits_match(size, mem.max, limits) {
+                    return Err(mismatch());
+                }
+                inst.mem_addrs.push(mem_addr);
+            }
+            ImportDesc::Table(table_ty) => {
+                let table_addr = match ext {
+                    Extern::Table(table_addr) => table_addr,
+                    _ => return Err(mismatch()),
+                };
+                let table = &rt.store.tables[table_addr];
+                let size = table.elems.len() as u64;
+                if table.elem_type != table_ty.elem_type
+                    || !limits_match(size, table.max, &table_ty.limits)
+                {
+                    return Err(mismatch());
+                }
+                inst.table_addrs.push(table_addr);
             }
-            ImportDesc::Table(_) | ImportDesc::MemType(_) | ImportDesc::Global(_) => {}
         }
     }
 
     // Allocate functions
     for fun in funs {
-        let fun_idx = rt.store.funcs.len();
-        rt.store.funcs.push(store::Func { module_idx, fun });
-        inst.func_addrs.push(fun_idx as u32);
+        let fun_addr = FuncAddr(rt.store.funcs.len() as u32);
+        rt.store.funcs.push(Func::Wasm(store::WasmFunc {
+            module_idx,
+            fun_idx: FuncIdx(inst.func_addrs.len() as u32),
+            fun,
+        }));
+        inst.func_addrs.push(fun_addr);
     }
 
     // Allocate tables
     for table in tables {
-        let table_idx = rt.store.tables.len();
-        rt.store.tables.push(vec![None; table.limits.min as usize]);
-        inst.table_addrs.push(table_idx as u32);
+        let table_addr = TableAddr(rt.store.tables.len() as u32);
+        let elems = try_alloc(table.limits.min, None).ok_or(InstantiationError::OutOfMemory)?;
+        rt.store.tables.push(store::Table {
+            elems,
+            max: table.limits.max,
+            elem_type: table.elem_type,
+        });
+        inst.table_addrs.push(table_addr);
     }
 
     // Allocate memories
-    assert!(mem_addrs.len() <= 1); // No more than 1 currently
     for mem in mem_addrs {
-        let mem_idx = rt.store.mems.len();
-        rt.store.mems.push(vec![0; mem.min as usize * PAGE_SIZE]);
-        inst.mem_addrs.push(mem_idx as u32);
+        let mem_addr = MemAddr(rt.store.mems.len() as u32);
+        let data = mem
+            .min
+            .checked_mul(PAGE_SIZE as u64)
+            .and_then(|size| try_alloc(size, 0))
+            .ok_or(InstantiationError::OutOfMemory)?;
+        rt.store.mems.push(store::Mem {
+            data,
+            max: mem.max,
+            memory64: mem.memory64,
+        });
+        inst.mem_addrs.push(mem_addr);
     }
 
     // Allocate globals
     for global in globals {
-        let global_idx = rt.store.globals.len();
-        let value = match ConstExpr::from_expr(&global.expr) {
-            None => panic!(
-                "Global value is not a constant expression: {:?}",
-                global.expr
-            ),
-            Some(ConstExpr::Const(value)) => value,
-            Some(ConstExpr::GlobalGet(_idx)) =>
-            // See the comments in `ConstExpr` type. This can only be an import.
-            {
-                todo!()
-            }
-        };
+        let global_addr = GlobalAddr(rt.store.globals.len() as u32);
+        let value = eval_const_expr(rt, &inst, &global.expr)?;
+        if !value_has_type(&value, &global.ty.ty) {
+            return Err(InstantiationError::InvalidConstExpr);
+        }
         rt.store.globals.push(Global {
             value,
             mutable: global.ty.mut_ == parser::types::Mutability::Var,
         });
-        inst.global_addrs.push(global_idx as u32);
+        inst.global_addrs.push(global_addr);
+    }
+
+    // Allocate element segments. Like data segments, active segments are copied to the table and
+    // then dropped. Declarative segments are dropped right away.
+    for elem in elems {
+        let elem_addr = ElemAddr(rt.store.elems.len() as u32);
+        let fun_addrs: Vec<FuncAddr> = elem
+            .init
+            .iter()
+            .map(|fun_idx| inst.func_addrs[fun_idx.0 as usize])
+            .collect();
+        match elem.mode {
+            ElemMode::Passive => {
+                rt.store.elems.push(fun_addrs);
+            }
+            ElemMode::Active { table, offset } => {
+                let offset = eval_offset_expr(rt, &inst, &offset)?;
+                let table_addr = inst.table_addrs[table as usize];
+                let table = &mut rt.store.tables[table_addr].elems;
+                let end = u128::from(offset) + fun_addrs.len() as u128;
+                if end > table.len() as u128 {
+                    return Err(InstantiationError::Trap(TrapKind::TableOutOfBounds));
+                }
+                for (i, fun_addr) in fun_addrs.into_iter().enumerate() {
+                    table[offset as usize + i] = Some(Ref::Func(fun_addr));
+                }
+                rt.store.elems.push(vec![]);
+            }
+            ElemMode::Declarative => {
+                rt.store.elems.push(vec![]);
+            }
+        }
+        inst.elem_addrs.push(elem_addr);
     }
 
-    // TODO: Initialize the table with 'elems'
-    // TODO: Initialize the memory with 'data'
+    // Allocate data segments. Active segments are copied to the memory and then dropped, as if
+    // initialized with `memory.init` followed by `data.drop`.
+    for data in data {
+        let data_addr = DataAddr(rt.store.datas.len() as u32);
+        match data.mode {
+            DataMode::Passive => {
+                rt.store.datas.push(data.init);
+            }
+            DataMode::Active { mem, offset } => {
+                let offset = eval_offset_expr(rt, &inst, &offset)?;
+
+                let mem_addr = inst.mem_addrs[mem as usize];
+                let mem = &mut rt.store.mems[mem_addr].data;
+                let end = u128::from(offset) + data.init.len() as u128;
+                if end > mem.len() as u128 {
+                    return Err(InstantiationError::Trap(TrapKind::MemoryOutOfBounds));
+                }
+                mem[offset as usize..end as usize].copy_from_slice(&data.init);
+
+                rt.store.datas.push(vec![]);
+            }
+        }
+        inst.data_addrs.push(data_addr);
+    }
 
     // Set start
     inst.start = start;
@@ -182,248 +929,1887 @@ pub fn allocate_module(rt: &mut Runtime, parsed_module: parser::Module) -> Modul
     // Done
     rt.modules.push(inst);
 
-    module_idx
+    Ok(module_idx)
 }
 
-pub fn call(rt: &mut Runtime, module_idx: ModuleIdx, fun_idx: u32) {
-    let fun_addr = rt.modules[module_idx].func_addrs[fun_idx as usize];
-    let func = &rt.store.funcs[fun_addr as usize];
+// Evaluate a global initializer or a segment offset. See the comments in `ConstExpr` type:
+// `global.get` can only refer to imported globals, which are resolved before globals and segments
+// are allocated.
+fn eval_const_expr(
+    rt: &Runtime,
+    inst: &Module,
+    expr: &parser::Expr,
+) -> Result<Value, InstantiationError> {
+    match ConstExpr::from_expr(expr) {
+        None => Err(InstantiationError::InvalidConstExpr),
+        Some(ConstExpr::Const(value)) => Ok(value),
+        Some(ConstExpr::GlobalGet(idx)) => match inst.global_addrs.get(idx.0 as usize) {
+            Some(global_addr) => Ok(rt.store.globals[*global_addr].value),
+            None => Err(InstantiationError::InvalidConstExpr),
+        },
+    }
+}
+
+// Evaluate offset of an active element or data segment. Offsets of segments of memory64 memories
+// are i64.
+fn eval_offset_expr(
+    rt: &Runtime,
+    inst: &Module,
+    expr: &parser::Expr,
+) -> Result<u64, InstantiationError> {
+    match eval_const_expr(rt, inst, expr)? {
+        Value::I32(offset) => Ok(u64::from(offset as u32)),
+        Value::I64(offset) => Ok(offset as u64),
+        _ => Err(InstantiationError::InvalidConstExpr),
+    }
+}
+
+/// Call a function with the arguments on the stack and return the function's results.
+pub fn call(rt: &mut Runtime, module_idx: ModuleIdx, fun_idx: FuncIdx) -> Result<Vec<Value>, Trap> {
+    let fun_addr = rt.modules[module_idx].func_addrs[fun_idx.0 as usize];
+    call_addr(rt, fun_addr)?;
+
+    let n_results = rt.func_type(fun_addr).ret.len();
+    let mut results = Vec::with_capacity(n_results);
+    for _ in 0..n_results {
+        results.push(rt.stack.pop_value()?);
+    }
+    results.reverse();
+    Ok(results)
+}
+
+// Call a function with the arguments on the stack, leaving the results on the stack
+
+fn call_addr(rt: &mut Runtime, fun_addr: FuncAddr) -> Result<(), Trap> {
+    match &rt.store.funcs[fun_addr] {
+        Func::Wasm(_) => {}
+        Func::Host(func) => {
+            let fun = func.fun.clone();
+            let fun_arity = func.ty.args.len();
+            let mut args = Vec::with_capacity(fun_arity);
+            for _ in 0..fun_arity {
+                args.push(rt.stack.pop_value()?);
+            }
+            args.reverse();
+            for value in fun(rt, &args)? {
+                rt.stack.push_value(value);
+            }
+            return Ok(());
+        }
+    }
+
+    enter_wasm_func(rt, fun_addr)?;
 
-    // println!("func: {:#?}", func);
+    // Run until the end of the function, or until a trap. Frame and blocks of the function are
+    // popped in both cases.
+    let result = exec(rt);
+    leave_wasm_func(rt);
+    result
+}
+
+// Push a frame for the wasm function with the arguments on the stack, and start executing the
+// function body
+fn enter_wasm_func(rt: &mut Runtime, fun_addr: FuncAddr) -> Result<(), Trap> {
+    let func = match &rt.store.funcs[fun_addr] {
+        Func::Wasm(func) => func,
+        Func::Host(_) => panic!("enter_wasm_func: not a wasm function (addr={:?})", fun_addr),
+    };
 
-    rt.frames.push(func);
+    if rt.frames.len() >= rt.max_call_depth {
+        return Err(TrapKind::CallStackExhausted.into());
+    }
 
-    // Set locals for arguments
-    let fun_arity = rt.get_module(module_idx).types[func.fun.ty as usize]
+    let fun_arity = rt.get_module(func.module_idx).types[func.fun.ty.0 as usize]
         .args
         .len();
 
-    for local_idx in (0..fun_arity).rev() {
-        let arg_val = rt.stack.pop_value();
-        rt.frames.current_mut().set_local(local_idx as u32, arg_val);
+    let mut args = Vec::with_capacity(fun_arity);
+    for _ in 0..fun_arity {
+        args.push(rt.stack.pop_value()?);
     }
+    args.reverse();
 
-    // Initialize instruction pointer
-    rt.ip
-        .push((BlockType::Function, func.fun.expr.instrs.clone(), 0));
+    rt.frames.push(func, args);
 
-    // Run until the end of the function.
-    exec(rt);
+    // Initialize instruction pointer
+    let n_results = rt.get_module(func.module_idx).types[func.fun.ty.0 as usize]
+        .ret
+        .len();
+    let instrs = func.fun.expr.instrs.clone();
+    rt.push_block(
+        BlockIp {
+            ty: BlockType::Function,
+            instrs,
+            ip: 0,
+        },
+        Label {
+            target: LabelTarget::End,
+            stack_height: rt.stack.len(),
+            arity: n_results,
+        },
+    );
+
+    Ok(())
+}
 
+// Pop frame and blocks of the current function
+fn leave_wasm_func(rt: &mut Runtime) {
     // Pop function frame
     rt.frames.pop();
 
     // Pop blocks of the function
-    while let Some((BlockType::Block | BlockType::Loop, _, _)) = rt.ip.last() {
-        let _ = rt.ip.pop().unwrap();
+    while let Some(BlockIp {
+        ty: BlockType::Block | BlockType::Loop,
+        ..
+    }) = rt.ip.last()
+    {
+        rt.pop_block();
     }
     // Pop the function block
-    let _ = rt.ip.pop().unwrap();
+    rt.pop_block();
 }
 
-pub fn exec(rt: &mut Runtime) {
-    while let Some((_, block, ip)) = rt.ip.last().cloned() {
-        use Instruction::*;
+// Replace the current function with the function at `fun_addr`, with the arguments on the stack.
+// The current function's frame and blocks are popped before entering the callee, so tail calls
+// don't grow the call stack. The callee's results are returned to the current function's caller.
+fn tail_call(rt: &mut Runtime, fun_addr: FuncAddr) -> Result<(), Trap> {
+    if let Func::Host(_) = &rt.store.funcs[fun_addr] {
+        // Host functions don't use wasm frames, call it and return its results
+        call_addr(rt, fun_addr)?;
+        rt.br(rt.function_label_idx())?;
+        return Ok(());
+    }
+
+    // Drop the values of the current function other than the arguments
+    let n_args = rt.func_type(fun_addr).args.len();
+    let function_label = rt.labels[rt.labels.len() - 1 - rt.function_label_idx() as usize];
+    rt.stack.unwind(function_label.stack_height, n_args);
 
-        if ip as usize == block.len() {
-            rt.next_instr(); // pop the block
-            return;
-        }
+    leave_wasm_func(rt);
+    enter_wasm_func(rt, fun_addr)
+}
 
-        let instr = &block[ip as usize];
+// Function of the current module's table at the index on the stack, for `call_indirect` with
+// type `type_idx`
+fn indirect_callee(rt: &mut Runtime, type_idx: TypeIdx) -> Result<FuncAddr, Trap> {
+    let module_idx = rt.frames.current().module();
+    let table_addr = rt.modules[module_idx].table_addrs[0];
+    let table = &rt.store.tables[table_addr];
+    // Only possible in programs that are not validated
+    if table.elem_type != ElemType::FuncRef {
+        return Err(TrapKind::IndirectCallTypeMismatch.into());
+    }
+    let elem_idx = rt.stack.pop_i32()? as u32;
+    let fun_addr = match table.elems.get(elem_idx as usize) {
+        None => return Err(TrapKind::UndefinedElement.into()),
+        Some(None) => return Err(TrapKind::UninitializedElement.into()),
+        Some(Some(Ref::Func(fun_addr))) => *fun_addr,
+        Some(Some(Ref::Extern(_))) => return Err(TrapKind::IndirectCallTypeMismatch.into()),
+    };
+
+    // Types are compared structurally as the function may be defined in another module
+    let fun_ty = rt.func_type(fun_addr);
+    let expected_ty = &rt.modules[module_idx].types[type_idx.0 as usize];
+    if fun_ty != expected_ty {
+        return Err(TrapKind::IndirectCallTypeMismatch.into());
+    }
 
-        println!("{}: {:?}", ip, instr);
-        // println!("frames: {:?}", runtime.frames);
-        // println!("block: {:?}", runtime.ip);
+    Ok(fun_addr)
+}
 
-        match instr {
-            I32Store(MemArg { align: _, offset }) => {
-                let value = rt.stack.pop_i32();
-                let addr = rt.stack.pop_i32() as u32;
-                let addr = (addr + offset) as usize;
-                let end_addr = addr + 4;
+pub fn exec(rt: &mut Runtime) -> Result<(), Trap> {
+    // Run until the end of the function, the function frame will be popped by `call`.
+    while let StepResult::Continue = step(rt)? {}
+    Ok(())
+}
 
-                let current_module = rt.frames.current().module();
-                let mem = &mut rt.store.mems[current_module];
-                if end_addr as usize > mem.len() {
-                    panic!("OOB I32Store (mem size={}, addr={})", mem.len(), addr);
+// Pop blocks that reached their end and return whether the current function reached its end.
+fn finish_blocks(rt: &mut Runtime) -> bool {
+    loop {
+        match rt.ip.last() {
+            Some(block) if block.ip as usize == block.instrs.len() => match block.ty {
+                BlockType::Function => return true,
+                BlockType::Block | BlockType::Loop => {
+                    // End of the block, continue with the parent block.
+                    rt.pop_block();
                 }
+            },
+            Some(_) => return false,
+            None => panic!("exec: no function to execute"),
+        }
+    }
+}
 
-                let [b1, b2, b3, b4] = value.to_le_bytes();
-                mem[addr] = b1;
-                mem[addr + 1] = b2;
-                mem[addr + 2] = b3;
-                mem[addr + 4] = b4;
+// Execute one instruction of the current function. Calls to other functions are executed in one
+// step. Traps get the backtrace of the instruction that trapped.
+fn step(rt: &mut Runtime) -> Result<StepResult, Trap> {
+    exec_instr(rt).map_err(|mut trap| {
+        // Traps from called wasm functions already have a backtrace
+        if trap.backtrace.is_empty() {
+            trap.backtrace = rt.backtrace();
+        }
+        trap
+    })
+}
 
-                rt.next_instr();
-            }
+fn exec_instr(rt: &mut Runtime) -> Result<StepResult, Trap> {
+    use Instruction::*;
 
-            I32Load(MemArg { align: _, offset }) => {
-                let addr = rt.stack.pop_i32() as u32;
-                let addr = (addr + offset) as usize;
-                let end_addr = addr + 4;
+    if finish_blocks(rt) {
+        return Ok(StepResult::Finished);
+    }
 
-                let current_module = rt.frames.current().module();
-                let mem = &rt.store.mems[current_module];
-                if end_addr as usize > mem.len() {
-                    panic!("OOB I32Load (mem size={}, addr={})", mem.len(), addr);
-                }
+    let BlockIp { instrs, ip, .. } = rt.ip.last().cloned().unwrap();
+    let instr = &instrs[ip as usize];
 
-                let b1 = mem[addr];
-                let b2 = mem[addr + 1];
-                let b3 = mem[addr + 2];
-                let b4 = mem[addr + 3];
-                rt.stack.push_i32(i32::from_le_bytes([b1, b2, b3, b4]));
+    if let Some(fuel) = &mut rt.fuel {
+        if *fuel == 0 {
+            return Err(TrapKind::OutOfFuel.into());
+        }
+        *fuel -= 1;
+    }
 
-                rt.next_instr();
-            }
+    trace!("{}: {:?}", ip, instr);
 
-            LocalGet(idx) => {
-                let val = rt.frames.current().get_local(*idx);
-                rt.stack.push_value(val);
-                rt.next_instr();
-            }
+    if let Some(trace_hook) = &mut rt.trace_hook {
+        let frame = rt.frames.current();
+        trace_hook(&TraceEvent {
+            module_idx: frame.module(),
+            func_idx: frame.fun(),
+            ip,
+            instr,
+            stack_depth: rt.stack.len(),
+        });
+    }
 
-            LocalSet(idx) => {
-                let val = rt.stack.pop_value();
-                rt.frames.current_mut().set_local(*idx, val);
-                rt.next_instr();
-            }
+    match instr {
+        I32Store(memarg) => {
+            let value = rt.stack.pop_i32()?;
+            let addr = rt.pop_addr(memarg.mem)?;
+            rt.store(value.to_le_bytes(), addr, memarg)?;
+            rt.next_instr();
+        }
 
-            LocalTee(idx) => {
-                let val = rt.stack.pop_value();
-                rt.frames.current_mut().set_local(*idx, val);
-                rt.stack.push_value(val);
-                rt.next_instr();
-            }
+        I64Store(memarg) => {
+            let value = rt.stack.pop_i64()?;
+            let addr = rt.pop_addr(memarg.mem)?;
+            rt.store(value.to_le_bytes(), addr, memarg)?;
+            rt.next_instr();
+        }
 
-            GlobalGet(idx) => {
-                let current_module = rt.frames.current().module();
-                let global_idx = rt.modules[current_module].global_addrs[*idx as usize];
-                let value = rt.store.globals[global_idx as usize].value;
-                rt.stack.push_value(value);
-                rt.next_instr();
-            }
+        F32Store(memarg) => {
+            let value = rt.stack.pop_f32()?;
+            let addr = rt.pop_addr(memarg.mem)?;
+            rt.store(value.to_le_bytes(), addr, memarg)?;
+            rt.next_instr();
+        }
 
-            GlobalSet(idx) => {
-                let current_module = rt.frames.current().module();
-                let global_idx = rt.modules[current_module].global_addrs[*idx as usize];
-                let value = rt.stack.pop_value();
-                rt.store.globals[global_idx as usize].value = value;
-                rt.next_instr();
-            }
+        F64Store(memarg) => {
+            let value = rt.stack.pop_f64()?;
+            let addr = rt.pop_addr(memarg.mem)?;
+            rt.store(value.to_le_bytes(), addr, memarg)?;
+            rt.next_instr();
+        }
 
-            I32Const(i) => {
-                rt.stack.push_i32(*i);
-                rt.next_instr();
-            }
+        I32Store8(memarg) => {
+            let value = rt.stack.pop_i32()?;
+            let addr = rt.pop_addr(memarg.mem)?;
+            rt.store((value as u8).to_le_bytes(), addr, memarg)?;
+            rt.next_instr();
+        }
 
-            I64Const(i) => {
-                rt.stack.push_i64(*i);
-                rt.next_instr();
+        I32Store16(memarg) => {
+            let value = rt.stack.pop_i32()?;
+            let addr = rt.pop_addr(memarg.mem)?;
+            rt.store((value as u16).to_le_bytes(), addr, memarg)?;
+            rt.next_instr();
+        }
+
+        I64Store8(memarg) => {
+            let value = rt.stack.pop_i64()?;
+            let addr = rt.pop_addr(memarg.mem)?;
+            rt.store((value as u8).to_le_bytes(), addr, memarg)?;
+            rt.next_instr();
+        }
+
+        I64Store16(memarg) => {
+            let value = rt.stack.pop_i64()?;
+            let addr = rt.pop_addr(memarg.mem)?;
+            rt.store((value as u16).to_le_bytes(), addr, memarg)?;
+            rt.next_instr();
+        }
+
+        I64Store32(memarg) => {
+            let value = rt.stack.pop_i64()?;
+            let addr = rt.pop_addr(memarg.mem)?;
+            rt.store((value as u32).to_le_bytes(), addr, memarg)?;
+            rt.next_instr();
+        }
+
+        I32Load(memarg) => {
+            let addr = rt.pop_addr(memarg.mem)?;
+            let bytes = rt.load::<4>(addr, memarg)?;
+            rt.stack.push_i32(i32::from_le_bytes(bytes));
+            rt.next_instr();
+        }
+
+        I64Load(memarg) => {
+            let addr = rt.pop_addr(memarg.mem)?;
+            let bytes = rt.load::<8>(addr, memarg)?;
+            rt.stack.push_i64(i64::from_le_bytes(bytes));
+            rt.next_instr();
+        }
+
+        F32Load(memarg) => {
+            let addr = rt.pop_addr(memarg.mem)?;
+            let bytes = rt.load::<4>(addr, memarg)?;
+            rt.stack.push_f32(f32::from_le_bytes(bytes));
+            rt.next_instr();
+        }
+
+        F64Load(memarg) => {
+            let addr = rt.pop_addr(memarg.mem)?;
+            let bytes = rt.load::<8>(addr, memarg)?;
+            rt.stack.push_f64(f64::from_le_bytes(bytes));
+            rt.next_instr();
+        }
+
+        I32Load8s(memarg) => {
+            let addr = rt.pop_addr(memarg.mem)?;
+            let bytes = rt.load::<1>(addr, memarg)?;
+            rt.stack.push_i32(i32::from(i8::from_le_bytes(bytes)));
+            rt.next_instr();
+        }
+
+        I32Load8u(memarg) => {
+            let addr = rt.pop_addr(memarg.mem)?;
+            let bytes = rt.load::<1>(addr, memarg)?;
+            rt.stack.push_i32(i32::from(u8::from_le_bytes(bytes)));
+            rt.next_instr();
+        }
+
+        I32Load16s(memarg) => {
+            let addr = rt.pop_addr(memarg.mem)?;
+            let bytes = rt.load::<2>(addr, memarg)?;
+            rt.stack.push_i32(i32::from(i16::from_le_bytes(bytes)));
+            rt.next_instr();
+        }
+
+        I32Load16u(memarg) => {
+            let addr = rt.pop_addr(memarg.mem)?;
+            let bytes = rt.load::<2>(addr, memarg)?;
+            rt.stack.push_i32(i32::from(u16::from_le_bytes(bytes)));
+            rt.next_instr();
+        }
+
+        I64Load8s(memarg) => {
+            let addr = rt.pop_addr(memarg.mem)?;
+            let bytes = rt.load::<1>(addr, memarg)?;
+            rt.stack.push_i64(i64::from(i8::from_le_bytes(bytes)));
+            rt.next_instr();
+        }
+
+        I64Load8u(memarg) => {
+            let addr = rt.pop_addr(memarg.mem)?;
+            let bytes = rt.load::<1>(addr, memarg)?;
+            rt.stack.push_i64(i64::from(u8::from_le_bytes(bytes)));
+            rt.next_instr();
+        }
+
+        I64Load16s(memarg) => {
+            let addr = rt.pop_addr(memarg.mem)?;
+            let bytes = rt.load::<2>(addr, memarg)?;
+            rt.stack.push_i64(i64::from(i16::from_le_bytes(bytes)));
+            rt.next_instr();
+        }
+
+        I64Load16u(memarg) => {
+            let addr = rt.pop_addr(memarg.mem)?;
+            let bytes = rt.load::<2>(addr, memarg)?;
+            rt.stack.push_i64(i64::from(u16::from_le_bytes(bytes)));
+            rt.next_instr();
+        }
+
+        I64Load32s(memarg) => {
+            let addr = rt.pop_addr(memarg.mem)?;
+            let bytes = rt.load::<4>(addr, memarg)?;
+            rt.stack.push_i64(i64::from(i32::from_le_bytes(bytes)));
+            rt.next_instr();
+        }
+
+        I64Load32u(memarg) => {
+            let addr = rt.pop_addr(memarg.mem)?;
+            let bytes = rt.load::<4>(addr, memarg)?;
+            rt.stack.push_i64(i64::from(u32::from_le_bytes(bytes)));
+            rt.next_instr();
+        }
+
+        MemorySize => {
+            let mem_addr = rt.current_mem_addr(0);
+            let mem = &rt.store.mems[mem_addr];
+            let size = (mem.data.len() / PAGE_SIZE) as u32;
+            if mem.memory64 {
+                rt.stack.push_u64(u64::from(size));
+            } else {
+                rt.stack.push_u32(size);
             }
+            rt.next_instr();
+        }
 
-            F32Const(f) => {
-                rt.stack.push_f32(*f);
-                rt.next_instr();
+        MemoryGrow => {
+            let delta = rt.pop_addr(0)?;
+            let mem_addr = rt.current_mem_addr(0);
+            let mem = &mut rt.store.mems[mem_addr];
+            let old_size = (mem.data.len() / PAGE_SIZE) as u32;
+            let new_size = u128::from(old_size) + u128::from(delta);
+            let max = mem.max.unwrap_or_else(|| u64::from(MAX_PAGES));
+            // Growing also fails when the memory can't be allocated
+            let new_len = (new_size as usize).checked_mul(PAGE_SIZE);
+            let result = match new_len {
+                Some(new_len)
+                    if new_size <= u128::from(max)
+                        && mem.data.try_reserve_exact(new_len - mem.data.len()).is_ok() =>
+                {
+                    mem.data.resize(new_len, 0);
+                    i64::from(old_size)
+                }
+                _ => -1,
+            };
+            if mem.memory64 {
+                rt.stack.push_i64(result);
+            } else {
+                rt.stack.push_i32(result as i32);
             }
+            rt.next_instr();
+        }
 
-            F64Const(f) => {
-                rt.stack.push_f64(*f);
-                rt.next_instr();
+        MemoryFill => {
+            let n = rt.pop_addr(0)?;
+            let val = rt.stack.pop_i32()? as u8;
+            let dst = rt.pop_addr(0)?;
+            let mem_addr = rt.current_mem_addr(0);
+            let mem = &mut rt.store.mems[mem_addr].data;
+            if u128::from(dst) + u128::from(n) > mem.len() as u128 {
+                return Err(TrapKind::MemoryOutOfBounds.into());
             }
+            mem[dst as usize..(dst + n) as usize].fill(val);
+            rt.next_instr();
+        }
 
-            I32Eqz => {
-                let val = rt.stack.pop_i32();
-                rt.stack.push_bool(val == 0);
-                rt.next_instr();
+        MemoryCopy => {
+            let n = rt.pop_addr(0)?;
+            let src = rt.pop_addr(0)?;
+            let dst = rt.pop_addr(0)?;
+            let mem_addr = rt.current_mem_addr(0);
+            let mem = &mut rt.store.mems[mem_addr].data;
+            if u128::from(src) + u128::from(n) > mem.len() as u128
+                || u128::from(dst) + u128::from(n) > mem.len() as u128
+            {
+                return Err(TrapKind::MemoryOutOfBounds.into());
             }
+            // `copy_within` handles overlapping ranges like `memmove`
+            mem.copy_within(src as usize..(src + n) as usize, dst as usize);
+            rt.next_instr();
+        }
 
-            I32Le_u => {
-                let val2 = rt.stack.pop_i32();
-                let val1 = rt.stack.pop_i32();
-                rt.stack.push_bool(val1 <= val2);
-                rt.next_instr();
+        MemoryInit(data_idx) => {
+            let n = u64::from(rt.stack.pop_i32()? as u32);
+            let src = u64::from(rt.stack.pop_i32()? as u32);
+            let dst = rt.pop_addr(0)?;
+            let current_module = rt.frames.current().module();
+            let data_addr = rt.modules[current_module].data_addrs[*data_idx as usize];
+            let mem_addr = rt.current_mem_addr(0);
+            let data = &rt.store.datas[data_addr];
+            let mem = &mut rt.store.mems[mem_addr].data;
+            if src + n > data.len() as u64 || u128::from(dst) + u128::from(n) > mem.len() as u128 {
+                return Err(TrapKind::MemoryOutOfBounds.into());
             }
+            mem[dst as usize..(dst + n) as usize]
+                .copy_from_slice(&data[src as usize..(src + n) as usize]);
+            rt.next_instr();
+        }
 
-            I32Sub => {
-                let val2 = rt.stack.pop_i32();
-                let val1 = rt.stack.pop_i32();
-                rt.stack.push_i32(val1 - val2);
-                rt.next_instr();
+        DataDrop(data_idx) => {
+            let current_module = rt.frames.current().module();
+            let data_addr = rt.modules[current_module].data_addrs[*data_idx as usize];
+            rt.store.datas[data_addr] = vec![];
+            rt.next_instr();
+        }
+
+        Drop => {
+            let _ = rt.stack.pop_value()?;
+            rt.next_instr();
+        }
+
+        Select => {
+            let c = rt.stack.pop_i32()?;
+            let val2 = rt.stack.pop_value()?;
+            let val1 = rt.stack.pop_value()?;
+            rt.stack.push_value(if c != 0 { val1 } else { val2 });
+            rt.next_instr();
+        }
+
+        SelectT(tys) => {
+            let c = rt.stack.pop_i32()?;
+            let val2 = rt.stack.pop_value()?;
+            let val1 = rt.stack.pop_value()?;
+            let val = if c != 0 { val1 } else { val2 };
+            debug_assert!(
+                matches!(&tys[..], [ty] if value_has_type(&val, ty)),
+                "select: value {:?} doesn't match annotated type {:?}",
+                val,
+                tys
+            );
+            rt.stack.push_value(val);
+            rt.next_instr();
+        }
+
+        LocalGet(idx) => {
+            let val = rt.frames.current().get_local(*idx);
+            rt.stack.push_value(val);
+            rt.next_instr();
+        }
+
+        LocalSet(idx) => {
+            let val = rt.stack.pop_value()?;
+            rt.frames.current_mut().set_local(*idx, val);
+            rt.next_instr();
+        }
+
+        LocalTee(idx) => {
+            let val = rt.stack.pop_value()?;
+            rt.frames.current_mut().set_local(*idx, val);
+            rt.stack.push_value(val);
+            rt.next_instr();
+        }
+
+        GlobalGet(idx) => {
+            let current_module = rt.frames.current().module();
+            let global_addr = rt.modules[current_module].global_addrs[idx.0 as usize];
+            let value = rt.store.globals[global_addr].value;
+            rt.stack.push_value(value);
+            rt.next_instr();
+        }
+
+        GlobalSet(idx) => {
+            let current_module = rt.frames.current().module();
+            let global_addr = rt.modules[current_module].global_addrs[idx.0 as usize];
+            let value = rt.stack.pop_value()?;
+            rt.store.globals[global_addr].value = value;
+            rt.next_instr();
+        }
+
+        TableGet(idx) => {
+            let current_module = rt.frames.current().module();
+            let table_addr = rt.modules[current_module].table_addrs[*idx as usize];
+            let elem_idx = rt.stack.pop_i32()? as u32;
+            let table = &rt.store.tables[table_addr];
+            let elem = match table.elems.get(elem_idx as usize) {
+                None => return Err(TrapKind::TableOutOfBounds.into()),
+                Some(elem) => *elem,
+            };
+            table.push_elem(&mut rt.stack, elem);
+            rt.next_instr();
+        }
+
+        TableSet(idx) => {
+            let current_module = rt.frames.current().module();
+            let table_addr = rt.modules[current_module].table_addrs[*idx as usize];
+            let elem = rt.store.tables[table_addr].pop_elem(&mut rt.stack)?;
+            let elem_idx = rt.stack.pop_i32()? as u32;
+            match rt.store.tables[table_addr].elems.get_mut(elem_idx as usize) {
+                None => return Err(TrapKind::TableOutOfBounds.into()),
+                Some(slot) => *slot = elem,
             }
+            rt.next_instr();
+        }
 
-            //////////////////////////
-            // Control instructions //
-            //////////////////////////
-            Call(func_idx) => {
-                let module_idx = rt.frames.current().module();
-                call(rt, module_idx, *func_idx);
-                rt.next_instr();
+        TableInit(elem_idx, table_idx) => {
+            let n = rt.stack.pop_i32()? as u32;
+            let src = rt.stack.pop_i32()? as u32;
+            let dst = rt.stack.pop_i32()? as u32;
+            let current_module = rt.frames.current().module();
+            let elem_addr = rt.modules[current_module].elem_addrs[*elem_idx as usize];
+            let table_addr = rt.modules[current_module].table_addrs[*table_idx as usize];
+            let elem = &rt.store.elems[elem_addr];
+            let table = &mut rt.store.tables[table_addr].elems;
+            if u64::from(src) + u64::from(n) > elem.len() as u64
+                || u64::from(dst) + u64::from(n) > table.len() as u64
+            {
+                return Err(TrapKind::TableOutOfBounds.into());
+            }
+            for i in 0..n as usize {
+                table[dst as usize + i] = Some(Ref::Func(elem[src as usize + i]));
             }
+            rt.next_instr();
+        }
 
-            CallIndirect(_type_idx) => {
-                todo!()
-                /*
-                let module_idx = runtime.frames.current().module();
-                let table_idx = runtime.modules[module_idx].table_addrs[0];
-                let table = &runtime.store.tables[table_idx as usize];
-                let fun_idx = runtime.stack.pop_i32();
-                match table.get(fun_idx as usize) {
-                    None => {
-                        panic!("call_indirect: OOB function index (function idx={}, table idx={}, table size={})",
-                               fun_idx, table_idx, table.len());
-                    }
-                    Some(None) => {
-                        panic!("call_indirect: function index not initialized (function idx={}, table idx={})",
-                               fun_idx, table_idx);
-                    }
-                    Some(Some(fun_addr)) => {
-                        let fun = &runtime.store.funcs[*fun_addr as usize];
-
-                        let fun_ty = fun.fun.ty;
-                        if fun_ty != *type_idx {
-                            panic!("call_indirect: function type doesn't match expected type (fun ty={}, expected={})",
-                                   fun_ty, type_idx);
-                        }
-
-                        runtime.frames.push(fun);
-                        let instrs = fun.fun.expr.instrs.clone();
-                        exec(runtime, &*instrs, 0);
-                        runtime.frames.pop();
-                        ip += 1;
-                    }
-                }
-                */
+        ElemDrop(elem_idx) => {
+            let current_module = rt.frames.current().module();
+            let elem_addr = rt.modules[current_module].elem_addrs[*elem_idx as usize];
+            rt.store.elems[elem_addr] = vec![];
+            rt.next_instr();
+        }
+
+        TableCopy(dst_idx, src_idx) => {
+            let n = rt.stack.pop_i32()? as u32;
+            let src = rt.stack.pop_i32()? as u32;
+            let dst = rt.stack.pop_i32()? as u32;
+            let current_module = rt.frames.current().module();
+            let dst_addr = rt.modules[current_module].table_addrs[*dst_idx as usize];
+            let src_addr = rt.modules[current_module].table_addrs[*src_idx as usize];
+            let src_table = &rt.store.tables[src_addr].elems;
+            let dst_len = rt.store.tables[dst_addr].elems.len();
+            if u64::from(src) + u64::from(n) > src_table.len() as u64
+                || u64::from(dst) + u64::from(n) > dst_len as u64
+            {
+                return Err(TrapKind::TableOutOfBounds.into());
+            }
+            // Copy via a temporary buffer as the tables may be the same, with overlapping ranges
+            let elems = src_table[src as usize..(src + n) as usize].to_vec();
+            rt.store.tables[dst_addr].elems[dst as usize..(dst + n) as usize]
+                .copy_from_slice(&elems);
+            rt.next_instr();
+        }
+
+        TableGrow(idx) => {
+            let current_module = rt.frames.current().module();
+            let table_addr = rt.modules[current_module].table_addrs[*idx as usize];
+            let delta = rt.stack.pop_i32()? as u32;
+            let init = rt.store.tables[table_addr].pop_elem(&mut rt.stack)?;
+            match rt.store.tables[table_addr].grow(delta, init) {
+                Some(old_size) => rt.stack.push_u32(old_size),
+                None => rt.stack.push_i32(-1),
+            }
+            rt.next_instr();
+        }
+
+        TableSize(idx) => {
+            let current_module = rt.frames.current().module();
+            let table_addr = rt.modules[current_module].table_addrs[*idx as usize];
+            let size = rt.store.tables[table_addr].elems.len();
+            rt.stack.push_u32(size as u32);
+            rt.next_instr();
+        }
+
+        TableFill(idx) => {
+            let current_module = rt.frames.current().module();
+            let table_addr = rt.modules[current_module].table_addrs[*idx as usize];
+            let n = rt.stack.pop_i32()? as u32;
+            let val = rt.store.tables[table_addr].pop_elem(&mut rt.stack)?;
+            let dst = rt.stack.pop_i32()? as u32;
+            let elems = &mut rt.store.tables[table_addr].elems;
+            if u64::from(dst) + u64::from(n) > elems.len() as u64 {
+                return Err(TrapKind::TableOutOfBounds.into());
+            }
+            elems[dst as usize..(dst + n) as usize].fill(val);
+            rt.next_instr();
+        }
+
+        RefNull(ty) => {
+            rt.stack.push_value(Value::default_for_type(*ty));
+            rt.next_instr();
+        }
+
+        RefIsNull => {
+            let is_null = match rt.stack.pop_value()? {
+                Value::FuncRef(fun_addr) => fun_addr.is_none(),
+                Value::ExternRef(extern_addr) => extern_addr.is_none(),
+                _ => return Err(TrapKind::OperandTypeMismatch.into()),
+            };
+            rt.stack.push_bool(is_null);
+            rt.next_instr();
+        }
+
+        RefFunc(func_idx) => {
+            let current_module = rt.frames.current().module();
+            let fun_addr = rt.modules[current_module].func_addrs[func_idx.0 as usize];
+            rt.stack.push_funcref(Some(fun_addr));
+            rt.next_instr();
+        }
+
+        I32Const(i) => {
+            rt.stack.push_i32(*i);
+            rt.next_instr();
+        }
+
+        I64Const(i) => {
+            rt.stack.push_i64(*i);
+            rt.next_instr();
+        }
+
+        F32Const(f) => {
+            rt.stack.push_f32(*f);
+            rt.next_instr();
+        }
+
+        F64Const(f) => {
+            rt.stack.push_f64(*f);
+            rt.next_instr();
+        }
+
+        I32Eqz => {
+            let val = rt.stack.pop_i32()?;
+            rt.stack.push_bool(val == 0);
+            rt.next_instr();
+        }
+
+        I32Eq => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_bool(val1 == val2);
+            rt.next_instr();
+        }
+
+        I32Ne => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_bool(val1 != val2);
+            rt.next_instr();
+        }
+
+        I32Lt_s => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_bool(val1 < val2);
+            rt.next_instr();
+        }
+
+        I32Lt_u => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_bool((val1 as u32) < (val2 as u32));
+            rt.next_instr();
+        }
+
+        I32Gt_s => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_bool(val1 > val2);
+            rt.next_instr();
+        }
+
+        I32Gt_u => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_bool((val1 as u32) > (val2 as u32));
+            rt.next_instr();
+        }
+
+        I32Le_s => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_bool(val1 <= val2);
+            rt.next_instr();
+        }
+
+        I32Le_u => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_bool((val1 as u32) <= (val2 as u32));
+            rt.next_instr();
+        }
+
+        I32Ge_s => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_bool(val1 >= val2);
+            rt.next_instr();
+        }
+
+        I32Ge_u => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_bool((val1 as u32) >= (val2 as u32));
+            rt.next_instr();
+        }
+
+        I64Eqz => {
+            let val = rt.stack.pop_i64()?;
+            rt.stack.push_bool(val == 0);
+            rt.next_instr();
+        }
+
+        I64Eq => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_bool(val1 == val2);
+            rt.next_instr();
+        }
+
+        I64Ne => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_bool(val1 != val2);
+            rt.next_instr();
+        }
+
+        I64Lt_s => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_bool(val1 < val2);
+            rt.next_instr();
+        }
+
+        I64Lt_u => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_bool((val1 as u64) < (val2 as u64));
+            rt.next_instr();
+        }
+
+        I64Gt_s => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_bool(val1 > val2);
+            rt.next_instr();
+        }
+
+        I64Gt_u => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_bool((val1 as u64) > (val2 as u64));
+            rt.next_instr();
+        }
+
+        I64Le_s => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_bool(val1 <= val2);
+            rt.next_instr();
+        }
+
+        I64Le_u => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_bool((val1 as u64) <= (val2 as u64));
+            rt.next_instr();
+        }
+
+        I64Ge_s => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_bool(val1 >= val2);
+            rt.next_instr();
+        }
+
+        I64Ge_u => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_bool((val1 as u64) >= (val2 as u64));
+            rt.next_instr();
+        }
+
+        F32Eq => {
+            let val2 = rt.stack.pop_f32()?;
+            let val1 = rt.stack.pop_f32()?;
+            rt.stack.push_bool(val1 == val2);
+            rt.next_instr();
+        }
+
+        F32Ne => {
+            let val2 = rt.stack.pop_f32()?;
+            let val1 = rt.stack.pop_f32()?;
+            rt.stack.push_bool(val1 != val2);
+            rt.next_instr();
+        }
+
+        F32Lt => {
+            let val2 = rt.stack.pop_f32()?;
+            let val1 = rt.stack.pop_f32()?;
+            rt.stack.push_bool(val1 < val2);
+            rt.next_instr();
+        }
+
+        F32Gt => {
+            let val2 = rt.stack.pop_f32()?;
+            let val1 = rt.stack.pop_f32()?;
+            rt.stack.push_bool(val1 > val2);
+            rt.next_instr();
+        }
+
+        F32Le => {
+            let val2 = rt.stack.pop_f32()?;
+            let val1 = rt.stack.pop_f32()?;
+            rt.stack.push_bool(val1 <= val2);
+            rt.next_instr();
+        }
+
+        F32Ge => {
+            let val2 = rt.stack.pop_f32()?;
+            let val1 = rt.stack.pop_f32()?;
+            rt.stack.push_bool(val1 >= val2);
+            rt.next_instr();
+        }
+
+        F64Eq => {
+            let val2 = rt.stack.pop_f64()?;
+            let val1 = rt.stack.pop_f64()?;
+            rt.stack.push_bool(val1 == val2);
+            rt.next_instr();
+        }
+
+        F64Ne => {
+            let val2 = rt.stack.pop_f64()?;
+            let val1 = rt.stack.pop_f64()?;
+            rt.stack.push_bool(val1 != val2);
+            rt.next_instr();
+        }
+
+        F64Lt => {
+            let val2 = rt.stack.pop_f64()?;
+            let val1 = rt.stack.pop_f64()?;
+            rt.stack.push_bool(val1 < val2);
+            rt.next_instr();
+        }
+
+        F64Gt => {
+            let val2 = rt.stack.pop_f64()?;
+            let val1 = rt.stack.pop_f64()?;
+            rt.stack.push_bool(val1 > val2);
+            rt.next_instr();
+        }
+
+        F64Le => {
+            let val2 = rt.stack.pop_f64()?;
+            let val1 = rt.stack.pop_f64()?;
+            rt.stack.push_bool(val1 <= val2);
+            rt.next_instr();
+        }
+
+        F64Ge => {
+            let val2 = rt.stack.pop_f64()?;
+            let val1 = rt.stack.pop_f64()?;
+            rt.stack.push_bool(val1 >= val2);
+            rt.next_instr();
+        }
+
+        I32Clz => {
+            let val = rt.stack.pop_i32()?;
+            rt.stack.push_i32(val.leading_zeros() as i32);
+            rt.next_instr();
+        }
+
+        I32Ctz => {
+            let val = rt.stack.pop_i32()?;
+            rt.stack.push_i32(val.trailing_zeros() as i32);
+            rt.next_instr();
+        }
+
+        I32Popcnt => {
+            let val = rt.stack.pop_i32()?;
+            rt.stack.push_i32(val.count_ones() as i32);
+            rt.next_instr();
+        }
+
+        I32Add => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_i32(val1.wrapping_add(val2));
+            rt.next_instr();
+        }
+
+        I32Sub => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_i32(val1.wrapping_sub(val2));
+            rt.next_instr();
+        }
+
+        I32Mul => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_i32(val1.wrapping_mul(val2));
+            rt.next_instr();
+        }
+
+        I32Div_s => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            if val2 == 0 {
+                return Err(TrapKind::IntegerDivideByZero.into());
+            }
+            match val1.checked_div(val2) {
+                Some(result) => rt.stack.push_i32(result),
+                None => return Err(TrapKind::IntegerOverflow.into()),
+            }
+            rt.next_instr();
+        }
+
+        I32Div_u => {
+            let val2 = rt.stack.pop_i32()? as u32;
+            let val1 = rt.stack.pop_i32()? as u32;
+            if val2 == 0 {
+                return Err(TrapKind::IntegerDivideByZero.into());
+            }
+            rt.stack.push_u32(val1 / val2);
+            rt.next_instr();
+        }
+
+        I32Rem_s => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            if val2 == 0 {
+                return Err(TrapKind::IntegerDivideByZero.into());
+            }
+            // `MIN % -1` overflows in Rust, but the result is 0 in wasm
+            rt.stack.push_i32(val1.wrapping_rem(val2));
+            rt.next_instr();
+        }
+
+        I32Rem_u => {
+            let val2 = rt.stack.pop_i32()? as u32;
+            let val1 = rt.stack.pop_i32()? as u32;
+            if val2 == 0 {
+                return Err(TrapKind::IntegerDivideByZero.into());
             }
+            rt.stack.push_u32(val1 % val2);
+            rt.next_instr();
+        }
+
+        I32And => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_i32(val1 & val2);
+            rt.next_instr();
+        }
 
-            Return => {
-                break;
+        I32Or => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_i32(val1 | val2);
+            rt.next_instr();
+        }
+
+        I32Xor => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_i32(val1 ^ val2);
+            rt.next_instr();
+        }
+
+        // Shift counts are taken modulo the bit width
+        I32Shl => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_i32(val1 << (val2 & 31));
+            rt.next_instr();
+        }
+
+        I32Shr_s => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_i32(val1 >> (val2 & 31));
+            rt.next_instr();
+        }
+
+        I32Shr_u => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_u32((val1 as u32) >> (val2 & 31));
+            rt.next_instr();
+        }
+
+        I32Rotl => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_i32(val1.rotate_left((val2 & 31) as u32));
+            rt.next_instr();
+        }
+
+        I32Rotr => {
+            let val2 = rt.stack.pop_i32()?;
+            let val1 = rt.stack.pop_i32()?;
+            rt.stack.push_i32(val1.rotate_right((val2 & 31) as u32));
+            rt.next_instr();
+        }
+
+        I64Clz => {
+            let val = rt.stack.pop_i64()?;
+            rt.stack.push_i64(val.leading_zeros() as i64);
+            rt.next_instr();
+        }
+
+        I64Ctz => {
+            let val = rt.stack.pop_i64()?;
+            rt.stack.push_i64(val.trailing_zeros() as i64);
+            rt.next_instr();
+        }
+
+        I64Popcnt => {
+            let val = rt.stack.pop_i64()?;
+            rt.stack.push_i64(val.count_ones() as i64);
+            rt.next_instr();
+        }
+
+        I64Add => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_i64(val1.wrapping_add(val2));
+            rt.next_instr();
+        }
+
+        I64Sub => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_i64(val1.wrapping_sub(val2));
+            rt.next_instr();
+        }
+
+        I64Mul => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_i64(val1.wrapping_mul(val2));
+            rt.next_instr();
+        }
+
+        I64Div_s => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            if val2 == 0 {
+                return Err(TrapKind::IntegerDivideByZero.into());
+            }
+            match val1.checked_div(val2) {
+                Some(result) => rt.stack.push_i64(result),
+                None => return Err(TrapKind::IntegerOverflow.into()),
             }
+            rt.next_instr();
+        }
 
-            Block(parser::types::Block { ty: _, instrs }) => {
-                // Bump instruction pointer for the current block
+        I64Div_u => {
+            let val2 = rt.stack.pop_i64()? as u64;
+            let val1 = rt.stack.pop_i64()? as u64;
+            if val2 == 0 {
+                return Err(TrapKind::IntegerDivideByZero.into());
+            }
+            rt.stack.push_u64(val1 / val2);
+            rt.next_instr();
+        }
+
+        I64Rem_s => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            if val2 == 0 {
+                return Err(TrapKind::IntegerDivideByZero.into());
+            }
+            // `MIN % -1` overflows in Rust, but the result is 0 in wasm
+            rt.stack.push_i64(val1.wrapping_rem(val2));
+            rt.next_instr();
+        }
+
+        I64Rem_u => {
+            let val2 = rt.stack.pop_i64()? as u64;
+            let val1 = rt.stack.pop_i64()? as u64;
+            if val2 == 0 {
+                return Err(TrapKind::IntegerDivideByZero.into());
+            }
+            rt.stack.push_u64(val1 % val2);
+            rt.next_instr();
+        }
+
+        I64And => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_i64(val1 & val2);
+            rt.next_instr();
+        }
+
+        I64Or => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_i64(val1 | val2);
+            rt.next_instr();
+        }
+
+        I64Xor => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_i64(val1 ^ val2);
+            rt.next_instr();
+        }
+
+        // Shift counts are taken modulo the bit width
+        I64Shl => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_i64(val1 << (val2 & 63));
+            rt.next_instr();
+        }
+
+        I64Shr_s => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_i64(val1 >> (val2 & 63));
+            rt.next_instr();
+        }
+
+        I64Shr_u => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_u64((val1 as u64) >> (val2 & 63));
+            rt.next_instr();
+        }
+
+        I64Rotl => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_i64(val1.rotate_left((val2 & 63) as u32));
+            rt.next_instr();
+        }
+
+        I64Rotr => {
+            let val2 = rt.stack.pop_i64()?;
+            let val1 = rt.stack.pop_i64()?;
+            rt.stack.push_i64(val1.rotate_right((val2 & 63) as u32));
+            rt.next_instr();
+        }
+
+        F32Abs => {
+            let val = rt.stack.pop_f32()?;
+            rt.stack.push_f32(val.abs());
+            rt.next_instr();
+        }
+
+        F32Neg => {
+            let val = rt.stack.pop_f32()?;
+            rt.stack.push_f32(-val);
+            rt.next_instr();
+        }
+
+        F32Ceil => {
+            let val = rt.stack.pop_f32()?;
+            rt.stack.push_f32(canonicalize_f32(val.ceil()));
+            rt.next_instr();
+        }
+
+        F32Floor => {
+            let val = rt.stack.pop_f32()?;
+            rt.stack.push_f32(canonicalize_f32(val.floor()));
+            rt.next_instr();
+        }
+
+        F32Trunc => {
+            let val = rt.stack.pop_f32()?;
+            rt.stack.push_f32(canonicalize_f32(val.trunc()));
+            rt.next_instr();
+        }
+
+        // Round to nearest, ties to even
+        F32Nearest => {
+            let val = rt.stack.pop_f32()?;
+            rt.stack.push_f32(canonicalize_f32(val.round_ties_even()));
+            rt.next_instr();
+        }
+
+        F32Sqrt => {
+            let val = rt.stack.pop_f32()?;
+            rt.stack.push_f32(canonicalize_f32(val.sqrt()));
+            rt.next_instr();
+        }
+
+        F32Add => {
+            let val2 = rt.stack.pop_f32()?;
+            let val1 = rt.stack.pop_f32()?;
+            rt.stack.push_f32(canonicalize_f32(val1 + val2));
+            rt.next_instr();
+        }
+
+        F32Sub => {
+            let val2 = rt.stack.pop_f32()?;
+            let val1 = rt.stack.pop_f32()?;
+            rt.stack.push_f32(canonicalize_f32(val1 - val2));
+            rt.next_instr();
+        }
+
+        F32Mul => {
+            let val2 = rt.stack.pop_f32()?;
+            let val1 = rt.stack.pop_f32()?;
+            rt.stack.push_f32(canonicalize_f32(val1 * val2));
+            rt.next_instr();
+        }
+
+        F32Div => {
+            let val2 = rt.stack.pop_f32()?;
+            let val1 = rt.stack.pop_f32()?;
+            rt.stack.push_f32(canonicalize_f32(val1 / val2));
+            rt.next_instr();
+        }
+
+        F32Min => {
+            let val2 = rt.stack.pop_f32()?;
+            let val1 = rt.stack.pop_f32()?;
+            rt.stack.push_f32(canonicalize_f32(f32_min(val1, val2)));
+            rt.next_instr();
+        }
+
+        F32Max => {
+            let val2 = rt.stack.pop_f32()?;
+            let val1 = rt.stack.pop_f32()?;
+            rt.stack.push_f32(canonicalize_f32(f32_max(val1, val2)));
+            rt.next_instr();
+        }
+
+        F32Copysign => {
+            let val2 = rt.stack.pop_f32()?;
+            let val1 = rt.stack.pop_f32()?;
+            rt.stack.push_f32(val1.copysign(val2));
+            rt.next_instr();
+        }
+
+        F64Abs => {
+            let val = rt.stack.pop_f64()?;
+            rt.stack.push_f64(val.abs());
+            rt.next_instr();
+        }
+
+        F64Neg => {
+            let val = rt.stack.pop_f64()?;
+            rt.stack.push_f64(-val);
+            rt.next_instr();
+        }
+
+        F64Ceil => {
+            let val = rt.stack.pop_f64()?;
+            rt.stack.push_f64(canonicalize_f64(val.ceil()));
+            rt.next_instr();
+        }
+
+        F64Floor => {
+            let val = rt.stack.pop_f64()?;
+            rt.stack.push_f64(canonicalize_f64(val.floor()));
+            rt.next_instr();
+        }
+
+        F64Trunc => {
+            let val = rt.stack.pop_f64()?;
+            rt.stack.push_f64(canonicalize_f64(val.trunc()));
+            rt.next_instr();
+        }
+
+        // Round to nearest, ties to even
+        F64Nearest => {
+            let val = rt.stack.pop_f64()?;
+            rt.stack.push_f64(canonicalize_f64(val.round_ties_even()));
+            rt.next_instr();
+        }
+
+        F64Sqrt => {
+            let val = rt.stack.pop_f64()?;
+            rt.stack.push_f64(canonicalize_f64(val.sqrt()));
+            rt.next_instr();
+        }
+
+        F64Add => {
+            let val2 = rt.stack.pop_f64()?;
+            let val1 = rt.stack.pop_f64()?;
+            rt.stack.push_f64(canonicalize_f64(val1 + val2));
+            rt.next_instr();
+        }
+
+        F64Sub => {
+            let val2 = rt.stack.pop_f64()?;
+            let val1 = rt.stack.pop_f64()?;
+            rt.stack.push_f64(canonicalize_f64(val1 - val2));
+            rt.next_instr();
+        }
+
+        F64Mul => {
+            let val2 = rt.stack.pop_f64()?;
+            let val1 = rt.stack.pop_f64()?;
+            rt.stack.push_f64(canonicalize_f64(val1 * val2));
+            rt.next_instr();
+        }
+
+        F64Div => {
+            let val2 = rt.stack.pop_f64()?;
+            let val1 = rt.stack.pop_f64()?;
+            rt.stack.push_f64(canonicalize_f64(val1 / val2));
+            rt.next_instr();
+        }
+
+        F64Min => {
+            let val2 = rt.stack.pop_f64()?;
+            let val1 = rt.stack.pop_f64()?;
+            rt.stack.push_f64(canonicalize_f64(f64_min(val1, val2)));
+            rt.next_instr();
+        }
+
+        F64Max => {
+            let val2 = rt.stack.pop_f64()?;
+            let val1 = rt.stack.pop_f64()?;
+            rt.stack.push_f64(canonicalize_f64(f64_max(val1, val2)));
+            rt.next_instr();
+        }
+
+        F64Copysign => {
+            let val2 = rt.stack.pop_f64()?;
+            let val1 = rt.stack.pop_f64()?;
+            rt.stack.push_f64(val1.copysign(val2));
+            rt.next_instr();
+        }
+
+        I32Wrapi64 => {
+            let val = rt.stack.pop_i64()?;
+            rt.stack.push_i32(val as i32);
+            rt.next_instr();
+        }
+
+        I32Truncf32_s => {
+            let val = rt.stack.pop_f32()?;
+            rt.stack.push_i32(trunc_to_i32(f64::from(val))?);
+            rt.next_instr();
+        }
+
+        I32Truncf32_u => {
+            let val = rt.stack.pop_f32()?;
+            rt.stack.push_u32(trunc_to_u32(f64::from(val))?);
+            rt.next_instr();
+        }
+
+        I32Truncf64_s => {
+            let val = rt.stack.pop_f64()?;
+            rt.stack.push_i32(trunc_to_i32(val)?);
+            rt.next_instr();
+        }
+
+        I32Truncf64_u => {
+            let val = rt.stack.pop_f64()?;
+            rt.stack.push_u32(trunc_to_u32(val)?);
+            rt.next_instr();
+        }
+
+        I64Extendi32_s => {
+            let val = rt.stack.pop_i32()?;
+            rt.stack.push_i64(i64::from(val));
+            rt.next_instr();
+        }
+
+        I64Extendi32_u => {
+            let val = rt.stack.pop_i32()?;
+            rt.stack.push_i64(i64::from(val as u32));
+            rt.next_instr();
+        }
+
+        I64Truncf32_s => {
+            let val = rt.stack.pop_f32()?;
+            rt.stack.push_i64(trunc_to_i64(f64::from(val))?);
+            rt.next_instr();
+        }
+
+        I64Truncf32_u => {
+            let val = rt.stack.pop_f32()?;
+            rt.stack.push_u64(trunc_to_u64(f64::from(val))?);
+            rt.next_instr();
+        }
+
+        I64Truncf64_s => {
+            let val = rt.stack.pop_f64()?;
+            rt.stack.push_i64(trunc_to_i64(val)?);
+            rt.next_instr();
+        }
+
+        I64Truncf64_u => {
+            let val = rt.stack.pop_f64()?;
+            rt.stack.push_u64(trunc_to_u64(val)?);
+            rt.next_instr();
+        }
+
+        F32Converti32_s => {
+            let val = rt.stack.pop_i32()?;
+            rt.stack.push_f32(val as f32);
+            rt.next_instr();
+        }
+
+        F32Converti32_u => {
+            let val = rt.stack.pop_i32()?;
+            rt.stack.push_f32(val as u32 as f32);
+            rt.next_instr();
+        }
+
+        F32Converti64_s => {
+            let val = rt.stack.pop_i64()?;
+            rt.stack.push_f32(val as f32);
+            rt.next_instr();
+        }
+
+        F32Converti64_u => {
+            let val = rt.stack.pop_i64()?;
+            rt.stack.push_f32(val as u64 as f32);
+            rt.next_instr();
+        }
+
+        F64Converti32_s => {
+            let val = rt.stack.pop_i32()?;
+            rt.stack.push_f64(val as f64);
+            rt.next_instr();
+        }
+
+        F64Converti32_u => {
+            let val = rt.stack.pop_i32()?;
+            rt.stack.push_f64(val as u32 as f64);
+            rt.next_instr();
+        }
+
+        F64Converti64_s => {
+            let val = rt.stack.pop_i64()?;
+            rt.stack.push_f64(val as f64);
+            rt.next_instr();
+        }
+
+        F64Converti64_u => {
+            let val = rt.stack.pop_i64()?;
+            rt.stack.push_f64(val as u64 as f64);
+            rt.next_instr();
+        }
+
+        F32Demotef64 => {
+            let val = rt.stack.pop_f64()?;
+            rt.stack.push_f32(canonicalize_f32(val as f32));
+            rt.next_instr();
+        }
+
+        F64Promotef32 => {
+            let val = rt.stack.pop_f32()?;
+            rt.stack.push_f64(canonicalize_f64(f64::from(val)));
+            rt.next_instr();
+        }
+
+        I32Reinterpretf32 => {
+            let val = rt.stack.pop_f32()?;
+            rt.stack.push_u32(val.to_bits());
+            rt.next_instr();
+        }
+
+        I64Reinterpretf64 => {
+            let val = rt.stack.pop_f64()?;
+            rt.stack.push_u64(val.to_bits());
+            rt.next_instr();
+        }
+
+        F32Reinterpreti32 => {
+            let val = rt.stack.pop_i32()?;
+            rt.stack.push_f32(f32::from_bits(val as u32));
+            rt.next_instr();
+        }
+
+        F64Reinterpreti64 => {
+            let val = rt.stack.pop_i64()?;
+            rt.stack.push_f64(f64::from_bits(val as u64));
+            rt.next_instr();
+        }
+
+        I32Extend8_s => {
+            let val = rt.stack.pop_i32()?;
+            rt.stack.push_i32(i32::from(val as i8));
+            rt.next_instr();
+        }
+
+        I32Extend16_s => {
+            let val = rt.stack.pop_i32()?;
+            rt.stack.push_i32(i32::from(val as i16));
+            rt.next_instr();
+        }
+
+        I64Extend8_s => {
+            let val = rt.stack.pop_i64()?;
+            rt.stack.push_i64(i64::from(val as i8));
+            rt.next_instr();
+        }
+
+        I64Extend16_s => {
+            let val = rt.stack.pop_i64()?;
+            rt.stack.push_i64(i64::from(val as i16));
+            rt.next_instr();
+        }
+
+        I64Extend32_s => {
+            let val = rt.stack.pop_i64()?;
+            rt.stack.push_i64(i64::from(val as i32));
+            rt.next_instr();
+        }
+
+        // Saturating truncation. Rust's `as` casts from float to integer saturate and map NaN to 0,
+        // which is exactly the semantics of these instructions.
+        I32TruncSatf32_s => {
+            let val = rt.stack.pop_f32()?;
+            rt.stack.push_i32(val as i32);
+            rt.next_instr();
+        }
+
+        I32TruncSatf32_u => {
+            let val = rt.stack.pop_f32()?;
+            rt.stack.push_u32(val as u32);
+            rt.next_instr();
+        }
+
+        I32TruncSatf64_s => {
+            let val = rt.stack.pop_f64()?;
+            rt.stack.push_i32(val as i32);
+            rt.next_instr();
+        }
+
+        I32TruncSatf64_u => {
+            let val = rt.stack.pop_f64()?;
+            rt.stack.push_u32(val as u32);
+            rt.next_instr();
+        }
+
+        I64TruncSatf32_s => {
+            let val = rt.stack.pop_f32()?;
+            rt.stack.push_i64(val as i64);
+            rt.next_instr();
+        }
+
+        I64TruncSatf32_u => {
+            let val = rt.stack.pop_f32()?;
+            rt.stack.push_u64(val as u64);
+            rt.next_instr();
+        }
+
+        I64TruncSatf64_s => {
+            let val = rt.stack.pop_f64()?;
+            rt.stack.push_i64(val as i64);
+            rt.next_instr();
+        }
+
+        I64TruncSatf64_u => {
+            let val = rt.stack.pop_f64()?;
+            rt.stack.push_u64(val as u64);
+            rt.next_instr();
+        }
+
+        //////////////////////////
+        // Control instructions //
+        //////////////////////////
+        Call(func_idx) => {
+            let module_idx = rt.frames.current().module();
+            let fun_addr = rt.modules[module_idx].func_addrs[func_idx.0 as usize];
+            call_addr(rt, fun_addr)?;
+            rt.next_instr();
+        }
+
+        CallIndirect(type_idx) => {
+            let fun_addr = indirect_callee(rt, *type_idx)?;
+            call_addr(rt, fun_addr)?;
+            rt.next_instr();
+        }
+
+        ReturnCall(func_idx) => {
+            let module_idx = rt.frames.current().module();
+            let fun_addr = rt.modules[module_idx].func_addrs[func_idx.0 as usize];
+            tail_call(rt, fun_addr)?;
+        }
+
+        ReturnCallIndirect(type_idx) => {
+            let fun_addr = indirect_callee(rt, *type_idx)?;
+            tail_call(rt, fun_addr)?;
+        }
+
+        Unreachable => {
+            return Err(TrapKind::Unreachable.into());
+        }
+
+        Nop => {
+            rt.next_instr();
+        }
+
+        Return => {
+            // Branch to the function block
+            rt.br(rt.function_label_idx())?;
+        }
+
+        Block(parser::types::Block { ty, instrs }) => {
+            // Bump instruction pointer for the current block
+            rt.next_instr();
+            // Execute the new block
+            rt.enter_block(BlockType::Block, ty, instrs);
+        }
+
+        Loop(parser::types::Block { ty, instrs }) => {
+            // Bump instruction pointer for the current block
+            rt.next_instr();
+            // Execute the loop. Branching to the loop continues from the beginning, reaching
+            // the end exits the loop.
+            rt.enter_block(BlockType::Loop, ty, instrs);
+        }
+
+        If(parser::types::If {
+            ty,
+            then_instrs,
+            else_instrs,
+        }) => {
+            let cond = rt.stack.pop_i32()?;
+            // Bump instruction pointer for the current block
+            rt.next_instr();
+            // Execute the branch as a new block
+            let instrs = if cond != 0 { then_instrs } else { else_instrs };
+            if !instrs.is_empty() {
+                rt.enter_block(BlockType::Block, ty, instrs);
+            }
+        }
+
+        Br(lbl_idx) => {
+            rt.br(*lbl_idx)?;
+        }
+
+        BrIf(lbl_idx) => {
+            let val = rt.stack.pop_i32()?;
+            if val != 0 {
+                rt.br(*lbl_idx)?;
+            } else {
                 rt.next_instr();
-                // Execute the new block
-                rt.ip.push((BlockType::Block, instrs.clone(), 0));
-            }
-
-            Loop(parser::types::Block { ty: _, instrs: _ }) => todo!(),
-
-            BrIf(lbl_idx) => {
-                let val = rt.stack.pop_i32();
-                if val != 0 {
-                    for _ in 0..=*lbl_idx {
-                        rt.ip.pop();
-                    }
-                // Parent block's instruction pointer was already bumped by 'Block' case above,
-                // so no need to update it
-                } else {
-                    rt.next_instr();
-                }
             }
+        }
+
+        BrTable(parser::types::BrTable { tbl, def }) => {
+            let idx = rt.stack.pop_i32()? as u32;
+            let lbl_idx = tbl.get(idx as usize).unwrap_or(def);
+            rt.br(*lbl_idx)?;
+        }
+    }
+
+    if finish_blocks(rt) {
+        Ok(StepResult::Finished)
+    } else {
+        Ok(StepResult::Continue)
+    }
+}
+
+// Allocate a vector of `len` copies of `elem`. Returns `None` instead of aborting when the
+// allocation fails.
+fn try_alloc<A: Clone>(len: u64, elem: A) -> Option<Vec<A>> {
+    let len = usize::try_from(len).ok()?;
+    let mut vec = Vec::new();
+    vec.try_reserve_exact(len).ok()?;
+    vec.resize(len, elem);
+    Some(vec)
+}
+
+// Import matching for limits: the provided memory or table must be at least as large as the
+// import's minimum, and must have a maximum no larger than the import's maximum, if it has one.
+fn limits_match(size: u64, max: Option<u64>, expected: &parser::Limits) -> bool {
+    size >= expected.min
+        && match expected.max {
+            None => true,
+            Some(expected_max) => matches!(max, Some(max) if max <= expected_max),
+        }
+}
+
+fn value_has_type(value: &Value, ty: &ValType) -> bool {
+    matches!(
+        (value, ty),
+        (Value::I32(_), ValType::I32)
+            | (Value::I64(_), ValType::I64)
+            | (Value::F32(_), ValType::F32)
+            | (Value::F64(_), ValType::F64)
+            | (Value::FuncRef(_), ValType::FuncRef)
+            | (Value::ExternRef(_), ValType::ExternRef)
+    )
+}
+
+// `f32::min` and friends return the non-NaN operand when one of the operands is NaN, and don't
+// distinguish +0 and -0. Wasm `min` and `max` propagate NaNs and order -0 below +0. See
+// https://webassembly.github.io/spec/core/exec/numerics.html#op-fmin
+//
+// When one of the operands is NaN we return `val1 + val2`, which is a quiet NaN with the payload of
+// one of the operands.
+
+fn f32_min(val1: f32, val2: f32) -> f32 {
+    if val1.is_nan() || val2.is_nan() {
+        val1 + val2
+    } else if val1 == 0.0 && val2 == 0.0 {
+        if val1.is_sign_negative() {
+            val1
+        } else {
+            val2
+        }
+    } else {
+        val1.min(val2)
+    }
+}
+
+fn f32_max(val1: f32, val2: f32) -> f32 {
+    if val1.is_nan() || val2.is_nan() {
+        val1 + val2
+    } else if val1 == 0.0 && val2 == 0.0 {
+        if val1.is_sign_positive() {
+            val1
+        } else {
+            val2
+        }
+    } else {
+        val1.max(val2)
+    }
+}
 
-            _ => todo!("unhandled instruction: {:?}", instr),
+fn f64_min(val1: f64, val2: f64) -> f64 {
+    if val1.is_nan() || val2.is_nan() {
+        val1 + val2
+    } else if val1 == 0.0 && val2 == 0.0 {
+        if val1.is_sign_negative() {
+            val1
+        } else {
+            val2
         }
+    } else {
+        val1.min(val2)
+    }
+}
+
+fn f64_max(val1: f64, val2: f64) -> f64 {
+    if val1.is_nan() || val2.is_nan() {
+        val1 + val2
+    } else if val1 == 0.0 && val2 == 0.0 {
+        if val1.is_sign_positive() {
+            val1
+        } else {
+            val2
+        }
+    } else {
+        val1.max(val2)
+    }
+}
+
+// Float to integer truncation. Traps when the value is NaN or the truncated value cannot be
+// represented in the target type. Arguments are taken as `f64` as all `f32` values can be
+// represented exactly in `f64`.
+
+fn trunc_to_i32(val: f64) -> Result<i32, TrapKind> {
+    if val.is_nan() {
+        return Err(TrapKind::InvalidConversionToInteger);
+    }
+    if !(val > -2147483649.0 && val < 2147483648.0) {
+        return Err(TrapKind::IntegerOverflow);
+    }
+    Ok(val as i32)
+}
+
+fn trunc_to_u32(val: f64) -> Result<u32, TrapKind> {
+    if val.is_nan() {
+        return Err(TrapKind::InvalidConversionToInteger);
+    }
+    if !(val > -1.0 && val < 4294967296.0) {
+        return Err(TrapKind::IntegerOverflow);
+    }
+    Ok(val as u32)
+}
+
+fn trunc_to_i64(val: f64) -> Result<i64, TrapKind> {
+    if val.is_nan() {
+        return Err(TrapKind::InvalidConversionToInteger);
+    }
+    if !(-9223372036854775808.0..9223372036854775808.0).contains(&val) {
+        return Err(TrapKind::IntegerOverflow);
+    }
+    Ok(val as i64)
+}
+
+fn trunc_to_u64(val: f64) -> Result<u64, TrapKind> {
+    if val.is_nan() {
+        return Err(TrapKind::InvalidConversionToInteger);
+    }
+    if !(val > -1.0 && val < 18446744073709551616.0) {
+        return Err(TrapKind::IntegerOverflow);
     }
+    Ok(val as u64)
 }
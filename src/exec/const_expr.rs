@@ -1,4 +1,4 @@
-use super::parser::types::{Expr, Instruction};
+use super::parser::types::{Expr, GlobalIdx, Instruction};
 use super::value::Value;
 
 /// A constant expression
@@ -20,7 +20,7 @@ use super::value::Value;
 #[derive(Debug)]
 pub enum ConstExpr {
     Const(Value),
-    GlobalGet(u32),
+    GlobalGet(GlobalIdx),
 }
 
 impl ConstExpr {
@@ -38,6 +38,7 @@ impl ConstExpr {
             I64Const(i) => Some(ConstExpr::Const(Value::I64(*i))),
             F32Const(f) => Some(ConstExpr::Const(Value::F32(*f))),
             F64Const(f) => Some(ConstExpr::Const(Value::F64(*f))),
+            GlobalGet(idx) => Some(ConstExpr::GlobalGet(*idx)),
             _ => None,
         }
     }
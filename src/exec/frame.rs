@@ -1,19 +1,32 @@
-use super::store::{Func, ModuleIdx};
+use super::store::{ModuleIdx, WasmFunc};
 use super::value::Value;
-use crate::parser::Local;
+use crate::parser::{FuncIdx, Local, LocalIdx};
 
-use std::iter::repeat;
+use std::iter::repeat_n;
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct FrameStack(Vec<Frame>);
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Frame {
     module_idx: ModuleIdx,
+    fun_idx: FuncIdx,
     locals: Vec<Value>,
 }
 
 impl FrameStack {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Frame> {
+        self.0.iter()
+    }
+
     pub fn current(&self) -> &Frame {
         match self.0.last() {
             None => panic!("FrameStack::current: call stack empty"),
@@ -21,6 +34,11 @@ impl FrameStack {
         }
     }
 
+    /// The current frame, or `None` if the call stack is empty
+    pub fn last(&self) -> Option<&Frame> {
+        self.0.last()
+    }
+
     pub fn current_mut(&mut self) -> &mut Frame {
         match self.0.last_mut() {
             None => panic!("FrameStack::current_mut: call stack empty"),
@@ -28,14 +46,17 @@ impl FrameStack {
         }
     }
 
-    pub(super) fn push(&mut self, fun: &Func) {
+    // Push a frame for the function. Arguments are the first locals, followed by the locals
+    // declared in the function, initialized to zero.
+    pub(super) fn push(&mut self, fun: &WasmFunc, args: Vec<Value>) {
         self.0.push(Frame {
             module_idx: fun.module_idx,
-            locals: fun
-                .fun
-                .locals
-                .iter()
-                .flat_map(|Local { n, ty: _ }| repeat(Value::Uninitialized).take(*n as usize))
+            fun_idx: fun.fun_idx,
+            locals: args
+                .into_iter()
+                .chain(fun.fun.locals.iter().flat_map(|Local { n, ty }| {
+                    repeat_n(Value::default_for_type(*ty), *n as usize)
+                }))
                 .collect(),
         });
     }
@@ -50,8 +71,12 @@ impl Frame {
         self.module_idx
     }
 
-    pub fn get_local(&self, idx: u32) -> Value {
-        match self.locals.get(idx as usize) {
+    pub fn fun(&self) -> FuncIdx {
+        self.fun_idx
+    }
+
+    pub fn get_local(&self, idx: LocalIdx) -> Value {
+        match self.locals.get(idx.0 as usize) {
             Some(value) => *value,
             None => panic!(
                 "Frame::get_local: local index OOB (n locals={}, local idx={})",
@@ -61,8 +86,8 @@ impl Frame {
         }
     }
 
-    pub fn set_local(&mut self, idx: u32, value: Value) {
-        match self.locals.get_mut(idx as usize) {
+    pub fn set_local(&mut self, idx: LocalIdx, value: Value) {
+        match self.locals.get_mut(idx.0 as usize) {
             Some(slot) => {
                 *slot = value;
             }
@@ -0,0 +1,254 @@
+use super::store::{
+    Func, FuncAddr, Global, GlobalAddr, HostFunc, Mem, MemAddr, ModuleIdx, Table, TableAddr,
+};
+use super::value::Value;
+use super::PAGE_SIZE;
+use super::{allocate_module, InstantiationError, Runtime, Trap};
+use crate::parser::{self, ExportDesc, FuncType, Limits};
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// An external value that can be imported by a module.
+#[derive(Debug, Clone, Copy)]
+pub enum Extern {
+    Func(FuncAddr),
+    Global(GlobalAddr),
+    Mem(MemAddr),
+    Table(TableAddr),
+}
+
+/// Maps import names (module name, field name) to external values in the store. Imports of a
+/// module are resolved against a linker when the module is allocated.
+#[derive(Debug, Default)]
+pub struct Linker {
+    externs: HashMap<(String, String), Extern>,
+}
+
+impl Linker {
+    pub fn define(&mut self, module: &str, name: &str, ext: Extern) {
+        self.externs
+            .insert((module.to_owned(), name.to_owned()), ext);
+    }
+
+    pub fn get(&self, module: &str, name: &str) -> Option<Extern> {
+        self.externs
+            .get(&(module.to_owned(), name.to_owned()))
+            .copied()
+    }
+
+    /// Allocate a host function in the store and define it with the given name.
+    pub fn func<F>(&mut self, rt: &mut Runtime, module: &str, name: &str, ty: FuncType, fun: F)
+    where
+        F: Fn(&mut Runtime, &[Value]) -> Result<Vec<Value>, Trap> + 'static,
+    {
+        let addr = allocate_host_func(rt, ty, fun);
+        self.define(module, name, Extern::Func(addr));
+    }
+
+    /// Allocate a global in the store and define it with the given name.
+    pub fn global(
+        &mut self,
+        rt: &mut Runtime,
+        module: &str,
+        name: &str,
+        value: Value,
+        mutable: bool,
+    ) {
+        let addr = allocate_host_global(rt, value, mutable);
+        self.define(module, name, Extern::Global(addr));
+    }
+
+    /// Allocate a memory in the store and define it with the given name.
+    pub fn memory(&mut self, rt: &mut Runtime, module: &str, name: &str, limits: Limits) {
+        let addr = allocate_host_mem(rt, limits);
+        self.define(module, name, Extern::Mem(addr));
+    }
+
+    /// Allocate a table in the store and define it with the given name.
+    pub fn table(&mut self, rt: &mut Runtime, module: &str, name: &str, table: parser::Table) {
+        let addr = allocate_host_table(rt, table);
+        self.define(module, name, Extern::Table(addr));
+    }
+
+    /// Define exports of an allocated module with the given module name.
+    pub fn instance(&mut self, rt: &Runtime, module: &str, module_idx: ModuleIdx) {
+        let inst = rt.get_module(module_idx);
+        for export in &inst.exports {
+            let ext = match export.desc {
+                ExportDesc::Func(idx) => Extern::Func(inst.func_addrs[idx.0 as usize]),
+                ExportDesc::Table(idx) => Extern::Table(inst.table_addrs[idx as usize]),
+                ExportDesc::Mem(idx) => Extern::Mem(inst.mem_addrs[idx as usize]),
+                ExportDesc::Global(idx) => Extern::Global(inst.global_addrs[idx.0 as usize]),
+            };
+            self.define(module, &export.nm, ext);
+        }
+    }
+}
+
+/// Errors when instantiating modules with `instantiate_modules`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LinkError {
+    /// An import is not defined in the linker and not exported by any of the modules
+    MissingImport { module: String, name: String },
+    /// Modules import from each other in a cycle. Each module in `modules` imports from the next
+    /// one, and the last module imports from the first one.
+    Cycle { modules: Vec<String> },
+    /// Allocating a module failed, e.g. because an import has an incompatible type
+    Instantiation {
+        module: String,
+        error: InstantiationError,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VisitState {
+    NotVisited,
+    InProgress,
+    Done,
+}
+
+/// Allocate named modules, resolving imports against the linker and exports of the other modules.
+/// Modules are allocated in dependency order, and exports of each module are defined in the
+/// linker with the module's name. Returns module indices in the order of `modules`.
+///
+/// Missing imports and cycles are checked before allocating any of the modules, so on those
+/// errors the store is not modified.
+pub fn instantiate_modules(
+    rt: &mut Runtime,
+    linker: &mut Linker,
+    modules: Vec<(String, parser::Module)>,
+) -> Result<Vec<ModuleIdx>, LinkError> {
+    // Find dependencies of each module and check that all imports can be resolved
+    let mut deps: Vec<Vec<usize>> = Vec::with_capacity(modules.len());
+    for (_, module) in &modules {
+        let mut module_deps = vec![];
+        for import in &module.imports {
+            match modules.iter().position(|(name, _)| *name == import.module) {
+                Some(dep_idx) => {
+                    let exported = modules[dep_idx]
+                        .1
+                        .exports
+                        .iter()
+                        .any(|export| export.nm == import.name);
+                    if !exported {
+                        return Err(LinkError::MissingImport {
+                            module: import.module.clone(),
+                            name: import.name.clone(),
+                        });
+                    }
+                    if !module_deps.contains(&dep_idx) {
+                        module_deps.push(dep_idx);
+                    }
+                }
+                None => {
+                    if linker.get(&import.module, &import.name).is_none() {
+                        return Err(LinkError::MissingImport {
+                            module: import.module.clone(),
+                            name: import.name.clone(),
+                        });
+                    }
+                }
+            }
+        }
+        deps.push(module_deps);
+    }
+
+    // Sort modules so that each module comes after the modules it imports from
+    let mut states = vec![VisitState::NotVisited; modules.len()];
+    let mut path = vec![];
+    let mut order = Vec::with_capacity(modules.len());
+    for module_idx in 0..modules.len() {
+        if let Err(cycle) = visit(module_idx, &deps, &mut states, &mut path, &mut order) {
+            return Err(LinkError::Cycle {
+                modules: cycle
+                    .into_iter()
+                    .map(|idx| modules[idx].0.clone())
+                    .collect(),
+            });
+        }
+    }
+
+    // Allocate the modules
+    let mut modules: Vec<Option<(String, parser::Module)>> =
+        modules.into_iter().map(Some).collect();
+    let mut module_idxs = vec![0; modules.len()];
+    for idx in order {
+        let (name, module) = modules[idx].take().unwrap();
+        let module_idx =
+            allocate_module(rt, linker, module).map_err(|error| LinkError::Instantiation {
+                module: name.clone(),
+                error,
+            })?;
+        linker.instance(rt, &name, module_idx);
+        module_idxs[idx] = module_idx;
+    }
+
+    Ok(module_idxs)
+}
+
+// Depth-first search for topologically sorting modules. On error returns the modules in the cycle.
+fn visit(
+    idx: usize,
+    deps: &[Vec<usize>],
+    states: &mut [VisitState],
+    path: &mut Vec<usize>,
+    order: &mut Vec<usize>,
+) -> Result<(), Vec<usize>> {
+    match states[idx] {
+        VisitState::Done => return Ok(()),
+        VisitState::InProgress => {
+            let cycle_start = path.iter().position(|i| *i == idx).unwrap();
+            return Err(path[cycle_start..].to_vec());
+        }
+        VisitState::NotVisited => {}
+    }
+
+    states[idx] = VisitState::InProgress;
+    path.push(idx);
+    for dep in &deps[idx] {
+        visit(*dep, deps, states, path, order)?;
+    }
+    path.pop();
+    states[idx] = VisitState::Done;
+    order.push(idx);
+    Ok(())
+}
+
+pub fn allocate_host_func<F>(rt: &mut Runtime, ty: FuncType, fun: F) -> FuncAddr
+where
+    F: Fn(&mut Runtime, &[Value]) -> Result<Vec<Value>, Trap> + 'static,
+{
+    let fun_addr = FuncAddr(rt.store.funcs.len() as u32);
+    rt.store.funcs.push(Func::Host(HostFunc {
+        ty,
+        fun: Rc::new(fun),
+    }));
+    fun_addr
+}
+
+pub fn allocate_host_global(rt: &mut Runtime, value: Value, mutable: bool) -> GlobalAddr {
+    let global_addr = GlobalAddr(rt.store.globals.len() as u32);
+    rt.store.globals.push(Global { value, mutable });
+    global_addr
+}
+
+pub fn allocate_host_mem(rt: &mut Runtime, limits: Limits) -> MemAddr {
+    let mem_addr = MemAddr(rt.store.mems.len() as u32);
+    rt.store.mems.push(Mem {
+        data: vec![0; limits.min as usize * PAGE_SIZE],
+        max: limits.max,
+        memory64: limits.memory64,
+    });
+    mem_addr
+}
+
+pub fn allocate_host_table(rt: &mut Runtime, table: parser::Table) -> TableAddr {
+    let table_addr = TableAddr(rt.store.tables.len() as u32);
+    rt.store.tables.push(Table {
+        elems: vec![None; table.limits.min as usize],
+        max: table.limits.max,
+        elem_type: table.elem_type,
+    });
+    table_addr
+}
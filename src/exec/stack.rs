@@ -1,21 +1,85 @@
+use super::store::FuncAddr;
+use super::trap::TrapKind;
 use super::value::Value;
 
-#[derive(Debug, Default)]
+/// The value stack. Pops trap with `StackUnderflow` when the stack is empty, and with
+/// `OperandTypeMismatch` when the value has an unexpected type. Both can only happen in programs
+/// that are not validated.
+#[derive(Debug, Default, Clone)]
 pub struct Stack(Vec<Value>);
 
 impl Stack {
-    pub fn pop_value(&mut self) -> Value {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear()
+    }
+
+    // Drop values above `height`, except for the top `n_kept` values
+    pub fn unwind(&mut self, height: usize, n_kept: usize) {
+        let kept_start = self.0.len() - n_kept;
+        self.0.drain(height..kept_start);
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.0
+    }
+
+    pub fn pop_value(&mut self) -> Result<Value, TrapKind> {
+        self.0.pop().ok_or(TrapKind::StackUnderflow)
+    }
+
+    pub fn pop_i32(&mut self) -> Result<i32, TrapKind> {
+        match self.0.pop() {
+            Some(Value::I32(val)) => Ok(val),
+            Some(_) => Err(TrapKind::OperandTypeMismatch),
+            None => Err(TrapKind::StackUnderflow),
+        }
+    }
+
+    pub fn pop_i64(&mut self) -> Result<i64, TrapKind> {
+        match self.0.pop() {
+            Some(Value::I64(val)) => Ok(val),
+            Some(_) => Err(TrapKind::OperandTypeMismatch),
+            None => Err(TrapKind::StackUnderflow),
+        }
+    }
+
+    pub fn pop_f32(&mut self) -> Result<f32, TrapKind> {
         match self.0.pop() {
-            Some(val) => val,
-            None => panic!("Stack::pop: empty stack"),
+            Some(Value::F32(val)) => Ok(val),
+            Some(_) => Err(TrapKind::OperandTypeMismatch),
+            None => Err(TrapKind::StackUnderflow),
         }
     }
 
-    pub fn pop_i32(&mut self) -> i32 {
+    pub fn pop_f64(&mut self) -> Result<f64, TrapKind> {
         match self.0.pop() {
-            Some(Value::I32(val)) => val,
-            Some(other) => panic!("Stack::pop_i32: {:#?}", other),
-            None => panic!("Stack::pop_i32: empty stack"),
+            Some(Value::F64(val)) => Ok(val),
+            Some(_) => Err(TrapKind::OperandTypeMismatch),
+            None => Err(TrapKind::StackUnderflow),
+        }
+    }
+
+    pub fn pop_funcref(&mut self) -> Result<Option<FuncAddr>, TrapKind> {
+        match self.0.pop() {
+            Some(Value::FuncRef(val)) => Ok(val),
+            Some(_) => Err(TrapKind::OperandTypeMismatch),
+            None => Err(TrapKind::StackUnderflow),
+        }
+    }
+
+    pub fn pop_externref(&mut self) -> Result<Option<u32>, TrapKind> {
+        match self.0.pop() {
+            Some(Value::ExternRef(val)) => Ok(val),
+            Some(_) => Err(TrapKind::OperandTypeMismatch),
+            None => Err(TrapKind::StackUnderflow),
         }
     }
 
@@ -35,6 +99,10 @@ impl Stack {
         self.0.push(Value::I64(i))
     }
 
+    pub fn push_u64(&mut self, i: u64) {
+        self.0.push(Value::I64(i as i64))
+    }
+
     pub fn push_f32(&mut self, f: f32) {
         self.0.push(Value::F32(f))
     }
@@ -43,6 +111,14 @@ impl Stack {
         self.0.push(Value::F64(f))
     }
 
+    pub fn push_funcref(&mut self, fun_addr: Option<FuncAddr>) {
+        self.0.push(Value::FuncRef(fun_addr))
+    }
+
+    pub fn push_externref(&mut self, extern_addr: Option<u32>) {
+        self.0.push(Value::ExternRef(extern_addr))
+    }
+
     pub fn push_bool(&mut self, bool: bool) {
         self.push_u32(if bool { 1 } else { 0 })
     }
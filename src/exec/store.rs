@@ -1,24 +1,181 @@
-use super::parser::Fun;
+use super::parser::{ElemType, Fun, FuncIdx, FuncType};
+use super::stack::Stack;
 use super::value::Value;
+use super::{Runtime, Trap, TrapKind};
+
+use std::fmt;
+use std::ops::{Index, IndexMut};
+use std::rc::Rc;
 
 pub type ModuleIdx = usize;
 
-#[derive(Default, Debug)]
+// Defines an address type for items of type `$item`, and indexing `Vec<$item>` with it
+macro_rules! addr_type {
+    ($(#[$attr:meta])* $addr:ident, $item:ty) => {
+        $(#[$attr])*
+        #[repr(transparent)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub struct $addr(pub u32);
+
+        impl Index<$addr> for Vec<$item> {
+            type Output = $item;
+
+            fn index(&self, addr: $addr) -> &$item {
+                &self[addr.0 as usize]
+            }
+        }
+
+        impl IndexMut<$addr> for Vec<$item> {
+            fn index_mut(&mut self, addr: $addr) -> &mut $item {
+                &mut self[addr.0 as usize]
+            }
+        }
+    };
+}
+
+addr_type!(
+    /// Address of a function in the store. Module-local indices can't be used as addresses:
+    ///
+    /// ```compile_fail,E0308
+    /// use wasmrun::exec::FuncAddr;
+    /// use wasmrun::parser::FuncIdx;
+    ///
+    /// let fun_addr: FuncAddr = FuncIdx(0);
+    /// ```
+    FuncAddr,
+    Func
+);
+addr_type!(TableAddr, Table);
+addr_type!(MemAddr, Mem);
+addr_type!(GlobalAddr, Global);
+addr_type!(DataAddr, Vec<u8>);
+addr_type!(ElemAddr, Vec<FuncAddr>);
+
+#[derive(Default, Debug, Clone)]
 pub struct Store {
     pub funcs: Vec<Func>,
-    pub tables: Vec<Vec<Option<u32>>>, // indexed by table address (table_addrs), returns function address (index into Store.funcs)
-    pub mems: Vec<Vec<u8>>,            // indexed by module idx
+    pub tables: Vec<Table>, // indexed by table address (table_addrs)
+    pub mems: Vec<Mem>,     // indexed by memory address (mem_addrs)
     pub globals: Vec<Global>,
+    pub datas: Vec<Vec<u8>>, // indexed by data address (data_addrs), emptied by `data.drop`
+    pub elems: Vec<Vec<FuncAddr>>, // indexed by elem address (elem_addrs), emptied by `elem.drop`
 }
 
-#[derive(Debug)]
-pub struct Func {
+impl Store {
+    /// Restore the mutable parts of the store (everything except the functions) to their contents
+    /// in `state`, a copy of the store taken earlier. Contents are copied into the existing
+    /// buffers, reusing their allocations. Items allocated after copying the state are not
+    /// changed.
+    pub fn reset(&mut self, state: &Store) {
+        for (table, initial) in self.tables.iter_mut().zip(&state.tables) {
+            table.elems.clone_from(&initial.elems);
+        }
+        for (mem, initial) in self.mems.iter_mut().zip(&state.mems) {
+            mem.data.clone_from(&initial.data);
+        }
+        for (global, initial) in self.globals.iter_mut().zip(&state.globals) {
+            global.value = initial.value;
+        }
+        for (data, initial) in self.datas.iter_mut().zip(&state.datas) {
+            data.clone_from(initial);
+        }
+        for (elem, initial) in self.elems.iter_mut().zip(&state.elems) {
+            elem.clone_from(initial);
+        }
+    }
+}
+
+/// A function instance is either a wasm function defined in a module or a host function
+/// registered via a `Linker`. Function addresses index both kinds in `Store.funcs`.
+#[derive(Debug, Clone)]
+pub enum Func {
+    Wasm(WasmFunc),
+    Host(HostFunc),
+}
+
+#[derive(Debug, Clone)]
+pub struct WasmFunc {
     pub module_idx: ModuleIdx,
+    // Index of the function in the defining module
+    pub fun_idx: FuncIdx,
     pub fun: Fun,
 }
 
-#[derive(Debug)]
+/// Host functions take arguments in order (first argument at index 0) and return results in order.
+/// A trap returned by a host function aborts the calling wasm program.
+pub type HostFn = dyn Fn(&mut Runtime, &[Value]) -> Result<Vec<Value>, Trap>;
+
+#[derive(Clone)]
+pub struct HostFunc {
+    pub ty: FuncType,
+    // `Rc` so that the closure can be cloned out of the store before calling it with `&mut Runtime`
+    pub fun: Rc<HostFn>,
+}
+
+impl fmt::Debug for HostFunc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostFunc").field("ty", &self.ty).finish()
+    }
+}
+
+/// A non-null reference stored in a table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ref {
+    Func(FuncAddr),
+    Extern(u32),
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    // `None` for null
+    pub elems: Vec<Option<Ref>>,
+    pub max: Option<u64>,
+    pub elem_type: ElemType,
+}
+
+impl Table {
+    /// Push element `elem` of the table as a reference value. Nulls have the table's element type.
+    pub fn push_elem(&self, stack: &mut Stack, elem: Option<Ref>) {
+        match (elem, self.elem_type) {
+            (Some(Ref::Func(addr)), _) => stack.push_funcref(Some(addr)),
+            (Some(Ref::Extern(addr)), _) => stack.push_externref(Some(addr)),
+            (None, ElemType::FuncRef) => stack.push_funcref(None),
+            (None, ElemType::ExternRef) => stack.push_externref(None),
+        }
+    }
+
+    /// Pop a reference value of the table's element type to store in the table
+    pub fn pop_elem(&self, stack: &mut Stack) -> Result<Option<Ref>, TrapKind> {
+        match self.elem_type {
+            ElemType::FuncRef => Ok(stack.pop_funcref()?.map(Ref::Func)),
+            ElemType::ExternRef => Ok(stack.pop_externref()?.map(Ref::Extern)),
+        }
+    }
+
+    /// Grow the table by `delta` elements initialized to `init`. Returns the old size, or `None`
+    /// if the new size would exceed the table's max.
+    pub fn grow(&mut self, delta: u32, init: Option<Ref>) -> Option<u32> {
+        let old_size = self.elems.len() as u32;
+        let new_size = u64::from(old_size) + u64::from(delta);
+        if new_size > self.max.unwrap_or_else(|| u64::from(u32::MAX)) {
+            return None;
+        }
+        self.elems.resize(new_size as usize, init);
+        Some(old_size)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Mem {
+    pub data: Vec<u8>,
+    pub max: Option<u64>, // in pages
+    // Addresses, sizes, and lengths of bulk operations are i64 in memory64 memories
+    pub memory64: bool,
+}
+
+#[derive(Debug, Clone)]
 pub struct Global {
     pub value: Value,
-    pub mutable: bool, // Only needed for validation
+    // Checked by `Runtime::set_global` and when matching global imports
+    pub mutable: bool,
 }
@@ -0,0 +1,4289 @@
+use super::*;
+use crate::parser::types::{
+    Block, BlockType, Data, DataMode, ElemMode, ElemType, Element, Expr, Fun, GlobalType, If,
+    Import, Limits, Local, LocalIdx, Mutability, Table,
+};
+
+use Instruction::*;
+
+/// Allocate a module with a single `[] -> []` function with the given body and one page of memory,
+/// call the function, and return the runtime for inspecting the stack.
+fn run(instrs: Vec<Instruction>) -> Runtime {
+    run_with_locals(vec![], instrs)
+}
+
+/// Like `run`, but the function has the given locals.
+fn run_with_locals(locals: Vec<Local>, instrs: Vec<Instruction>) -> Runtime {
+    run_module(test_module(locals, instrs))
+}
+
+/// Like `run`, but expects the function to trap and returns the trap.
+fn run_trap(instrs: Vec<Instruction>) -> TrapKind {
+    expect_trap(try_run_module(test_module(vec![], instrs)))
+}
+
+/// The module used by `run`: a `[] -> []` function with the given locals and body, and one page of
+/// memory. Other test modules extend this one.
+fn test_module(locals: Vec<Local>, instrs: Vec<Instruction>) -> parser::Module {
+    parser::Module {
+        types: vec![FuncType {
+            args: vec![],
+            ret: vec![],
+        }],
+        funs: vec![Fun {
+            ty: TypeIdx(0),
+            locals,
+            expr: Expr {
+                instrs: instrs.into(),
+            },
+        }],
+        mem_addrs: vec![Limits {
+            min: 1,
+            max: None,
+            memory64: false,
+        }],
+        ..Default::default()
+    }
+}
+
+/// Allocate the module, call function 0, and return the runtime.
+fn run_module(module: parser::Module) -> Runtime {
+    match try_run_module(module) {
+        Ok(rt) => rt,
+        Err(trap) => panic!("Unexpected trap: {:?}", trap),
+    }
+}
+
+/// Like `run_module`, but returns the trap if the function traps.
+fn try_run_module(module: parser::Module) -> Result<Runtime, Trap> {
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(&mut rt, &Linker::default(), module).unwrap();
+    call(&mut rt, module_idx, FuncIdx(0))?;
+    Ok(rt)
+}
+
+fn expect_trap(result: Result<Runtime, Trap>) -> TrapKind {
+    match result {
+        Ok(_) => panic!("Expected a trap"),
+        Err(trap) => trap.kind,
+    }
+}
+
+fn run_i32(instrs: Vec<Instruction>) -> i32 {
+    run(instrs).stack.pop_i32().unwrap()
+}
+
+#[test]
+fn i32_add_wraps() {
+    assert_eq!(
+        run_i32(vec![I32Const(i32::MAX), I32Const(1), I32Add]),
+        i32::MIN
+    );
+}
+
+#[test]
+fn i32_sub_mul_wrap() {
+    assert_eq!(
+        run_i32(vec![I32Const(i32::MIN), I32Const(1), I32Sub]),
+        i32::MAX
+    );
+    assert_eq!(run_i32(vec![I32Const(0x4000_0000), I32Const(4), I32Mul]), 0);
+}
+
+#[test]
+fn i32_div_rem() {
+    assert_eq!(run_i32(vec![I32Const(-7), I32Const(2), I32Div_s]), -3);
+    assert_eq!(run_i32(vec![I32Const(-7), I32Const(2), I32Rem_s]), -1);
+    assert_eq!(
+        run_i32(vec![I32Const(-7), I32Const(2), I32Div_u]),
+        0x7FFF_FFFC
+    );
+    assert_eq!(run_i32(vec![I32Const(-7), I32Const(2), I32Rem_u]), 1);
+
+    // `MIN / -1` overflows, `MIN % -1` is 0
+    assert_eq!(
+        run_trap(vec![I32Const(i32::MIN), I32Const(-1), I32Div_s, Drop]),
+        TrapKind::IntegerOverflow
+    );
+    assert_eq!(run_i32(vec![I32Const(i32::MIN), I32Const(-1), I32Rem_s]), 0);
+    assert_eq!(run_i32(vec![I32Const(i32::MIN), I32Const(-1), I32Div_u]), 0);
+}
+
+#[test]
+fn i32_div_rem_by_zero() {
+    for instr in [I32Div_s, I32Div_u, I32Rem_s, I32Rem_u] {
+        assert_eq!(
+            run_trap(vec![I32Const(1), I32Const(0), instr, Drop]),
+            TrapKind::IntegerDivideByZero
+        );
+    }
+}
+
+#[test]
+fn i32_bitwise() {
+    assert_eq!(
+        run_i32(vec![I32Const(0b1100), I32Const(0b1010), I32And]),
+        0b1000
+    );
+    assert_eq!(
+        run_i32(vec![I32Const(0b1100), I32Const(0b1010), I32Or]),
+        0b1110
+    );
+    assert_eq!(
+        run_i32(vec![I32Const(0b1100), I32Const(0b1010), I32Xor]),
+        0b0110
+    );
+}
+
+#[test]
+fn i32_shift_count_masked() {
+    assert_eq!(
+        run_i32(vec![I32Const(3), I32Const(33), I32Shl]),
+        run_i32(vec![I32Const(3), I32Const(1), I32Shl]),
+    );
+    assert_eq!(run_i32(vec![I32Const(-8), I32Const(33), I32Shr_s]), -4);
+    assert_eq!(
+        run_i32(vec![I32Const(-8), I32Const(1), I32Shr_u]),
+        0x7FFF_FFFC
+    );
+    assert_eq!(
+        run_i32(vec![I32Const(-8), I32Const(33), I32Shr_u]),
+        0x7FFF_FFFC
+    );
+    // Negative counts are masked too
+    assert_eq!(run_i32(vec![I32Const(1), I32Const(-1), I32Shl]), i32::MIN);
+}
+
+#[test]
+fn i32_eq_ne() {
+    assert_eq!(run_i32(vec![I32Const(5), I32Const(5), I32Eq]), 1);
+    assert_eq!(run_i32(vec![I32Const(5), I32Const(5), I32Ne]), 0);
+}
+
+#[test]
+fn i32_lt_signedness() {
+    // 0xFFFFFFFF is -1 when signed, u32::MAX when unsigned
+    assert_eq!(run_i32(vec![I32Const(-1), I32Const(1), I32Lt_s]), 1);
+    assert_eq!(run_i32(vec![I32Const(-1), I32Const(1), I32Lt_u]), 0);
+    assert_eq!(run_i32(vec![I32Const(1), I32Const(-1), I32Lt_u]), 1);
+}
+
+#[test]
+fn i32_gt_le_ge_signedness() {
+    assert_eq!(run_i32(vec![I32Const(-1), I32Const(1), I32Gt_s]), 0);
+    assert_eq!(run_i32(vec![I32Const(-1), I32Const(1), I32Gt_u]), 1);
+    assert_eq!(run_i32(vec![I32Const(-1), I32Const(1), I32Le_s]), 1);
+    assert_eq!(run_i32(vec![I32Const(-1), I32Const(1), I32Le_u]), 0);
+    assert_eq!(run_i32(vec![I32Const(-1), I32Const(-1), I32Ge_s]), 1);
+    assert_eq!(run_i32(vec![I32Const(1), I32Const(-1), I32Ge_u]), 0);
+}
+
+fn run_i64(instrs: Vec<Instruction>) -> i64 {
+    run(instrs).stack.pop_i64().unwrap()
+}
+
+#[test]
+fn i64_arith_wraps() {
+    assert_eq!(
+        run_i64(vec![I64Const(i64::MAX), I64Const(1), I64Add]),
+        i64::MIN
+    );
+    assert_eq!(
+        run_i64(vec![I64Const(i64::MIN), I64Const(1), I64Sub]),
+        i64::MAX
+    );
+    assert_eq!(run_i64(vec![I64Const(i64::MIN), I64Const(2), I64Mul]), 0);
+}
+
+#[test]
+fn i64_div_rem() {
+    assert_eq!(run_i64(vec![I64Const(-7), I64Const(2), I64Div_s]), -3);
+    assert_eq!(run_i64(vec![I64Const(-7), I64Const(2), I64Rem_s]), -1);
+    assert_eq!(
+        run_i64(vec![I64Const(-7), I64Const(2), I64Div_u]),
+        0x7FFF_FFFF_FFFF_FFFC
+    );
+    assert_eq!(run_i64(vec![I64Const(-7), I64Const(2), I64Rem_u]), 1);
+
+    // `MIN / -1` overflows, `MIN % -1` is 0
+    assert_eq!(
+        run_trap(vec![I64Const(i64::MIN), I64Const(-1), I64Div_s, Drop]),
+        TrapKind::IntegerOverflow
+    );
+    assert_eq!(run_i64(vec![I64Const(i64::MIN), I64Const(-1), I64Rem_s]), 0);
+
+    for instr in [I64Div_s, I64Div_u, I64Rem_s, I64Rem_u] {
+        assert_eq!(
+            run_trap(vec![I64Const(1), I64Const(0), instr, Drop]),
+            TrapKind::IntegerDivideByZero
+        );
+    }
+}
+
+#[test]
+fn i64_bitwise() {
+    assert_eq!(
+        run_i64(vec![I64Const(0b1100), I64Const(0b1010), I64And]),
+        0b1000
+    );
+    assert_eq!(
+        run_i64(vec![I64Const(0b1100), I64Const(0b1010), I64Or]),
+        0b1110
+    );
+    assert_eq!(
+        run_i64(vec![I64Const(0b1100), I64Const(0b1010), I64Xor]),
+        0b0110
+    );
+}
+
+#[test]
+fn i64_shift_count_masked() {
+    assert_eq!(run_i64(vec![I64Const(3), I64Const(64), I64Shl]), 3);
+    assert_eq!(run_i64(vec![I64Const(3), I64Const(65), I64Shl]), 6);
+    assert_eq!(run_i64(vec![I64Const(-8), I64Const(65), I64Shr_s]), -4);
+    assert_eq!(
+        run_i64(vec![I64Const(-8), I64Const(1), I64Shr_u]),
+        0x7FFF_FFFF_FFFF_FFFC
+    );
+    assert_eq!(run_i64(vec![I64Const(-8), I64Const(64), I64Shr_u]), -8);
+    assert_eq!(run_i64(vec![I64Const(-8), I64Const(64), I64Shr_s]), -8);
+}
+
+#[test]
+fn i64_bit_counting() {
+    assert_eq!(run_i64(vec![I64Const(1), I64Clz]), 63);
+    assert_eq!(run_i64(vec![I64Const(0), I64Ctz]), 64);
+    assert_eq!(run_i64(vec![I64Const(-1), I64Popcnt]), 64);
+}
+
+#[test]
+fn i64_eqz_eq_ne() {
+    assert_eq!(run_i32(vec![I64Const(0), I64Eqz]), 1);
+    assert_eq!(run_i32(vec![I64Const(1 << 32), I64Eqz]), 0);
+    assert_eq!(run_i32(vec![I64Const(7), I64Const(7), I64Eq]), 1);
+    assert_eq!(run_i32(vec![I64Const(7), I64Const(7), I64Ne]), 0);
+}
+
+#[test]
+fn i64_lt_signedness() {
+    // The bit pattern of -1 is the largest u64
+    assert_eq!(run_i32(vec![I64Const(-1), I64Const(1), I64Lt_u]), 0);
+    assert_eq!(run_i32(vec![I64Const(-1), I64Const(1), I64Lt_s]), 1);
+}
+
+#[test]
+fn i64_gt_le_ge_signedness() {
+    assert_eq!(run_i32(vec![I64Const(-1), I64Const(1), I64Gt_s]), 0);
+    assert_eq!(run_i32(vec![I64Const(-1), I64Const(1), I64Gt_u]), 1);
+    assert_eq!(run_i32(vec![I64Const(-1), I64Const(1), I64Le_s]), 1);
+    assert_eq!(run_i32(vec![I64Const(-1), I64Const(1), I64Le_u]), 0);
+    assert_eq!(run_i32(vec![I64Const(-1), I64Const(-1), I64Ge_s]), 1);
+    assert_eq!(run_i32(vec![I64Const(1), I64Const(-1), I64Ge_u]), 0);
+}
+
+fn run_f32(instrs: Vec<Instruction>) -> f32 {
+    run(instrs).stack.pop_f32().unwrap()
+}
+
+#[test]
+fn f32_arith() {
+    assert_eq!(run_f32(vec![F32Const(1.5), F32Const(2.25), F32Add]), 3.75);
+    assert_eq!(run_f32(vec![F32Const(1.5), F32Const(2.25), F32Sub]), -0.75);
+    assert_eq!(run_f32(vec![F32Const(1.5), F32Const(2.0), F32Mul]), 3.0);
+    assert_eq!(run_f32(vec![F32Const(1.0), F32Const(4.0), F32Div]), 0.25);
+    assert_eq!(
+        run_f32(vec![F32Const(1.0), F32Const(0.0), F32Div]),
+        f32::INFINITY
+    );
+}
+
+#[test]
+fn f32_unary() {
+    assert_eq!(run_f32(vec![F32Const(-2.5), F32Abs]), 2.5);
+    assert_eq!(run_f32(vec![F32Const(2.5), F32Neg]), -2.5);
+    assert_eq!(run_f32(vec![F32Const(9.0), F32Sqrt]), 3.0);
+    assert_eq!(run_f32(vec![F32Const(-1.5), F32Ceil]), -1.0);
+    assert_eq!(run_f32(vec![F32Const(-1.5), F32Floor]), -2.0);
+    assert_eq!(run_f32(vec![F32Const(-1.5), F32Trunc]), -1.0);
+}
+
+#[test]
+fn f32_nearest_ties_to_even() {
+    assert_eq!(run_f32(vec![F32Const(2.5), F32Nearest]), 2.0);
+    assert_eq!(run_f32(vec![F32Const(3.5), F32Nearest]), 4.0);
+    assert_eq!(run_f32(vec![F32Const(-2.5), F32Nearest]), -2.0);
+}
+
+fn run_f64(instrs: Vec<Instruction>) -> f64 {
+    run(instrs).stack.pop_f64().unwrap()
+}
+
+#[test]
+fn f64_arith() {
+    assert_eq!(run_f64(vec![F64Const(1.5), F64Const(2.25), F64Add]), 3.75);
+    assert_eq!(run_f64(vec![F64Const(1.5), F64Const(2.25), F64Sub]), -0.75);
+    assert_eq!(run_f64(vec![F64Const(1.5), F64Const(2.0), F64Mul]), 3.0);
+    assert_eq!(run_f64(vec![F64Const(1.0), F64Const(4.0), F64Div]), 0.25);
+}
+
+#[test]
+fn f64_unary() {
+    assert_eq!(run_f64(vec![F64Const(-2.5), F64Abs]), 2.5);
+    assert_eq!(run_f64(vec![F64Const(2.5), F64Neg]), -2.5);
+    assert_eq!(run_f64(vec![F64Const(16.0), F64Sqrt]), 4.0);
+    assert_eq!(run_f64(vec![F64Const(-1.5), F64Ceil]), -1.0);
+    assert_eq!(run_f64(vec![F64Const(-1.5), F64Floor]), -2.0);
+    assert_eq!(run_f64(vec![F64Const(2.5), F64Nearest]), 2.0);
+    assert_eq!(run_f64(vec![F64Const(3.5), F64Nearest]), 4.0);
+}
+
+#[test]
+fn f64_trunc_preserves_sign_of_zero() {
+    let val = run_f64(vec![F64Const(-0.5), F64Trunc]);
+    assert_eq!(val.to_bits(), (-0.0f64).to_bits());
+    assert_eq!(run_f64(vec![F64Const(-1.7), F64Trunc]), -1.0);
+}
+
+#[test]
+fn float_min_max_signed_zero() {
+    let val = run_f32(vec![F32Const(0.0), F32Const(-0.0), F32Min]);
+    assert_eq!(val.to_bits(), (-0.0f32).to_bits());
+    let val = run_f32(vec![F32Const(-0.0), F32Const(0.0), F32Max]);
+    assert_eq!(val.to_bits(), 0.0f32.to_bits());
+    let val = run_f64(vec![F64Const(0.0), F64Const(-0.0), F64Min]);
+    assert_eq!(val.to_bits(), (-0.0f64).to_bits());
+    let val = run_f64(vec![F64Const(-0.0), F64Const(0.0), F64Max]);
+    assert_eq!(val.to_bits(), 0.0f64.to_bits());
+}
+
+#[test]
+fn float_min_max_propagate_nan() {
+    assert!(run_f32(vec![F32Const(f32::NAN), F32Const(1.0), F32Min]).is_nan());
+    assert!(run_f32(vec![F32Const(1.0), F32Const(f32::NAN), F32Max]).is_nan());
+    assert!(run_f64(vec![F64Const(1.0), F64Const(f64::NAN), F64Min]).is_nan());
+    assert!(run_f64(vec![F64Const(f64::NAN), F64Const(1.0), F64Max]).is_nan());
+    assert_eq!(run_f32(vec![F32Const(1.0), F32Const(2.0), F32Min]), 1.0);
+    assert_eq!(run_f64(vec![F64Const(1.0), F64Const(2.0), F64Max]), 2.0);
+}
+
+#[test]
+fn float_copysign() {
+    assert_eq!(
+        run_f32(vec![F32Const(1.5), F32Const(-0.0), F32Copysign]),
+        -1.5
+    );
+    assert_eq!(
+        run_f64(vec![F64Const(-1.5), F64Const(2.0), F64Copysign]),
+        1.5
+    );
+}
+
+#[test]
+fn float_comparisons() {
+    assert_eq!(run_i32(vec![F32Const(1.0), F32Const(2.0), F32Lt]), 1);
+    assert_eq!(run_i32(vec![F32Const(1.0), F32Const(2.0), F32Ge]), 0);
+    assert_eq!(run_i32(vec![F64Const(2.0), F64Const(2.0), F64Le]), 1);
+    assert_eq!(run_i32(vec![F64Const(2.0), F64Const(1.0), F64Gt]), 1);
+    assert_eq!(run_i32(vec![F32Const(0.0), F32Const(-0.0), F32Eq]), 1);
+}
+
+#[test]
+fn float_comparisons_nan() {
+    assert_eq!(run_i32(vec![F32Const(f32::NAN), F32Const(1.0), F32Lt]), 0);
+    assert_eq!(run_i32(vec![F32Const(f32::NAN), F32Const(1.0), F32Ne]), 1);
+    assert_eq!(
+        run_i32(vec![F32Const(f32::NAN), F32Const(f32::NAN), F32Eq]),
+        0
+    );
+    assert_eq!(run_i32(vec![F64Const(1.0), F64Const(f64::NAN), F64Ge]), 0);
+    assert_eq!(run_i32(vec![F64Const(1.0), F64Const(f64::NAN), F64Ne]), 1);
+}
+
+#[test]
+fn int_to_float_signedness() {
+    assert_eq!(run_f32(vec![I32Const(-1), F32Converti32_s]), -1.0);
+    assert_eq!(run_f32(vec![I32Const(-1), F32Converti32_u]), 4294967296.0);
+    assert_eq!(run_f64(vec![I32Const(-1), F64Converti32_s]), -1.0);
+    assert_eq!(run_f64(vec![I32Const(-1), F64Converti32_u]), 4294967295.0);
+    assert_eq!(run_f32(vec![I64Const(-1), F32Converti64_s]), -1.0);
+    assert_eq!(
+        run_f64(vec![I64Const(-1), F64Converti64_u]),
+        18446744073709551615.0
+    );
+}
+
+#[test]
+fn float_to_int_trunc() {
+    assert_eq!(run_i32(vec![F32Const(3.7), I32Truncf32_s]), 3);
+    assert_eq!(run_i32(vec![F32Const(-3.7), I32Truncf32_s]), -3);
+    assert_eq!(run_i32(vec![F64Const(-0.9), I32Truncf64_u]), 0);
+    assert_eq!(run_i32(vec![F64Const(4294967295.0), I32Truncf64_u]), -1);
+    assert_eq!(run_i64(vec![F32Const(-1e10), I64Truncf32_s]), -10000000000);
+    assert_eq!(
+        run_i64(vec![F64Const(1e19), I64Truncf64_u]),
+        1e19 as u64 as i64
+    );
+}
+
+#[test]
+fn float_to_int_trunc_nan() {
+    assert_eq!(
+        run_trap(vec![F32Const(f32::NAN), I32Truncf32_s]),
+        TrapKind::InvalidConversionToInteger
+    );
+}
+
+#[test]
+fn float_to_int_trunc_overflow() {
+    assert_eq!(
+        run_trap(vec![F32Const(1e30), I32Truncf32_s]),
+        TrapKind::IntegerOverflow
+    );
+}
+
+#[test]
+fn float_to_int_trunc_unsigned_negative() {
+    assert_eq!(
+        run_trap(vec![F64Const(-1.0), I64Truncf64_u]),
+        TrapKind::IntegerOverflow
+    );
+}
+
+#[test]
+fn float_to_int_trunc_sat() {
+    assert_eq!(
+        run_i32(vec![F32Const(f32::INFINITY), I32TruncSatf32_s]),
+        i32::MAX
+    );
+    assert_eq!(
+        run_i32(vec![F32Const(f32::NEG_INFINITY), I32TruncSatf32_s]),
+        i32::MIN
+    );
+    assert_eq!(run_i32(vec![F32Const(f32::NAN), I32TruncSatf32_s]), 0);
+    assert_eq!(run_i32(vec![F64Const(-5.0), I32TruncSatf64_u]), 0);
+    assert_eq!(run_i32(vec![F64Const(1e20), I32TruncSatf64_u]), -1);
+    assert_eq!(run_i64(vec![F32Const(-3.7), I64TruncSatf32_s]), -3);
+    assert_eq!(run_i64(vec![F64Const(1e30), I64TruncSatf64_u]), -1);
+    assert_eq!(run_i64(vec![F64Const(f64::NAN), I64TruncSatf64_s]), 0);
+}
+
+#[test]
+fn int_wrap_extend() {
+    assert_eq!(run_i32(vec![I64Const(0x1_0000_0001), I32Wrapi64]), 1);
+    assert_eq!(run_i64(vec![I32Const(-1), I64Extendi32_s]), -1);
+    assert_eq!(run_i64(vec![I32Const(-1), I64Extendi32_u]), 0xFFFF_FFFF);
+}
+
+#[test]
+fn reinterpret() {
+    assert_eq!(run_i32(vec![F32Const(1.0), I32Reinterpretf32]), 0x3F80_0000);
+    assert_eq!(run_f32(vec![I32Const(0x3F80_0000), F32Reinterpreti32]), 1.0);
+    assert_eq!(
+        run_i64(vec![F64Const(1.0), I64Reinterpretf64]),
+        0x3FF0_0000_0000_0000
+    );
+
+    // NaN payloads are preserved
+    let nan = f32::from_bits(0x7FA0_0001);
+    assert_eq!(run_i32(vec![F32Const(nan), I32Reinterpretf32]), 0x7FA0_0001);
+    let val = run_f32(vec![I32Const(0x7FA0_0001), F32Reinterpreti32]);
+    assert_eq!(val.to_bits(), 0x7FA0_0001);
+    let val = run_f64(vec![I64Const(0x7FF4_0000_0000_0001), F64Reinterpreti64]);
+    assert_eq!(val.to_bits(), 0x7FF4_0000_0000_0001);
+}
+
+#[test]
+fn sign_extension() {
+    assert_eq!(run_i32(vec![I32Const(0xFF), I32Extend8_s]), -1);
+    assert_eq!(run_i32(vec![I32Const(0x7F), I32Extend8_s]), 127);
+    assert_eq!(run_i32(vec![I32Const(0x1_8000), I32Extend16_s]), -32768);
+    assert_eq!(run_i64(vec![I64Const(0x180), I64Extend8_s]), -128);
+    assert_eq!(run_i64(vec![I64Const(0x7FFF), I64Extend16_s]), 32767);
+    assert_eq!(run_i64(vec![I64Const(0x1_FFFF_FFFF), I64Extend32_s]), -1);
+}
+
+#[test]
+fn i32_bit_counting() {
+    assert_eq!(run_i32(vec![I32Const(1), I32Clz]), 31);
+    assert_eq!(run_i32(vec![I32Const(0), I32Clz]), 32);
+    assert_eq!(run_i32(vec![I32Const(0x100), I32Ctz]), 8);
+    assert_eq!(run_i32(vec![I32Const(0), I32Ctz]), 32);
+    assert_eq!(run_i32(vec![I32Const(0xF0F0), I32Popcnt]), 8);
+    assert_eq!(run_i64(vec![I64Const(0x100), I64Ctz]), 8);
+    assert_eq!(run_i64(vec![I64Const(0xF0F0), I64Popcnt]), 8);
+}
+
+#[test]
+fn rotate() {
+    assert_eq!(
+        run_i32(vec![I32Const(0x1234_5678), I32Const(8), I32Rotl]),
+        0x3456_7812
+    );
+    assert_eq!(
+        run_i32(vec![I32Const(0x1234_5678), I32Const(8), I32Rotr]),
+        0x7812_3456
+    );
+    assert_eq!(
+        run_i32(vec![I32Const(0x1234_5678), I32Const(32), I32Rotl]),
+        0x1234_5678
+    );
+    assert_eq!(
+        run_i64(vec![I64Const(0x1234_5678_9ABC_DEF0), I64Const(8), I64Rotl]),
+        0x3456_789A_BCDE_F012
+    );
+    assert_eq!(
+        run_i64(vec![I64Const(0x1234_5678_9ABC_DEF0), I64Const(64), I64Rotr]),
+        0x1234_5678_9ABC_DEF0
+    );
+}
+
+#[test]
+fn narrow_loads() {
+    // Memory at 0: FF 80 FF FF
+    let store = vec![
+        I32Const(0),
+        I32Const(-32513),
+        I32Store(MemArg {
+            align: 0,
+            offset: 0,
+            mem: 0,
+        }),
+    ];
+    let load = |instr: Instruction| {
+        let mut instrs = store.clone();
+        instrs.push(I32Const(0));
+        instrs.push(instr);
+        run(instrs)
+    };
+    let memarg = MemArg {
+        align: 0,
+        offset: 0,
+        mem: 0,
+    };
+    let memarg1 = MemArg {
+        align: 0,
+        offset: 1,
+        mem: 0,
+    };
+
+    assert_eq!(load(I32Load8s(memarg.clone())).stack.pop_i32().unwrap(), -1);
+    assert_eq!(
+        load(I32Load8u(memarg.clone())).stack.pop_i32().unwrap(),
+        255
+    );
+    assert_eq!(
+        load(I32Load8u(memarg1.clone())).stack.pop_i32().unwrap(),
+        0x80
+    );
+    assert_eq!(
+        load(I32Load16s(memarg.clone())).stack.pop_i32().unwrap(),
+        -32513
+    );
+    assert_eq!(
+        load(I32Load16u(memarg.clone())).stack.pop_i32().unwrap(),
+        0x80FF
+    );
+    assert_eq!(
+        load(I64Load8s(memarg1.clone())).stack.pop_i64().unwrap(),
+        -128
+    );
+    assert_eq!(
+        load(I64Load8u(memarg.clone())).stack.pop_i64().unwrap(),
+        255
+    );
+    assert_eq!(
+        load(I64Load16s(memarg1.clone())).stack.pop_i64().unwrap(),
+        -128
+    );
+    assert_eq!(
+        load(I64Load16u(memarg.clone())).stack.pop_i64().unwrap(),
+        0x80FF
+    );
+    assert_eq!(
+        load(I64Load32s(memarg.clone())).stack.pop_i64().unwrap(),
+        -32513
+    );
+    assert_eq!(
+        load(I64Load32u(memarg)).stack.pop_i64().unwrap(),
+        0xFFFF_80FF
+    );
+}
+
+#[test]
+fn narrow_load_oob() {
+    assert_eq!(
+        run_trap(vec![
+            I32Const(PAGE_SIZE as i32 - 1),
+            I32Load16u(MemArg {
+                align: 0,
+                offset: 0,
+                mem: 0,
+            }),
+        ]),
+        TrapKind::MemoryOutOfBounds
+    );
+}
+
+#[test]
+fn narrow_stores() {
+    let memarg = MemArg {
+        align: 0,
+        offset: 0,
+        mem: 0,
+    };
+    let store_then_load = |value: Instruction, store: Instruction| {
+        run(vec![
+            // Fill the first 8 bytes with 0xFF
+            I32Const(0),
+            I64Const(-1),
+            I64Store32(memarg.clone()),
+            I32Const(4),
+            I64Const(-1),
+            I64Store32(memarg.clone()),
+            // Do the store
+            I32Const(0),
+            value,
+            store,
+            I32Const(0),
+            I64Load32u(MemArg {
+                align: 0,
+                offset: 0,
+                mem: 0,
+            }),
+        ])
+        .stack
+        .pop_i64()
+        .unwrap()
+    };
+
+    assert_eq!(
+        store_then_load(I32Const(0x1234), I32Store8(memarg.clone())),
+        0xFFFF_FF34
+    );
+    assert_eq!(
+        store_then_load(I32Const(0x12_3456), I32Store16(memarg.clone())),
+        0xFFFF_3456
+    );
+    assert_eq!(
+        store_then_load(I64Const(0x1234), I64Store8(memarg.clone())),
+        0xFFFF_FF34
+    );
+    assert_eq!(
+        store_then_load(I64Const(0x12_3456), I64Store16(memarg.clone())),
+        0xFFFF_3456
+    );
+    assert_eq!(
+        store_then_load(I64Const(0x1_1234_5678), I64Store32(memarg.clone())),
+        0x1234_5678
+    );
+}
+
+#[test]
+fn i32_store8_leaves_next_byte() {
+    let rt = run(vec![
+        I32Const(0),
+        I32Const(0x1234),
+        I32Store8(MemArg {
+            align: 0,
+            offset: 0,
+            mem: 0,
+        }),
+    ]);
+    assert_eq!(&rt.store.mems[0].data[0..2], &[0x34, 0x00]);
+}
+
+#[test]
+fn load_effective_address_overflow() {
+    // `addr + offset` wraps around to 0x10 in 32 bits
+    assert_eq!(
+        run_trap(vec![
+            I32Const(0xFFFFFFF0u32 as i32),
+            I32Load(MemArg {
+                align: 2,
+                offset: 0x20,
+                mem: 0,
+            }),
+        ]),
+        TrapKind::MemoryOutOfBounds
+    );
+}
+
+#[test]
+fn store_effective_address_overflow() {
+    assert_eq!(
+        run_trap(vec![
+            I32Const(0xFFFFFFF0u32 as i32),
+            I32Const(0),
+            I32Store(MemArg {
+                align: 2,
+                offset: 0x20,
+                mem: 0,
+            }),
+        ]),
+        TrapKind::MemoryOutOfBounds
+    );
+}
+
+#[test]
+fn narrow_store_oob() {
+    assert_eq!(
+        run_trap(vec![
+            I32Const(PAGE_SIZE as i32 - 1),
+            I32Const(0),
+            I32Store16(MemArg {
+                align: 0,
+                offset: 0,
+                mem: 0,
+            }),
+        ]),
+        TrapKind::MemoryOutOfBounds
+    );
+}
+
+#[test]
+fn full_width_load_store_round_trip() {
+    let memarg = MemArg {
+        align: 0,
+        offset: 13,
+        mem: 0,
+    };
+
+    let i64_val = 0x0123_4567_89AB_CDEF;
+    assert_eq!(
+        run_i64(vec![
+            I32Const(3),
+            I64Const(i64_val),
+            I64Store(memarg.clone()),
+            I32Const(3),
+            I64Load(memarg.clone()),
+        ]),
+        i64_val
+    );
+
+    let f32_val = f32::from_bits(0x7FA0_0001); // NaN with payload
+    let val = run_f32(vec![
+        I32Const(3),
+        F32Const(f32_val),
+        F32Store(memarg.clone()),
+        I32Const(3),
+        F32Load(memarg.clone()),
+    ]);
+    assert_eq!(val.to_bits(), f32_val.to_bits());
+
+    let f64_val = -1.0e-300;
+    let val = run_f64(vec![
+        I32Const(3),
+        F64Const(f64_val),
+        F64Store(memarg.clone()),
+        I32Const(3),
+        F64Load(memarg.clone()),
+    ]);
+    assert_eq!(val.to_bits(), f64_val.to_bits());
+
+    let f64_val = f64::from_bits(0xFFF8_0000_DEAD_BEEF); // NaN with payload
+    let rt = run(vec![
+        I32Const(3),
+        F64Const(f64_val),
+        F64Store(memarg.clone()),
+        I32Const(3),
+        F64Load(memarg),
+    ]);
+    assert_eq!(&rt.store.mems[0].data[16..24], &f64_val.to_le_bytes());
+}
+
+#[test]
+fn drop() {
+    assert_eq!(run_i32(vec![I32Const(1), I32Const(2), Drop]), 1);
+}
+
+#[test]
+fn select() {
+    assert_eq!(
+        run_i32(vec![I32Const(1), I32Const(2), I32Const(0), Select]),
+        2
+    );
+    assert_eq!(
+        run_i32(vec![I32Const(1), I32Const(2), I32Const(1), Select]),
+        1
+    );
+    assert_eq!(
+        run_f64(vec![F64Const(1.5), F64Const(2.5), I32Const(0), Select]),
+        2.5
+    );
+    assert_eq!(
+        run_f64(vec![F64Const(1.5), F64Const(2.5), I32Const(-1), Select]),
+        1.5
+    );
+}
+
+#[test]
+fn select_t() {
+    assert_eq!(
+        run_i32(vec![
+            I32Const(1),
+            I32Const(2),
+            I32Const(0),
+            SelectT(vec![ValType::I32])
+        ]),
+        2
+    );
+    assert_eq!(
+        run_i32(vec![
+            I32Const(1),
+            I32Const(2),
+            I32Const(1),
+            SelectT(vec![ValType::I32])
+        ]),
+        1
+    );
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "doesn't match annotated type")]
+fn select_t_type_mismatch() {
+    run(vec![
+        I32Const(1),
+        I32Const(2),
+        I32Const(1),
+        SelectT(vec![ValType::F32]),
+    ]);
+}
+
+fn block(instrs: Vec<Instruction>) -> Block {
+    Block {
+        ty: BlockType::Empty,
+        instrs: instrs.into(),
+    }
+}
+
+#[test]
+fn loop_sum() {
+    // i = 0; sum = 0; do { i += 1; sum += i; } while (i < 10)
+    let mut rt = run_with_locals(
+        vec![Local {
+            n: 2,
+            ty: ValType::I32,
+        }],
+        vec![
+            I32Const(0),
+            LocalSet(LocalIdx(0)),
+            I32Const(0),
+            LocalSet(LocalIdx(1)),
+            Loop(block(vec![
+                LocalGet(LocalIdx(1)),
+                LocalGet(LocalIdx(0)),
+                I32Const(1),
+                I32Add,
+                LocalTee(LocalIdx(0)),
+                I32Add,
+                LocalSet(LocalIdx(1)),
+                LocalGet(LocalIdx(0)),
+                I32Const(10),
+                I32Lt_u,
+                BrIf(0),
+            ])),
+            LocalGet(LocalIdx(1)),
+        ],
+    );
+    assert_eq!(rt.stack.pop_i32().unwrap(), 55);
+}
+
+#[test]
+fn loop_exit_enclosing_block() {
+    // Loop is the last instruction in the block, branching out of the loop exits the block
+    let mut rt = run_with_locals(
+        vec![Local {
+            n: 1,
+            ty: ValType::I32,
+        }],
+        vec![
+            I32Const(0),
+            LocalSet(LocalIdx(0)),
+            Block(block(vec![Loop(block(vec![
+                LocalGet(LocalIdx(0)),
+                I32Const(1),
+                I32Add,
+                LocalTee(LocalIdx(0)),
+                I32Const(5),
+                I32Eq,
+                BrIf(1),
+                I32Const(1),
+                BrIf(0),
+            ]))])),
+            LocalGet(LocalIdx(0)),
+        ],
+    );
+    assert_eq!(rt.stack.pop_i32().unwrap(), 5);
+}
+
+fn if_(then_instrs: Vec<Instruction>, else_instrs: Vec<Instruction>) -> Instruction {
+    If(If {
+        ty: BlockType::Empty,
+        then_instrs: then_instrs.into(),
+        else_instrs: else_instrs.into(),
+    })
+}
+
+#[test]
+fn if_else() {
+    let instrs = |cond| {
+        vec![
+            I32Const(cond),
+            if_(vec![I32Const(1)], vec![I32Const(2)]),
+            I32Const(10),
+            I32Add,
+        ]
+    };
+    assert_eq!(run_i32(instrs(1)), 11);
+    assert_eq!(run_i32(instrs(0)), 12);
+}
+
+#[test]
+fn if_without_else() {
+    let instrs = |cond| {
+        vec![
+            I32Const(5),
+            I32Const(cond),
+            if_(vec![Drop, I32Const(1)], vec![]),
+        ]
+    };
+    assert_eq!(run_i32(instrs(1)), 1);
+    assert_eq!(run_i32(instrs(0)), 5);
+}
+
+#[test]
+fn if_branch_to_end() {
+    assert_eq!(
+        run_i32(vec![
+            I32Const(1),
+            If(If {
+                ty: BlockType::ValType(ValType::I32),
+                then_instrs: vec![I32Const(1), I32Const(1), BrIf(0), Drop, I32Const(2)].into(),
+                else_instrs: vec![I32Const(3)].into(),
+            }),
+        ]),
+        1
+    );
+}
+
+#[test]
+fn br_to_loop() {
+    // Count to 3 with a `br` to the enclosing loop in an `if`
+    let mut rt = run_with_locals(
+        vec![Local {
+            n: 1,
+            ty: ValType::I32,
+        }],
+        vec![
+            I32Const(0),
+            LocalSet(LocalIdx(0)),
+            Loop(block(vec![
+                LocalGet(LocalIdx(0)),
+                I32Const(1),
+                I32Add,
+                LocalTee(LocalIdx(0)),
+                I32Const(3),
+                I32Lt_s,
+                if_(vec![Br(1)], vec![]),
+            ])),
+            LocalGet(LocalIdx(0)),
+        ],
+    );
+    assert_eq!(rt.stack.pop_i32().unwrap(), 3);
+}
+
+#[test]
+fn br_out_of_blocks() {
+    assert_eq!(
+        run_i32(vec![
+            I32Const(1),
+            Block(block(vec![Block(block(vec![Br(1), Drop])), Drop])),
+        ]),
+        1
+    );
+}
+
+#[test]
+fn br_to_function() {
+    // Branching to the function's label returns from the function
+    let results = call_multi_value_module(vec![
+        I32Const(1),
+        I32Const(2),
+        I32Const(3),
+        Block(block(vec![Br(1)])),
+        I32Const(4),
+    ]);
+    assert_eq!(
+        results,
+        Ok(vec![Value::I32(1), Value::I32(2), Value::I32(3)])
+    );
+}
+
+#[test]
+fn br_table() {
+    // Dispatch to one of three blocks, each of which pushes a different value
+    let instrs = |idx| {
+        vec![Block(Block {
+            ty: BlockType::ValType(ValType::I32),
+            instrs: vec![
+                Block(block(vec![
+                    Block(block(vec![
+                        Block(block(vec![
+                            I32Const(40),
+                            I32Const(idx),
+                            BrTable(parser::types::BrTable {
+                                tbl: vec![0, 1, 2],
+                                def: 3,
+                            }),
+                        ])),
+                        I32Const(10),
+                        Br(2),
+                    ])),
+                    I32Const(20),
+                    Br(1),
+                ])),
+                I32Const(30),
+                Br(0),
+            ]
+            .into(),
+        })]
+    };
+    assert_eq!(run_i32(instrs(0)), 10);
+    assert_eq!(run_i32(instrs(1)), 20);
+    assert_eq!(run_i32(instrs(2)), 30);
+    // Out of range index takes the default label, which exits all blocks
+    assert_eq!(run_i32(instrs(3)), 40);
+    assert_eq!(run_i32(instrs(-1)), 40);
+}
+
+fn fun(ty: u32, instrs: Vec<Instruction>) -> Fun {
+    Fun {
+        ty: TypeIdx(ty),
+        locals: vec![],
+        expr: Expr {
+            instrs: instrs.into(),
+        },
+    }
+}
+
+// A module with types `[] -> []` and `[] -> [i32]`, function 0 of type `[] -> []` that calls
+// through table element `elem_idx` with type `call_ty`, and a table with two functions
+// `[] -> [i32]` and `[] -> []`.
+fn call_indirect_module(elem_idx: i32, call_ty: u32) -> Result<Runtime, Trap> {
+    let module = parser::Module {
+        types: vec![
+            FuncType {
+                args: vec![],
+                ret: vec![],
+            },
+            FuncType {
+                args: vec![],
+                ret: vec![ValType::I32],
+            },
+        ],
+        funs: vec![
+            fun(0, vec![I32Const(elem_idx), CallIndirect(TypeIdx(call_ty))]),
+            fun(1, vec![I32Const(42)]),
+            fun(0, vec![]),
+        ],
+        tables: vec![funcref_table(3)],
+        ..Default::default()
+    };
+
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(&mut rt, &Linker::default(), module).unwrap();
+    let table_addr = rt.modules[module_idx].table_addrs[0];
+    let func_addrs = &rt.modules[module_idx].func_addrs;
+    rt.store.tables[table_addr].elems[0] = Some(Ref::Func(func_addrs[1]));
+    rt.store.tables[table_addr].elems[1] = Some(Ref::Func(func_addrs[2]));
+    call(&mut rt, module_idx, FuncIdx(0))?;
+    Ok(rt)
+}
+
+#[test]
+fn call_indirect() {
+    assert_eq!(
+        call_indirect_module(0, 1).unwrap().stack.pop_i32().unwrap(),
+        42
+    );
+    assert!(call_indirect_module(1, 0).unwrap().stack.is_empty());
+}
+
+#[test]
+fn call_indirect_type_mismatch() {
+    assert_eq!(
+        expect_trap(call_indirect_module(1, 1)),
+        TrapKind::IndirectCallTypeMismatch
+    );
+}
+
+#[test]
+fn call_indirect_uninitialized() {
+    assert_eq!(
+        expect_trap(call_indirect_module(2, 0)),
+        TrapKind::UninitializedElement
+    );
+}
+
+#[test]
+fn call_indirect_oob() {
+    assert_eq!(
+        expect_trap(call_indirect_module(3, 0)),
+        TrapKind::UndefinedElement
+    );
+}
+
+#[test]
+fn memory_size_grow() {
+    let mut rt = run_module(test_module(
+        vec![],
+        vec![MemorySize, I32Const(1), MemoryGrow, MemorySize],
+    ));
+    assert_eq!(rt.stack.pop_i32().unwrap(), 2); // size after grow
+    assert_eq!(rt.stack.pop_i32().unwrap(), 1); // grow returns old size
+    assert_eq!(rt.stack.pop_i32().unwrap(), 1); // initial size
+    assert_eq!(rt.store.mems[0].data.len(), 2 * PAGE_SIZE);
+}
+
+#[test]
+fn memory_grow_past_max() {
+    let mut rt = run_module(parser::Module {
+        mem_addrs: vec![Limits {
+            min: 1,
+            max: Some(2),
+            memory64: false,
+        }],
+        ..test_module(
+            vec![],
+            vec![I32Const(2), MemoryGrow, I32Const(1), MemoryGrow, MemorySize],
+        )
+    });
+    assert_eq!(rt.stack.pop_i32().unwrap(), 2);
+    assert_eq!(rt.stack.pop_i32().unwrap(), 1);
+    assert_eq!(rt.stack.pop_i32().unwrap(), -1);
+    assert_eq!(rt.store.mems[0].data.len(), 2 * PAGE_SIZE);
+}
+
+#[test]
+fn memory_grow_past_page_limit() {
+    let mut rt = run_module(test_module(
+        vec![],
+        vec![I32Const(MAX_PAGES as i32), MemoryGrow, MemorySize],
+    ));
+    assert_eq!(rt.stack.pop_i32().unwrap(), 1);
+    assert_eq!(rt.stack.pop_i32().unwrap(), -1);
+}
+
+#[test]
+fn memory_copy_overlapping() {
+    let memarg = MemArg {
+        align: 0,
+        offset: 0,
+        mem: 0,
+    };
+    // Memory at 0: 01 02 03 04, copy 4 bytes from 0 to 2
+    let rt = run(vec![
+        I32Const(0),
+        I32Const(0x0403_0201),
+        I32Store(memarg),
+        I32Const(2),
+        I32Const(0),
+        I32Const(4),
+        MemoryCopy,
+    ]);
+    assert_eq!(&rt.store.mems[0].data[0..7], &[1, 2, 1, 2, 3, 4, 0]);
+}
+
+#[test]
+fn memory_fill_to_end() {
+    let rt = run(vec![
+        I32Const(PAGE_SIZE as i32 - 3),
+        I32Const(0xAB),
+        I32Const(3),
+        MemoryFill,
+    ]);
+    let mem = &rt.store.mems[0].data;
+    assert_eq!(&mem[PAGE_SIZE - 4..], &[0, 0xAB, 0xAB, 0xAB]);
+}
+
+#[test]
+fn memory_fill_oob() {
+    assert_eq!(
+        run_trap(vec![
+            I32Const(PAGE_SIZE as i32 - 3),
+            I32Const(0xAB),
+            I32Const(4),
+            MemoryFill,
+        ]),
+        TrapKind::MemoryOutOfBounds
+    );
+}
+
+#[test]
+fn memory_copy_oob() {
+    assert_eq!(
+        run_trap(vec![
+            I32Const(0),
+            I32Const(PAGE_SIZE as i32 - 1),
+            I32Const(2),
+            MemoryCopy,
+        ]),
+        TrapKind::MemoryOutOfBounds
+    );
+}
+
+// `test_module` with a passive data segment `[1, 2, 3, 4]`
+fn passive_data_module(instrs: Vec<Instruction>) -> parser::Module {
+    with_data(test_module(vec![], instrs), DataMode::Passive)
+}
+
+#[test]
+fn memory_init() {
+    let rt = run_module(passive_data_module(vec![
+        I32Const(10),
+        I32Const(1),
+        I32Const(3),
+        MemoryInit(0),
+    ]));
+    assert_eq!(&rt.store.mems[0].data[9..14], &[0, 2, 3, 4, 0]);
+}
+
+#[test]
+fn memory_init_after_data_drop() {
+    let trap = expect_trap(try_run_module(passive_data_module(vec![
+        DataDrop(0),
+        I32Const(0),
+        I32Const(0),
+        I32Const(1),
+        MemoryInit(0),
+    ])));
+    assert_eq!(trap, TrapKind::MemoryOutOfBounds);
+}
+
+// `test_module` with an active data segment `[1, 2, 3, 4]` at the given offset of memory 0
+fn active_data_module(offset: i32, instrs: Vec<Instruction>) -> parser::Module {
+    let mode = DataMode::Active {
+        mem: 0,
+        offset: Expr {
+            instrs: vec![I32Const(offset)].into(),
+        },
+    };
+    with_data(test_module(vec![], instrs), mode)
+}
+
+fn with_data(module: parser::Module, mode: DataMode) -> parser::Module {
+    parser::Module {
+        data: vec![Data {
+            mode,
+            init: vec![1, 2, 3, 4],
+        }],
+        ..module
+    }
+}
+
+#[test]
+fn active_data_initializes_memory() {
+    let mut rt = run_module(active_data_module(
+        2,
+        vec![
+            I32Const(0),
+            I64Load(MemArg {
+                align: 0,
+                offset: 0,
+                mem: 0,
+            }),
+        ],
+    ));
+    assert_eq!(rt.stack.pop_i64().unwrap(), 0x0000_0403_0201_0000);
+    // Active segments are dropped after initialization
+    assert!(rt.store.datas[0].is_empty());
+}
+
+#[test]
+fn active_data_oob() {
+    let mut rt = Runtime::default();
+    let module = active_data_module(PAGE_SIZE as i32 - 3, vec![]);
+    assert_eq!(
+        allocate_module(&mut rt, &Linker::default(), module),
+        Err(InstantiationError::Trap(TrapKind::MemoryOutOfBounds))
+    );
+}
+
+#[test]
+fn elem_segment_initializes_table() {
+    let module = parser::Module {
+        types: vec![
+            FuncType {
+                args: vec![],
+                ret: vec![],
+            },
+            FuncType {
+                args: vec![],
+                ret: vec![ValType::I32],
+            },
+        ],
+        funs: vec![
+            fun(0, vec![I32Const(2), CallIndirect(TypeIdx(1))]),
+            fun(1, vec![I32Const(42)]),
+        ],
+        tables: vec![funcref_table(3)],
+        elems: vec![Element {
+            mode: ElemMode::Active {
+                table: 0,
+                offset: Expr {
+                    instrs: vec![I32Const(1)].into(),
+                },
+            },
+            init: vec![FuncIdx(0), FuncIdx(1)],
+        }],
+        ..Default::default()
+    };
+
+    let mut rt = run_module(module);
+    let func_addrs = &rt.modules[0].func_addrs;
+    assert_eq!(
+        rt.store.tables[0].elems,
+        vec![
+            None,
+            Some(Ref::Func(func_addrs[0])),
+            Some(Ref::Func(func_addrs[1]))
+        ]
+    );
+    assert_eq!(rt.stack.pop_i32().unwrap(), 42);
+}
+
+#[test]
+fn elem_segment_oob() {
+    let module = parser::Module {
+        types: vec![FuncType {
+            args: vec![],
+            ret: vec![],
+        }],
+        funs: vec![fun(0, vec![])],
+        tables: vec![funcref_table(1)],
+        elems: vec![Element {
+            mode: ElemMode::Active {
+                table: 0,
+                offset: Expr {
+                    instrs: vec![I32Const(1)].into(),
+                },
+            },
+            init: vec![FuncIdx(0)],
+        }],
+        ..Default::default()
+    };
+    let mut rt = Runtime::default();
+    assert_eq!(
+        allocate_module(&mut rt, &Linker::default(), module),
+        Err(InstantiationError::Trap(TrapKind::TableOutOfBounds))
+    );
+}
+
+fn binop_i32_ty() -> FuncType {
+    FuncType {
+        args: vec![ValType::I32, ValType::I32],
+        ret: vec![ValType::I32],
+    }
+}
+
+// A module that imports `env.add` as function 0 and calls it from function 1 with the given
+// import type
+fn host_add_module(import_ty: FuncType) -> parser::Module {
+    parser::Module {
+        types: vec![
+            import_ty,
+            FuncType {
+                args: vec![],
+                ret: vec![ValType::I32],
+            },
+        ],
+        imports: vec![Import {
+            module: "env".to_owned(),
+            name: "add".to_owned(),
+            desc: ImportDesc::Func(TypeIdx(0)),
+        }],
+        funs: vec![fun(1, vec![I32Const(40), I32Const(2), Call(FuncIdx(0))])],
+        ..Default::default()
+    }
+}
+
+fn host_add_linker(rt: &mut Runtime) -> Linker {
+    let mut linker = Linker::default();
+    linker.func(rt, "env", "add", binop_i32_ty(), |_rt, args| match args {
+        [Value::I32(a), Value::I32(b)] => Ok(vec![Value::I32(a.wrapping_add(*b))]),
+        _ => panic!("add: unexpected arguments: {:?}", args),
+    });
+    linker
+}
+
+#[test]
+fn call_host_func() {
+    let mut rt = Runtime::default();
+    let linker = host_add_linker(&mut rt);
+    let module_idx = allocate_module(&mut rt, &linker, host_add_module(binop_i32_ty())).unwrap();
+    assert_eq!(
+        call(&mut rt, module_idx, FuncIdx(1)),
+        Ok(vec![Value::I32(42)])
+    );
+    assert!(rt.stack.is_empty());
+}
+
+#[test]
+fn unresolved_func_import() {
+    let mut rt = Runtime::default();
+    assert_eq!(
+        allocate_module(&mut rt, &Linker::default(), host_add_module(binop_i32_ty())),
+        Err(InstantiationError::UnresolvedImport {
+            module: "env".to_owned(),
+            name: "add".to_owned(),
+        })
+    );
+}
+
+#[test]
+fn func_import_type_mismatch() {
+    let mut rt = Runtime::default();
+    let linker = host_add_linker(&mut rt);
+    let import_ty = FuncType {
+        args: vec![ValType::I32],
+        ret: vec![ValType::I32],
+    };
+    assert_eq!(
+        allocate_module(&mut rt, &linker, host_add_module(import_ty)),
+        Err(InstantiationError::ImportTypeMismatch {
+            module: "env".to_owned(),
+            name: "add".to_owned(),
+        })
+    );
+}
+
+#[test]
+fn imported_global_initializer() {
+    let mut rt = Runtime::default();
+    let mut linker = Linker::default();
+    linker.global(&mut rt, "env", "base", Value::I32(40), false);
+
+    let module = parser::Module {
+        types: vec![FuncType {
+            args: vec![],
+            ret: vec![ValType::I32],
+        }],
+        imports: vec![Import {
+            module: "env".to_owned(),
+            name: "base".to_owned(),
+            desc: ImportDesc::Global(GlobalType {
+                ty: ValType::I32,
+                mut_: Mutability::Const,
+            }),
+        }],
+        globals: vec![parser::Global {
+            ty: GlobalType {
+                ty: ValType::I32,
+                mut_: Mutability::Var,
+            },
+            expr: Expr {
+                instrs: vec![GlobalGet(GlobalIdx(0))].into(),
+            },
+        }],
+        funs: vec![fun(
+            0,
+            vec![
+                GlobalGet(GlobalIdx(1)),
+                I32Const(2),
+                I32Add,
+                GlobalSet(GlobalIdx(1)),
+                GlobalGet(GlobalIdx(1)),
+            ],
+        )],
+        ..Default::default()
+    };
+
+    let module_idx = allocate_module(&mut rt, &linker, module).unwrap();
+    assert_eq!(
+        call(&mut rt, module_idx, FuncIdx(0)),
+        Ok(vec![Value::I32(42)])
+    );
+}
+
+#[test]
+fn global_import_type_mismatch() {
+    let mut rt = Runtime::default();
+    let mut linker = Linker::default();
+    linker.global(&mut rt, "env", "base", Value::I64(40), false);
+
+    let module = parser::Module {
+        imports: vec![Import {
+            module: "env".to_owned(),
+            name: "base".to_owned(),
+            desc: ImportDesc::Global(GlobalType {
+                ty: ValType::I32,
+                mut_: Mutability::Const,
+            }),
+        }],
+        ..Default::default()
+    };
+    assert_eq!(
+        allocate_module(&mut rt, &linker, module),
+        Err(InstantiationError::ImportTypeMismatch {
+            module: "env".to_owned(),
+            name: "base".to_owned(),
+        })
+    );
+}
+
+// A module that imports memory `a.mem` with the given limits and stores 42 at address 8
+fn mem_import_module(limits: Limits) -> parser::Module {
+    parser::Module {
+        types: vec![FuncType {
+            args: vec![],
+            ret: vec![],
+        }],
+        imports: vec![Import {
+            module: "a".to_owned(),
+            name: "mem".to_owned(),
+            desc: ImportDesc::MemType(limits),
+        }],
+        funs: vec![fun(
+            0,
+            vec![
+                I32Const(8),
+                I32Const(42),
+                I32Store(MemArg {
+                    align: 2,
+                    offset: 0,
+                    mem: 0,
+                }),
+            ],
+        )],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn imported_memory_is_shared() {
+    let mut rt = Runtime::default();
+    let module_a = allocate_module(
+        &mut rt,
+        &Linker::default(),
+        test_module(
+            vec![],
+            vec![
+                I32Const(8),
+                I32Load(MemArg {
+                    align: 2,
+                    offset: 0,
+                    mem: 0,
+                }),
+            ],
+        ),
+    )
+    .unwrap();
+
+    let mut linker = Linker::default();
+    let mem_addr = rt.modules[module_a].mem_addrs[0];
+    linker.define("a", "mem", Extern::Mem(mem_addr));
+    let module_b = allocate_module(
+        &mut rt,
+        &linker,
+        mem_import_module(Limits {
+            min: 1,
+            max: None,
+            memory64: false,
+        }),
+    )
+    .unwrap();
+
+    call(&mut rt, module_b, FuncIdx(0)).unwrap();
+    call(&mut rt, module_a, FuncIdx(0)).unwrap();
+    assert_eq!(rt.stack.pop_i32().unwrap(), 42);
+}
+
+#[test]
+fn memory_import_limits_mismatch() {
+    let mut rt = Runtime::default();
+    let mut linker = Linker::default();
+    linker.memory(
+        &mut rt,
+        "a",
+        "mem",
+        Limits {
+            min: 1,
+            max: None,
+            memory64: false,
+        },
+    );
+    let module = mem_import_module(Limits {
+        min: 1,
+        max: Some(2),
+        memory64: false,
+    });
+    assert_eq!(
+        allocate_module(&mut rt, &linker, module),
+        Err(InstantiationError::ImportTypeMismatch {
+            module: "a".to_owned(),
+            name: "mem".to_owned(),
+        })
+    );
+}
+
+// A module that imports table `a.table` with the given limits and calls element 0 with type
+// `[] -> [i32]`
+fn table_import_module(limits: Limits) -> parser::Module {
+    parser::Module {
+        types: vec![FuncType {
+            args: vec![],
+            ret: vec![ValType::I32],
+        }],
+        imports: vec![Import {
+            module: "a".to_owned(),
+            name: "table".to_owned(),
+            desc: ImportDesc::Table(Table {
+                limits,
+                elem_type: ElemType::FuncRef,
+            }),
+        }],
+        funs: vec![fun(0, vec![I32Const(0), CallIndirect(TypeIdx(0))])],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn imported_table_call_indirect() {
+    let mut rt = Runtime::default();
+    let module_a = allocate_module(
+        &mut rt,
+        &Linker::default(),
+        parser::Module {
+            types: vec![FuncType {
+                args: vec![],
+                ret: vec![ValType::I32],
+            }],
+            funs: vec![fun(0, vec![I32Const(42)])],
+            tables: vec![funcref_table(1)],
+            elems: vec![Element {
+                mode: ElemMode::Active {
+                    table: 0,
+                    offset: Expr {
+                        instrs: vec![I32Const(0)].into(),
+                    },
+                },
+                init: vec![FuncIdx(0)],
+            }],
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let mut linker = Linker::default();
+    let table_addr = rt.modules[module_a].table_addrs[0];
+    linker.define("a", "table", Extern::Table(table_addr));
+    let module_b = allocate_module(
+        &mut rt,
+        &linker,
+        table_import_module(Limits {
+            min: 1,
+            max: None,
+            memory64: false,
+        }),
+    )
+    .unwrap();
+
+    assert_eq!(
+        call(&mut rt, module_b, FuncIdx(0)),
+        Ok(vec![Value::I32(42)])
+    );
+}
+
+#[test]
+fn table_import_limits_mismatch() {
+    let mut rt = Runtime::default();
+    let mut linker = Linker::default();
+    linker.table(&mut rt, "a", "table", funcref_table(1));
+    let module = table_import_module(Limits {
+        min: 2,
+        max: None,
+        memory64: false,
+    });
+    assert_eq!(
+        allocate_module(&mut rt, &linker, module),
+        Err(InstantiationError::ImportTypeMismatch {
+            module: "a".to_owned(),
+            name: "table".to_owned(),
+        })
+    );
+}
+
+// A module that imports the given WASI functions as functions `0..imports.len()`, has one page of
+// memory initialized with the given `(offset, bytes)` segments, and a `[] -> []` function with the
+// given body after the imports
+fn wasi_module(
+    imports: Vec<(&str, FuncType)>,
+    segments: Vec<(i32, Vec<u8>)>,
+    instrs: Vec<Instruction>,
+) -> parser::Module {
+    let n_imports = imports.len() as u32;
+    let (names, mut types): (Vec<&str>, Vec<FuncType>) = imports.into_iter().unzip();
+    types.push(FuncType {
+        args: vec![],
+        ret: vec![],
+    });
+    parser::Module {
+        types,
+        imports: names
+            .into_iter()
+            .enumerate()
+            .map(|(type_idx, name)| Import {
+                module: "wasi_snapshot_preview1".to_owned(),
+                name: name.to_owned(),
+                desc: ImportDesc::Func(TypeIdx(type_idx as u32)),
+            })
+            .collect(),
+        funs: vec![fun(n_imports, instrs)],
+        mem_addrs: vec![Limits {
+            min: 1,
+            max: None,
+            memory64: false,
+        }],
+        data: segments
+            .into_iter()
+            .map(|(offset, init)| Data {
+                mode: DataMode::Active {
+                    mem: 0,
+                    offset: Expr {
+                        instrs: vec![I32Const(offset)].into(),
+                    },
+                },
+                init,
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+// A writer that can be inspected after being moved into a `WasiCtx`
+#[derive(Clone, Default)]
+struct SharedBuf(Rc<std::cell::RefCell<Vec<u8>>>);
+
+impl std::io::Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// A module that writes "Hello, world!" to stdout with `fd_write` and pushes the errno and the
+// number of bytes written
+fn hello_world_module() -> parser::Module {
+    wasi_module(
+        vec![(
+            "fd_write",
+            FuncType {
+                args: vec![ValType::I32; 4],
+                ret: vec![ValType::I32],
+            },
+        )],
+        // Two iovecs at address 0, strings at 16 and 32
+        vec![
+            (0, vec![16, 0, 0, 0, 7, 0, 0, 0, 32, 0, 0, 0, 6, 0, 0, 0]),
+            (16, b"Hello, ".to_vec()),
+            (32, b"world!".to_vec()),
+        ],
+        vec![
+            I32Const(1),  // fd
+            I32Const(0),  // iovs
+            I32Const(2),  // iovs_len
+            I32Const(64), // nwritten
+            Call(FuncIdx(0)),
+            I32Const(64),
+            I32Load(MemArg {
+                align: 2,
+                offset: 0,
+                mem: 0,
+            }),
+        ],
+    )
+}
+
+// Run `hello_world_module` and return the runtime and the stdout of the program
+fn run_hello_world() -> (Runtime, SharedBuf) {
+    let stdout = SharedBuf::default();
+    let mut rt = Runtime::default();
+    let mut linker = Linker::default();
+    wasi::add_to_linker(
+        &mut linker,
+        &mut rt,
+        wasi::WasiCtx {
+            stdout: Box::new(stdout.clone()),
+            ..Default::default()
+        },
+    );
+
+    let module_idx = allocate_module(&mut rt, &linker, hello_world_module()).unwrap();
+    call(&mut rt, module_idx, FuncIdx(1)).unwrap();
+    (rt, stdout)
+}
+
+#[test]
+fn wasi_fd_write() {
+    let (mut rt, stdout) = run_hello_world();
+    assert_eq!(rt.stack.pop_i32().unwrap(), 13); // nwritten
+    assert_eq!(rt.stack.pop_i32().unwrap(), 0); // errno
+    assert_eq!(&*stdout.0.borrow(), b"Hello, world!");
+}
+
+#[test]
+fn wasi_fd_write_fault() {
+    let fd_write = |iovs: i32, iovs_len: i32| {
+        wasi_module(
+            vec![(
+                "fd_write",
+                FuncType {
+                    args: vec![ValType::I32; 4],
+                    ret: vec![ValType::I32],
+                },
+            )],
+            vec![],
+            vec![
+                I32Const(1), // fd
+                I32Const(iovs),
+                I32Const(iovs_len),
+                I32Const(0), // nwritten
+                Call(FuncIdx(0)),
+            ],
+        )
+    };
+    let run = |module: parser::Module| {
+        let mut rt = Runtime::default();
+        let mut linker = Linker::default();
+        wasi::add_to_linker(
+            &mut linker,
+            &mut rt,
+            wasi::WasiCtx {
+                stdout: Box::new(SharedBuf::default()),
+                ..Default::default()
+            },
+        );
+        let module_idx = allocate_module(&mut rt, &linker, module).unwrap();
+        call(&mut rt, module_idx, FuncIdx(1)).unwrap();
+        rt.stack.pop_i32().unwrap()
+    };
+
+    // Address of the second iovec overflows
+    assert_eq!(run(fd_write(-8, 2)), 21);
+    // Address of the length of the iovec overflows
+    assert_eq!(run(fd_write(-4, 1)), 21);
+    // Module without a memory
+    let module = parser::Module {
+        mem_addrs: vec![],
+        ..fd_write(0, 1)
+    };
+    assert_eq!(run(module), 21);
+
+    // Re-exported import called without a wasm caller
+    let module = parser::Module {
+        exports: vec![Export {
+            nm: "fd_write".to_owned(),
+            desc: ExportDesc::Func(FuncIdx(0)),
+        }],
+        ..fd_write(0, 1)
+    };
+    let mut rt = Runtime::default();
+    let mut linker = Linker::default();
+    wasi::add_to_linker(&mut linker, &mut rt, wasi::WasiCtx::default());
+    let module_idx = allocate_module(&mut rt, &linker, module).unwrap();
+    let args = [Value::I32(1), Value::I32(0), Value::I32(1), Value::I32(0)];
+    assert_eq!(
+        rt.invoke(module_idx, "fd_write", &args),
+        Ok(vec![Value::I32(21)])
+    );
+}
+
+#[test]
+fn wasi_proc_exit() {
+    let mut rt = Runtime::default();
+    let mut linker = Linker::default();
+    wasi::add_to_linker(&mut linker, &mut rt, wasi::WasiCtx::default());
+
+    let module = wasi_module(
+        vec![(
+            "proc_exit",
+            FuncType {
+                args: vec![ValType::I32],
+                ret: vec![],
+            },
+        )],
+        vec![],
+        vec![I32Const(42), Call(FuncIdx(0)), I32Const(1)],
+    );
+    let module_idx = allocate_module(&mut rt, &linker, module).unwrap();
+
+    assert_eq!(
+        call(&mut rt, module_idx, FuncIdx(1)).unwrap_err().kind,
+        TrapKind::Exit(42)
+    );
+    // Instructions after `proc_exit` are not executed
+    assert!(rt.stack.is_empty());
+}
+
+#[test]
+fn wasi_args() {
+    let mut rt = Runtime::default();
+    let mut linker = Linker::default();
+    wasi::add_to_linker(
+        &mut linker,
+        &mut rt,
+        wasi::WasiCtx {
+            args: vec!["foo".to_owned(), "barbaz".to_owned()],
+            ..Default::default()
+        },
+    );
+
+    let ty = FuncType {
+        args: vec![ValType::I32; 2],
+        ret: vec![ValType::I32],
+    };
+    let module = wasi_module(
+        vec![("args_sizes_get", ty.clone()), ("args_get", ty)],
+        vec![],
+        vec![
+            I32Const(0), // argc
+            I32Const(4), // argv_buf_size
+            Call(FuncIdx(0)),
+            I32Const(16), // argv
+            I32Const(64), // argv_buf
+            Call(FuncIdx(1)),
+        ],
+    );
+    let module_idx = allocate_module(&mut rt, &linker, module).unwrap();
+    call(&mut rt, module_idx, FuncIdx(2)).unwrap();
+
+    assert_eq!(rt.stack.pop_i32().unwrap(), 0); // errno
+    assert_eq!(rt.stack.pop_i32().unwrap(), 0); // errno
+
+    let mem = &rt.store.mems[0].data;
+    let read_u32 =
+        |addr: usize| u32::from_le_bytes([mem[addr], mem[addr + 1], mem[addr + 2], mem[addr + 3]]);
+    assert_eq!(read_u32(0), 2);
+    assert_eq!(read_u32(4), 11);
+    assert_eq!(read_u32(16), 64);
+    assert_eq!(read_u32(20), 68);
+    assert_eq!(&mem[64..75], b"foo\0barbaz\0");
+}
+
+#[test]
+fn wasi_args_fault() {
+    let mut rt = Runtime::default();
+    let mut linker = Linker::default();
+    wasi::add_to_linker(
+        &mut linker,
+        &mut rt,
+        wasi::WasiCtx {
+            args: vec!["foo".to_owned(), "barbaz".to_owned()],
+            ..Default::default()
+        },
+    );
+
+    let module = wasi_module(
+        vec![(
+            "args_get",
+            FuncType {
+                args: vec![ValType::I32; 2],
+                ret: vec![ValType::I32],
+            },
+        )],
+        vec![],
+        vec![
+            I32Const(-4), // argv, the second pointer overflows
+            I32Const(64), // argv_buf
+            Call(FuncIdx(0)),
+            I32Const(0),  // argv
+            I32Const(-2), // argv_buf, the first string overflows
+            Call(FuncIdx(0)),
+        ],
+    );
+    let module_idx = allocate_module(&mut rt, &linker, module).unwrap();
+    call(&mut rt, module_idx, FuncIdx(1)).unwrap();
+
+    assert_eq!(rt.stack.pop_i32().unwrap(), 21); // errno
+    assert_eq!(rt.stack.pop_i32().unwrap(), 21); // errno
+}
+
+#[test]
+fn wasi_environ() {
+    let mut rt = Runtime::default();
+    let mut linker = Linker::default();
+    wasi::add_to_linker(
+        &mut linker,
+        &mut rt,
+        wasi::WasiCtx {
+            env: vec![("FOO".to_owned(), "bar".to_owned())],
+            ..Default::default()
+        },
+    );
+
+    let ty = FuncType {
+        args: vec![ValType::I32; 2],
+        ret: vec![ValType::I32],
+    };
+    let module = wasi_module(
+        vec![("environ_sizes_get", ty.clone()), ("environ_get", ty)],
+        vec![],
+        vec![
+            I32Const(0), // environc
+            I32Const(4), // environ_buf_size
+            Call(FuncIdx(0)),
+            I32Const(16), // environ
+            I32Const(64), // environ_buf
+            Call(FuncIdx(1)),
+        ],
+    );
+    let module_idx = allocate_module(&mut rt, &linker, module).unwrap();
+    call(&mut rt, module_idx, FuncIdx(2)).unwrap();
+
+    assert_eq!(rt.stack.pop_i32().unwrap(), 0); // errno
+    assert_eq!(rt.stack.pop_i32().unwrap(), 0); // errno
+
+    let mem = &rt.store.mems[0].data;
+    let read_u32 =
+        |addr: usize| u32::from_le_bytes([mem[addr], mem[addr + 1], mem[addr + 2], mem[addr + 3]]);
+    assert_eq!(read_u32(0), 1);
+    assert_eq!(read_u32(4), 8);
+    assert_eq!(read_u32(16), 64);
+    assert_eq!(&mem[64..72], b"FOO=bar\0");
+}
+
+#[test]
+fn wasi_clock_and_random() {
+    let mut rt = Runtime::default();
+    let mut linker = Linker::default();
+    wasi::add_to_linker(
+        &mut linker,
+        &mut rt,
+        wasi::WasiCtx {
+            rng: wasi::Rng::new(123),
+            ..Default::default()
+        },
+    );
+
+    let module = wasi_module(
+        vec![
+            (
+                "clock_time_get",
+                FuncType {
+                    args: vec![ValType::I32, ValType::I64, ValType::I32],
+                    ret: vec![ValType::I32],
+                },
+            ),
+            (
+                "random_get",
+                FuncType {
+                    args: vec![ValType::I32; 2],
+                    ret: vec![ValType::I32],
+                },
+            ),
+        ],
+        vec![],
+        vec![
+            I32Const(0), // clock id (realtime)
+            I64Const(1), // precision
+            I32Const(0), // time
+            Call(FuncIdx(0)),
+            I32Const(16), // buf
+            I32Const(13), // buf_len
+            Call(FuncIdx(1)),
+        ],
+    );
+    let module_idx = allocate_module(&mut rt, &linker, module).unwrap();
+    call(&mut rt, module_idx, FuncIdx(2)).unwrap();
+
+    assert_eq!(rt.stack.pop_i32().unwrap(), 0); // errno
+    assert_eq!(rt.stack.pop_i32().unwrap(), 0); // errno
+
+    let mem = &rt.store.mems[0].data;
+    let mut time = [0; 8];
+    time.copy_from_slice(&mem[0..8]);
+    assert_ne!(u64::from_le_bytes(time), 0);
+
+    let mut expected = [0; 13];
+    wasi::Rng::new(123).fill(&mut expected);
+    assert_eq!(mem[16..29], expected);
+    assert_eq!(mem[29], 0);
+}
+
+#[test]
+fn load_oob_traps() {
+    assert_eq!(
+        run_trap(vec![
+            I32Const(PAGE_SIZE as i32),
+            I32Load(MemArg {
+                align: 2,
+                offset: 0,
+                mem: 0,
+            }),
+        ]),
+        TrapKind::MemoryOutOfBounds
+    );
+}
+
+#[test]
+fn unreachable_traps() {
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(
+        &mut rt,
+        &Linker::default(),
+        test_module(vec![], vec![I32Const(1), Unreachable, I32Const(2)]),
+    )
+    .unwrap();
+    assert_eq!(
+        call(&mut rt, module_idx, FuncIdx(0)).unwrap_err().kind,
+        TrapKind::Unreachable
+    );
+    assert_eq!(rt.stack.pop_i32().unwrap(), 1);
+    assert!(rt.stack.is_empty());
+    // Frames and blocks of the trapping function are popped
+    assert!(rt.ip.is_empty());
+}
+
+// A module that exports an `add` function of type `[i32, i32] -> [i32]`
+fn add_module() -> parser::Module {
+    parser::Module {
+        types: vec![binop_i32_ty()],
+        funs: vec![fun(
+            0,
+            vec![LocalGet(LocalIdx(0)), LocalGet(LocalIdx(1)), I32Add],
+        )],
+        exports: vec![Export {
+            nm: "add".to_owned(),
+            desc: ExportDesc::Func(FuncIdx(0)),
+        }],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn call_returns_results() {
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(&mut rt, &Linker::default(), add_module()).unwrap();
+    let fun_idx = match rt.get_module(module_idx).exports[0].desc {
+        ExportDesc::Func(fun_idx) => fun_idx,
+        _ => panic!(),
+    };
+
+    rt.stack.push_i32(40);
+    rt.stack.push_i32(2);
+    assert_eq!(call(&mut rt, module_idx, fun_idx), Ok(vec![Value::I32(42)]));
+    assert!(rt.stack.is_empty());
+}
+
+#[test]
+fn invoke() {
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(&mut rt, &Linker::default(), add_module()).unwrap();
+
+    assert_eq!(
+        rt.invoke(module_idx, "add", &[Value::I32(40), Value::I32(2)]),
+        Ok(vec![Value::I32(42)])
+    );
+    assert_eq!(
+        rt.invoke(module_idx, "add", &[Value::I32(40)])
+            .unwrap_err()
+            .kind,
+        TrapKind::ArgumentTypeMismatch
+    );
+    assert_eq!(
+        rt.invoke(module_idx, "add", &[Value::I32(40), Value::I64(2)])
+            .unwrap_err()
+            .kind,
+        TrapKind::ArgumentTypeMismatch
+    );
+    assert_eq!(
+        rt.invoke(module_idx, "sub", &[]).unwrap_err().kind,
+        TrapKind::UnknownExport("sub".to_owned())
+    );
+    assert!(rt.stack.is_empty());
+}
+
+#[test]
+fn get_export() {
+    let module = parser::Module {
+        globals: vec![parser::Global {
+            ty: GlobalType {
+                ty: ValType::I32,
+                mut_: Mutability::Const,
+            },
+            expr: Expr {
+                instrs: vec![I32Const(0)].into(),
+            },
+        }],
+        exports: vec![
+            Export {
+                nm: "add".to_owned(),
+                desc: ExportDesc::Func(FuncIdx(0)),
+            },
+            Export {
+                nm: "g".to_owned(),
+                desc: ExportDesc::Global(GlobalIdx(0)),
+            },
+        ],
+        ..add_module()
+    };
+
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(&mut rt, &Linker::default(), module).unwrap();
+
+    assert_eq!(rt.get_exported_func(module_idx, "add"), Some(FuncIdx(0)));
+    assert_eq!(rt.get_exported_global(module_idx, "g"), Some(GlobalIdx(0)));
+    assert!(rt.get_export(module_idx, "sub").is_none());
+    // Wrong kind
+    assert_eq!(rt.get_exported_func(module_idx, "g"), None);
+}
+
+#[test]
+fn call_stack_exhausted() {
+    // Interpreter frames are large in debug builds, run with a larger stack than the default for
+    // test threads to be able to reach the default max. call depth.
+    let thread = std::thread::Builder::new()
+        .stack_size(64 * 1024 * 1024)
+        .spawn(|| {
+            // A function that calls itself forever
+            let module = parser::Module {
+                types: vec![FuncType {
+                    args: vec![],
+                    ret: vec![],
+                }],
+                funs: vec![fun(0, vec![Call(FuncIdx(0))])],
+                ..Default::default()
+            };
+
+            let mut rt = Runtime::default();
+            let module_idx = allocate_module(&mut rt, &Linker::default(), module).unwrap();
+            assert_eq!(
+                call(&mut rt, module_idx, FuncIdx(0)).unwrap_err().kind,
+                TrapKind::CallStackExhausted
+            );
+            assert_eq!(rt.frames.len(), 0);
+
+            rt.max_call_depth = 10;
+            assert_eq!(
+                call(&mut rt, module_idx, FuncIdx(0)).unwrap_err().kind,
+                TrapKind::CallStackExhausted
+            );
+        })
+        .unwrap();
+    thread.join().unwrap();
+}
+
+// A module with a mutable i32 global and a function that increments the global in an infinite
+// loop. Each iteration executes 5 instructions.
+fn infinite_loop_module() -> parser::Module {
+    parser::Module {
+        types: vec![FuncType {
+            args: vec![],
+            ret: vec![],
+        }],
+        funs: vec![fun(
+            0,
+            vec![Loop(block(vec![
+                GlobalGet(GlobalIdx(0)),
+                I32Const(1),
+                I32Add,
+                GlobalSet(GlobalIdx(0)),
+                Br(0),
+            ]))],
+        )],
+        globals: vec![parser::Global {
+            ty: GlobalType {
+                ty: ValType::I32,
+                mut_: Mutability::Var,
+            },
+            expr: Expr {
+                instrs: vec![I32Const(0)].into(),
+            },
+        }],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn out_of_fuel() {
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(&mut rt, &Linker::default(), infinite_loop_module()).unwrap();
+    // `loop` + 10 iterations
+    rt.add_fuel(51);
+    assert_eq!(
+        call(&mut rt, module_idx, FuncIdx(0)).unwrap_err().kind,
+        TrapKind::OutOfFuel
+    );
+    assert_eq!(rt.fuel(), Some(0));
+    assert_eq!(rt.store.globals[0].value, Value::I32(10));
+}
+
+#[test]
+fn unfueled_runs_to_completion() {
+    let mut rt = run_with_locals(
+        vec![Local {
+            n: 1,
+            ty: ValType::I32,
+        }],
+        vec![
+            I32Const(0),
+            LocalSet(LocalIdx(0)),
+            Loop(block(vec![
+                LocalGet(LocalIdx(0)),
+                I32Const(1),
+                I32Add,
+                LocalTee(LocalIdx(0)),
+                I32Const(1000),
+                I32Lt_s,
+                BrIf(0),
+            ])),
+            LocalGet(LocalIdx(0)),
+        ],
+    );
+    assert_eq!(rt.fuel(), None);
+    assert_eq!(rt.stack.pop_i32().unwrap(), 1000);
+}
+
+#[test]
+fn step() {
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(&mut rt, &Linker::default(), add_module()).unwrap();
+    rt.stack.push_i32(1);
+    rt.stack.push_i32(2);
+    rt.start_call(module_idx, FuncIdx(0)).unwrap();
+    assert!(rt.stack.values().is_empty());
+
+    assert_eq!(rt.step(), Ok(StepResult::Continue));
+    assert_eq!(rt.stack.values(), &[Value::I32(1)]);
+
+    assert_eq!(rt.step(), Ok(StepResult::Continue));
+    assert_eq!(rt.stack.values(), &[Value::I32(1), Value::I32(2)]);
+
+    assert_eq!(rt.step(), Ok(StepResult::Finished));
+    assert_eq!(rt.stack.values(), &[Value::I32(3)]);
+    assert_eq!(rt.frames.len(), 0);
+}
+
+#[test]
+fn run_until_break() {
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(&mut rt, &Linker::default(), add_module()).unwrap();
+    rt.add_breakpoint(module_idx, FuncIdx(0), 2);
+    rt.stack.push_i32(1);
+    rt.stack.push_i32(2);
+    rt.start_call(module_idx, FuncIdx(0)).unwrap();
+
+    // Stops before `i32.add`
+    assert_eq!(rt.run_until_break(), Ok(BreakResult::Breakpoint));
+    assert_eq!(rt.stack.values(), &[Value::I32(1), Value::I32(2)]);
+
+    assert_eq!(rt.run_until_break(), Ok(BreakResult::Finished));
+    assert_eq!(rt.stack.values(), &[Value::I32(3)]);
+}
+
+#[test]
+fn trap_backtrace() {
+    let module = parser::Module {
+        types: vec![FuncType {
+            args: vec![],
+            ret: vec![],
+        }],
+        funs: vec![
+            fun(
+                0,
+                vec![I32Const(0), Drop, Block(block(vec![Call(FuncIdx(1))]))],
+            ),
+            fun(
+                0,
+                vec![
+                    I32Const(-1),
+                    I32Load(MemArg {
+                        align: 0,
+                        offset: 0,
+                        mem: 0,
+                    }),
+                    Drop,
+                ],
+            ),
+        ],
+        mem_addrs: vec![Limits {
+            min: 1,
+            max: None,
+            memory64: false,
+        }],
+        ..Default::default()
+    };
+
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(&mut rt, &Linker::default(), module).unwrap();
+    let trap = call(&mut rt, module_idx, FuncIdx(0)).unwrap_err();
+    assert_eq!(trap.kind, TrapKind::MemoryOutOfBounds);
+    assert_eq!(
+        trap.backtrace,
+        vec![
+            FrameInfo {
+                module_idx,
+                func_idx: FuncIdx(1),
+                func_name: None,
+                instr_offset: 1,
+            },
+            FrameInfo {
+                module_idx,
+                func_idx: FuncIdx(0),
+                func_name: None,
+                // The `call` in the block
+                instr_offset: 3,
+            },
+        ]
+    );
+}
+
+#[test]
+fn table_grow_past_max() {
+    let mut rt = Runtime::default();
+    allocate_module(
+        &mut rt,
+        &Linker::default(),
+        parser::Module {
+            tables: vec![Table {
+                limits: Limits {
+                    min: 1,
+                    max: Some(3),
+                    memory64: false,
+                },
+                elem_type: ElemType::FuncRef,
+            }],
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let table = &mut rt.store.tables[0];
+    assert_eq!(table.grow(2, Some(Ref::Func(FuncAddr(7)))), Some(1));
+    assert_eq!(
+        table.elems,
+        vec![
+            None,
+            Some(Ref::Func(FuncAddr(7))),
+            Some(Ref::Func(FuncAddr(7)))
+        ]
+    );
+    assert_eq!(table.grow(1, None), None);
+    assert_eq!(table.elems.len(), 3);
+    assert_eq!(table.grow(0, None), Some(3));
+}
+
+// `test_module` with a table of two null function references
+fn table_module(instrs: Vec<Instruction>) -> parser::Module {
+    parser::Module {
+        tables: vec![funcref_table(2)],
+        ..test_module(vec![], instrs)
+    }
+}
+
+// A function reference table with `min` elements and no maximum
+fn funcref_table(min: u64) -> Table {
+    Table {
+        limits: Limits {
+            min,
+            max: None,
+            memory64: false,
+        },
+        elem_type: ElemType::FuncRef,
+    }
+}
+
+#[test]
+fn table_get_set() {
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(
+        &mut rt,
+        &Linker::default(),
+        table_module(vec![
+            // Copy element 0 to element 1
+            I32Const(1),
+            I32Const(0),
+            TableGet(0),
+            TableSet(0),
+            I32Const(1),
+            TableGet(0),
+        ]),
+    )
+    .unwrap();
+    rt.store.tables[0].elems[0] = Some(Ref::Func(FuncAddr(0)));
+    call(&mut rt, module_idx, FuncIdx(0)).unwrap();
+    assert_eq!(rt.stack.pop_funcref().unwrap(), Some(FuncAddr(0)));
+    assert_eq!(
+        rt.store.tables[0].elems,
+        vec![Some(Ref::Func(FuncAddr(0))), Some(Ref::Func(FuncAddr(0)))]
+    );
+}
+
+#[test]
+fn table_get_set_oob() {
+    assert_eq!(
+        expect_trap(try_run_module(table_module(vec![I32Const(2), TableGet(0)]))),
+        TrapKind::TableOutOfBounds
+    );
+    assert_eq!(
+        expect_trap(try_run_module(table_module(vec![
+            I32Const(2),
+            I32Const(0),
+            TableGet(0),
+            TableSet(0),
+        ]))),
+        TrapKind::TableOutOfBounds
+    );
+}
+
+#[test]
+fn table_grow_size() {
+    let mut rt = run_module(table_module(vec![
+        TableSize(0),
+        I32Const(0),
+        TableGet(0),
+        I32Const(3),
+        TableGrow(0),
+        TableSize(0),
+    ]));
+    assert_eq!(rt.stack.pop_i32().unwrap(), 5); // size after grow
+    assert_eq!(rt.stack.pop_i32().unwrap(), 2); // grow returns old size
+    assert_eq!(rt.stack.pop_i32().unwrap(), 2); // initial size
+}
+
+#[test]
+fn table_fill() {
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(
+        &mut rt,
+        &Linker::default(),
+        table_module(vec![
+            // Grow to 4 elements with null element 1, fill elements 1 and 2 with element 0
+            I32Const(1),
+            TableGet(0),
+            I32Const(2),
+            TableGrow(0),
+            Drop,
+            I32Const(1),
+            I32Const(0),
+            TableGet(0),
+            I32Const(2),
+            TableFill(0),
+        ]),
+    )
+    .unwrap();
+    rt.store.tables[0].elems[0] = Some(Ref::Func(FuncAddr(0)));
+    call(&mut rt, module_idx, FuncIdx(0)).unwrap();
+    assert_eq!(
+        rt.store.tables[0].elems,
+        vec![
+            Some(Ref::Func(FuncAddr(0))),
+            Some(Ref::Func(FuncAddr(0))),
+            Some(Ref::Func(FuncAddr(0))),
+            None
+        ]
+    );
+}
+
+#[test]
+fn table_fill_oob() {
+    assert_eq!(
+        expect_trap(try_run_module(table_module(vec![
+            I32Const(1),
+            I32Const(0),
+            TableGet(0),
+            I32Const(2),
+            TableFill(0),
+        ]))),
+        TrapKind::TableOutOfBounds
+    );
+}
+
+// A module with a `[] -> []` function 0 with the given body, two more functions, a table with 4
+// elements, and a passive element segment with functions 1 and 2
+fn passive_elem_module(instrs: Vec<Instruction>) -> parser::Module {
+    let mut module = test_module(vec![], instrs);
+    module.funs.extend(vec![fun(0, vec![]), fun(0, vec![])]);
+    parser::Module {
+        tables: vec![funcref_table(4)],
+        elems: vec![Element {
+            mode: ElemMode::Passive,
+            init: vec![FuncIdx(1), FuncIdx(2)],
+        }],
+        ..module
+    }
+}
+
+#[test]
+fn table_init() {
+    let rt = run_module(passive_elem_module(vec![
+        I32Const(1),
+        I32Const(0),
+        I32Const(2),
+        TableInit(0, 0),
+    ]));
+    let func_addrs = &rt.modules[0].func_addrs;
+    assert_eq!(
+        rt.store.tables[0].elems,
+        vec![
+            None,
+            Some(Ref::Func(func_addrs[1])),
+            Some(Ref::Func(func_addrs[2])),
+            None
+        ]
+    );
+}
+
+#[test]
+fn table_init_after_elem_drop() {
+    let trap = expect_trap(try_run_module(passive_elem_module(vec![
+        ElemDrop(0),
+        I32Const(0),
+        I32Const(0),
+        I32Const(1),
+        TableInit(0, 0),
+    ])));
+    assert_eq!(trap, TrapKind::TableOutOfBounds);
+}
+
+#[test]
+fn table_copy_overlapping() {
+    let rt = run_module(passive_elem_module(vec![
+        I32Const(1),
+        I32Const(0),
+        I32Const(2),
+        TableInit(0, 0),
+        // Copy elements 1 and 2 to 2 and 3
+        I32Const(2),
+        I32Const(1),
+        I32Const(2),
+        TableCopy(0, 0),
+    ]));
+    let func_addrs = &rt.modules[0].func_addrs;
+    assert_eq!(
+        rt.store.tables[0].elems,
+        vec![
+            None,
+            Some(Ref::Func(func_addrs[1])),
+            Some(Ref::Func(func_addrs[1])),
+            Some(Ref::Func(func_addrs[2]))
+        ]
+    );
+}
+
+#[test]
+fn declared_locals_are_zero() {
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(
+        &mut rt,
+        &Linker::default(),
+        parser::Module {
+            types: vec![FuncType {
+                args: vec![ValType::I32],
+                ret: vec![ValType::I32, ValType::I64, ValType::F32, ValType::F64],
+            }],
+            funs: vec![Fun {
+                ty: TypeIdx(0),
+                locals: vec![
+                    Local {
+                        n: 1,
+                        ty: ValType::I32,
+                    },
+                    Local {
+                        n: 1,
+                        ty: ValType::I64,
+                    },
+                    Local {
+                        n: 2,
+                        ty: ValType::F32,
+                    },
+                    Local {
+                        n: 1,
+                        ty: ValType::F64,
+                    },
+                ],
+                expr: Expr {
+                    instrs: vec![
+                        LocalGet(LocalIdx(1)),
+                        LocalGet(LocalIdx(2)),
+                        LocalGet(LocalIdx(4)),
+                        LocalGet(LocalIdx(5)),
+                    ]
+                    .into(),
+                },
+            }],
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    rt.stack.push_i32(7);
+    assert_eq!(
+        call(&mut rt, module_idx, FuncIdx(0)),
+        Ok(vec![
+            Value::I32(0),
+            Value::I64(0),
+            Value::F32(0.0),
+            Value::F64(0.0)
+        ])
+    );
+}
+
+#[test]
+fn value_default_for_type() {
+    assert_eq!(Value::default_for_type(ValType::I32), Value::I32(0));
+    assert_eq!(Value::default_for_type(ValType::I64), Value::I64(0));
+    assert_eq!(Value::default_for_type(ValType::F32), Value::F32(0.0));
+    assert_eq!(Value::default_for_type(ValType::F64), Value::F64(0.0));
+    assert_eq!(
+        Value::default_for_type(ValType::FuncRef),
+        Value::FuncRef(None)
+    );
+    assert_eq!(
+        Value::default_for_type(ValType::ExternRef),
+        Value::ExternRef(None)
+    );
+}
+
+#[test]
+fn value_ty() {
+    for ty in [
+        ValType::I32,
+        ValType::I64,
+        ValType::F32,
+        ValType::F64,
+        ValType::FuncRef,
+        ValType::ExternRef,
+    ] {
+        assert_eq!(Value::default_for_type(ty).ty(), ty);
+    }
+    assert_eq!(Value::I64(-1).ty(), ValType::I64);
+    assert_eq!(Value::FuncRef(Some(FuncAddr(0))).ty(), ValType::FuncRef);
+}
+
+// A module with types `[] -> [i32 i32 i32]`, `[] -> [i32 i32]`, and `[i32 i32] -> [i32 i32]`, and
+// function 0 of the first type with the given body
+fn multi_value_module(instrs: Vec<Instruction>) -> parser::Module {
+    parser::Module {
+        types: vec![
+            FuncType {
+                args: vec![],
+                ret: vec![ValType::I32, ValType::I32, ValType::I32],
+            },
+            FuncType {
+                args: vec![],
+                ret: vec![ValType::I32, ValType::I32],
+            },
+            FuncType {
+                args: vec![ValType::I32, ValType::I32],
+                ret: vec![ValType::I32, ValType::I32],
+            },
+        ],
+        ..test_module(vec![], instrs)
+    }
+}
+
+fn call_multi_value_module(instrs: Vec<Instruction>) -> Result<Vec<Value>, Trap> {
+    let mut rt = Runtime::default();
+    let module_idx =
+        allocate_module(&mut rt, &Linker::default(), multi_value_module(instrs)).unwrap();
+    let results = call(&mut rt, module_idx, FuncIdx(0));
+    assert!(rt.stack.is_empty());
+    results
+}
+
+#[test]
+fn multi_value_block() {
+    let results = call_multi_value_module(vec![
+        I32Const(1),
+        Block(Block {
+            ty: BlockType::TypeIdx(TypeIdx(1)),
+            instrs: vec![I32Const(2), I32Const(3)].into(),
+        }),
+    ]);
+    assert_eq!(
+        results,
+        Ok(vec![Value::I32(1), Value::I32(2), Value::I32(3)])
+    );
+}
+
+#[test]
+fn multi_value_br_drops_extra_values() {
+    let results = call_multi_value_module(vec![
+        I32Const(1),
+        Block(Block {
+            ty: BlockType::TypeIdx(TypeIdx(1)),
+            instrs: vec![I32Const(10), I32Const(2), I32Const(3), Br(0), Unreachable].into(),
+        }),
+    ]);
+    assert_eq!(
+        results,
+        Ok(vec![Value::I32(1), Value::I32(2), Value::I32(3)])
+    );
+}
+
+#[test]
+fn multi_value_return_drops_extra_values() {
+    let results = call_multi_value_module(vec![
+        I32Const(10),
+        I32Const(1),
+        Block(block(vec![I32Const(2), I32Const(3), Return])),
+        Unreachable,
+    ]);
+    assert_eq!(
+        results,
+        Ok(vec![Value::I32(1), Value::I32(2), Value::I32(3)])
+    );
+}
+
+#[test]
+fn loop_params() {
+    // sum = 0; i = 0; do { i += 1; sum += i; } while (i < 10), with `sum` threaded through the
+    // loop as its parameter
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(
+        &mut rt,
+        &Linker::default(),
+        parser::Module {
+            types: vec![
+                FuncType {
+                    args: vec![],
+                    ret: vec![ValType::I32],
+                },
+                FuncType {
+                    args: vec![ValType::I32],
+                    ret: vec![ValType::I32],
+                },
+            ],
+            funs: vec![Fun {
+                ty: TypeIdx(0),
+                locals: vec![Local {
+                    n: 1,
+                    ty: ValType::I32,
+                }],
+                expr: Expr {
+                    instrs: vec![
+                        I32Const(0),
+                        Loop(Block {
+                            ty: BlockType::TypeIdx(TypeIdx(1)),
+                            instrs: vec![
+                                LocalGet(LocalIdx(0)),
+                                I32Const(1),
+                                I32Add,
+                                LocalTee(LocalIdx(0)),
+                                I32Add,
+                                LocalGet(LocalIdx(0)),
+                                I32Const(10),
+                                I32Lt_u,
+                                BrIf(0),
+                            ]
+                            .into(),
+                        }),
+                    ]
+                    .into(),
+                },
+            }],
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        call(&mut rt, module_idx, FuncIdx(0)),
+        Ok(vec![Value::I32(55)])
+    );
+    assert!(rt.stack.is_empty());
+}
+
+#[test]
+fn block_params() {
+    // Parameters of a block are dropped when branching out of the block
+    let results = call_multi_value_module(vec![
+        I32Const(1),
+        I32Const(2),
+        I32Const(3),
+        Block(Block {
+            ty: BlockType::TypeIdx(TypeIdx(2)),
+            instrs: vec![I32Add, I32Const(4), I32Const(6), Br(0)].into(),
+        }),
+    ]);
+    assert_eq!(
+        results,
+        Ok(vec![Value::I32(1), Value::I32(4), Value::I32(6)])
+    );
+}
+
+#[test]
+fn br_if_keeps_block_results() {
+    let instrs = |cond| {
+        vec![
+            I32Const(1),
+            Block(Block {
+                ty: BlockType::TypeIdx(TypeIdx(1)),
+                instrs: vec![
+                    I32Const(10),
+                    I32Const(2),
+                    I32Const(3),
+                    I32Const(cond),
+                    BrIf(0),
+                    Drop,
+                    Drop,
+                    Drop,
+                    I32Const(4),
+                    I32Const(5),
+                ]
+                .into(),
+            }),
+        ]
+    };
+    assert_eq!(
+        call_multi_value_module(instrs(1)),
+        Ok(vec![Value::I32(1), Value::I32(2), Value::I32(3)])
+    );
+    assert_eq!(
+        call_multi_value_module(instrs(0)),
+        Ok(vec![Value::I32(1), Value::I32(4), Value::I32(5)])
+    );
+}
+
+#[test]
+fn br_table_keeps_block_results() {
+    // Label 0 is the inner block without results, label 1 is the outer block with two results
+    let instrs = |idx| {
+        vec![
+            I32Const(1),
+            Block(Block {
+                ty: BlockType::TypeIdx(TypeIdx(1)),
+                instrs: vec![
+                    Block(block(vec![
+                        I32Const(10),
+                        I32Const(2),
+                        I32Const(3),
+                        I32Const(idx),
+                        BrTable(parser::types::BrTable {
+                            tbl: vec![0],
+                            def: 1,
+                        }),
+                    ])),
+                    I32Const(4),
+                    I32Const(5),
+                ]
+                .into(),
+            }),
+        ]
+    };
+    assert_eq!(
+        call_multi_value_module(instrs(0)),
+        Ok(vec![Value::I32(1), Value::I32(4), Value::I32(5)])
+    );
+    assert_eq!(
+        call_multi_value_module(instrs(1)),
+        Ok(vec![Value::I32(1), Value::I32(2), Value::I32(3)])
+    );
+}
+
+#[test]
+fn return_from_callee_in_block() {
+    // Returning from a function only exits the blocks of that function
+    let mut module = multi_value_module(vec![
+        Block(Block {
+            ty: BlockType::TypeIdx(TypeIdx(1)),
+            instrs: vec![Call(FuncIdx(1))].into(),
+        }),
+        I32Const(3),
+    ]);
+    module.funs.push(fun(
+        1,
+        vec![
+            Block(block(vec![I32Const(10), I32Const(1), I32Const(2), Return])),
+            Unreachable,
+        ],
+    ));
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(&mut rt, &Linker::default(), module).unwrap();
+    assert_eq!(
+        call(&mut rt, module_idx, FuncIdx(0)),
+        Ok(vec![Value::I32(1), Value::I32(2), Value::I32(3)])
+    );
+    assert!(rt.stack.is_empty());
+}
+
+#[test]
+fn push_pop_refs() {
+    let mut stack = Stack::default();
+    stack.push_funcref(None);
+    stack.push_funcref(Some(FuncAddr(3)));
+    stack.push_externref(None);
+    stack.push_externref(Some(7));
+    assert_eq!(stack.pop_externref().unwrap(), Some(7));
+    assert_eq!(stack.pop_externref().unwrap(), None);
+    assert_eq!(stack.pop_funcref().unwrap(), Some(FuncAddr(3)));
+    assert_eq!(stack.pop_funcref().unwrap(), None);
+    assert!(stack.is_empty());
+}
+
+#[test]
+fn ref_is_null() {
+    assert_eq!(run_i32(vec![RefFunc(FuncIdx(0)), RefIsNull]), 0);
+    assert_eq!(run_i32(vec![RefNull(ValType::FuncRef), RefIsNull]), 1);
+    assert_eq!(run_i32(vec![RefNull(ValType::ExternRef), RefIsNull]), 1);
+}
+
+#[test]
+fn ref_func() {
+    let mut rt = run(vec![RefFunc(FuncIdx(0))]);
+    let fun_addr = rt.modules[0].func_addrs[0];
+    assert_eq!(rt.stack.pop_funcref().unwrap(), Some(fun_addr));
+}
+
+#[test]
+fn trap_backtrace_names() {
+    let module = parser::Module {
+        names: Names {
+            fun_names: vec![(FuncIdx(0), "main".to_owned())].into_iter().collect(),
+            ..Default::default()
+        },
+        ..test_module(vec![], vec![Unreachable])
+    };
+    let trap = try_run_module(module).err().unwrap();
+    assert_eq!(trap.backtrace[0].func_name.as_deref(), Some("main"));
+}
+
+#[test]
+fn nan_canonicalization() {
+    use super::value::{F32_CANONICAL_NAN, F64_CANONICAL_NAN};
+
+    let val = run_f32(vec![F32Const(0.0), F32Const(0.0), F32Div]);
+    assert_eq!(val.to_bits(), F32_CANONICAL_NAN);
+    let val = run_f64(vec![F64Const(-1.0), F64Sqrt]);
+    assert_eq!(val.to_bits(), F64_CANONICAL_NAN);
+
+    // Arithmetic on a NaN with a payload results in a canonical NaN
+    let nan = f32::from_bits(0xFF80_0001);
+    let val = run_f32(vec![F32Const(nan), F32Const(1.0), F32Add]);
+    assert_eq!(val.to_bits(), F32_CANONICAL_NAN);
+    let nan = f64::from_bits(0x7FF0_0000_0000_0001);
+    let val = run_f64(vec![F64Const(nan), F64Const(1.0), F64Min]);
+    assert_eq!(val.to_bits(), F64_CANONICAL_NAN);
+
+    // Sign operations keep the payload
+    let nan = f32::from_bits(0x7F80_0001);
+    assert_eq!(run_f32(vec![F32Const(nan), F32Neg]).to_bits(), 0xFF80_0001);
+    let nan = f64::from_bits(0xFFF0_0000_0000_0001);
+    assert_eq!(
+        run_f64(vec![F64Const(nan), F64Abs]).to_bits(),
+        0x7FF0_0000_0000_0001
+    );
+    let nan = f32::from_bits(0x7F80_0001);
+    let val = run_f32(vec![F32Const(nan), F32Const(-1.0), F32Copysign]);
+    assert_eq!(val.to_bits(), 0xFF80_0001);
+}
+
+// Records log messages of each thread, so that tests running in parallel don't see each other's
+// messages
+struct TestLogger {
+    records: std::sync::Mutex<Vec<(std::thread::ThreadId, String)>>,
+}
+
+impl log::Log for TestLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.records
+            .lock()
+            .unwrap()
+            .push((std::thread::current().id(), record.args().to_string()));
+    }
+
+    fn flush(&self) {}
+}
+
+static TEST_LOGGER: TestLogger = TestLogger {
+    records: std::sync::Mutex::new(vec![]),
+};
+
+fn thread_log_records() -> Vec<String> {
+    let thread = std::thread::current().id();
+    TEST_LOGGER
+        .records
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(thread_, _)| *thread_ == thread)
+        .map(|(_, record)| record.clone())
+        .collect()
+}
+
+// This is the only test that sets the logger and the log level
+#[test]
+fn instruction_trace_logging() {
+    log::set_logger(&TEST_LOGGER).unwrap();
+
+    // Nothing is logged at the default level
+    assert_eq!(log::max_level(), log::LevelFilter::Off);
+    let (_, stdout) = run_hello_world();
+    assert!(thread_log_records().is_empty());
+    assert_eq!(&*stdout.0.borrow(), b"Hello, world!");
+
+    // Instructions are logged at trace level, without polluting the program's stdout
+    log::set_max_level(log::LevelFilter::Trace);
+    let (_, stdout) = run_hello_world();
+    log::set_max_level(log::LevelFilter::Off);
+    let records = thread_log_records();
+    assert!(records
+        .iter()
+        .any(|record| record.contains("Call(FuncIdx(0))")));
+    assert_eq!(&*stdout.0.borrow(), b"Hello, world!");
+}
+
+#[test]
+fn trace_hook() {
+    let events = Rc::new(std::cell::RefCell::new(vec![]));
+    let events_ = events.clone();
+
+    let mut rt = Runtime {
+        trace_hook: Some(Box::new(move |event: &TraceEvent| {
+            events_.borrow_mut().push((
+                event.func_idx,
+                event.ip,
+                format!("{:?}", event.instr),
+                event.stack_depth,
+            ));
+        })),
+        ..Default::default()
+    };
+
+    let module_idx = allocate_module(
+        &mut rt,
+        &Linker::default(),
+        test_module(
+            vec![],
+            vec![
+                I32Const(1),
+                Block(Block {
+                    ty: BlockType::Empty,
+                    instrs: vec![I32Const(2), Drop].into(),
+                }),
+                Drop,
+            ],
+        ),
+    )
+    .unwrap();
+    call(&mut rt, module_idx, FuncIdx(0)).unwrap();
+
+    let events = events.borrow();
+    let events = events
+        .iter()
+        .map(|(func_idx, ip, instr, stack_depth)| {
+            (
+                func_idx.0,
+                *ip,
+                instr.split('(').next().unwrap(),
+                *stack_depth,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        events,
+        vec![
+            (0, 0, "I32Const", 0),
+            (0, 1, "Block", 1),
+            (0, 0, "I32Const", 1),
+            (0, 1, "Drop", 2),
+            (0, 2, "Drop", 1),
+        ]
+    );
+}
+
+#[test]
+fn snapshot_restore() {
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(
+        &mut rt,
+        &Linker::default(),
+        test_module(
+            vec![],
+            vec![
+                I32Const(0),
+                I32Const(0),
+                // mem[0] += 1
+                I32Load(MemArg {
+                    align: 2,
+                    offset: 0,
+                    mem: 0,
+                }),
+                I32Const(1),
+                I32Add,
+                I32Store(MemArg {
+                    align: 2,
+                    offset: 0,
+                    mem: 0,
+                }),
+                // Push mem[0]
+                I32Const(0),
+                I32Load(MemArg {
+                    align: 2,
+                    offset: 0,
+                    mem: 0,
+                }),
+            ],
+        ),
+    )
+    .unwrap();
+    rt.start_call(module_idx, FuncIdx(0)).unwrap();
+    assert_eq!(rt.step(), Ok(StepResult::Continue));
+    assert_eq!(rt.step(), Ok(StepResult::Continue));
+
+    let snapshot = rt.snapshot();
+    assert_eq!(rt.run_until_break(), Ok(BreakResult::Finished));
+    assert_eq!(rt.stack.values(), &[Value::I32(1)]);
+
+    // Memory and the stack are rolled back, so running again gives the same result
+    rt.restore(snapshot.clone());
+    assert_eq!(rt.stack.values(), &[Value::I32(0), Value::I32(0)]);
+    assert_eq!(rt.run_until_break(), Ok(BreakResult::Finished));
+    assert_eq!(rt.stack.values(), &[Value::I32(1)]);
+
+    rt.restore(snapshot);
+    assert_eq!(rt.step(), Ok(StepResult::Continue));
+    assert_eq!(rt.stack.values(), &[Value::I32(0), Value::I32(0)]);
+}
+
+#[test]
+fn read_write_memory() {
+    let mut rt = Runtime::default();
+    let module_idx =
+        allocate_module(&mut rt, &Linker::default(), test_module(vec![], vec![])).unwrap();
+
+    rt.write_memory(module_idx, 100, b"hello").unwrap();
+    assert_eq!(rt.read_memory(module_idx, 100, 5), Ok(&b"hello"[..]));
+    assert_eq!(rt.read_memory(module_idx, 99, 3), Ok(&b"\0he"[..]));
+
+    // Last bytes of the memory
+    let end = PAGE_SIZE as u32;
+    rt.write_memory(module_idx, end - 2, &[1, 2]).unwrap();
+    assert_eq!(rt.read_memory(module_idx, end - 2, 2), Ok(&[1, 2][..]));
+    assert_eq!(rt.read_memory(module_idx, end, 0), Ok(&[][..]));
+
+    assert_eq!(
+        rt.read_memory(module_idx, end - 2, 3),
+        Err(TrapKind::MemoryOutOfBounds.into())
+    );
+    assert_eq!(
+        rt.read_memory(module_idx, u32::MAX, 2),
+        Err(TrapKind::MemoryOutOfBounds.into())
+    );
+    assert_eq!(
+        rt.write_memory(module_idx, end - 1, &[3, 4]),
+        Err(TrapKind::MemoryOutOfBounds.into())
+    );
+    // Failed write doesn't change the memory
+    assert_eq!(rt.read_memory(module_idx, end - 2, 2), Ok(&[1, 2][..]));
+}
+
+#[test]
+fn get_set_global() {
+    let module = parser::Module {
+        globals: vec![
+            parser::Global {
+                ty: GlobalType {
+                    ty: ValType::I32,
+                    mut_: Mutability::Const,
+                },
+                expr: Expr {
+                    instrs: vec![I32Const(10)].into(),
+                },
+            },
+            parser::Global {
+                ty: GlobalType {
+                    ty: ValType::I64,
+                    mut_: Mutability::Var,
+                },
+                expr: Expr {
+                    instrs: vec![I64Const(20)].into(),
+                },
+            },
+        ],
+        exports: vec![
+            Export {
+                nm: "const".to_owned(),
+                desc: ExportDesc::Global(GlobalIdx(0)),
+            },
+            Export {
+                nm: "var".to_owned(),
+                desc: ExportDesc::Global(GlobalIdx(1)),
+            },
+        ],
+        ..test_module(vec![], vec![])
+    };
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(&mut rt, &Linker::default(), module).unwrap();
+
+    assert_eq!(rt.get_global(module_idx, "const"), Some(Value::I32(10)));
+    assert_eq!(rt.get_global(module_idx, "var"), Some(Value::I64(20)));
+    assert_eq!(rt.get_global(module_idx, "missing"), None);
+
+    assert_eq!(rt.set_global(module_idx, "var", Value::I64(30)), Ok(()));
+    assert_eq!(rt.get_global(module_idx, "var"), Some(Value::I64(30)));
+
+    assert_eq!(
+        rt.set_global(module_idx, "const", Value::I32(11)),
+        Err(TrapKind::ImmutableGlobal.into())
+    );
+    assert_eq!(rt.get_global(module_idx, "const"), Some(Value::I32(10)));
+    assert_eq!(
+        rt.set_global(module_idx, "var", Value::I32(30)),
+        Err(TrapKind::ArgumentTypeMismatch.into())
+    );
+    assert_eq!(
+        rt.set_global(module_idx, "missing", Value::I32(30)),
+        Err(TrapKind::UnknownExport("missing".to_owned()).into())
+    );
+}
+
+#[test]
+fn instantiate_modules_in_dependency_order() {
+    let mut rt = Runtime::default();
+    let mut linker = Linker::default();
+
+    // The importing module comes first, so it has to be allocated after the module it imports
+    let module_idxs = instantiate_modules(
+        &mut rt,
+        &mut linker,
+        vec![
+            ("b".to_owned(), host_add_module(binop_i32_ty())),
+            ("env".to_owned(), add_module()),
+        ],
+    )
+    .unwrap();
+
+    assert_eq!(module_idxs, vec![1, 0]);
+    assert_eq!(
+        call(&mut rt, module_idxs[0], FuncIdx(1)),
+        Ok(vec![Value::I32(42)])
+    );
+    assert!(matches!(linker.get("env", "add"), Some(Extern::Func(_))));
+}
+
+#[test]
+fn instantiate_modules_missing_import() {
+    let mut rt = Runtime::default();
+    let result = instantiate_modules(
+        &mut rt,
+        &mut Linker::default(),
+        vec![
+            ("b".to_owned(), host_add_module(binop_i32_ty())),
+            ("env".to_owned(), parser::Module::default()),
+        ],
+    );
+
+    assert_eq!(
+        result,
+        Err(LinkError::MissingImport {
+            module: "env".to_owned(),
+            name: "add".to_owned(),
+        })
+    );
+    assert!(rt.modules.is_empty());
+}
+
+#[test]
+fn instantiate_modules_cycle() {
+    // A module that imports `add` from `import_from` and exports its own `add`
+    let module = |import_from: &str| parser::Module {
+        types: vec![binop_i32_ty()],
+        imports: vec![Import {
+            module: import_from.to_owned(),
+            name: "add".to_owned(),
+            desc: ImportDesc::Func(TypeIdx(0)),
+        }],
+        exports: vec![Export {
+            nm: "add".to_owned(),
+            desc: ExportDesc::Func(FuncIdx(0)),
+        }],
+        ..Default::default()
+    };
+
+    let mut rt = Runtime::default();
+    let result = instantiate_modules(
+        &mut rt,
+        &mut Linker::default(),
+        vec![
+            ("a".to_owned(), module("b")),
+            ("b".to_owned(), module("c")),
+            ("c".to_owned(), module("b")),
+        ],
+    );
+
+    assert_eq!(
+        result,
+        Err(LinkError::Cycle {
+            modules: vec!["b".to_owned(), "c".to_owned()],
+        })
+    );
+}
+
+#[test]
+fn imported_global_segment_offset() {
+    let mut rt = Runtime::default();
+    let mut linker = Linker::default();
+    linker.global(&mut rt, "env", "base", Value::I32(8), false);
+
+    let module = parser::Module {
+        imports: vec![Import {
+            module: "env".to_owned(),
+            name: "base".to_owned(),
+            desc: ImportDesc::Global(GlobalType {
+                ty: ValType::I32,
+                mut_: Mutability::Const,
+            }),
+        }],
+        globals: vec![parser::Global {
+            ty: GlobalType {
+                ty: ValType::I32,
+                mut_: Mutability::Const,
+            },
+            expr: Expr {
+                instrs: vec![GlobalGet(GlobalIdx(0))].into(),
+            },
+        }],
+        data: vec![Data {
+            mode: DataMode::Active {
+                mem: 0,
+                offset: Expr {
+                    instrs: vec![GlobalGet(GlobalIdx(0))].into(),
+                },
+            },
+            init: vec![1, 2, 3, 4],
+        }],
+        exports: vec![Export {
+            nm: "offset".to_owned(),
+            desc: ExportDesc::Global(GlobalIdx(1)),
+        }],
+        ..test_module(vec![], vec![])
+    };
+
+    let module_idx = allocate_module(&mut rt, &linker, module).unwrap();
+    assert_eq!(rt.get_global(module_idx, "offset"), Some(Value::I32(8)));
+    assert_eq!(
+        rt.read_memory(module_idx, 6, 6),
+        Ok(&[0, 0, 1, 2, 3, 4][..])
+    );
+}
+
+// A module with a start function that stores 40 at address 0, and `_start` and `answer` functions
+// that add 2 to it
+fn program_module() -> parser::Module {
+    let store = I32Store(MemArg {
+        align: 2,
+        offset: 0,
+        mem: 0,
+    });
+    let load = I32Load(MemArg {
+        align: 2,
+        offset: 0,
+        mem: 0,
+    });
+    parser::Module {
+        types: vec![
+            FuncType {
+                args: vec![],
+                ret: vec![],
+            },
+            FuncType {
+                args: vec![],
+                ret: vec![ValType::I32],
+            },
+        ],
+        funs: vec![
+            fun(0, vec![I32Const(0), I32Const(40), store.clone()]),
+            fun(
+                0,
+                vec![
+                    I32Const(0),
+                    I32Const(0),
+                    load.clone(),
+                    I32Const(2),
+                    I32Add,
+                    store,
+                ],
+            ),
+            fun(1, vec![I32Const(0), load, I32Const(2), I32Add]),
+        ],
+        mem_addrs: vec![Limits {
+            min: 1,
+            max: None,
+            memory64: false,
+        }],
+        start: Some(FuncIdx(0)),
+        exports: vec![
+            Export {
+                nm: "_start".to_owned(),
+                desc: ExportDesc::Func(FuncIdx(1)),
+            },
+            Export {
+                nm: "answer".to_owned(),
+                desc: ExportDesc::Func(FuncIdx(2)),
+            },
+        ],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn run_program_start() {
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(&mut rt, &Linker::default(), program_module()).unwrap();
+
+    assert_eq!(rt.run_program(module_idx, None, &[]), Ok(vec![]));
+    assert_eq!(
+        rt.read_memory(module_idx, 0, 4),
+        Ok(&42i32.to_le_bytes()[..])
+    );
+}
+
+#[test]
+fn run_program_invoke() {
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(&mut rt, &Linker::default(), program_module()).unwrap();
+
+    assert_eq!(
+        rt.run_program(module_idx, Some("answer"), &[]),
+        Ok(vec![Value::I32(42)])
+    );
+    // `_start` is not called
+    assert_eq!(
+        rt.read_memory(module_idx, 0, 4),
+        Ok(&40i32.to_le_bytes()[..])
+    );
+    assert_eq!(
+        rt.run_program(module_idx, Some("missing"), &[]),
+        Err(TrapKind::UnknownExport("missing".to_owned()).into())
+    );
+}
+
+#[test]
+fn stack_underflow() {
+    assert_eq!(run_trap(vec![I32Add]), TrapKind::StackUnderflow);
+
+    let trap = try_run_module(test_module(vec![], vec![I32Const(1), I32Add]))
+        .err()
+        .unwrap();
+    assert_eq!(trap.kind, TrapKind::StackUnderflow);
+    assert_eq!(trap.backtrace.len(), 1);
+    assert_eq!(trap.backtrace[0].instr_offset, 1);
+}
+
+#[test]
+fn unvalidated_operands_and_labels() {
+    assert_eq!(
+        run_trap(vec![I32Const(1), F32Neg]),
+        TrapKind::OperandTypeMismatch
+    );
+    assert_eq!(
+        run_trap(vec![I32Const(1), RefIsNull]),
+        TrapKind::OperandTypeMismatch
+    );
+    // Label 1 is outside of the function
+    assert_eq!(run_trap(vec![Br(1)]), TrapKind::StackUnderflow);
+
+    // Labels of the caller are not visible to the callee
+    let module = parser::Module {
+        types: vec![FuncType {
+            args: vec![],
+            ret: vec![],
+        }],
+        funs: vec![
+            fun(
+                0,
+                vec![Block(Block {
+                    ty: BlockType::Empty,
+                    instrs: vec![Call(FuncIdx(1))].into(),
+                })],
+            ),
+            fun(0, vec![Br(1)]),
+        ],
+        ..Default::default()
+    };
+    assert_eq!(
+        expect_trap(try_run_module(module)),
+        TrapKind::StackUnderflow
+    );
+}
+
+// A module with tail-recursive countdown functions taking `n` and returning 42 when `n` reaches 0.
+// Function 0 recurses with `return_call`, function 1 with `call`, and function 2 with
+// `return_call_indirect` through table element 0.
+fn countdown_module() -> parser::Module {
+    let countdown = |recurse: Vec<Instruction>| {
+        let mut instrs = vec![
+            LocalGet(LocalIdx(0)),
+            I32Eqz,
+            If(If {
+                ty: BlockType::Empty,
+                then_instrs: vec![I32Const(42), Return].into(),
+                else_instrs: vec![].into(),
+            }),
+            LocalGet(LocalIdx(0)),
+            I32Const(1),
+            I32Sub,
+        ];
+        instrs.extend(recurse);
+        fun(0, instrs)
+    };
+    parser::Module {
+        types: vec![FuncType {
+            args: vec![ValType::I32],
+            ret: vec![ValType::I32],
+        }],
+        funs: vec![
+            countdown(vec![ReturnCall(FuncIdx(0))]),
+            countdown(vec![Call(FuncIdx(1))]),
+            countdown(vec![I32Const(0), ReturnCallIndirect(TypeIdx(0))]),
+        ],
+        tables: vec![funcref_table(1)],
+        elems: vec![Element {
+            mode: ElemMode::Active {
+                table: 0,
+                offset: Expr {
+                    instrs: vec![I32Const(0)].into(),
+                },
+            },
+            init: vec![FuncIdx(2)],
+        }],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn return_call_constant_stack() {
+    let mut rt = Runtime {
+        max_call_depth: 10,
+        ..Default::default()
+    };
+    let module_idx = allocate_module(&mut rt, &Linker::default(), countdown_module()).unwrap();
+
+    for fun_idx in [FuncIdx(0), FuncIdx(2)] {
+        rt.stack.push_i32(100_000);
+        assert_eq!(call(&mut rt, module_idx, fun_idx), Ok(vec![Value::I32(42)]));
+        assert!(rt.stack.is_empty());
+        assert_eq!(rt.frames.len(), 0);
+    }
+
+    // The same recursion with `call` runs out of frames
+    rt.stack.push_i32(100_000);
+    assert_eq!(
+        call(&mut rt, module_idx, FuncIdx(1)).unwrap_err().kind,
+        TrapKind::CallStackExhausted
+    );
+}
+
+#[test]
+fn return_call_host_function() {
+    let mut rt = Runtime::default();
+    let linker = host_add_linker(&mut rt);
+    let module = parser::Module {
+        types: vec![binop_i32_ty()],
+        imports: vec![Import {
+            module: "env".to_owned(),
+            name: "add".to_owned(),
+            desc: ImportDesc::Func(TypeIdx(0)),
+        }],
+        funs: vec![fun(
+            0,
+            vec![
+                LocalGet(LocalIdx(0)),
+                LocalGet(LocalIdx(1)),
+                ReturnCall(FuncIdx(0)),
+                Unreachable,
+            ],
+        )],
+        ..Default::default()
+    };
+    let module_idx = allocate_module(&mut rt, &linker, module).unwrap();
+
+    rt.stack.push_i32(40);
+    rt.stack.push_i32(2);
+    assert_eq!(
+        call(&mut rt, module_idx, FuncIdx(1)),
+        Ok(vec![Value::I32(42)])
+    );
+    assert!(rt.stack.is_empty());
+}
+
+#[test]
+fn multiple_memories() {
+    let memarg = |mem| MemArg {
+        align: 2,
+        offset: 0,
+        mem,
+    };
+    let module = parser::Module {
+        mem_addrs: vec![
+            Limits {
+                min: 1,
+                max: None,
+                memory64: false,
+            },
+            Limits {
+                min: 1,
+                max: None,
+                memory64: false,
+            },
+        ],
+        ..test_module(
+            vec![],
+            vec![
+                I32Const(8),
+                I32Const(1),
+                I32Store(memarg(0)),
+                I32Const(8),
+                I32Const(2),
+                I32Store(memarg(1)),
+                I32Const(8),
+                I32Load(memarg(0)),
+                I32Const(8),
+                I32Load(memarg(1)),
+            ],
+        )
+    };
+
+    let mut rt = run_module(module);
+    assert_eq!(rt.stack.pop_i32().unwrap(), 2);
+    assert_eq!(rt.stack.pop_i32().unwrap(), 1);
+    let mem_addrs = rt.modules[0].mem_addrs.clone();
+    assert_eq!(mem_addrs.len(), 2);
+    assert_eq!(rt.store.mems[mem_addrs[0]].data[8], 1);
+    assert_eq!(rt.store.mems[mem_addrs[1]].data[8], 2);
+}
+
+#[test]
+fn imported_memory_address() {
+    // The imported memory is at address 1, while the importing module is module 0
+    let mut rt = Runtime::default();
+    let mut linker = Linker::default();
+    linker.memory(
+        &mut rt,
+        "a",
+        "unused",
+        Limits {
+            min: 1,
+            max: None,
+            memory64: false,
+        },
+    );
+    linker.memory(
+        &mut rt,
+        "a",
+        "mem",
+        Limits {
+            min: 1,
+            max: None,
+            memory64: false,
+        },
+    );
+
+    let module_idx = allocate_module(
+        &mut rt,
+        &linker,
+        mem_import_module(Limits {
+            min: 1,
+            max: None,
+            memory64: false,
+        }),
+    )
+    .unwrap();
+    assert_eq!(module_idx, 0);
+    assert_eq!(rt.modules[module_idx].mem_addrs, vec![MemAddr(1)]);
+
+    call(&mut rt, module_idx, FuncIdx(0)).unwrap();
+    assert_eq!(rt.store.mems[MemAddr(0)].data[8], 0);
+    assert_eq!(rt.store.mems[MemAddr(1)].data[8], 42);
+    assert_eq!(rt.read_memory(module_idx, 8, 1), Ok(&[42][..]));
+}
+
+#[test]
+fn oversized_limits() {
+    // Modules are not validated here, allocation fails instead of aborting
+    let limits = |min, max, memory64| Limits { min, max, memory64 };
+    for limits in [
+        limits(0xFFFF_FFFF, None, false),
+        limits(1 << 40, None, true),
+        limits(1 << 48, None, true),
+    ] {
+        let module = parser::Module {
+            mem_addrs: vec![limits],
+            ..test_module(vec![], vec![])
+        };
+        assert_eq!(
+            allocate_module(&mut Runtime::default(), &Linker::default(), module),
+            Err(InstantiationError::OutOfMemory)
+        );
+    }
+
+    let module = parser::Module {
+        tables: vec![funcref_table(u64::MAX)],
+        ..test_module(vec![], vec![])
+    };
+    assert_eq!(
+        allocate_module(&mut Runtime::default(), &Linker::default(), module),
+        Err(InstantiationError::OutOfMemory)
+    );
+
+    // Growing fails when the memory can't be allocated
+    let mut rt = run_module(parser::Module {
+        mem_addrs: vec![limits(1, Some(1 << 48), true)],
+        ..test_module(vec![], vec![I64Const(1 << 40), MemoryGrow, MemorySize])
+    });
+    assert_eq!(rt.stack.pop_i64().unwrap(), 1);
+    assert_eq!(rt.stack.pop_i64().unwrap(), -1);
+}
+
+#[test]
+fn memory64_load_store() {
+    let memarg = |offset| MemArg {
+        align: 3,
+        offset,
+        mem: 0,
+    };
+    let mem64_module = |instrs| parser::Module {
+        mem_addrs: vec![Limits {
+            min: 1,
+            max: None,
+            memory64: true,
+        }],
+        ..test_module(vec![], instrs)
+    };
+    let mut rt = run_module(mem64_module(vec![
+        // Address computed with i64 arithmetic
+        I64Const(2),
+        I64Const(4),
+        I64Mul,
+        I64Const(0x0102_0304_0506_0708),
+        I64Store(memarg(8)),
+        I64Const(16),
+        I64Load(memarg(0)),
+        MemorySize,
+        I64Const(1),
+        MemoryGrow,
+    ]));
+    assert_eq!(rt.stack.pop_i64().unwrap(), 1); // grow returns old size
+    assert_eq!(rt.stack.pop_i64().unwrap(), 1); // size
+    assert_eq!(rt.stack.pop_i64().unwrap(), 0x0102_0304_0506_0708);
+
+    // Addresses beyond 4GiB don't wrap around
+    for (addr, offset) in [(0x1_0000_0000, 0), (-8, u64::MAX)] {
+        assert_eq!(
+            expect_trap(try_run_module(mem64_module(vec![
+                I64Const(addr),
+                I64Load(memarg(offset))
+            ]))),
+            TrapKind::MemoryOutOfBounds
+        );
+    }
+}
+
+#[test]
+fn trap_message() {
+    // Function 0 calls function 1, which stores out of bounds
+    let mut module = test_module(vec![], vec![Call(FuncIdx(1))]);
+    module.funs.push(fun(
+        0,
+        vec![
+            I32Const(PAGE_SIZE as i32),
+            I32Const(1),
+            I32Store(MemArg {
+                align: 2,
+                offset: 0,
+                mem: 0,
+            }),
+        ],
+    ));
+    let trap = try_run_module(module).err().unwrap();
+    assert_eq!(
+        trap.to_string(),
+        "trap: out of bounds memory access at func 1 instr 2"
+    );
+
+    assert_eq!(
+        Trap::from(TrapKind::Unreachable).to_string(),
+        "trap: unreachable"
+    );
+}
+
+#[test]
+fn trap_message_nested_blocks() {
+    // The offset counts the instructions of nested blocks, and the instructions of the `then`
+    // branch before the `else` branch
+    let trap = try_run_module(test_module(
+        vec![],
+        vec![
+            I32Const(0),
+            if_(
+                vec![Nop, Nop],
+                vec![Loop(block(vec![Block(block(vec![
+                    I32Const(PAGE_SIZE as i32),
+                    I32Const(1),
+                    I32Store(MemArg {
+                        align: 2,
+                        offset: 0,
+                        mem: 0,
+                    }),
+                ]))]))],
+            ),
+        ],
+    ))
+    .err()
+    .unwrap();
+    assert_eq!(
+        trap.to_string(),
+        "trap: out of bounds memory access at func 0 instr 8"
+    );
+}
+
+#[test]
+fn memory_grow_zeroes_pages() {
+    let memarg = |align| MemArg {
+        align,
+        offset: 0,
+        mem: 0,
+    };
+    let page_size = PAGE_SIZE as i32;
+    let mut rt = run_module(test_module(
+        vec![],
+        vec![
+            // Write up to the end of the first page
+            I32Const(page_size - 8),
+            I64Const(-1),
+            I64Store(memarg(3)),
+            I32Const(2),
+            MemoryGrow,
+            Drop,
+            I32Const(page_size - 8),
+            I64Load(memarg(3)),
+            I32Const(page_size),
+            I64Load(memarg(3)),
+            I32Const(page_size * 2 - 4),
+            I32Load(memarg(2)),
+            I32Const(page_size * 3 - 8),
+            I64Load(memarg(3)),
+        ],
+    ));
+    assert_eq!(rt.stack.pop_i64().unwrap(), 0);
+    assert_eq!(rt.stack.pop_i32().unwrap(), 0);
+    assert_eq!(rt.stack.pop_i64().unwrap(), 0);
+    assert_eq!(rt.stack.pop_i64().unwrap(), -1);
+    let mem = &rt.store.mems[0].data;
+    assert_eq!(mem.len(), 3 * PAGE_SIZE);
+    assert!(mem[PAGE_SIZE..].iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn externref_table() {
+    let module = parser::Module {
+        tables: vec![Table {
+            limits: Limits {
+                min: 1,
+                max: None,
+                memory64: false,
+            },
+            elem_type: ElemType::ExternRef,
+        }],
+        ..test_module(vec![], vec![I32Const(0), TableGet(0)])
+    };
+    let mut rt = run_module(module);
+    assert_eq!(rt.stack.pop_value().unwrap(), Value::ExternRef(None));
+
+    // Extern references are stored as extern references, and can be copied to funcref slots only
+    // in programs that are not validated
+    let module = parser::Module {
+        tables: vec![
+            Table {
+                limits: Limits {
+                    min: 2,
+                    max: None,
+                    memory64: false,
+                },
+                elem_type: ElemType::ExternRef,
+            },
+            funcref_table(1),
+        ],
+        ..test_module(
+            vec![],
+            vec![
+                I32Const(1),
+                I32Const(0),
+                TableGet(0),
+                TableSet(0),
+                I32Const(0),
+                I32Const(0),
+                I32Const(1),
+                TableCopy(1, 0),
+                I32Const(0),
+                CallIndirect(TypeIdx(0)),
+            ],
+        )
+    };
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(&mut rt, &Linker::default(), module).unwrap();
+    rt.store.tables[0].elems[0] = Some(Ref::Extern(5));
+    let trap = call(&mut rt, module_idx, FuncIdx(0)).err().unwrap();
+    assert_eq!(trap.kind, TrapKind::IndirectCallTypeMismatch);
+    assert_eq!(rt.store.tables[0].elems, vec![Some(Ref::Extern(5)); 2]);
+    assert_eq!(rt.store.tables[1].elems, vec![Some(Ref::Extern(5))]);
+
+    // Indirect calls through externref tables are rejected by validation, and trap in programs
+    // that are not validated
+    let module = parser::Module {
+        tables: vec![Table {
+            limits: Limits {
+                min: 1,
+                max: None,
+                memory64: false,
+            },
+            elem_type: ElemType::ExternRef,
+        }],
+        ..test_module(vec![], vec![I32Const(0), CallIndirect(TypeIdx(0))])
+    };
+    assert_eq!(
+        expect_trap(try_run_module(module)),
+        TrapKind::IndirectCallTypeMismatch
+    );
+}
+
+#[test]
+fn host_func_trap() {
+    let mut rt = Runtime::default();
+    let mut linker = Linker::default();
+    linker.func(&mut rt, "env", "add", binop_i32_ty(), |_rt, _args| {
+        Err(TrapKind::Exit(3).into())
+    });
+    let module_idx = allocate_module(&mut rt, &linker, host_add_module(binop_i32_ty())).unwrap();
+    let trap = call(&mut rt, module_idx, FuncIdx(1)).err().unwrap();
+    assert_eq!(trap.kind, TrapKind::Exit(3));
+    // The trap has the location of the `call` of the host function
+    assert_eq!(trap.backtrace.len(), 1);
+    assert_eq!(trap.backtrace[0].func_idx, FuncIdx(1));
+    assert_eq!(trap.backtrace[0].instr_offset, 2);
+}
+
+#[test]
+fn reset() {
+    let memarg = || MemArg {
+        align: 2,
+        offset: 0,
+        mem: 0,
+    };
+    // Increments a global and a memory cell, grows the memory, and returns the sum of the global,
+    // the memory cell, and the memory size
+    let module = parser::Module {
+        types: vec![FuncType {
+            args: vec![],
+            ret: vec![ValType::I32],
+        }],
+        globals: vec![parser::Global {
+            ty: GlobalType {
+                ty: ValType::I32,
+                mut_: Mutability::Var,
+            },
+            expr: Expr {
+                instrs: vec![I32Const(10)].into(),
+            },
+        }],
+        mem_addrs: vec![Limits {
+            min: 1,
+            max: None,
+            memory64: false,
+        }],
+        data: vec![Data {
+            init: vec![20, 0, 0, 0],
+            mode: DataMode::Active {
+                mem: 0,
+                offset: Expr {
+                    instrs: vec![I32Const(0)].into(),
+                },
+            },
+        }],
+        funs: vec![fun(
+            0,
+            vec![
+                GlobalGet(GlobalIdx(0)),
+                I32Const(1),
+                I32Add,
+                GlobalSet(GlobalIdx(0)),
+                I32Const(0),
+                I32Const(0),
+                I32Load(memarg()),
+                I32Const(1),
+                I32Add,
+                I32Store(memarg()),
+                I32Const(1),
+                MemoryGrow,
+                Drop,
+                GlobalGet(GlobalIdx(0)),
+                I32Const(0),
+                I32Load(memarg()),
+                I32Add,
+                MemorySize,
+                I32Add,
+            ],
+        )],
+        ..Default::default()
+    };
+
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(&mut rt, &Linker::default(), module).unwrap();
+    let snapshot = rt.snapshot();
+    assert_eq!(
+        call(&mut rt, module_idx, FuncIdx(0)),
+        Ok(vec![Value::I32(11 + 21 + 2)])
+    );
+    assert_eq!(
+        call(&mut rt, module_idx, FuncIdx(0)),
+        Ok(vec![Value::I32(12 + 22 + 3)])
+    );
+
+    // The snapshot can be used more than once
+    for _ in 0..2 {
+        rt.reset(&snapshot);
+        assert_eq!(rt.store.mems[0].data.len(), PAGE_SIZE);
+        assert_eq!(
+            call(&mut rt, module_idx, FuncIdx(0)),
+            Ok(vec![Value::I32(11 + 21 + 2)])
+        );
+    }
+
+    // Values left on the stack are dropped
+    rt.stack.push_i32(1);
+    rt.reset(&snapshot);
+    assert!(rt.stack.is_empty());
+}
+
+#[test]
+fn call_imported_funcs_by_index() {
+    let mut rt = Runtime::default();
+    let mut linker = host_add_linker(&mut rt);
+    linker.func(
+        &mut rt,
+        "env",
+        "sub",
+        binop_i32_ty(),
+        |_rt, args| match args {
+            [Value::I32(a), Value::I32(b)] => Ok(vec![Value::I32(a.wrapping_sub(*b))]),
+            _ => panic!("sub: unexpected arguments: {:?}", args),
+        },
+    );
+
+    // Imported functions come before the module's own functions in the function index space
+    let import = |name: &str| Import {
+        module: "env".to_owned(),
+        name: name.to_owned(),
+        desc: ImportDesc::Func(TypeIdx(0)),
+    };
+    let module = parser::Module {
+        types: vec![
+            binop_i32_ty(),
+            FuncType {
+                args: vec![],
+                ret: vec![ValType::I32],
+            },
+        ],
+        imports: vec![import("sub"), import("add")],
+        funs: vec![
+            fun(
+                1,
+                vec![
+                    I32Const(40),
+                    I32Const(5),
+                    Call(FuncIdx(1)),
+                    I32Const(3),
+                    Call(FuncIdx(0)),
+                ],
+            ),
+            fun(1, vec![Call(FuncIdx(2))]),
+        ],
+        ..Default::default()
+    };
+    let module_idx = allocate_module(&mut rt, &linker, module).unwrap();
+    assert_eq!(
+        call(&mut rt, module_idx, FuncIdx(3)),
+        Ok(vec![Value::I32(42)])
+    );
+    assert!(rt.stack.is_empty());
+}
+
+#[test]
+fn nop_demote_promote() {
+    use super::value::{F32_CANONICAL_NAN, F64_CANONICAL_NAN};
+
+    assert_eq!(run_f32(vec![Nop, F32Const(1.5), Nop]), 1.5);
+
+    assert_eq!(run_f32(vec![F64Const(1.5), F32Demotef64]), 1.5);
+    assert_eq!(run_f32(vec![F64Const(1e40), F32Demotef64]), f32::INFINITY);
+    assert_eq!(run_f64(vec![F32Const(-2.25), F64Promotef32]), -2.25);
+
+    let nan = f64::from_bits(0x7FF0_0000_0000_0001);
+    let val = run_f32(vec![F64Const(nan), F32Demotef64]);
+    assert_eq!(val.to_bits(), F32_CANONICAL_NAN);
+    let nan = f32::from_bits(0x7F80_0001);
+    let val = run_f64(vec![F32Const(nan), F64Promotef32]);
+    assert_eq!(val.to_bits(), F64_CANONICAL_NAN);
+}
+
+#[test]
+fn invalid_const_expr() {
+    let global = |instrs: Vec<Instruction>| parser::Global {
+        ty: GlobalType {
+            ty: ValType::I32,
+            mut_: Mutability::Const,
+        },
+        expr: Expr {
+            instrs: instrs.into(),
+        },
+    };
+    for instrs in &[
+        vec![I32Const(1), I32Const(2), I32Add],
+        vec![I64Const(1)],
+        vec![GlobalGet(GlobalIdx(0))],
+    ] {
+        let module = parser::Module {
+            globals: vec![global(instrs.clone())],
+            ..Default::default()
+        };
+        let mut rt = Runtime::default();
+        assert_eq!(
+            allocate_module(&mut rt, &Linker::default(), module),
+            Err(InstantiationError::InvalidConstExpr)
+        );
+    }
+}
+
+#[test]
+fn instantiate_modules_import_type_mismatch() {
+    let mut rt = Runtime::default();
+    let mut linker = host_add_linker(&mut rt);
+    let import_ty = FuncType {
+        args: vec![ValType::I32],
+        ret: vec![ValType::I32],
+    };
+    let result = instantiate_modules(
+        &mut rt,
+        &mut linker,
+        vec![("b".to_owned(), host_add_module(import_ty))],
+    );
+
+    assert_eq!(
+        result,
+        Err(LinkError::Instantiation {
+            module: "b".to_owned(),
+            error: InstantiationError::ImportTypeMismatch {
+                module: "env".to_owned(),
+                name: "add".to_owned(),
+            },
+        })
+    );
+}
@@ -0,0 +1,120 @@
+use super::store::ModuleIdx;
+use crate::parser::FuncIdx;
+
+use std::fmt;
+
+/// Aborted execution of a wasm program. Traps unwind all the way up to the caller of `call`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Trap {
+    pub kind: TrapKind,
+    /// Wasm functions on the call stack when the trap occurred, innermost first. Empty when the
+    /// trap occurred outside of wasm functions.
+    pub backtrace: Vec<FrameInfo>,
+}
+
+/// A wasm function in a trap backtrace
+#[derive(Debug, PartialEq, Eq)]
+pub struct FrameInfo {
+    pub module_idx: ModuleIdx,
+    pub func_idx: FuncIdx,
+    /// Name of the function in the module's name section
+    pub func_name: Option<String>,
+    /// Offset of the executed instruction in the function body. Instructions of nested blocks are
+    /// counted in order, with the `then` branch of an `if` before its `else` branch. Block ends and
+    /// `else` are not counted.
+    pub instr_offset: u32,
+}
+
+/// Reasons for traps
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrapKind {
+    /// The program called WASI `proc_exit` with the given exit code
+    Exit(i32),
+    /// `unreachable` instruction was executed
+    Unreachable,
+    /// A load, store, or bulk memory instruction accessed memory out of bounds
+    MemoryOutOfBounds,
+    /// Float to integer conversion of a value that is too large or small for the target type, or
+    /// signed division of the minimum integer by -1
+    IntegerOverflow,
+    /// Integer division or remainder by zero
+    IntegerDivideByZero,
+    /// Float to integer conversion of a NaN
+    InvalidConversionToInteger,
+    /// A table instruction accessed a table out of bounds
+    TableOutOfBounds,
+    /// `call_indirect` with a table index out of bounds
+    UndefinedElement,
+    /// `call_indirect` with a table element that has not been initialized
+    UninitializedElement,
+    /// `call_indirect` with a function of unexpected type
+    IndirectCallTypeMismatch,
+    /// Call depth exceeded `Runtime::max_call_depth`
+    CallStackExhausted,
+    /// Fuel added with `Runtime::add_fuel` ran out
+    OutOfFuel,
+    /// `Runtime::invoke` or `Runtime::set_global` with a name that is not an exported function or
+    /// global
+    UnknownExport(String),
+    /// `Runtime::invoke` with arguments that don't match the function's type, or
+    /// `Runtime::set_global` with a value that doesn't match the global's type
+    ArgumentTypeMismatch,
+    /// `Runtime::set_global` with an immutable global
+    ImmutableGlobal,
+    /// An instruction popped a value from an empty stack, or branched to a label outside of the
+    /// current function. Only happens in programs that are not validated.
+    StackUnderflow,
+    /// An instruction popped a value of an unexpected type. Only happens in programs that are not
+    /// validated.
+    OperandTypeMismatch,
+}
+
+/// Formats the trap kind followed by the location of the innermost wasm function, e.g. "trap: out
+/// of bounds memory access at func 3 instr 12"
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "trap: {}", self.kind)?;
+        if let Some(frame) = self.backtrace.first() {
+            write!(
+                f,
+                " at func {} instr {}",
+                frame.func_idx, frame.instr_offset
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Messages of the traps in the spec are the same as the reference interpreter's
+impl fmt::Display for TrapKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrapKind::Exit(code) => write!(f, "exit with code {}", code),
+            TrapKind::Unreachable => write!(f, "unreachable"),
+            TrapKind::MemoryOutOfBounds => write!(f, "out of bounds memory access"),
+            TrapKind::IntegerOverflow => write!(f, "integer overflow"),
+            TrapKind::IntegerDivideByZero => write!(f, "integer divide by zero"),
+            TrapKind::InvalidConversionToInteger => write!(f, "invalid conversion to integer"),
+            TrapKind::TableOutOfBounds => write!(f, "out of bounds table access"),
+            TrapKind::UndefinedElement => write!(f, "undefined element"),
+            TrapKind::UninitializedElement => write!(f, "uninitialized element"),
+            TrapKind::IndirectCallTypeMismatch => write!(f, "indirect call type mismatch"),
+            TrapKind::CallStackExhausted => write!(f, "call stack exhausted"),
+            TrapKind::OutOfFuel => write!(f, "out of fuel"),
+            TrapKind::UnknownExport(name) => write!(f, "unknown export {:?}", name),
+            TrapKind::ArgumentTypeMismatch => write!(f, "argument type mismatch"),
+            TrapKind::ImmutableGlobal => write!(f, "immutable global"),
+            TrapKind::StackUnderflow => write!(f, "stack underflow"),
+            TrapKind::OperandTypeMismatch => write!(f, "operand type mismatch"),
+        }
+    }
+}
+
+impl From<TrapKind> for Trap {
+    fn from(kind: TrapKind) -> Self {
+        Trap {
+            kind,
+            backtrace: vec![],
+        }
+    }
+}
@@ -1,8 +1,67 @@
-#[derive(Debug, Clone, Copy)]
+use super::store::FuncAddr;
+use crate::parser::ValType;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Value {
     I32(i32),
     I64(i64),
     F32(f32),
     F64(f64),
-    Uninitialized, // TODO: I don't remember why this was needed
+    FuncRef(Option<FuncAddr>), // `None` for null
+    ExternRef(Option<u32>),    // `None` for null
+}
+
+impl Value {
+    /// Zero value of the type, or null for references
+    pub fn default_for_type(ty: ValType) -> Value {
+        match ty {
+            ValType::I32 => Value::I32(0),
+            ValType::I64 => Value::I64(0),
+            ValType::F32 => Value::F32(0.0),
+            ValType::F64 => Value::F64(0.0),
+            ValType::FuncRef => Value::FuncRef(None),
+            ValType::ExternRef => Value::ExternRef(None),
+        }
+    }
+
+    pub fn ty(&self) -> ValType {
+        match self {
+            Value::I32(_) => ValType::I32,
+            Value::I64(_) => ValType::I64,
+            Value::F32(_) => ValType::F32,
+            Value::F64(_) => ValType::F64,
+            Value::FuncRef(_) => ValType::FuncRef,
+            Value::ExternRef(_) => ValType::ExternRef,
+        }
+    }
+}
+
+// NaN results of float arithmetic are canonical NaNs: a quiet NaN with an empty payload. The spec
+// also allows other quiet NaNs when an operand is a non-canonical NaN, but canonical NaNs are
+// always valid and make results deterministic. See
+// https://webassembly.github.io/spec/core/exec/numerics.html#nan-propagation
+//
+// Operations that only change the sign bit (`abs`, `neg`, `copysign`) keep the payload and should
+// not be canonicalized.
+
+/// Canonical f32 NaN with a positive sign
+pub const F32_CANONICAL_NAN: u32 = 0x7FC0_0000;
+
+/// Canonical f64 NaN with a positive sign
+pub const F64_CANONICAL_NAN: u64 = 0x7FF8_0000_0000_0000;
+
+pub fn canonicalize_f32(val: f32) -> f32 {
+    if val.is_nan() {
+        f32::from_bits(F32_CANONICAL_NAN)
+    } else {
+        val
+    }
+}
+
+pub fn canonicalize_f64(val: f64) -> f64 {
+    if val.is_nan() {
+        f64::from_bits(F64_CANONICAL_NAN)
+    } else {
+        val
+    }
 }
@@ -0,0 +1,411 @@
+// A minimal implementation of `wasi_snapshot_preview1` host functions.
+//
+// https://github.com/WebAssembly/WASI/blob/main/legacy/preview1/docs.md
+
+use super::store::MemAddr;
+use super::value::Value;
+use super::{Linker, Runtime, TrapKind};
+use crate::parser::{FuncType, ValType};
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+const MODULE: &str = "wasi_snapshot_preview1";
+
+// Error codes returned by WASI functions
+const ERRNO_SUCCESS: i32 = 0;
+const ERRNO_BADF: i32 = 8;
+const ERRNO_FAULT: i32 = 21;
+const ERRNO_INVAL: i32 = 28;
+const ERRNO_IO: i32 = 29;
+
+/// State of the WASI host functions.
+pub struct WasiCtx {
+    pub stdout: Box<dyn Write>,
+    pub stderr: Box<dyn Write>,
+    /// Command line arguments of the program, including the program name
+    pub args: Vec<String>,
+    /// Environment variables of the program. Empty by default rather than inherited from the host
+    /// so that programs run deterministically.
+    pub env: Vec<(String, String)>,
+    /// Clock source for `clock_time_get`. Takes a clock id and returns the time in nanoseconds, or
+    /// `None` if the clock is not supported.
+    pub clock: Box<dyn Fn(u32) -> Option<u64>>,
+    /// Random number generator for `random_get`
+    pub rng: Rng,
+}
+
+// Clock ids
+const CLOCK_REALTIME: u32 = 0;
+const CLOCK_MONOTONIC: u32 = 1;
+
+/// A splitmix64 generator. Not cryptographically secure, but deterministic for a given seed, which
+/// is useful for testing.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+fn system_clock() -> Box<dyn Fn(u32) -> Option<u64>> {
+    let start = Instant::now();
+    Box::new(move |clock_id| match clock_id {
+        CLOCK_REALTIME => Some(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()?
+                .as_nanos() as u64,
+        ),
+        CLOCK_MONOTONIC => Some(start.elapsed().as_nanos() as u64),
+        _ => None,
+    })
+}
+
+fn time_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+impl Default for WasiCtx {
+    fn default() -> Self {
+        WasiCtx {
+            stdout: Box::new(io::stdout()),
+            stderr: Box::new(io::stderr()),
+            args: vec![],
+            env: vec![],
+            clock: system_clock(),
+            rng: Rng::new(time_seed()),
+        }
+    }
+}
+
+impl WasiCtx {
+    // Environment variables in `key=value` form
+    fn environ(&self) -> Vec<String> {
+        self.env
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect()
+    }
+}
+
+/// Register WASI host functions in the linker.
+pub fn add_to_linker(linker: &mut Linker, rt: &mut Runtime, ctx: WasiCtx) {
+    let ctx = Rc::new(RefCell::new(ctx));
+
+    let ctx_ = ctx.clone();
+    linker.func(
+        rt,
+        MODULE,
+        "args_sizes_get",
+        fun_ty(&[ValType::I32; 2]),
+        move |rt, args| match args {
+            [Value::I32(argc), Value::I32(argv_buf_size)] => {
+                let errno =
+                    strings_sizes_get(rt, &ctx_.borrow().args, *argc as u32, *argv_buf_size as u32);
+                Ok(vec![Value::I32(errno)])
+            }
+            _ => panic!("args_sizes_get: unexpected arguments: {:?}", args),
+        },
+    );
+
+    let ctx_ = ctx.clone();
+    linker.func(
+        rt,
+        MODULE,
+        "args_get",
+        fun_ty(&[ValType::I32; 2]),
+        move |rt, args| match args {
+            [Value::I32(argv), Value::I32(argv_buf)] => {
+                let errno = strings_get(rt, &ctx_.borrow().args, *argv as u32, *argv_buf as u32);
+                Ok(vec![Value::I32(errno)])
+            }
+            _ => panic!("args_get: unexpected arguments: {:?}", args),
+        },
+    );
+
+    let ctx_ = ctx.clone();
+    linker.func(
+        rt,
+        MODULE,
+        "environ_sizes_get",
+        fun_ty(&[ValType::I32; 2]),
+        move |rt, args| match args {
+            [Value::I32(environc), Value::I32(environ_buf_size)] => {
+                let errno = strings_sizes_get(
+                    rt,
+                    &ctx_.borrow().environ(),
+                    *environc as u32,
+                    *environ_buf_size as u32,
+                );
+                Ok(vec![Value::I32(errno)])
+            }
+            _ => panic!("environ_sizes_get: unexpected arguments: {:?}", args),
+        },
+    );
+
+    let ctx_ = ctx.clone();
+    linker.func(
+        rt,
+        MODULE,
+        "environ_get",
+        fun_ty(&[ValType::I32; 2]),
+        move |rt, args| match args {
+            [Value::I32(environ), Value::I32(environ_buf)] => {
+                let errno = strings_get(
+                    rt,
+                    &ctx_.borrow().environ(),
+                    *environ as u32,
+                    *environ_buf as u32,
+                );
+                Ok(vec![Value::I32(errno)])
+            }
+            _ => panic!("environ_get: unexpected arguments: {:?}", args),
+        },
+    );
+
+    let ctx_ = ctx.clone();
+    linker.func(
+        rt,
+        MODULE,
+        "clock_time_get",
+        fun_ty(&[ValType::I32, ValType::I64, ValType::I32]),
+        move |rt, args| match args {
+            [Value::I32(clock_id), Value::I64(_precision), Value::I32(time)] => {
+                let errno = match (ctx_.borrow().clock)(*clock_id as u32) {
+                    None => ERRNO_INVAL,
+                    Some(nanos) => {
+                        match current_mem_mut(rt)
+                            .and_then(|mem| write_u64(mem, *time as u32, nanos))
+                        {
+                            Some(()) => ERRNO_SUCCESS,
+                            None => ERRNO_FAULT,
+                        }
+                    }
+                };
+                Ok(vec![Value::I32(errno)])
+            }
+            _ => panic!("clock_time_get: unexpected arguments: {:?}", args),
+        },
+    );
+
+    let ctx_ = ctx.clone();
+    linker.func(
+        rt,
+        MODULE,
+        "random_get",
+        fun_ty(&[ValType::I32; 2]),
+        move |rt, args| match args {
+            [Value::I32(buf), Value::I32(buf_len)] => {
+                let (buf, buf_len) = (*buf as u32 as usize, *buf_len as u32 as usize);
+                let errno =
+                    match current_mem_mut(rt).and_then(|mem| mem.get_mut(buf..buf + buf_len)) {
+                        None => ERRNO_FAULT,
+                        Some(bytes) => {
+                            ctx_.borrow_mut().rng.fill(bytes);
+                            ERRNO_SUCCESS
+                        }
+                    };
+                Ok(vec![Value::I32(errno)])
+            }
+            _ => panic!("random_get: unexpected arguments: {:?}", args),
+        },
+    );
+
+    linker.func(
+        rt,
+        MODULE,
+        "fd_write",
+        fun_ty(&[ValType::I32; 4]),
+        move |rt, args| match args {
+            [Value::I32(fd), Value::I32(iovs), Value::I32(iovs_len), Value::I32(nwritten)] => {
+                let errno = fd_write(
+                    rt,
+                    &mut ctx.borrow_mut(),
+                    *fd,
+                    *iovs as u32,
+                    *iovs_len as u32,
+                    *nwritten as u32,
+                );
+                Ok(vec![Value::I32(errno)])
+            }
+            _ => panic!("fd_write: unexpected arguments: {:?}", args),
+        },
+    );
+
+    linker.func(
+        rt,
+        MODULE,
+        "proc_exit",
+        FuncType {
+            args: vec![ValType::I32],
+            ret: vec![],
+        },
+        |_rt, args| match args {
+            [Value::I32(code)] => Err(TrapKind::Exit(*code).into()),
+            _ => panic!("proc_exit: unexpected arguments: {:?}", args),
+        },
+    );
+}
+
+// Type of a WASI function with the given arguments. WASI functions other than `proc_exit` return
+// an errno.
+fn fun_ty(args: &[ValType]) -> FuncType {
+    FuncType {
+        args: args.to_vec(),
+        ret: vec![ValType::I32],
+    }
+}
+
+fn fd_write(
+    rt: &mut Runtime,
+    ctx: &mut WasiCtx,
+    fd: i32,
+    iovs: u32,
+    iovs_len: u32,
+    nwritten: u32,
+) -> i32 {
+    let writer = match fd {
+        1 => &mut ctx.stdout,
+        2 => &mut ctx.stderr,
+        _ => return ERRNO_BADF,
+    };
+
+    let mem = match current_mem(rt) {
+        Some(mem) => mem,
+        None => return ERRNO_FAULT,
+    };
+    let mut total: u32 = 0;
+    for iov_idx in 0..iovs_len {
+        let (buf, buf_len) = match read_iovec(mem, iovs, iov_idx) {
+            Some(iovec) => iovec,
+            None => return ERRNO_FAULT,
+        };
+        let bytes = match mem.get(buf as usize..buf as usize + buf_len as usize) {
+            Some(bytes) => bytes,
+            None => return ERRNO_FAULT,
+        };
+        if writer.write_all(bytes).is_err() {
+            return ERRNO_IO;
+        }
+        total = total.wrapping_add(buf_len);
+    }
+
+    if writer.flush().is_err() {
+        return ERRNO_IO;
+    }
+
+    match current_mem_mut(rt).and_then(|mem| write_u32(mem, nwritten, total)) {
+        Some(()) => ERRNO_SUCCESS,
+        None => ERRNO_FAULT,
+    }
+}
+
+// Read iovec `iov_idx` of the array at `iovs`. Each iovec is a `(buf: u32, buf_len: u32)` pair.
+fn read_iovec(mem: &[u8], iovs: u32, iov_idx: u32) -> Option<(u32, u32)> {
+    let iov = iovs.checked_add(iov_idx.checked_mul(8)?)?;
+    Some((read_u32(mem, iov)?, read_u32(mem, iov.checked_add(4)?)?))
+}
+
+// Write number of strings to `count` and total size of the NUL-terminated strings to `buf_size`.
+// Used for `args_sizes_get` and `environ_sizes_get`.
+fn strings_sizes_get(rt: &mut Runtime, strings: &[String], count: u32, buf_size: u32) -> i32 {
+    let size: usize = strings.iter().map(|s| s.len() + 1).sum();
+    let mem = match current_mem_mut(rt) {
+        Some(mem) => mem,
+        None => return ERRNO_FAULT,
+    };
+    match (
+        write_u32(mem, count, strings.len() as u32),
+        write_u32(mem, buf_size, size as u32),
+    ) {
+        (Some(()), Some(())) => ERRNO_SUCCESS,
+        _ => ERRNO_FAULT,
+    }
+}
+
+// Write the strings NUL-terminated and back-to-back to `buf`, and pointers to the strings to the
+// array at `ptrs`. Used for `args_get` and `environ_get`.
+fn strings_get(rt: &mut Runtime, strings: &[String], ptrs: u32, buf: u32) -> i32 {
+    let mem = match current_mem_mut(rt) {
+        Some(mem) => mem,
+        None => return ERRNO_FAULT,
+    };
+    match write_strings(mem, strings, ptrs, buf) {
+        Some(()) => ERRNO_SUCCESS,
+        None => ERRNO_FAULT,
+    }
+}
+
+fn write_strings(mem: &mut [u8], strings: &[String], ptrs: u32, buf: u32) -> Option<()> {
+    // `usize` so that the pointer after the last string doesn't overflow when the strings end at
+    // the end of a 4 GiB memory
+    let mut ptr = buf as usize;
+    for (i, string) in strings.iter().enumerate() {
+        let ptr_addr = ptrs.checked_add((i as u32).checked_mul(4)?)?;
+        write_u32(mem, ptr_addr, u32::try_from(ptr).ok()?)?;
+        let bytes = mem.get_mut(ptr..ptr + string.len() + 1)?;
+        bytes[..string.len()].copy_from_slice(string.as_bytes());
+        bytes[string.len()] = 0;
+        ptr += string.len() + 1;
+    }
+    Some(())
+}
+
+// Memory of the calling module, or `None` if the module doesn't have a memory. Host functions
+// don't push a frame, so the current frame is the caller's. There is no caller when a host
+// function is called directly, e.g. an imported function re-exported and called with
+// `Runtime::invoke`.
+fn current_mem(rt: &Runtime) -> Option<&[u8]> {
+    let mem_addr = current_mem_addr(rt)?;
+    Some(&rt.store.mems[mem_addr].data)
+}
+
+fn current_mem_mut(rt: &mut Runtime) -> Option<&mut [u8]> {
+    let mem_addr = current_mem_addr(rt)?;
+    Some(&mut rt.store.mems[mem_addr].data)
+}
+
+fn current_mem_addr(rt: &Runtime) -> Option<MemAddr> {
+    let current_module = rt.frames.last()?.module();
+    rt.modules[current_module].mem_addrs.first().copied()
+}
+
+fn read_u32(mem: &[u8], addr: u32) -> Option<u32> {
+    let bytes = mem.get(addr as usize..addr as usize + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn write_u32(mem: &mut [u8], addr: u32, value: u32) -> Option<()> {
+    let bytes = mem.get_mut(addr as usize..addr as usize + 4)?;
+    bytes.copy_from_slice(&value.to_le_bytes());
+    Some(())
+}
+
+fn write_u64(mem: &mut [u8], addr: u32, value: u64) -> Option<()> {
+    let bytes = mem.get_mut(addr as usize..addr as usize + 8)?;
+    bytes.copy_from_slice(&value.to_le_bytes());
+    Some(())
+}
@@ -0,0 +1,20 @@
+// NOTE Index vs. address
+// ~~~~~~~~~~~~~~~~~~~~~~
+//
+// Indices are module-local, e.g. "function 5" doesn't make sense in a program, "function 5 in
+// module 10" makes sense.
+//
+// Addresses are indices in heap, rather than module, and global. (i.e. no two function live at the
+// same address, but they may have same indices in their own modules)
+//
+// Addresses are newtypes (`FuncAddr`, `MemAddr`, ...) defined in `exec::store`. Type, function,
+// global, and local indices are newtypes (`TypeIdx`, `FuncIdx`, ...) defined in `parser::types`,
+// so these can't be mixed with each other or with addresses. Other indices are still `u32`s.
+
+#![feature(backtrace, or_patterns)]
+
+pub mod exec;
+pub mod parser;
+#[cfg(test)]
+mod spec;
+pub mod validate;
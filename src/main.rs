@@ -1,78 +1,76 @@
-// NOTE Index vs. address
-// ~~~~~~~~~~~~~~~~~~~~~~
-//
-// Indices are module-local, e.g. "function 5" doesn't make sense in a program, "function 5 in
-// module 10" makes sense.
-//
-// Addresses are indices in heap, rather than module, and global. (i.e. no two function live at the
-// same address, but they may have same indices in their own modules)
-//
-// TODO: We should introduce newtypes for these.
+mod cli;
 
-#![feature(backtrace, or_patterns)]
-
-mod exec;
-mod parser;
-
-use exec::Runtime;
+use wasmrun::exec::{self, Linker, Runtime, Trap, TrapKind};
+use wasmrun::{parser, validate};
 
 fn main() {
-    let args = std::env::args().collect::<Vec<_>>();
-    let file = &args[1];
-    let file_contents = ::std::fs::read_to_string(file).unwrap();
-
-    let lexer = parser::wast::Lexer::new(file_contents.as_bytes());
+    let args = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, cli::USAGE);
+            ::std::process::exit(1);
+        }
+    };
 
-    for token in lexer {
-        match token {
-            Ok(token) => {
-                println!("{:?}", token);
-            }
-            Err(err) => {
-                println!("ERROR: {:?}", err);
-            }
+    let file = match std::fs::File::open(&args.file) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("error: cannot read {}: {}", args.file, err);
+            ::std::process::exit(1);
         }
+    };
+    // The parser reads the section headers a few bytes at a time
+    let module = match parser::parse_reader(std::io::BufReader::new(file)) {
+        Ok(module) => module,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ::std::process::exit(1);
+        }
+    };
+
+    if let Err(err) = validate::validate(&module) {
+        eprintln!("{:#?}", err);
+        ::std::process::exit(1);
     }
 
-    /*
-        let bytes = std::fs::read(file).unwrap();
-        let module = match parser::parse(&bytes) {
-            Ok(module) => module,
-            Err(err) => {
-                eprintln!("{:#?}", err);
-                ::std::process::exit(1);
-            }
+    let mut runtime = Runtime::default();
+    let mut linker = Linker::default();
+    if args.wasi {
+        // The module path is the program name
+        let wasi_ctx = exec::wasi::WasiCtx {
+            args: std::iter::once(args.file.clone())
+                .chain(args.program_args.iter().cloned())
+                .collect(),
+            ..Default::default()
         };
-        // println!("{:#?}", module);
-
-        let mut runtime = Runtime::default();
-        let module_idx = exec::allocate_module(&mut runtime, module);
-
-        // Run the 'start' function if it exists
-        if let Some(start_idx) = runtime.get_module_start(module_idx) {
-            println!("Calling start function {}", start_idx);
-            exec::call(&mut runtime, module_idx, start_idx);
+        exec::wasi::add_to_linker(&mut linker, &mut runtime, wasi_ctx);
+    }
+    let module_idx = match exec::allocate_module(&mut runtime, &linker, module) {
+        Ok(module_idx) => module_idx,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            ::std::process::exit(1);
         }
+    };
 
-        // Find exported _start function and call it
-        let mut start_fn = None;
-        for export in &runtime.get_module(module_idx).exports {
-            if export.nm == "_start" {
-                match export.desc {
-                    parser::ExportDesc::Func(func_idx) => {
-                        start_fn = Some(func_idx);
-                        break;
-                    }
-                    _ => {
-                        break;
-                    }
-                }
+    match runtime.run_program(module_idx, args.invoke.as_deref(), &args.args) {
+        Ok(results) => {
+            if args.invoke.is_some() {
+                println!("{:?}", results);
             }
         }
+        Err(trap) => exit_on_trap(trap),
+    }
+}
 
-        if let Some(start_fn) = start_fn {
-            println!("Calling _start ({})", start_fn);
-            exec::call(&mut runtime, module_idx, start_fn);
+// Exit the process after a trap. `proc_exit` exits with the program's exit code, other traps are
+// reported and exit with a non-zero code.
+fn exit_on_trap(trap: Trap) -> ! {
+    match trap.kind {
+        TrapKind::Exit(code) => ::std::process::exit(code),
+        _ => {
+            eprintln!("{}", trap);
+            ::std::process::exit(1);
         }
-    */
+    }
 }
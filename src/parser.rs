@@ -7,304 +7,428 @@ pub use internal::{ErrorKind, ParseError, Result};
 pub use types::*;
 
 use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::io::Read;
+use std::mem;
 use std::str;
 
 pub fn parse(bytes: &[u8]) -> Result<Module> {
     let mut parser = Parser::new(bytes);
+    parse_header(&mut parser)?;
 
-    // Magic number: "\0wasm"
-    parser.consume_const(&[0x00, 0x61, 0x73, 0x6D])?;
-
-    // Version number: 1
-    parser.consume_const(&[0x01, 0x00, 0x00, 0x00])?;
-
-    skip_customsecs(&mut parser)?;
-
-    let types = parse_type_section(&mut parser)?.unwrap_or_default();
-
-    // skip_customsecs(&mut parser)?;
-
-    let imports = parse_import_section(&mut parser)?.unwrap_or_default();
-
-    // skip_customsecs(&mut parser)?;
-
-    let funs = parse_fun_section(&mut parser)?.unwrap_or_default();
-
-    // skip_customsecs(&mut parser)?;
-
-    let tables = parse_table_section(&mut parser)?.unwrap_or_default();
-
-    // skip_customsecs(&mut parser)?;
+    let mut sections = Sections::default();
+    while !parser.all_consumed() {
+        let section_id = parser.consume_byte()?;
+        let section_size = parser.consume_uleb128()?;
+        let mut section_parser = parser.fork(section_size as usize)?;
+        sections.parse_section(section_id, &mut section_parser)?;
+    }
 
-    let mem_addrs = parse_mem_section(&mut parser)?.unwrap_or_default();
+    sections.finish(parser.get_cursor())
+}
 
-    // skip_customsecs(&mut parser)?;
+/// Parse a module from a reader. Sections are read and parsed one at a time, so only one section
+/// of the module is in memory at a time.
+pub fn parse_reader<R: Read>(mut reader: R) -> Result<Module> {
+    // Offset of the next byte in the module
+    let mut offset = 0;
 
-    let globals = parse_global_section(&mut parser)?.unwrap_or_default();
+    let header = read_bytes(&mut reader, 8, &mut offset)?;
+    parse_header(&mut Parser::new(&header))?;
 
-    // skip_customsecs(&mut parser)?;
+    let mut sections = Sections::default();
+    loop {
+        let section_id = match read_up_to(&mut reader, 1, &mut offset)?.first() {
+            None => break,
+            Some(section_id) => *section_id,
+        };
+
+        // Read the bytes of the section size, then decode with the parser to get the same errors
+        // as `parse`. A 64-bit LEB128 value has at most 10 bytes.
+        let size_offset = offset;
+        let mut size_bytes = vec![];
+        loop {
+            let byte = read_bytes(&mut reader, 1, &mut offset)?[0];
+            size_bytes.push(byte);
+            if byte & 0b1000_0000 == 0 || size_bytes.len() == 10 {
+                break;
+            }
+        }
+        let section_size = Parser::new_at(&size_bytes, size_offset).consume_uleb128()?;
 
-    let exports = parse_export_section(&mut parser)?.unwrap_or_default();
+        let section_offset = offset;
+        let section = read_bytes(&mut reader, section_size as usize, &mut offset)?;
+        sections.parse_section(section_id, &mut Parser::new_at(&section, section_offset))?;
+    }
 
-    // skip_customsecs(&mut parser)?;
+    sections.finish(offset)
+}
 
-    let start = parse_start_section(&mut parser)?;
+fn parse_header<'a>(parser: &mut Parser<'a>) -> Result<()> {
+    // Magic number: "\0wasm"
+    parser.consume_const(&[0x00, 0x61, 0x73, 0x6D])?;
 
-    // skip_customsecs(&mut parser)?;
+    // Version number: 1
+    parser.consume_const(&[0x01, 0x00, 0x00, 0x00])?;
 
-    let elems = parse_element_section(&mut parser)?.unwrap_or_default();
+    Ok(())
+}
 
-    // skip_customsecs(&mut parser)?;
+// Sections of a module, parsed one at a time in the order they appear in the module
+#[derive(Default)]
+struct Sections {
+    // Position of the last non-custom section in `SECTION_ORDER`
+    last_section: Option<usize>,
+    names: Names,
+    types: Vec<FuncType>,
+    imports: Vec<Import>,
+    fun_tys: Vec<TypeIdx>,
+    tables: Vec<Table>,
+    mem_addrs: Vec<Limits>,
+    globals: Vec<Global>,
+    exports: Vec<Export>,
+    start: Option<FuncIdx>,
+    elems: Vec<Element>,
+    datacount: Option<u32>,
+    funs: Vec<Fun>,
+    data: Vec<Data>,
+}
 
-    // https://github.com/WebAssembly/bulk-memory-operations/blob/master/proposals/bulk-memory-operations/Overview.md#datacount-section
-    let datacount = parse_datacount_section(&mut parser)?;
+// Ids of non-custom sections, in the order they need to appear in a module. The datacount section
+// (12) comes before the code section (10).
+// https://github.com/WebAssembly/bulk-memory-operations/blob/master/proposals/bulk-memory-operations/Overview.md#datacount-section
+const SECTION_ORDER: [u8; 12] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 12, 10, 11];
+
+impl Sections {
+    // Parse the contents of a section. `parser` should have only the section contents, without the
+    // section id and size.
+    fn parse_section<'a>(&mut self, section_id: u8, parser: &mut Parser<'a>) -> Result<()> {
+        if section_id != 0 {
+            let section = match SECTION_ORDER.iter().position(|id| *id == section_id) {
+                None => {
+                    return Err(ParseError {
+                        kind: ErrorKind::UnexpectedSection { id: section_id },
+                        offset: parser.get_cursor(),
+                        backtrace: Backtrace::capture(),
+                    });
+                }
+                Some(section) => section,
+            };
+            // Sections need to be in order, which also rules out duplicate sections
+            if let Some(last_section) = self.last_section {
+                if section <= last_section {
+                    return Err(ParseError {
+                        kind: ErrorKind::SectionOrder {
+                            id: section_id,
+                            previous: SECTION_ORDER[last_section],
+                        },
+                        offset: parser.get_cursor(),
+                        backtrace: Backtrace::capture(),
+                    });
+                }
+            }
+            self.last_section = Some(section);
+        }
 
-    // skip_customsecs(&mut parser)?;
+        match section_id {
+            0 => parse_custom_section(parser, &mut self.names)?,
+            1 => self.types = parse_type_section(parser)?,
+            2 => self.imports = parse_import_section(parser)?,
+            3 => self.fun_tys = parse_fun_section(parser)?,
+            4 => self.tables = parse_table_section(parser)?,
+            5 => self.mem_addrs = parse_mem_section(parser)?,
+            6 => self.globals = parse_global_section(parser)?,
+            7 => self.exports = parse_export_section(parser)?,
+            8 => self.start = Some(parse_start_section(parser)?),
+            9 => self.elems = parse_element_section(parser)?,
+            10 => self.funs = parse_code_section(parser, &self.fun_tys)?,
+            11 => self.data = parse_data_section(parser)?,
+            _ => self.datacount = Some(parse_datacount_section(parser)?),
+        }
 
-    let code = parse_code_section(&mut parser, &funs)?.unwrap_or_default();
+        if !parser.all_consumed() {
+            return Err(ParseError {
+                kind: ErrorKind::SectionNotEmpty {
+                    remains: parser.get_bytes().to_owned(),
+                },
+                offset: parser.get_cursor(),
+                backtrace: Backtrace::capture(),
+            });
+        }
 
-    // skip_customsecs(&mut parser)?;
+        Ok(())
+    }
 
-    let data = parse_data_section(&mut parser)?.unwrap_or_default();
+    // `offset` is the offset of the end of the module, used in errors
+    fn finish(self, offset: usize) -> Result<Module> {
+        if let Some(datacount) = self.datacount {
+            if datacount as usize != self.data.len() {
+                return Err(ParseError {
+                    kind: ErrorKind::DataCountMismatch {
+                        datacount,
+                        data: self.data.len(),
+                    },
+                    offset,
+                    backtrace: Backtrace::capture(),
+                });
+            }
+        }
 
-    // Parses 'name' section, skip other custom sections
-    // .debug_info, .debug_abbrev, .debug_line, .debug_str
-    let mut names = None;
-    while !parser.all_consumed() {
-        if let Some(names_) = parse_name_section(&mut parser)? {
-            assert!(names.is_none());
-            names = Some(names_);
-        }
-    }
-
-    let names = names.unwrap_or_default();
-
-    Ok(Module {
-        types,
-        funs: code,
-        tables,
-        mem_addrs,
-        globals,
-        elems,
-        data,
-        names,
-        start,
-        imports,
-        exports,
-        datacount,
-    })
+        let Sections {
+            last_section: _,
+            names,
+            types,
+            imports,
+            fun_tys: _,
+            tables,
+            mem_addrs,
+            globals,
+            exports,
+            start,
+            elems,
+            datacount,
+            funs,
+            data,
+        } = self;
+
+        Ok(Module {
+            types,
+            funs,
+            tables,
+            mem_addrs,
+            globals,
+            elems,
+            data,
+            names,
+            start,
+            imports,
+            exports,
+            datacount,
+        })
+    }
 }
 
 //////////////
 // Sections //
 //////////////
 
-fn parse_type_section<'a>(parser: &mut Parser<'a>) -> Result<Option<Vec<FuncType>>> {
-    parse_section(parser, 1, &|parser| {
-        parse_vec(parser, &mut |parser, _| {
-            parser.consume_const(&[0x60])?;
-            let args = parse_resulttype(parser)?;
-            let ret = parse_resulttype(parser)?;
-            Ok(FuncType { args, ret })
-        })
+fn parse_type_section<'a>(parser: &mut Parser<'a>) -> Result<Vec<FuncType>> {
+    parse_vec(parser, &mut |parser, _| {
+        parser.consume_const(&[0x60])?;
+        let args = parse_resulttype(parser)?;
+        let ret = parse_resulttype(parser)?;
+        Ok(FuncType { args, ret })
     })
 }
 
-fn parse_import_section<'a>(parser: &mut Parser<'a>) -> Result<Option<Vec<Import>>> {
-    parse_section(parser, 2, &|parser| {
-        parse_vec(parser, &mut |parser, _| {
-            let module = parse_name(parser)?;
-            let name = parse_name(parser)?;
-            let desc = parse_importdesc(parser)?;
-            Ok(Import { module, name, desc })
-        })
+fn parse_import_section<'a>(parser: &mut Parser<'a>) -> Result<Vec<Import>> {
+    parse_vec(parser, &mut |parser, _| {
+        let module = parse_name(parser)?;
+        let name = parse_name(parser)?;
+        let desc = parse_importdesc(parser)?;
+        Ok(Import { module, name, desc })
     })
 }
 
-fn parse_export_section<'a>(parser: &mut Parser<'a>) -> Result<Option<Vec<Export>>> {
-    parse_section(parser, 7, &|parser| {
-        parse_vec(parser, &mut |parser, _| {
-            let nm = parse_name(parser)?;
-            let desc = parse_export_desc(parser)?;
-            Ok(Export { nm, desc })
-        })
+fn parse_export_section<'a>(parser: &mut Parser<'a>) -> Result<Vec<Export>> {
+    parse_vec(parser, &mut |parser, _| {
+        let nm = parse_name(parser)?;
+        let desc = parse_export_desc(parser)?;
+        Ok(Export { nm, desc })
     })
 }
 
-fn parse_start_section<'a>(parser: &mut Parser<'a>) -> Result<Option<FuncIdx>> {
-    parse_section(parser, 8, &|parser| Ok(parser.consume_uleb128()? as u32))
+fn parse_start_section<'a>(parser: &mut Parser<'a>) -> Result<FuncIdx> {
+    Ok(FuncIdx(parser.consume_uleb128()? as u32))
 }
 
-fn parse_element_section<'a>(parser: &mut Parser<'a>) -> Result<Option<Vec<Element>>> {
-    parse_section(parser, 9, &|parser| {
-        parse_vec(parser, &mut |parser, _| {
-            let table = parser.consume_uleb128()? as u32;
-            let expr = parse_expr(parser)?;
+fn parse_element_section<'a>(parser: &mut Parser<'a>) -> Result<Vec<Element>> {
+    parse_vec(parser, &mut |parser, _| {
+        // Segments with flags 4-7 use expressions instead of function indices, which are not
+        // supported yet
+        let mode = match parser.consume_uleb128()? {
+            0 => ElemMode::Active {
+                table: 0,
+                offset: parse_expr(parser)?,
+            },
+            1 => {
+                parser.consume_const(&[0x00])?; // elemkind funcref
+                ElemMode::Passive
+            }
+            2 => {
+                let table = parser.consume_uleb128()? as u32;
+                let offset = parse_expr(parser)?;
+                parser.consume_const(&[0x00])?; // elemkind funcref
+                ElemMode::Active { table, offset }
+            }
+            3 => {
+                parser.consume_const(&[0x00])?; // elemkind funcref
+                ElemMode::Declarative
+            }
+            other => {
+                return Err(ParseError {
+                    kind: ErrorKind::UnexpectedElemSegmentFlag { found: other },
+                    offset: parser.get_cursor() - 1,
+                    backtrace: Backtrace::capture(),
+                })
+            }
+        };
 
-            let init = parse_vec(
-                parser,
-                &mut |parser, _| Ok(parser.consume_uleb128()? as u32),
-            )?;
+        let init = parse_vec(parser, &mut |parser, _| {
+            Ok(FuncIdx(parser.consume_uleb128()? as u32))
+        })?;
 
-            Ok(Element { table, expr, init })
-        })
+        Ok(Element { mode, init })
     })
 }
 
 // https://github.com/WebAssembly/bulk-memory-operations/blob/master/proposals/bulk-memory-operations/Overview.md#datacount-section
-fn parse_datacount_section<'a>(parser: &mut Parser<'a>) -> Result<Option<u32>> {
+fn parse_datacount_section<'a>(parser: &mut Parser<'a>) -> Result<u32> {
     // Comes before code section but has number 12. See the spec linked above.
-    parse_section(parser, 12, &|parser| {
-        let count = parser.consume_uleb128()? as u32;
-        Ok(count)
-    })
+    let count = parser.consume_uleb128()? as u32;
+    Ok(count)
 }
 
-fn parse_global_section<'a>(parser: &mut Parser<'a>) -> Result<Option<Vec<Global>>> {
-    parse_section(parser, 6, &|parser| {
-        parse_vec(parser, &mut |parser, _| {
-            let ty = parse_global_type(parser)?;
-            let expr = parse_expr(parser)?;
-            Ok(Global { ty, expr })
-        })
+fn parse_global_section<'a>(parser: &mut Parser<'a>) -> Result<Vec<Global>> {
+    parse_vec(parser, &mut |parser, _| {
+        let ty = parse_global_type(parser)?;
+        let expr = parse_expr(parser)?;
+        Ok(Global { ty, expr })
     })
 }
 
-fn parse_mem_section<'a>(parser: &mut Parser<'a>) -> Result<Option<Vec<Limits>>> {
-    parse_section(parser, 5, &|parser| {
-        parse_vec(parser, &mut |parser, _| Ok(parse_limits(parser)?))
-    })
+fn parse_mem_section<'a>(parser: &mut Parser<'a>) -> Result<Vec<Limits>> {
+    parse_vec(parser, &mut |parser, _| Ok(parse_limits(parser)?))
 }
 
-fn parse_table_section<'a>(parser: &mut Parser<'a>) -> Result<Option<Vec<Table>>> {
-    parse_section(parser, 4, &|parser| {
-        parse_vec(parser, &mut |parser, _| {
-            parser.consume_const(&[0x70])?; // funcref
-            Ok(Table {
-                limits: parse_limits(parser)?,
-                elem_type: ElemType::FuncRef,
-            })
+fn parse_table_section<'a>(parser: &mut Parser<'a>) -> Result<Vec<Table>> {
+    parse_vec(parser, &mut |parser, _| {
+        let elem_type = parse_elem_type(parser)?;
+        Ok(Table {
+            limits: parse_limits(parser)?,
+            elem_type,
         })
     })
 }
 
-fn parse_fun_section<'a>(parser: &mut Parser<'a>) -> Result<Option<Vec<TypeIdx>>> {
-    parse_section(parser, 3, &|parser| {
-        parse_vec(
-            parser,
-            &mut |parser, _| Ok(parser.consume_uleb128()? as u32),
-        )
+fn parse_fun_section<'a>(parser: &mut Parser<'a>) -> Result<Vec<TypeIdx>> {
+    parse_vec(parser, &mut |parser, _| {
+        Ok(TypeIdx(parser.consume_uleb128()? as u32))
     })
 }
 
-fn parse_code_section<'a>(
-    parser: &mut Parser<'a>,
-    fun_tys: &[TypeIdx],
-) -> Result<Option<Vec<Fun>>> {
-    parse_section(parser, 10, &|parser| {
-        parse_vec(parser, &mut |parser, i| {
-            let size = parser.consume_uleb128()?;
-            let mut function_data_parser = parser.fork(size as usize)?;
-
-            let locals = parse_vec(&mut function_data_parser, &mut |parser, _| {
-                let n = parser.consume_uleb128()?;
-                let ty = parse_valtype(parser)?;
-                Ok(Local { n: n as u32, ty })
-            })?;
+fn parse_code_section<'a>(parser: &mut Parser<'a>, fun_tys: &[TypeIdx]) -> Result<Vec<Fun>> {
+    let function_count_mismatch = |parser: &Parser<'a>| ParseError {
+        kind: ErrorKind::FunctionCountMismatch,
+        offset: parser.get_cursor(),
+        backtrace: Backtrace::capture(),
+    };
 
-            let expr = parse_expr(&mut function_data_parser)?;
-            Ok(Fun {
-                ty: fun_tys[i],
-                locals,
-                expr,
-            })
-        })
-    })
+    let funs = parse_vec(parser, &mut |parser, i| {
+        let ty = match fun_tys.get(i) {
+            None => return Err(function_count_mismatch(parser)),
+            Some(ty) => *ty,
+        };
+        let size = parser.consume_uleb128()?;
+        let mut function_data_parser = parser.fork(size as usize)?;
+
+        let locals = parse_vec(&mut function_data_parser, &mut |parser, _| {
+            let n = parser.consume_uleb128()?;
+            let ty = parse_valtype(parser)?;
+            Ok(Local { n: n as u32, ty })
+        })?;
+
+        let expr = parse_expr(&mut function_data_parser)?;
+        Ok(Fun { ty, locals, expr })
+    })?;
+
+    if funs.len() != fun_tys.len() {
+        return Err(function_count_mismatch(parser));
+    }
+    Ok(funs)
 }
 
-fn parse_data_section<'a>(parser: &mut Parser<'a>) -> Result<Option<Vec<Data>>> {
-    parse_section(parser, 11, &|parser| {
-        parse_vec(parser, &mut |parser, _| {
-            let data = parser.consume_uleb128()?;
-            let offset = parse_expr(parser)?;
-            let init: Vec<u8> = parse_vec(parser, &mut |parser, _| parser.consume_byte())?;
-            Ok(Data {
-                data: data as u32,
-                offset,
-                init,
-            })
-        })
+fn parse_data_section<'a>(parser: &mut Parser<'a>) -> Result<Vec<Data>> {
+    parse_vec(parser, &mut |parser, _| {
+        let mode = match parser.consume_uleb128()? {
+            0 => DataMode::Active {
+                mem: 0,
+                offset: parse_expr(parser)?,
+            },
+            1 => DataMode::Passive,
+            2 => {
+                let mem = parser.consume_uleb128()? as u32;
+                let offset = parse_expr(parser)?;
+                DataMode::Active { mem, offset }
+            }
+            other => {
+                return Err(ParseError {
+                    kind: ErrorKind::UnexpectedDataSegmentFlag { found: other },
+                    offset: parser.get_cursor() - 1,
+                    backtrace: Backtrace::capture(),
+                })
+            }
+        };
+        let init: Vec<u8> = parse_vec(parser, &mut |parser, _| parser.consume_byte())?;
+        Ok(Data { mode, init })
     })
 }
 
-// NB. Skips the section!
-fn parse_name_section<'a>(parser: &mut Parser<'a>) -> Result<Option<Names>> {
-    let mut section_parser = match parser.byte() {
-        Ok(0) => {
-            // Skip section idx, we're going to skip the section even if it's not a 'name' section
-            parser.skip(1)?;
-            let section_size = parser.consume_uleb128()?;
-            let mut section_parser = parser.fork(section_size as usize)?;
-            let name = parse_name(&mut section_parser)?;
-            if name != "name" {
-                return Ok(None);
-            }
-            section_parser
-        }
-        _ => {
-            // Not the section we're looking for or end of the file
-            return Ok(None);
-        }
-    };
+// Parse the contents of a custom section. The 'name' section is parsed into `names`, other custom
+// sections (producers, target_features, .debug_info, ...) are skipped.
+fn parse_custom_section<'a>(parser: &mut Parser<'a>, names: &mut Names) -> Result<()> {
+    if parse_name(parser)? == "name" {
+        *names = parse_name_section(parser)?;
+    } else {
+        parser.skip(parser.get_bytes().len())?;
+    }
+    Ok(())
+}
 
+fn parse_name_section<'a>(parser: &mut Parser<'a>) -> Result<Names> {
     let mut names = Default::default();
 
-    while section_parser.byte().is_ok() {
-        parse_name_subsection(&mut section_parser, &mut names)?;
+    while parser.byte().is_ok() {
+        parse_name_subsection(parser, &mut names)?;
     }
 
-    Ok(Some(names))
+    Ok(names)
 }
 
 /////////////
 // Helpers //
 /////////////
 
-// NB. The argument parser skips the section even when the section parser fails. The section is not
-// skipped if the type doesn't match the expected one.
-fn parse_section<'a, A>(
-    parser: &mut Parser<'a>,
-    section_ty: u8,
-    parse: &dyn Fn(&mut Parser<'a>) -> Result<A>,
-) -> Result<Option<A>> {
-    match parser.byte() {
-        Ok(ty) if ty == section_ty => {}
-        Ok(_) => {
-            return Ok(None);
-        }
-        Err(err) => {
-            return Err(err);
-        }
+// Read at most `n` bytes. Returns less than `n` bytes only at the end of the input. `offset` is
+// the offset of the next byte in the module, and is updated.
+fn read_up_to<R: Read>(reader: &mut R, n: usize, offset: &mut usize) -> Result<Vec<u8>> {
+    // `n` comes from the module, so we don't allocate `n` bytes before reading them
+    let mut bytes = vec![];
+    if let Err(error) = reader.take(n as u64).read_to_end(&mut bytes) {
+        return Err(ParseError {
+            kind: ErrorKind::IoError { error },
+            offset: *offset,
+            backtrace: Backtrace::capture(),
+        });
     }
+    *offset += bytes.len();
+    Ok(bytes)
+}
 
-    parser.skip(1)?;
-
-    let section_size = parser.consume_uleb128()?;
-    let mut section_parser = parser.fork(section_size as usize)?;
-
-    let ret = parse(&mut section_parser)?;
-
-    if !section_parser.all_consumed() {
+// Read exactly `n` bytes
+fn read_bytes<R: Read>(reader: &mut R, n: usize, offset: &mut usize) -> Result<Vec<u8>> {
+    let bytes = read_up_to(reader, n, offset)?;
+    if bytes.len() < n {
         return Err(ParseError {
-            kind: ErrorKind::SectionNotEmpty {
-                remains: section_parser.get_bytes().to_owned(),
+            kind: ErrorKind::NotEnoughBytes {
+                expected: n,
+                found: bytes.len(),
             },
-            offset: section_parser.get_cursor(),
+            offset: *offset,
             backtrace: Backtrace::capture(),
         });
     }
-
-    Ok(Some(ret))
+    Ok(bytes)
 }
 
 fn parse_vec<'a, A>(
@@ -312,124 +436,219 @@ fn parse_vec<'a, A>(
     parse: &mut dyn FnMut(&mut Parser<'a>, usize) -> Result<A>,
 ) -> Result<Vec<A>> {
     let vec_len = parser.consume_uleb128()?;
-    let mut vec = Vec::with_capacity(vec_len as usize);
+    // The length comes from the module, each element takes at least one byte
+    let mut vec = Vec::with_capacity((vec_len as usize).min(parser.get_bytes().len()));
     for i in 0..vec_len as usize {
         vec.push(parse(parser, i)?);
     }
     Ok(vec)
 }
 
+// Unknown subsections, e.g. label or type names of the extended name section, are skipped
 fn parse_name_subsection<'a>(parser: &mut Parser<'a>, names: &mut Names) -> Result<()> {
-    match parser.consume_byte() {
-        Ok(0) => {
-            let _subsection_size = parser.consume_uleb128()?;
+    let subsection_id = parser.consume_byte()?;
+    let subsection_size = parser.consume_uleb128()?;
+    let mut subsection_parser = parser.fork(subsection_size as usize)?;
+    let parser = &mut subsection_parser;
+    match subsection_id {
+        0 => {
             names.mod_name = Some(parse_name(parser)?);
-            Ok(())
         }
-        Ok(1) => {
-            let _subsection_size = parser.consume_uleb128()?;
-            let mut fun_names = vec![];
+        1 => {
+            let mut fun_names = HashMap::new();
             // TODO: Maybe introduce a variant of parse_vec that doesn't allocate a vector
             let _ = parse_vec(parser, &mut |parser, _| {
-                let idx = parser.consume_uleb128()? as usize;
+                let idx = FuncIdx(parser.consume_uleb128()? as u32);
                 let name = parse_name(parser)?;
-
-                fun_names.resize_with(idx + 1, Default::default);
-                fun_names[idx] = Some(name);
-
+                fun_names.insert(idx, name);
                 Ok(())
             })?;
 
             names.fun_names = fun_names;
-            Ok(())
         }
-        Ok(2) => {
-            let _subsection_size = parser.consume_uleb128()?;
-            let mut local_names = vec![];
+        2 => {
+            let mut local_names = HashMap::new();
 
             let _ = parse_vec(parser, &mut |parser, _| {
-                let mut fun_local_names = vec![];
-                let idx = parser.consume_uleb128()? as usize;
+                let mut fun_local_names = HashMap::new();
+                let idx = FuncIdx(parser.consume_uleb128()? as u32);
 
                 let _ = parse_vec(parser, &mut |parser, _| {
-                    let local_idx = parser.consume_uleb128()? as usize;
+                    let local_idx = LocalIdx(parser.consume_uleb128()? as u32);
                     let local_name = parse_name(parser)?;
-
-                    fun_local_names.resize_with(local_idx + 1, Default::default);
-                    fun_local_names[local_idx] = Some(local_name);
-
+                    fun_local_names.insert(local_idx, local_name);
                     Ok(())
                 })?;
 
-                local_names.resize_with(idx + 1, Default::default);
-                local_names[idx] = Some(fun_local_names);
-
+                local_names.insert(idx, fun_local_names);
                 Ok(())
             })?;
 
             names.local_names = local_names;
-            Ok(())
         }
-        Ok(other) => Err(ParseError {
-            kind: ErrorKind::UnexpectedNameSubsection { found: other },
-            offset: parser.get_cursor() - 1,
-            backtrace: Backtrace::capture(),
-        }),
-        Err(_) => Ok(()),
+        _ => {}
     }
+    Ok(())
 }
 
 fn parse_export_desc<'a>(parser: &mut Parser<'a>) -> Result<ExportDesc> {
     match parser.consume_byte()? {
-        0x00 => Ok(ExportDesc::Func(parser.consume_uleb128()? as u32)),
+        0x00 => Ok(ExportDesc::Func(FuncIdx(parser.consume_uleb128()? as u32))),
         0x01 => Ok(ExportDesc::Table(parser.consume_uleb128()? as u32)),
         0x02 => Ok(ExportDesc::Mem(parser.consume_uleb128()? as u32)),
-        0x03 => Ok(ExportDesc::Global(parser.consume_uleb128()? as u32)),
-        _ => todo!(),
+        0x03 => Ok(ExportDesc::Global(GlobalIdx(
+            parser.consume_uleb128()? as u32
+        ))),
+        other => Err(ParseError {
+            kind: ErrorKind::UnexpectedExportDesc { found: other },
+            offset: parser.get_cursor() - 1,
+            backtrace: Backtrace::capture(),
+        }),
     }
 }
 
+/// Max. nesting depth of blocks in a function body. Nested instructions are dropped and validated
+/// recursively, the limit keeps deeply nested blocks from overflowing the native stack.
+pub const MAX_BLOCK_DEPTH: usize = 1000;
+
+// Nested blocks are parsed with a stack of open blocks instead of recursion
 fn parse_expr<'a>(parser: &mut Parser<'a>) -> Result<Expr> {
+    // Blocks being parsed, innermost last, with the instructions parsed before the block
+    let mut open_blocks: Vec<(OpenBlock, Vec<Instruction>)> = vec![];
     let mut instrs = vec![];
-    while parser.byte()? != 0x0B {
-        instrs.push(parse_instr(parser)?);
+    loop {
+        match parser.byte()? {
+            op @ 0x02..=0x04 => {
+                if open_blocks.len() == MAX_BLOCK_DEPTH {
+                    return Err(ParseError {
+                        kind: ErrorKind::BlockTooDeep,
+                        offset: parser.get_cursor(),
+                        backtrace: Backtrace::capture(),
+                    });
+                }
+                parser.skip(1)?;
+                let ty = parse_block_type(parser)?;
+                let block = match op {
+                    0x02 => OpenBlock::Block(ty),
+                    0x03 => OpenBlock::Loop(ty),
+                    _ => OpenBlock::If {
+                        ty,
+                        then_instrs: None,
+                    },
+                };
+                open_blocks.push((block, mem::take(&mut instrs)));
+            }
+            0x05 => match open_blocks.last_mut() {
+                Some((
+                    OpenBlock::If {
+                        then_instrs: then_instrs @ None,
+                        ..
+                    },
+                    _,
+                )) => {
+                    parser.skip(1)?;
+                    *then_instrs = Some(mem::take(&mut instrs));
+                }
+                // `else` outside of an `if`, or a second `else`
+                _ => instrs.push(parse_instr(parser)?),
+            },
+            0x0B => {
+                parser.skip(1)?;
+                match open_blocks.pop() {
+                    None => {
+                        return Ok(Expr {
+                            instrs: instrs.into(),
+                        })
+                    }
+                    Some((block, outer_instrs)) => {
+                        let block_instrs = mem::replace(&mut instrs, outer_instrs);
+                        instrs.push(block.finish(block_instrs));
+                    }
+                }
+            }
+            _ => instrs.push(parse_instr(parser)?),
+        }
+    }
+}
+
+// A block in `parse_expr` that hasn't reached its `end` yet
+enum OpenBlock {
+    Block(BlockType),
+    Loop(BlockType),
+    If {
+        ty: BlockType,
+        // Set when the `else` is reached
+        then_instrs: Option<Vec<Instruction>>,
+    },
+}
+
+impl OpenBlock {
+    fn finish(self, instrs: Vec<Instruction>) -> Instruction {
+        match self {
+            OpenBlock::Block(ty) => Instruction::Block(Block {
+                ty,
+                instrs: instrs.into(),
+            }),
+            OpenBlock::Loop(ty) => Instruction::Loop(Block {
+                ty,
+                instrs: instrs.into(),
+            }),
+            OpenBlock::If { ty, then_instrs } => {
+                let (then_instrs, else_instrs) = match then_instrs {
+                    Some(then_instrs) => (then_instrs, instrs),
+                    None => (instrs, vec![]),
+                };
+                Instruction::If(If {
+                    ty,
+                    then_instrs: then_instrs.into(),
+                    else_instrs: else_instrs.into(),
+                })
+            }
+        }
     }
-    parser.skip(1)?; // consume 0x0B
-    Ok(Expr {
-        instrs: instrs.into(),
-    })
 }
 
+// Parse an instruction other than `block`, `loop`, and `if`, which are parsed by `parse_expr`
 fn parse_instr<'a>(parser: &mut Parser<'a>) -> Result<Instruction> {
     use Instruction::*;
     match parser.consume_byte()? {
         // Control instructions
         0x00 => Ok(Unreachable),
         0x01 => Ok(Nop),
-        0x02 => Ok(Block(parse_block(parser)?)),
-        0x03 => Ok(Loop(parse_block(parser)?)),
-        0x04 => Ok(If(parse_if(parser)?)),
         0x0C => Ok(Br(parser.consume_uleb128()? as u32)),
         0x0D => Ok(BrIf(parser.consume_uleb128()? as u32)),
         0x0E => Ok(BrTable(parse_br_table(parser)?)),
         0x0F => Ok(Return),
-        0x10 => Ok(Call(parser.consume_uleb128()? as u32)),
+        0x10 => Ok(Call(FuncIdx(parser.consume_uleb128()? as u32))),
         0x11 => {
             let type_idx = parser.consume_uleb128()?;
             parser.consume_const(&[0x00])?;
-            Ok(CallIndirect(type_idx as u32))
+            Ok(CallIndirect(TypeIdx(type_idx as u32)))
+        }
+        0x12 => Ok(ReturnCall(FuncIdx(parser.consume_uleb128()? as u32))),
+        0x13 => {
+            let type_idx = parser.consume_uleb128()?;
+            parser.consume_const(&[0x00])?;
+            Ok(ReturnCallIndirect(TypeIdx(type_idx as u32)))
         }
 
         // Parametric instructions
         0x1A => Ok(Drop),
         0x1B => Ok(Select),
+        0x1C => Ok(SelectT(parse_vec(parser, &mut |parser, _| {
+            parse_valtype(parser)
+        })?)),
 
         // Variable instructions
-        0x20 => Ok(LocalGet(parser.consume_uleb128()? as u32)),
-        0x21 => Ok(LocalSet(parser.consume_uleb128()? as u32)),
-        0x22 => Ok(LocalTee(parser.consume_uleb128()? as u32)),
-        0x23 => Ok(GlobalGet(parser.consume_uleb128()? as u32)),
-        0x24 => Ok(GlobalSet(parser.consume_uleb128()? as u32)),
+        0x20 => Ok(LocalGet(LocalIdx(parser.consume_uleb128()? as u32))),
+        0x21 => Ok(LocalSet(LocalIdx(parser.consume_uleb128()? as u32))),
+        0x22 => Ok(LocalTee(LocalIdx(parser.consume_uleb128()? as u32))),
+        0x23 => Ok(GlobalGet(GlobalIdx(parser.consume_uleb128()? as u32))),
+        0x24 => Ok(GlobalSet(GlobalIdx(parser.consume_uleb128()? as u32))),
+
+        // Table instructions
+        0x25 => Ok(TableGet(parser.consume_uleb128()? as u32)),
+        0x26 => Ok(TableSet(parser.consume_uleb128()? as u32)),
 
         // Memory instructions
         0x28 => Ok(I32Load(parse_memarg(parser)?)),
@@ -466,11 +685,11 @@ fn parse_instr<'a>(parser: &mut Parser<'a>) -> Result<Instruction> {
 
         // Numeric instructions
         0x41 => {
-            let i = parser.consume_sleb128()?;
+            let i = parser.consume_sleb128(32)?;
             Ok(I32Const(i as i32))
         }
         0x42 => {
-            let i = parser.consume_sleb128()?;
+            let i = parser.consume_sleb128(64)?;
             Ok(I64Const(i))
         }
         0x43 => {
@@ -621,6 +840,9 @@ fn parse_instr<'a>(parser: &mut Parser<'a>) -> Result<Instruction> {
         0xC2 => Ok(I64Extend8_s),
         0xC3 => Ok(I64Extend16_s),
         0xC4 => Ok(I64Extend32_s),
+        0xD0 => Ok(RefNull(parse_reftype(parser)?)),
+        0xD1 => Ok(RefIsNull),
+        0xD2 => Ok(RefFunc(FuncIdx(parser.consume_uleb128()? as u32))),
         0xFC => {
             match parser.consume_byte()? {
                 0x00 => Ok(I32TruncSatf32_s),
@@ -631,6 +853,34 @@ fn parse_instr<'a>(parser: &mut Parser<'a>) -> Result<Instruction> {
                 0x05 => Ok(I64TruncSatf32_u),
                 0x06 => Ok(I64TruncSatf64_s),
                 0x07 => Ok(I64TruncSatf64_u),
+                0x08 => {
+                    let data_idx = parser.consume_uleb128()? as u32;
+                    parser.consume_const(&[0x00])?;
+                    Ok(MemoryInit(data_idx))
+                }
+                0x09 => Ok(DataDrop(parser.consume_uleb128()? as u32)),
+                0x0A => {
+                    parser.consume_const(&[0x00, 0x00])?;
+                    Ok(MemoryCopy)
+                }
+                0x0B => {
+                    parser.consume_const(&[0x00])?;
+                    Ok(MemoryFill)
+                }
+                0x0C => {
+                    let elem_idx = parser.consume_uleb128()? as u32;
+                    let table_idx = parser.consume_uleb128()? as u32;
+                    Ok(TableInit(elem_idx, table_idx))
+                }
+                0x0D => Ok(ElemDrop(parser.consume_uleb128()? as u32)),
+                0x0E => {
+                    let dst = parser.consume_uleb128()? as u32;
+                    let src = parser.consume_uleb128()? as u32;
+                    Ok(TableCopy(dst, src))
+                }
+                0x0F => Ok(TableGrow(parser.consume_uleb128()? as u32)),
+                0x10 => Ok(TableSize(parser.consume_uleb128()? as u32)),
+                0x11 => Ok(TableFill(parser.consume_uleb128()? as u32)),
                 _other => Err(ParseError {
                     kind: ErrorKind::UnexpectedOpCode { op: 0xFC },
                     offset: parser.get_cursor() - 1,
@@ -646,52 +896,18 @@ fn parse_instr<'a>(parser: &mut Parser<'a>) -> Result<Instruction> {
     }
 }
 
+// With the multi-memory proposal, bit 6 of the alignment field indicates that a memory index
+// follows the alignment
 fn parse_memarg<'a>(parser: &mut Parser<'a>) -> Result<MemArg> {
-    let align = parser.consume_uleb128()? as u32;
-    let offset = parser.consume_uleb128()? as u32;
-    Ok(MemArg { align, offset })
-}
-
-fn parse_block<'a>(parser: &mut Parser<'a>) -> Result<Block> {
-    let ty = parse_block_type(parser)?;
-    let mut instrs = vec![];
-    while parser.byte()? != 0x0B {
-        instrs.push(parse_instr(parser)?);
-    }
-    parser.skip(1)?; // consume 0x0B
-    Ok(Block {
-        ty,
-        instrs: instrs.into(),
-    })
-}
-
-fn parse_if<'a>(parser: &mut Parser<'a>) -> Result<If> {
-    let ty = parse_block_type(parser)?;
-    let mut then_instrs = vec![];
-    let mut else_instrs = vec![];
-
-    loop {
-        let byte = parser.byte()?;
-        if byte == 0x05 {
-            parser.skip(1)?; // consume 0x05
-            while parser.byte()? != 0x0B {
-                else_instrs.push(parse_instr(parser)?);
-            }
-            break;
-        } else if byte == 0x0B {
-            break;
-        } else {
-            then_instrs.push(parse_instr(parser)?);
-        }
-    }
-
-    parser.skip(1)?; // consume 0x0B
-
-    Ok(If {
-        ty,
-        then_instrs: then_instrs.into(),
-        else_instrs: else_instrs.into(),
-    })
+    let mut align = parser.consume_uleb128()? as u32;
+    let mem = if align & 0x40 != 0 {
+        align &= !0x40;
+        parser.consume_uleb128()? as u32
+    } else {
+        0
+    };
+    let offset = parser.consume_uleb128()?;
+    Ok(MemArg { align, offset, mem })
 }
 
 fn parse_br_table<'a>(parser: &mut Parser<'a>) -> Result<BrTable> {
@@ -725,34 +941,10 @@ fn parse_block_type<'a>(parser: &mut Parser<'a>) -> Result<BlockType> {
             parser.skip(1)?;
             Ok(BlockType::ValType(ValType::F64))
         }
-        _ => Ok(BlockType::TypeIdx(parser.consume_sleb128()? as u32)), // TODO: sleb128 is probably buggy
-    }
-}
-
-// Ignore 'customsec'. Custom sections are for debug info or other third-party extensions, not
-// important for semantics.
-//
-// Returns whether it skipped a customsec.
-fn skip_customsec<'a>(parser: &mut Parser<'a>) -> Result<bool> {
-    match parser.byte() {
-        Ok(0) => {}
-        _ => {
-            return Ok(false);
-        }
+        _ => Ok(BlockType::TypeIdx(TypeIdx(
+            parser.consume_sleb128(33)? as u32
+        ))),
     }
-
-    parser.skip(1)?;
-
-    let section_size = parser.consume_uleb128()?;
-    parser.skip(section_size as usize)?;
-
-    Ok(true)
-}
-
-// Skip multiple 'customsecs'.
-fn skip_customsecs<'a>(parser: &mut Parser<'a>) -> Result<()> {
-    while skip_customsec(parser)? {}
-    Ok(())
 }
 
 fn parse_resulttype<'a>(parser: &mut Parser<'a>) -> Result<ResultType> {
@@ -767,6 +959,37 @@ fn parse_valtype<'a>(parser: &mut Parser<'a>) -> Result<ValType> {
         0x7E => Ok(ValType::I64),
         0x7D => Ok(ValType::F32),
         0x7C => Ok(ValType::F64),
+        0x70 => Ok(ValType::FuncRef),
+        0x6F => Ok(ValType::ExternRef),
+        _ => Err(ParseError {
+            kind: ErrorKind::UnexpectedValType { found: byte },
+            offset: parser.get_cursor() - 1,
+            backtrace: Backtrace::capture(),
+        }),
+    }
+}
+
+fn parse_reftype<'a>(parser: &mut Parser<'a>) -> Result<ValType> {
+    let byte = parser.consume_byte()?;
+
+    match byte {
+        0x70 => Ok(ValType::FuncRef),
+        0x6F => Ok(ValType::ExternRef),
+        _ => Err(ParseError {
+            kind: ErrorKind::UnexpectedValType { found: byte },
+            offset: parser.get_cursor() - 1,
+            backtrace: Backtrace::capture(),
+        }),
+    }
+}
+
+// Element type of a table
+fn parse_elem_type<'a>(parser: &mut Parser<'a>) -> Result<ElemType> {
+    let byte = parser.consume_byte()?;
+
+    match byte {
+        0x70 => Ok(ElemType::FuncRef),
+        0x6F => Ok(ElemType::ExternRef),
         _ => Err(ParseError {
             kind: ErrorKind::UnexpectedValType { found: byte },
             offset: parser.get_cursor() - 1,
@@ -777,14 +1000,21 @@ fn parse_valtype<'a>(parser: &mut Parser<'a>) -> Result<ValType> {
 
 fn parse_importdesc<'a>(parser: &mut Parser<'a>) -> Result<ImportDesc> {
     match parser.consume_byte()? {
-        0x00 => Ok(ImportDesc::Func(parser.consume_uleb128()? as u32)),
+        0x00 => Ok(ImportDesc::Func(TypeIdx(parser.consume_uleb128()? as u32))),
         0x01 => {
-            parser.consume_const(&[0x70])?;
-            Ok(ImportDesc::Table(parse_limits(parser)?))
+            let elem_type = parse_elem_type(parser)?;
+            Ok(ImportDesc::Table(Table {
+                limits: parse_limits(parser)?,
+                elem_type,
+            }))
         }
         0x02 => Ok(ImportDesc::MemType(parse_limits(parser)?)),
         0x03 => Ok(ImportDesc::Global(parse_global_type(parser)?)),
-        _other => todo!(), // parse error
+        other => Err(ParseError {
+            kind: ErrorKind::UnexpectedImportDesc { found: other },
+            offset: parser.get_cursor() - 1,
+            backtrace: Backtrace::capture(),
+        }),
     }
 }
 
@@ -794,29 +1024,49 @@ fn parse_global_type<'a>(parser: &mut Parser<'a>) -> Result<GlobalType> {
     Ok(GlobalType { ty, mut_ })
 }
 
+// Bit 0 of the flags indicates a max. size, bit 2 indicates a 64-bit memory (memory64 proposal)
 fn parse_limits<'a>(parser: &mut Parser<'a>) -> Result<Limits> {
-    match parser.consume_byte()? {
-        0x00 => Ok(Limits {
-            min: parser.consume_uleb128()? as u32,
-            max: None,
-        }),
-        0x01 => {
-            let min = parser.consume_uleb128()? as u32;
-            let max = parser.consume_uleb128()? as u32;
-            Ok(Limits {
-                min,
-                max: Some(max),
-            })
-        }
-        _other => todo!(), // parse error
+    let flags = parser.consume_byte()?;
+    if flags & !0b101 != 0 {
+        return Err(ParseError {
+            kind: ErrorKind::UnexpectedLimitsFlags { found: flags },
+            offset: parser.get_cursor() - 1,
+            backtrace: Backtrace::capture(),
+        });
+    }
+    let memory64 = flags & 0b100 != 0;
+    let min = parse_limit(parser, memory64)?;
+    let max = if flags & 0b1 != 0 {
+        Some(parse_limit(parser, memory64)?)
+    } else {
+        None
+    };
+    Ok(Limits { min, max, memory64 })
+}
+
+// Limits of memory64 memories are 64-bit, other limits are 32-bit
+fn parse_limit<'a>(parser: &mut Parser<'a>, memory64: bool) -> Result<u64> {
+    let offset = parser.get_cursor();
+    let limit = parser.consume_uleb128()?;
+    if !memory64 && limit > u64::from(u32::MAX) {
+        return Err(ParseError {
+            kind: ErrorKind::LimitTooLarge { found: limit },
+            offset,
+            backtrace: Backtrace::capture(),
+        });
     }
+    Ok(limit)
 }
 
 fn parse_mutability<'a>(parser: &mut Parser<'a>) -> Result<Mutability> {
     match parser.consume_byte()? {
         0x00 => Ok(Mutability::Const),
         0x01 => Ok(Mutability::Var),
-        _other => todo!(), // parse error
+        other => Err(ParseError {
+            kind: ErrorKind::UnexpectedMutability { found: other },
+            offset: parser.get_cursor() - 1,
+            backtrace: Backtrace::capture(),
+        }),
     }
 }
 
@@ -832,3 +1082,616 @@ fn parse_name<'a>(parser: &mut Parser<'a>) -> Result<String> {
         }),
     }
 }
+
+#[test]
+fn parse_select_t() {
+    let mut parser = Parser::new(&[0x1C, 0x01, 0x7F]);
+    match parse_instr(&mut parser) {
+        Ok(Instruction::SelectT(tys)) => match tys.as_slice() {
+            [ValType::I32] => {}
+            other => panic!("{:?}", other),
+        },
+        other => panic!("{:?}", other),
+    }
+    assert!(parser.all_consumed());
+}
+
+#[test]
+fn parse_table_get_set() {
+    let mut parser = Parser::new(&[0x25, 0x01, 0x26, 0x00]);
+    assert!(matches!(
+        parse_instr(&mut parser),
+        Ok(Instruction::TableGet(1))
+    ));
+    assert!(matches!(
+        parse_instr(&mut parser),
+        Ok(Instruction::TableSet(0))
+    ));
+    assert!(parser.all_consumed());
+}
+
+#[test]
+fn parse_ref_instrs() {
+    let mut parser = Parser::new(&[0xD0, 0x6F, 0xD1, 0xD2, 0x02]);
+    assert!(matches!(
+        parse_instr(&mut parser),
+        Ok(Instruction::RefNull(ValType::ExternRef))
+    ));
+    assert!(matches!(
+        parse_instr(&mut parser),
+        Ok(Instruction::RefIsNull)
+    ));
+    assert!(matches!(
+        parse_instr(&mut parser),
+        Ok(Instruction::RefFunc(FuncIdx(2)))
+    ));
+    assert!(parser.all_consumed());
+
+    // Numeric types are not reference types
+    let mut parser = Parser::new(&[0xD0, 0x7F]);
+    assert!(parse_instr(&mut parser).is_err());
+}
+
+#[test]
+fn parse_names() {
+    #[rustfmt::skip]
+    let bytes = [
+        0x00, 0x61, 0x73, 0x6D, // magic
+        0x01, 0x00, 0x00, 0x00, // version
+        0x01, 0x01, 0x00, // empty type section
+        0x00, // custom section
+        0x1E, // section size
+        0x04, b'n', b'a', b'm', b'e',
+        0x01, // function names
+        0x09, // subsection size
+        0x02, // 2 names
+        0x00, 0x01, b'f',
+        0x02, 0x03, b'b', b'a', b'r',
+        0x02, // local names
+        0x06, // subsection size
+        0x01, // 1 function
+        0x02, 0x01, 0x01, 0x01, b'x',
+        0x04, // type names, skipped
+        0x04, // subsection size
+        0x01, 0x00, 0x01, b't',
+    ];
+    let module = parse(&bytes).unwrap();
+    assert_eq!(module.names.fun_name(FuncIdx(0)), Some("f"));
+    assert_eq!(module.names.fun_name(FuncIdx(1)), None);
+    assert_eq!(module.names.fun_name(FuncIdx(2)), Some("bar"));
+    assert_eq!(module.names.fun_name(FuncIdx(3)), None);
+    assert_eq!(module.names.local_name(FuncIdx(2), LocalIdx(1)), Some("x"));
+    assert_eq!(module.names.local_name(FuncIdx(2), LocalIdx(0)), None);
+    assert_eq!(module.names.local_name(FuncIdx(0), LocalIdx(0)), None);
+}
+
+#[test]
+fn parse_skips_custom_sections() {
+    #[rustfmt::skip]
+    let bytes = [
+        0x00, 0x61, 0x73, 0x6D, // magic
+        0x01, 0x00, 0x00, 0x00, // version
+        0x00, // custom section
+        0x0E, // section size
+        0x09, b'p', b'r', b'o', b'd', b'u', b'c', b'e', b'r', b's',
+        0x01, 0x00, 0x00, 0x00, // contents are not parsed
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section with one `[] -> []` type
+        0x00, // custom section
+        0x0A, // section size
+        0x04, b'n', b'a', b'm', b'e',
+        0x00, // module name
+        0x03, // subsection size
+        0x02, b'm', b'd',
+    ];
+    let module = parse(&bytes).unwrap();
+    assert_eq!(module.types.len(), 1);
+    assert_eq!(module.names.mod_name.as_deref(), Some("md"));
+}
+
+#[test]
+fn parse_truncated_custom_section() {
+    #[rustfmt::skip]
+    let bytes = [
+        0x00, 0x61, 0x73, 0x6D, // magic
+        0x01, 0x00, 0x00, 0x00, // version
+        0x00, // custom section
+        0x0E, // section size
+        0x09, b'p', b'r', b'o', b'd', b'u', b'c', b'e', b'r', b's',
+    ];
+    assert!(matches!(
+        parse(&bytes),
+        Err(ParseError {
+            kind: ErrorKind::NotEnoughBytes {
+                expected: 14,
+                found: 10
+            },
+            ..
+        })
+    ));
+}
+
+#[test]
+fn parse_data_segments() {
+    #[rustfmt::skip]
+    let bytes = [
+        0x03, // 3 segments
+        0x00, 0x41, 0x01, 0x0B, 0x01, 0xAA, // active, memory 0, offset i32.const 1
+        0x01, 0x01, 0xBB,                   // passive
+        0x02, 0x00, 0x41, 0x02, 0x0B, 0x00, // active, memory 0, offset i32.const 2, empty
+    ];
+    let mut parser = Parser::new(&bytes);
+    let data = parse_data_section(&mut parser).unwrap();
+    assert!(parser.all_consumed());
+    match &data[..] {
+        [Data {
+            mode: DataMode::Active { mem: 0, .. },
+            init: init1,
+        }, Data {
+            mode: DataMode::Passive,
+            init: init2,
+        }, Data {
+            mode: DataMode::Active { mem: 0, .. },
+            init: init3,
+        }] => {
+            assert_eq!(init1, &[0xAA]);
+            assert_eq!(init2, &[0xBB]);
+            assert!(init3.is_empty());
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn parse_elem_segments() {
+    #[rustfmt::skip]
+    let bytes = [
+        0x04, // 4 segments
+        0x00, 0x41, 0x01, 0x0B, 0x01, 0x00,       // active, table 0, offset i32.const 1
+        0x01, 0x00, 0x02, 0x00, 0x01,             // passive
+        0x02, 0x01, 0x41, 0x02, 0x0B, 0x00, 0x00, // active, table 1, offset i32.const 2, empty
+        0x03, 0x00, 0x00,                         // declarative, empty
+    ];
+    let mut parser = Parser::new(&bytes);
+    let elems = parse_element_section(&mut parser).unwrap();
+    assert!(parser.all_consumed());
+    match &elems[..] {
+        [Element {
+            mode: ElemMode::Active { table: 0, .. },
+            init: init1,
+        }, Element {
+            mode: ElemMode::Passive,
+            init: init2,
+        }, Element {
+            mode: ElemMode::Active { table: 1, .. },
+            init: init3,
+        }, Element {
+            mode: ElemMode::Declarative,
+            init: init4,
+        }] => {
+            assert_eq!(init1, &[FuncIdx(0)]);
+            assert_eq!(init2, &[FuncIdx(0), FuncIdx(1)]);
+            assert!(init3.is_empty());
+            assert!(init4.is_empty());
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn parse_uleb128_overflow() {
+    let mut parser = Parser::new(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]);
+    assert_eq!(parser.consume_uleb128().unwrap(), u64::MAX);
+    assert!(parser.all_consumed());
+
+    let mut parser = Parser::new(&[0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]);
+    assert_eq!(parser.consume_uleb128().unwrap(), u64::MAX - 1);
+
+    // Value bits after the 64th bit
+    let mut parser = Parser::new(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x03]);
+    assert!(matches!(
+        parser.consume_uleb128(),
+        Err(ParseError {
+            kind: ErrorKind::Leb128Overflow,
+            ..
+        })
+    ));
+
+    // Too many bytes
+    let mut parser = Parser::new(&[
+        0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00,
+    ]);
+    assert!(matches!(
+        parser.consume_uleb128(),
+        Err(ParseError {
+            kind: ErrorKind::Leb128Overflow,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn parse_sleb128_overflow() {
+    let mut parser = Parser::new(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7F]);
+    assert_eq!(parser.consume_sleb128(64).unwrap(), i64::MIN);
+
+    let mut parser = Parser::new(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00]);
+    assert_eq!(parser.consume_sleb128(64).unwrap(), i64::MAX);
+
+    // Unused bits don't match the sign bit
+    let mut parser = Parser::new(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]);
+    assert!(matches!(
+        parser.consume_sleb128(64),
+        Err(ParseError {
+            kind: ErrorKind::Leb128Overflow,
+            ..
+        })
+    ));
+
+    // Too many bytes
+    let mut parser = Parser::new(&[
+        0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F,
+    ]);
+    assert!(matches!(
+        parser.consume_sleb128(64),
+        Err(ParseError {
+            kind: ErrorKind::Leb128Overflow,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn parse_i64_const() {
+    let mut parser = Parser::new(&[
+        0x42, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x7F, // i64::MIN
+        0x42, 0x7F, // -1
+        0x42, 0x80, 0x80, 0x80, 0x80, 0x78, // i32::MIN
+        0x42, 0xFF, 0xFF, 0xFF, 0xFF, 0x07, // i32::MAX
+        0x42, 0x80, 0x80, 0x80, 0x80, 0x08, // i32::MAX + 1
+    ]);
+    for expected in [
+        i64::MIN,
+        -1,
+        i64::from(i32::MIN),
+        i64::from(i32::MAX),
+        1 << 31,
+    ] {
+        match parse_instr(&mut parser) {
+            Ok(Instruction::I64Const(i)) => assert_eq!(i, expected),
+            other => panic!("{:?}", other),
+        }
+    }
+    assert!(parser.all_consumed());
+}
+
+#[test]
+fn parse_i32_const() {
+    let mut parser = Parser::new(&[
+        0x41, 0xFF, 0x7E, // -129
+        0x41, 0x80, 0x80, 0x80, 0x80, 0x78, // i32::MIN
+        0x41, 0xFF, 0xFF, 0xFF, 0xFF, 0x07, // i32::MAX
+    ]);
+    for expected in [-129, i32::MIN, i32::MAX] {
+        match parse_instr(&mut parser) {
+            Ok(Instruction::I32Const(i)) => assert_eq!(i, expected),
+            other => panic!("{:?}", other),
+        }
+    }
+    assert!(parser.all_consumed());
+
+    // i32::MAX + 1 doesn't fit into 32 bits
+    let mut parser = Parser::new(&[0x41, 0x80, 0x80, 0x80, 0x80, 0x08]);
+    assert!(matches!(
+        parse_instr(&mut parser),
+        Err(ParseError {
+            kind: ErrorKind::Leb128Overflow,
+            ..
+        })
+    ));
+}
+
+// A reader that returns at most 3 bytes in each read
+#[cfg(test)]
+struct ChunkedReader<'a>(&'a [u8]);
+
+#[cfg(test)]
+impl<'a> Read for ChunkedReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.0.len().min(buf.len()).min(3);
+        buf[..n].copy_from_slice(&self.0[..n]);
+        self.0 = &self.0[n..];
+        Ok(n)
+    }
+}
+
+#[test]
+fn parse_from_reader() {
+    #[rustfmt::skip]
+    let bytes = [
+        0x00, 0x61, 0x73, 0x6D, // magic
+        0x01, 0x00, 0x00, 0x00, // version
+        0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7F, // type section with one `[] -> [i32]` type
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00, // export section
+        0x00, // custom section
+        0x0A, // section size
+        0x04, b'n', b'a', b'm', b'e',
+        0x00, // module name
+        0x03, // subsection size
+        0x02, b'm', b'd',
+        0x0A, 0x06, 0x01, 0x04, 0x00, 0x41, 0x2A, 0x0B, // code section, `i32.const 42`
+    ];
+    let module = parse_reader(ChunkedReader(&bytes)).unwrap();
+    assert_eq!(
+        format!("{:?}", module),
+        format!("{:?}", parse(&bytes).unwrap())
+    );
+    assert_eq!(module.funs.len(), 1);
+    assert_eq!(module.exports.len(), 1);
+    assert_eq!(module.names.mod_name.as_deref(), Some("md"));
+
+    // Truncated code section
+    assert!(matches!(
+        parse_reader(ChunkedReader(&bytes[..bytes.len() - 2])),
+        Err(ParseError {
+            kind: ErrorKind::NotEnoughBytes {
+                expected: 6,
+                found: 4
+            },
+            ..
+        })
+    ));
+}
+
+#[test]
+fn parse_memarg_mem_idx() {
+    let mut parser = Parser::new(&[
+        0x28, 0x02, 0x08, // i32.load align=2 offset=8
+        0x36, 0x42, 0x01, 0x08, // i32.store memory 1 align=2 offset=8
+    ]);
+    for expected_mem in [0, 1] {
+        match parse_instr(&mut parser) {
+            Ok(Instruction::I32Load(MemArg { align, offset, mem }))
+            | Ok(Instruction::I32Store(MemArg { align, offset, mem })) => {
+                assert_eq!((align, offset, mem), (2, 8, expected_mem));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+    assert!(parser.all_consumed());
+}
+
+#[test]
+fn parse_memory64_limits() {
+    let mut parser = Parser::new(&[
+        0x04, 0x01, // i64 min=1
+        0x05, 0x01, 0x02, // i64 min=1 max=2
+        0x01, 0x01, 0x02, // i32 min=1 max=2
+    ]);
+    for expected in [(1, None, true), (1, Some(2), true), (1, Some(2), false)] {
+        let Limits { min, max, memory64 } = parse_limits(&mut parser).unwrap();
+        assert_eq!((min, max, memory64), expected);
+    }
+    assert!(parser.all_consumed());
+
+    // min=0x1_0000_0001 doesn't fit into 32 bits
+    let bytes = |flags| [flags, 0x81, 0x80, 0x80, 0x80, 0x10];
+    let limits = parse_limits(&mut Parser::new(&bytes(0x04))).unwrap();
+    assert_eq!(limits.min, 0x1_0000_0001);
+    assert!(matches!(
+        parse_limits(&mut Parser::new(&bytes(0x00))),
+        Err(ParseError {
+            kind: ErrorKind::LimitTooLarge {
+                found: 0x1_0000_0001
+            },
+            offset: 1,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn parse_externref_table() {
+    let mut parser = Parser::new(&[
+        0x02, // 2 tables
+        0x70, 0x00, 0x01, // funcref min=1
+        0x6F, 0x01, 0x00, 0x02, // externref min=0 max=2
+    ]);
+    let tables = parse_table_section(&mut parser).unwrap();
+    assert_eq!(tables[0].elem_type, ElemType::FuncRef);
+    assert_eq!(tables[1].elem_type, ElemType::ExternRef);
+    assert_eq!(tables[1].limits.max, Some(2));
+}
+
+#[test]
+fn parse_arbitrary_bytes() {
+    #[rustfmt::skip]
+    let module = [
+        0x00, 0x61, 0x73, 0x6D, // magic
+        0x01, 0x00, 0x00, 0x00, // version
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7F, 0x01, 0x7F, // type section
+        0x02, 0x0A, 0x01, 0x03, b'e', b'n', b'v', 0x01, b'g', 0x03, 0x7F, 0x00, // import section
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x04, 0x04, 0x01, 0x70, 0x00, 0x01, // table section
+        0x05, 0x04, 0x01, 0x01, 0x01, 0x02, // memory section
+        0x06, 0x06, 0x01, 0x7F, 0x01, 0x41, 0x00, 0x0B, // global section
+        0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00, // export section
+        0x09, 0x07, 0x01, 0x00, 0x41, 0x00, 0x0B, 0x01, 0x00, // element section
+        0x0A, 0x1C, 0x01, 0x1A, 0x01, 0x01, 0x7F, // code section
+        0x02, 0x40, 0x03, 0x40, 0x20, 0x00, 0x04, 0x7F, 0x41, 0x01, 0x05, 0x41, 0x02, 0x0B, 0x1A,
+        0x0B, 0x0B, 0x20, 0x00, 0x28, 0x02, 0x00, 0x0B,
+        0x0B, 0x08, 0x01, 0x00, 0x41, 0x00, 0x0B, 0x02, b'h', b'i', // data section
+    ];
+    parse(&module).unwrap();
+
+    // xorshift64
+    let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+    let mut random = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    for _ in 0..10_000 {
+        // Replace, insert, or remove a few random bytes of the module
+        let mut bytes = module.to_vec();
+        for _ in 0..random() % 4 + 1 {
+            let idx = random() as usize % bytes.len();
+            match random() % 3 {
+                0 => bytes[idx] = random() as u8,
+                1 => bytes.insert(idx, random() as u8),
+                _ => {
+                    bytes.remove(idx);
+                }
+            }
+        }
+        let _ = parse(&bytes);
+        let _ = parse_reader(bytes.as_slice());
+    }
+
+    // Random bytes after the header
+    for _ in 0..10_000 {
+        let mut bytes = module[..8].to_vec();
+        bytes.extend((0..random() % 64).map(|_| random() as u8));
+        let _ = parse(&bytes);
+    }
+
+    // Blocks nested too deeply
+    let nested_blocks = |depth| {
+        let mut bytes = vec![];
+        for _ in 0..depth {
+            bytes.extend_from_slice(&[0x02, 0x40]);
+        }
+        bytes.resize(bytes.len() + depth + 1, 0x0B);
+        bytes
+    };
+    assert!(parse_expr(&mut Parser::new(&nested_blocks(MAX_BLOCK_DEPTH))).is_ok());
+    assert!(matches!(
+        parse_expr(&mut Parser::new(&nested_blocks(100_000))),
+        Err(ParseError {
+            kind: ErrorKind::BlockTooDeep,
+            offset: 2000,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn parse_function_count_mismatch() {
+    #[rustfmt::skip]
+    let bytes = [
+        0x00, 0x61, 0x73, 0x6D, // magic
+        0x01, 0x00, 0x00, 0x00, // version
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section with one `[] -> []` type
+        0x03, 0x02, 0x01, 0x00, // function section with one function
+        0x0A, 0x07, 0x02, 0x02, 0x00, 0x0B, 0x02, 0x00, 0x0B, // code section with two bodies
+    ];
+    assert!(matches!(
+        parse(&bytes),
+        Err(ParseError {
+            kind: ErrorKind::FunctionCountMismatch,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn parse_section_order() {
+    #[rustfmt::skip]
+    let bytes = [
+        0x00, 0x61, 0x73, 0x6D, // magic
+        0x01, 0x00, 0x00, 0x00, // version
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section with one `[] -> []` type
+        0x0A, 0x01, 0x00, // empty code section
+        0x03, 0x02, 0x01, 0x00, // function section
+    ];
+    assert!(matches!(
+        parse(&bytes),
+        Err(ParseError {
+            kind: ErrorKind::SectionOrder {
+                id: 3,
+                previous: 10
+            },
+            offset: 19,
+            ..
+        })
+    ));
+    assert_eq!(
+        parse(&bytes).err().unwrap().to_string(),
+        "section 3 is duplicate or out of order after section 10 at offset 19"
+    );
+
+    #[rustfmt::skip]
+    let bytes = [
+        0x00, 0x61, 0x73, 0x6D, // magic
+        0x01, 0x00, 0x00, 0x00, // version
+        0x05, 0x03, 0x01, 0x00, 0x01, // memory section
+        0x00, // custom sections can appear anywhere
+        0x02, 0x01, b'x',
+        0x05, 0x03, 0x01, 0x00, 0x01, // memory section
+    ];
+    assert!(matches!(
+        parse(&bytes),
+        Err(ParseError {
+            kind: ErrorKind::SectionOrder { id: 5, previous: 5 },
+            ..
+        })
+    ));
+
+    // Unknown section
+    assert!(matches!(
+        parse(&[0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00, 0x0D, 0x00]),
+        Err(ParseError {
+            kind: ErrorKind::UnexpectedSection { id: 13 },
+            ..
+        })
+    ));
+}
+
+#[test]
+fn parse_datacount() {
+    let module = |datacount: u8, data: bool| {
+        let mut bytes = vec![
+            0x00, 0x61, 0x73, 0x6D, // magic
+            0x01, 0x00, 0x00, 0x00, // version
+            0x0C, 0x01, datacount, // datacount section
+        ];
+        if data {
+            // data section with one passive segment
+            bytes.extend_from_slice(&[0x0B, 0x04, 0x01, 0x01, 0x01, 0xAA]);
+        }
+        bytes
+    };
+
+    let parsed = parse(&module(1, true)).unwrap();
+    assert_eq!(parsed.datacount, Some(1));
+    assert_eq!(parsed.data.len(), 1);
+
+    assert!(matches!(
+        parse(&module(2, true)),
+        Err(ParseError {
+            kind: ErrorKind::DataCountMismatch {
+                datacount: 2,
+                data: 1
+            },
+            offset: 17,
+            ..
+        })
+    ));
+    assert_eq!(
+        parse(&module(2, true)).err().unwrap().to_string(),
+        "data count 2 doesn't match the number of data segments 1 at offset 17"
+    );
+    assert!(matches!(
+        parse_reader(module(1, false).as_slice()),
+        Err(ParseError {
+            kind: ErrorKind::DataCountMismatch {
+                datacount: 1,
+                data: 0
+            },
+            offset: 11,
+            ..
+        })
+    ));
+}
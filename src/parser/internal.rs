@@ -1,4 +1,5 @@
 use std::backtrace::Backtrace;
+use std::fmt;
 
 // TODO: Not internal
 #[derive(Debug)]
@@ -10,13 +11,140 @@ pub struct ParseError {
 
 #[derive(Debug)]
 pub enum ErrorKind {
-    NotEnoughBytes { expected: usize, found: usize },
-    UnexpectedConst { expected: Vec<u8>, found: Vec<u8> },
-    UnexpectedValType { found: u8 },
-    SectionNotEmpty { remains: Vec<u8> },
-    Utf8Error { error: ::std::str::Utf8Error },
-    UnexpectedOpCode { op: u8 },
-    UnexpectedNameSubsection { found: u8 },
+    NotEnoughBytes {
+        expected: usize,
+        found: usize,
+    },
+    UnexpectedConst {
+        expected: Vec<u8>,
+        found: Vec<u8>,
+    },
+    UnexpectedValType {
+        found: u8,
+    },
+    SectionNotEmpty {
+        remains: Vec<u8>,
+    },
+    Utf8Error {
+        error: ::std::str::Utf8Error,
+    },
+    UnexpectedOpCode {
+        op: u8,
+    },
+    UnexpectedDataSegmentFlag {
+        found: u64,
+    },
+    UnexpectedElemSegmentFlag {
+        found: u64,
+    },
+    UnexpectedImportDesc {
+        found: u8,
+    },
+    UnexpectedExportDesc {
+        found: u8,
+    },
+    UnexpectedLimitsFlags {
+        found: u8,
+    },
+    UnexpectedMutability {
+        found: u8,
+    },
+    /// The number of function bodies in the code section is different from the number of
+    /// functions in the function section
+    FunctionCountMismatch,
+    /// The datacount section doesn't match the number of segments in the data section
+    DataCountMismatch {
+        datacount: u32,
+        data: usize,
+    },
+    /// LEB128 encoded value doesn't fit into 64 bits
+    Leb128Overflow,
+    /// Unknown section id
+    UnexpectedSection {
+        id: u8,
+    },
+    /// Section `id` is a duplicate of, or should come before, the previous non-custom section
+    /// `previous`
+    SectionOrder {
+        id: u8,
+        previous: u8,
+    },
+    /// Reading the module failed
+    IoError {
+        error: ::std::io::Error,
+    },
+    /// Blocks are nested deeper than `MAX_BLOCK_DEPTH`
+    BlockTooDeep,
+    /// A limit of a table or a 32-bit memory doesn't fit into 32 bits
+    LimitTooLarge {
+        found: u64,
+    },
+}
+
+/// Formats the error kind followed by the offset, e.g. "unknown section 13 at offset 8"
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.kind, self.offset)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::NotEnoughBytes { expected, found } => write!(
+                f,
+                "unexpected end: expected {} bytes, found {}",
+                expected, found
+            ),
+            ErrorKind::UnexpectedConst { expected, found } => {
+                write!(f, "expected bytes {:?}, found {:?}", expected, found)
+            }
+            ErrorKind::UnexpectedValType { found } => {
+                write!(f, "unknown value type 0x{:02x}", found)
+            }
+            ErrorKind::SectionNotEmpty { remains } => {
+                write!(f, "{} unused bytes at the end of section", remains.len())
+            }
+            ErrorKind::Utf8Error { error } => write!(f, "malformed UTF-8 encoding: {}", error),
+            ErrorKind::UnexpectedOpCode { op } => write!(f, "unknown opcode 0x{:02x}", op),
+            ErrorKind::UnexpectedDataSegmentFlag { found } => {
+                write!(f, "unknown data segment flag {}", found)
+            }
+            ErrorKind::UnexpectedElemSegmentFlag { found } => {
+                write!(f, "unknown element segment flag {}", found)
+            }
+            ErrorKind::UnexpectedImportDesc { found } => {
+                write!(f, "unknown import kind 0x{:02x}", found)
+            }
+            ErrorKind::UnexpectedExportDesc { found } => {
+                write!(f, "unknown export kind 0x{:02x}", found)
+            }
+            ErrorKind::UnexpectedLimitsFlags { found } => {
+                write!(f, "unknown limits flags 0x{:02x}", found)
+            }
+            ErrorKind::UnexpectedMutability { found } => {
+                write!(f, "unknown mutability 0x{:02x}", found)
+            }
+            ErrorKind::FunctionCountMismatch => {
+                write!(f, "function and code section have inconsistent lengths")
+            }
+            ErrorKind::DataCountMismatch { datacount, data } => write!(
+                f,
+                "data count {} doesn't match the number of data segments {}",
+                datacount, data
+            ),
+            ErrorKind::Leb128Overflow => write!(f, "integer representation too long"),
+            ErrorKind::UnexpectedSection { id } => write!(f, "unknown section {}", id),
+            ErrorKind::SectionOrder { id, previous } => write!(
+                f,
+                "section {} is duplicate or out of order after section {}",
+                id, previous
+            ),
+            ErrorKind::IoError { error } => write!(f, "cannot read module: {}", error),
+            ErrorKind::BlockTooDeep => write!(f, "blocks nested too deeply"),
+            ErrorKind::LimitTooLarge { found } => write!(f, "integer too large: {}", found),
+        }
+    }
 }
 
 pub type Result<A> = ::std::result::Result<A, ParseError>;
@@ -32,6 +160,11 @@ impl<'a> Parser<'a> {
         Parser { bytes, cursor: 0 }
     }
 
+    /// Create a parser for bytes at offset `cursor` in the module. The offset is used in errors.
+    pub fn new_at(bytes: &'a [u8], cursor: usize) -> Parser<'a> {
+        Parser { bytes, cursor }
+    }
+
     pub fn get_bytes(&self) -> &[u8] {
         self.bytes
     }
@@ -96,6 +229,10 @@ impl<'a> Parser<'a> {
 
         loop {
             let byte = self.consume_byte()?;
+            // The 10th byte can only have the highest bit of the value, and has to be the last byte
+            if shift == 63 && byte & 0b1111_1110 != 0 {
+                return Err(self.leb128_overflow());
+            }
             result |= (u64::from(byte & 0b0111_1111)) << shift;
             if byte & 0b1000_0000 == 0 {
                 break;
@@ -106,31 +243,50 @@ impl<'a> Parser<'a> {
         Ok(result)
     }
 
-    /// Decode a signed LEB128 value
-    pub fn consume_sleb128(&mut self) -> Result<i64> {
+    /// Decode a signed LEB128 value of `size` bits (e.g. 32 for `i32`, 33 for block type
+    /// indices). The result is sign extended to `i64`.
+    pub fn consume_sleb128(&mut self, size: u32) -> Result<i64> {
         let mut result = 0;
         let mut shift = 0;
 
-        let size = 33;
-
-        let mut byte = self.consume_byte()?;
         loop {
+            let byte = self.consume_byte()?;
+
+            // When the byte has the last bits of the value it has to be the last byte, and the
+            // unused bits need to be the same as the sign bit
+            let remaining_bits = size - shift;
+            if remaining_bits < 7 {
+                let unused_bits = (byte & 0b0111_1111) >> (remaining_bits - 1);
+                if byte & 0b1000_0000 != 0
+                    || (unused_bits != 0 && unused_bits != 0b0111_1111 >> (remaining_bits - 1))
+                {
+                    return Err(self.leb128_overflow());
+                }
+            }
+
             result |= (i64::from(byte & 0b0111_1111)) << shift;
+            shift += 7;
+
             if byte & 0b1000_0000 == 0 {
+                // Sign extend
+                if shift < 64 && byte & 0b0100_0000 != 0 {
+                    result |= !0 << shift;
+                }
                 break;
             }
-            shift += 7;
-            byte = self.consume_byte()?;
-        }
-
-        if shift < size && byte & 0b0100_0000 != 0 {
-            // or 0x40
-            result |= !0 << shift;
         }
 
         Ok(result)
     }
 
+    fn leb128_overflow(&self) -> ParseError {
+        ParseError {
+            kind: ErrorKind::Leb128Overflow,
+            offset: self.cursor,
+            backtrace: Backtrace::capture(),
+        }
+    }
+
     /// Read one byte without consuming.
     pub fn byte(&self) -> Result<u8> {
         match self.bytes.get(0) {
@@ -1,16 +1,50 @@
 #![allow(non_camel_case_types)]
 
+use std::collections::HashMap;
+use std::fmt;
 use std::rc::Rc;
 
-pub type TypeIdx = u32;
-pub type FuncIdx = u32;
+// Defines a module-local index type. Indices are distinct from each other and from store
+// addresses (`exec::store::FuncAddr` etc.), so e.g. a function index can't be used as a local
+// index or a function address.
+macro_rules! idx_type {
+    ($(#[$attr:meta])* $idx:ident) => {
+        $(#[$attr])*
+        #[repr(transparent)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $idx(pub u32);
+
+        impl fmt::Display for $idx {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+    };
+}
+
+idx_type!(TypeIdx);
+idx_type!(
+    /// Index of a function in the module's function index space: imported functions first, then
+    /// the functions defined in the module
+    FuncIdx
+);
+idx_type!(
+    /// Index of a global in the module's global index space: imported globals first, then the
+    /// globals defined in the module
+    GlobalIdx
+);
+idx_type!(
+    /// Index of a local in a function: arguments first, then the declared locals
+    LocalIdx
+);
+
 pub type TableIdx = u32;
 pub type MemIdx = u32;
-pub type GlobalIdx = u32;
-pub type LocalIdx = u32;
 pub type LabelIdx = u32;
+pub type DataIdx = u32;
+pub type ElemIdx = u32;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Module {
     pub types: Vec<FuncType>,
 
@@ -28,17 +62,19 @@ pub struct Module {
     pub datacount: Option<u32>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ValType {
     I32,
     I64,
     F32,
     F64,
+    FuncRef,
+    ExternRef,
 }
 
 pub type ResultType = Vec<ValType>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FuncType {
     pub args: ResultType,
     pub ret: ResultType,
@@ -53,16 +89,19 @@ pub struct Import {
 
 #[derive(Debug)]
 pub enum ImportDesc {
-    Func(FuncIdx),
-    Table(Limits),
+    Func(TypeIdx),
+    Table(Table),
     MemType(Limits),
     Global(GlobalType),
 }
 
 #[derive(Debug)]
 pub struct Limits {
-    pub min: u32,         // in pages
-    pub max: Option<u32>, // in pages
+    /// In pages for memories, in elements for tables. Fits into 32 bits unless `memory64` is set.
+    pub min: u64,
+    pub max: Option<u64>,
+    /// The memory uses 64-bit addresses (memory64 proposal). Always false for tables.
+    pub memory64: bool,
 }
 
 #[derive(Debug)]
@@ -85,7 +124,7 @@ pub struct Global {
     pub expr: Expr,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Expr {
     pub instrs: Rc<[Instruction]>,
 }
@@ -118,6 +157,10 @@ pub enum Instruction {
     Call(FuncIdx),
     // 0x11
     CallIndirect(TypeIdx),
+    // 0x12
+    ReturnCall(FuncIdx),
+    // 0x13
+    ReturnCallIndirect(TypeIdx),
 
     //
     // Parametric instructions
@@ -127,6 +170,8 @@ pub enum Instruction {
     Drop,
     // 0x1B
     Select,
+    // 0x1C
+    SelectT(Vec<ValType>),
 
     //
     // Variable instructions
@@ -143,6 +188,15 @@ pub enum Instruction {
     // 0x24
     GlobalSet(GlobalIdx),
 
+    //
+    // Table instructions
+    //
+
+    // 0x25
+    TableGet(TableIdx),
+    // 0x26
+    TableSet(TableIdx),
+
     //
     // Memory instructions
     //
@@ -466,6 +520,12 @@ pub enum Instruction {
     I64Extend16_s,
     // 0xC4
     I64Extend32_s,
+    // 0xD0
+    RefNull(ValType),
+    // 0xD1
+    RefIsNull,
+    // 0xD2
+    RefFunc(FuncIdx),
     // 0xFC 0x00
     I32TruncSatf32_s,
     // 0xFC 0x01
@@ -482,6 +542,26 @@ pub enum Instruction {
     I64TruncSatf64_s,
     // 0xFC 0x07
     I64TruncSatf64_u,
+    // 0xFC 0x08
+    MemoryInit(DataIdx),
+    // 0xFC 0x09
+    DataDrop(DataIdx),
+    // 0xFC 0x0A
+    MemoryCopy,
+    // 0xFC 0x0B
+    MemoryFill,
+    // 0xFC 0x0C
+    TableInit(ElemIdx, TableIdx),
+    // 0xFC 0x0D
+    ElemDrop(ElemIdx),
+    // 0xFC 0x0E
+    TableCopy(TableIdx, TableIdx),
+    // 0xFC 0x0F
+    TableGrow(TableIdx),
+    // 0xFC 0x10
+    TableSize(TableIdx),
+    // 0xFC 0x11
+    TableFill(TableIdx),
 }
 
 #[derive(Debug, Clone)]
@@ -513,10 +593,12 @@ pub struct BrTable {
 #[derive(Debug, Clone)]
 pub struct MemArg {
     pub align: u32,
-    pub offset: u32,
+    pub offset: u64,
+    /// Index of the accessed memory (multi-memory proposal)
+    pub mem: MemIdx,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ExportDesc {
     Func(FuncIdx),
     Table(TableIdx),
@@ -524,7 +606,7 @@ pub enum ExportDesc {
     Global(GlobalIdx),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Export {
     pub nm: String,
     pub desc: ExportDesc,
@@ -532,18 +614,28 @@ pub struct Export {
 
 #[derive(Debug)]
 pub struct Element {
-    pub table: TableIdx,
-    pub expr: Expr,
+    pub mode: ElemMode,
     pub init: Vec<FuncIdx>,
 }
 
+// https://github.com/WebAssembly/bulk-memory-operations/blob/master/proposals/bulk-memory-operations/Overview.md#element-segments
 #[derive(Debug)]
+pub enum ElemMode {
+    /// Only copied to a table with `table.init`
+    Passive,
+    /// Copied to the table during instantiation
+    Active { table: TableIdx, offset: Expr },
+    /// Only declares the functions for `ref.func`, can't be copied to a table
+    Declarative,
+}
+
+#[derive(Debug, Clone)]
 pub struct Local {
     pub n: u32,
     pub ty: ValType,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Fun {
     pub ty: TypeIdx,
     /// DOES NOT INCLUDE ARGUMENTS! Use the type for number of args.
@@ -557,28 +649,61 @@ pub struct Table {
     pub elem_type: ElemType,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ElemType {
     FuncRef,
+    ExternRef,
+}
+
+impl ElemType {
+    /// Type of the table's elements as values
+    pub fn val_type(self) -> ValType {
+        match self {
+            ElemType::FuncRef => ValType::FuncRef,
+            ElemType::ExternRef => ValType::ExternRef,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Data {
-    pub data: MemIdx,
-    pub offset: Expr,
+    pub mode: DataMode,
     pub init: Vec<u8>,
 }
 
+// https://github.com/WebAssembly/bulk-memory-operations/blob/master/proposals/bulk-memory-operations/Overview.md#data-segments
+#[derive(Debug)]
+pub enum DataMode {
+    /// Only copied to a memory with `memory.init`
+    Passive,
+    /// Copied to the memory during instantiation
+    Active { mem: MemIdx, offset: Expr },
+}
+
 // TODO
 #[derive(Debug)]
 pub struct Linking {}
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Names {
     /// Module name
     pub mod_name: Option<String>,
-    /// Maps function indices to names
-    pub fun_names: Vec<Option<String>>,
+    /// Maps function indices to names. Indices come from the module, so maps are used instead of
+    /// vectors to avoid allocating based on them.
+    pub fun_names: HashMap<FuncIdx, String>,
     /// Maps function indices to maps to their locals to names
-    pub local_names: Vec<Option<Vec<Option<String>>>>,
+    pub local_names: HashMap<FuncIdx, HashMap<LocalIdx, String>>,
+}
+
+impl Names {
+    pub fn fun_name(&self, fun_idx: FuncIdx) -> Option<&str> {
+        self.fun_names.get(&fun_idx).map(String::as_str)
+    }
+
+    pub fn local_name(&self, fun_idx: FuncIdx, local_idx: LocalIdx) -> Option<&str> {
+        self.local_names
+            .get(&fun_idx)?
+            .get(&local_idx)
+            .map(String::as_str)
+    }
 }
@@ -2,34 +2,47 @@
 
 use std::convert::TryFrom;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Token {
     Id(String),
-    String(String),
+    String(Vec<u8>),
     LParen,
     RParen,
     Keyword(String),
     Reserved(String),
     Integer(Sign, u64),
-    // Uninterpreted floats
-    Float {
-        hex: bool,
-        // Float part before the `.`
-        integral: u64,
-        // Float part after the `.`
-        decimal: f64,
-        // The part after 'E' or 'e'. Exponent of `integral.decimal`. Either 2^exponent or
-        // 10^exponent.
-        exponent: i64,
-    },
+    Float(Float),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Sign {
     Pos,
     Neg,
 }
 
+/// A float literal. Floats are converted to `f32` or `f64` depending on where they are used, with
+/// `Float::to_f32` and `Float::to_f64`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Float {
+    pub sign: Sign,
+    pub kind: FloatKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FloatKind {
+    Inf,
+    /// `nan`, or `nan:0x...` with the given payload
+    Nan(Option<u64>),
+    /// Digits of the number, without underscores. The exponent is decimal for decimal floats, and
+    /// binary for hex floats.
+    Num {
+        hex: bool,
+        integral: String,
+        frac: String,
+        exponent: i64,
+    },
+}
+
 pub struct Lexer<'a> {
     buf: &'a [u8],
     cursor: usize,
@@ -48,22 +61,16 @@ pub enum LexerError {
     InvalidUnicodeValue,
     InvalidStringChar,
     InvalidHexNumber,
+    /// Integer literal doesn't fit into 64 bits
+    IntegerTooLarge,
+    /// `inf` or `nan` literal with unexpected characters, e.g. `nan:123`
+    InvalidFloat,
 }
 
 impl<'a> Iterator for Lexer<'a> {
     type Item = Result<Token, LexerError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.next()
-    }
-}
-
-impl<'a> Lexer<'a> {
-    pub fn new(buf: &'a [u8]) -> Lexer<'a> {
-        Lexer { buf, cursor: 0 }
-    }
-
-    pub fn next(&mut self) -> Option<Result<Token, LexerError>> {
         if self.cursor >= self.buf.len() {
             return None;
         }
@@ -74,7 +81,7 @@ impl<'a> Lexer<'a> {
             }
 
             match self.buf[self.cursor] {
-                b' ' | b'\t' | b'\n' => {
+                b' ' | b'\t' | b'\n' | b'\r' => {
                     self.cursor += 1;
                 }
                 b';' => {
@@ -131,6 +138,12 @@ impl<'a> Lexer<'a> {
 
         Some(tok)
     }
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(buf: &'a [u8]) -> Lexer<'a> {
+        Lexer { buf, cursor: 0 }
+    }
 
     fn keyword_or_reserved(&mut self) -> Result<Token, LexerError> {
         let mut str = String::with_capacity(10);
@@ -143,6 +156,13 @@ impl<'a> Lexer<'a> {
             self.cursor += 1;
         }
 
+        if let Some(kind) = inf_or_nan(&str)? {
+            return Ok(Token::Float(Float {
+                sign: Sign::Pos,
+                kind,
+            }));
+        }
+
         Ok(Token::Reserved(str)) // TODO
     }
 
@@ -207,21 +227,23 @@ impl<'a> Lexer<'a> {
         Ok(id)
     }
 
-    fn string(&mut self) -> Result<String, LexerError> {
+    // Strings can contain arbitrary bytes with `\HH` escapes, so they're not necessarily UTF-8
+    fn string(&mut self) -> Result<Vec<u8>, LexerError> {
         debug_assert_eq!(self.buf[self.cursor], b'"');
         self.cursor += 1;
 
-        let mut str = String::with_capacity(10);
+        let mut bytes = Vec::with_capacity(10);
 
-        if self.cursor >= self.buf.len() {
-            return Err(LexerError::NonTerminatedString);
-        }
-
-        while self.buf[self.cursor] != b'"' {
+        loop {
+            if self.cursor >= self.buf.len() {
+                return Err(LexerError::NonTerminatedString);
+            }
             let b = self.buf[self.cursor];
             self.cursor += 1;
-            if b >= 0x20 && b != 0x7F && b != b'"' && b != b'\\' {
-                str.push(char::from(b));
+            if b == b'"' {
+                break;
+            } else if b >= 0x20 && b != 0x7F && b != b'\\' {
+                bytes.push(b);
             } else if b == b'\\' {
                 if self.cursor >= self.buf.len() {
                     return Err(LexerError::NonTerminatedString);
@@ -230,29 +252,29 @@ impl<'a> Lexer<'a> {
                 self.cursor += 1;
                 match b {
                     b't' => {
-                        str.push('\t');
+                        bytes.push(b'\t');
                     }
                     b'n' => {
-                        str.push('\n');
+                        bytes.push(b'\n');
                     }
                     b'r' => {
-                        str.push('\r');
+                        bytes.push(b'\r');
                     }
                     b'"' => {
-                        str.push('"');
+                        bytes.push(b'"');
                     }
                     b'\'' => {
-                        str.push('\'');
+                        bytes.push(b'\'');
                     }
                     b'\\' => {
-                        str.push('\\');
+                        bytes.push(b'\\');
                     }
                     b'u' => {
                         if self.cursor >= self.buf.len() {
                             return Err(LexerError::NonTerminatedString);
                         }
                         if self.buf[self.cursor] != b'{' {
-                            return Err(LexerError::NonTerminatedString);
+                            return Err(LexerError::InvalidEscapeSequence);
                         }
                         self.cursor += 1;
                         let num = self.hexnum()?;
@@ -261,7 +283,8 @@ impl<'a> Lexer<'a> {
                             .and_then(|u32| {
                                 char::try_from(u32).map_err(|_| LexerError::InvalidUnicodeValue)
                             })?;
-                        str.push(char);
+                        let mut char_bytes = [0; 4];
+                        bytes.extend_from_slice(char.encode_utf8(&mut char_bytes).as_bytes());
                         if self.cursor >= self.buf.len() || self.buf[self.cursor] != b'}' {
                             return Err(LexerError::NonTerminatedString);
                         }
@@ -272,17 +295,11 @@ impl<'a> Lexer<'a> {
                             return Err(LexerError::NonTerminatedString);
                         }
                         let b2 = self.buf[self.cursor];
-                        self.cursor += 1;
-                        match char::try_from(
-                            u32::from(hex_value(b1)) * 16 + u32::from(hex_value(b2)),
-                        ) {
-                            Ok(char) => {
-                                str.push(char);
-                            }
-                            Err(_) => {
-                                return Err(LexerError::InvalidUnicodeValue);
-                            }
+                        if !b2.is_ascii_hexdigit() {
+                            return Err(LexerError::InvalidEscapeSequence);
                         }
+                        self.cursor += 1;
+                        bytes.push(hex_value(b1) * 16 + hex_value(b2));
                     }
                     _ => {
                         return Err(LexerError::InvalidEscapeSequence);
@@ -293,9 +310,7 @@ impl<'a> Lexer<'a> {
             }
         }
 
-        debug_assert_eq!(self.buf[self.cursor], b'"');
-        self.cursor += 1;
-        Ok(str)
+        Ok(bytes)
     }
 
     // Parse a sign + float or integer. Sign is consumed. Hex or not is not known.
@@ -304,6 +319,19 @@ impl<'a> Lexer<'a> {
             return Err(LexerError::NonTerminatedNumber);
         }
 
+        // Signed `inf` and `nan`
+        if self.buf[self.cursor] == b'i' || self.buf[self.cursor] == b'n' {
+            let begin = self.cursor;
+            while self.cursor < self.buf.len() && is_id_char(self.buf[self.cursor]) {
+                self.cursor += 1;
+            }
+            let str = String::from_utf8_lossy(&self.buf[begin..self.cursor]);
+            return match inf_or_nan(&str)? {
+                Some(kind) => Ok(Token::Float(Float { sign, kind })),
+                None => Err(LexerError::InvalidFloat),
+            };
+        }
+
         let mut hex = false;
 
         if self.buf[self.cursor] == b'0'
@@ -314,66 +342,44 @@ impl<'a> Lexer<'a> {
             hex = true;
         }
 
-        let num = self.num(hex)?;
+        // 'num' ('.' 'frac'?)? (('E'|'e') 'sign' 'num')?
+        //
+        // For hex numbers the exponent starts with 'P' or 'p', and is a decimal power of two.
+
+        let integral = self.digits(hex);
+        if integral.is_empty() {
+            return Err(LexerError::NonTerminatedNumber);
+        }
 
         let mut float = false;
+
+        let mut frac = String::new();
         if self.cursor < self.buf.len() && self.buf[self.cursor] == b'.' {
             self.cursor += 1;
             float = true;
+            frac = self.digits(hex);
         }
 
-        // 1. 'num' '.'? ^
-        // 2. 'num' '.'  ^ 'frac'
-        // 3. 'num' '.'? ^ ('E'|'e') 'sign' 'num'
-        // 4. 'num' '.'  ^ 'frac' ('E'|'e') 'sign' 'num'
-        //               ^
-        //             cursor
-        //
-        // Also handle the hex variant where 'E' is 'P'
-        //
-        // NB. Not sure how to distinguish (1) without a '.' from an integer
-
-        if self.cursor < self.buf.len() {
-            // We may see 'e' or 'E' even without a dot before in (3)
-            match self.exp_opt(hex)? {
-                Some(exponent) => {
-                    // (3)
-                    return Ok(Token::Float {
-                        hex,
-                        integral: num,
-                        decimal: 0f64,
-                        exponent,
-                    });
-                }
-                None => {
-                    // Not (3), but should be (2) or (4) or we've seen a '.'
-                    if float {
-                        // (2) or (4)
-                        let decimal = self.frac(hex)?;
-                        match self.exp_opt(hex)? {
-                            Some(exponent) => {
-                                return Ok(Token::Float {
-                                    hex,
-                                    integral: num,
-                                    decimal,
-                                    exponent,
-                                });
-                            }
-                            None => {
-                                // (2)
-                                return Ok(Token::Float {
-                                    hex,
-                                    integral: num,
-                                    decimal,
-                                    exponent: 0,
-                                });
-                            }
-                        }
-                    }
-                }
-            }
+        let mut exponent = 0;
+        if let Some(exp) = self.exp_opt(hex)? {
+            float = true;
+            exponent = exp;
+        }
+
+        if float {
+            return Ok(Token::Float(Float {
+                sign,
+                kind: FloatKind::Num {
+                    hex,
+                    integral,
+                    frac,
+                    exponent,
+                },
+            }));
         }
 
+        let radix = if hex { 16 } else { 10 };
+        let num = u64::from_str_radix(&integral, radix).map_err(|_| LexerError::IntegerTooLarge)?;
         Ok(Token::Integer(sign, num))
     }
 
@@ -383,23 +389,31 @@ impl<'a> Lexer<'a> {
         }
 
         let c = self.buf[self.cursor];
-        if c == b'E' || c == b'e' || c == b'P' || c == b'p' {
-            if (hex && (c != b'P' && c != b'p')) || (!hex && (c != b'E' || c != b'e')) {
-                return Err(LexerError::InvalidHexNumber);
-            }
-
-            self.cursor += 1;
-            let exp_sign = self.sign();
-            let exp_sign = match exp_sign {
-                Sign::Pos => 1,
-                Sign::Neg => -1,
-            };
-            let exp_num = self.num(hex)?;
-
-            Ok(Some(exp_sign * exp_num as i64))
+        let is_exp = if hex {
+            c == b'P' || c == b'p'
         } else {
-            Ok(None)
+            c == b'E' || c == b'e'
+        };
+        if !is_exp {
+            return Ok(None);
         }
+
+        self.cursor += 1;
+        if self.cursor >= self.buf.len() {
+            return Err(LexerError::NonTerminatedNumber);
+        }
+        let exp_sign = self.sign();
+        let exp_digits = self.digits(false);
+        if exp_digits.is_empty() {
+            return Err(LexerError::NonTerminatedNumber);
+        }
+        // Saturate large exponents, the number will be out of range of `f64` anyway
+        let exp_num = exp_digits.parse::<i64>().unwrap_or(i64::MAX / 2);
+
+        Ok(Some(match exp_sign {
+            Sign::Pos => exp_num,
+            Sign::Neg => -exp_num,
+        }))
     }
 
     fn sign(&mut self) -> Sign {
@@ -415,36 +429,23 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn frac(&mut self, hex: bool) -> Result<f64, LexerError> {
-        let range_begin = self.cursor;
-        let mut range_end = self.cursor;
-
-        while range_end < self.buf.len() {
-            let b = self.buf[range_end];
-            if (hex && b.is_ascii_hexdigit()) || b.is_ascii_digit() || b == b'_' {
-                range_end += 1;
-            } else {
-                break;
-            }
-        }
-
-        self.cursor = range_end;
+    // Consume decimal or hex digits, skipping '_' separators
+    fn digits(&mut self, hex: bool) -> String {
+        let mut digits = String::new();
 
-        let mut ret = 0f64;
-        let m = if hex { 16f64 } else { 10f64 };
-        for i in (range_begin..range_end - 1).rev() {
-            let b = self.buf[i];
+        while self.cursor < self.buf.len() {
+            let b = self.buf[self.cursor];
             if (hex && b.is_ascii_hexdigit()) || b.is_ascii_digit() {
-                let f = f64::from(b - b'0');
-                ret = (f + (ret / m)) / m;
+                digits.push(char::from(b));
+                self.cursor += 1;
             } else if b == b'_' {
-                continue;
+                self.cursor += 1;
             } else {
-                panic!("Unexpected character in reverse scan: {}", char::from(b));
+                break;
             }
         }
 
-        Ok(ret)
+        digits
     }
 
     fn hexnum(&mut self) -> Result<u64, LexerError> {
@@ -465,26 +466,195 @@ impl<'a> Lexer<'a> {
 
         Ok(ret)
     }
+}
 
-    fn num(&mut self, hex: bool) -> Result<u64, LexerError> {
-        let mut ret = 0;
-        let m = if hex { 16 } else { 10 };
+// Parse `inf`, `nan`, and `nan:0x...`. Returns `None` if the string is not one of these.
+// `nan:canonical` and `nan:arithmetic` are result patterns in scripts, and lexed as keywords.
+fn inf_or_nan(str: &str) -> Result<Option<FloatKind>, LexerError> {
+    if str == "nan:canonical" || str == "nan:arithmetic" {
+        return Ok(None);
+    }
+    if str == "inf" {
+        return Ok(Some(FloatKind::Inf));
+    }
+    if str == "nan" {
+        return Ok(Some(FloatKind::Nan(None)));
+    }
+    if let Some(payload) = str.strip_prefix("nan:") {
+        let payload = match payload.strip_prefix("0x") {
+            Some(payload) if !payload.is_empty() => payload.replace('_', ""),
+            _ => return Err(LexerError::InvalidFloat),
+        };
+        return match u64::from_str_radix(&payload, 16) {
+            Ok(payload) => Ok(Some(FloatKind::Nan(Some(payload)))),
+            Err(_) => Err(LexerError::InvalidFloat),
+        };
+    }
+    Ok(None)
+}
 
-        while self.cursor < self.buf.len() {
-            let b = self.buf[self.cursor];
-            if (hex && b.is_ascii_hexdigit()) || b.is_ascii_digit() {
-                ret *= m;
-                ret += u64::from(if hex { hex_value(b) } else { b - b'0' });
-                self.cursor += 1;
-            } else if b == b'_' {
-                self.cursor += 1;
-            } else {
-                break;
+impl Float {
+    /// Convert the literal to `f32`. Returns `None` if the number is too large for `f32`, or the
+    /// NaN payload doesn't fit into the significand.
+    pub fn to_f32(&self) -> Option<f32> {
+        let bits = match &self.kind {
+            FloatKind::Num {
+                hex: false,
+                integral,
+                frac,
+                exponent,
+            } => {
+                let f = decimal_str(integral, frac, *exponent).parse::<f32>().ok()?;
+                if f.is_infinite() {
+                    return None;
+                }
+                let f = match self.sign {
+                    Sign::Pos => f,
+                    Sign::Neg => -f,
+                };
+                u64::from(f.to_bits())
+            }
+            _ => self.to_bits(23, 8)?,
+        };
+        Some(f32::from_bits(bits as u32))
+    }
+
+    /// Convert the literal to `f64`. Returns `None` if the number is too large for `f64`, or the
+    /// NaN payload doesn't fit into the significand.
+    pub fn to_f64(&self) -> Option<f64> {
+        let bits = match &self.kind {
+            FloatKind::Num {
+                hex: false,
+                integral,
+                frac,
+                exponent,
+            } => {
+                let f = decimal_str(integral, frac, *exponent).parse::<f64>().ok()?;
+                if f.is_infinite() {
+                    return None;
+                }
+                let f = match self.sign {
+                    Sign::Pos => f,
+                    Sign::Neg => -f,
+                };
+                f.to_bits()
             }
+            _ => self.to_bits(52, 11)?,
+        };
+        Some(f64::from_bits(bits))
+    }
+
+    // Bits of the float in a format with the given significand and exponent widths. Decimal
+    // numbers are handled by the callers with the standard library's correctly rounded parsers.
+    fn to_bits(&self, mant_bits: u32, exp_bits: u32) -> Option<u64> {
+        let sign_bit = match self.sign {
+            Sign::Pos => 0,
+            Sign::Neg => 1 << (mant_bits + exp_bits),
+        };
+        let exp_mask = ((1 << exp_bits) - 1) << mant_bits;
+
+        let bits = match &self.kind {
+            FloatKind::Inf => exp_mask,
+            FloatKind::Nan(None) => exp_mask | (1 << (mant_bits - 1)),
+            FloatKind::Nan(Some(payload)) => {
+                if *payload == 0 || *payload >= 1 << mant_bits {
+                    return None;
+                }
+                exp_mask | payload
+            }
+            FloatKind::Num {
+                hex: true,
+                integral,
+                frac,
+                exponent,
+            } => hex_float_bits(integral, frac, *exponent, mant_bits, exp_bits)?,
+            FloatKind::Num { hex: false, .. } => panic!("Float::to_bits: decimal float"),
+        };
+
+        Some(sign_bit | bits)
+    }
+}
+
+fn decimal_str(integral: &str, frac: &str, exponent: i64) -> String {
+    format!(
+        "{}.{}e{}",
+        integral,
+        if frac.is_empty() { "0" } else { frac },
+        exponent
+    )
+}
+
+// Bits of the absolute value of the hex float `0x{integral}.{frac}p{exponent}`, rounded to nearest
+// with ties to even. Returns `None` if the number is too large.
+fn hex_float_bits(
+    integral: &str,
+    frac: &str,
+    exponent: i64,
+    mant_bits: u32,
+    exp_bits: u32,
+) -> Option<u64> {
+    // The number is `m * 2^exp`. Digits that don't fit into `m` are only tracked as a "sticky"
+    // bit for rounding.
+    let mut m: u64 = 0;
+    let mut exp: i64 = exponent.saturating_sub(4 * frac.len() as i64);
+    let mut sticky = false;
+    for digit in integral.bytes().chain(frac.bytes()) {
+        let digit = u64::from(hex_value(digit));
+        if m >> 60 == 0 {
+            m = m * 16 + digit;
+        } else {
+            exp = exp.saturating_add(4);
+            sticky |= digit != 0;
         }
+    }
 
-        Ok(ret)
+    if m == 0 {
+        return Some(0);
+    }
+
+    let bias = (1i64 << (exp_bits - 1)) - 1;
+    let min_exp = 1 - bias;
+
+    // Exponent of the most significant bit of the number
+    let top_exp = exp.saturating_add(63 - i64::from(m.leading_zeros()));
+    // Exponent of the least significant bit of the significand. Subnormals have less precision.
+    let mut unit_exp = top_exp.max(min_exp) - i64::from(mant_bits);
+
+    let shift = unit_exp.saturating_sub(exp);
+    let mut q = if shift <= 0 {
+        m << -shift
+    } else if shift >= 65 {
+        // Less than half of the smallest subnormal
+        0
+    } else {
+        let m = u128::from(m);
+        let q = m >> shift;
+        let rem = m & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        if rem > half || (rem == half && (sticky || q & 1 == 1)) {
+            (q + 1) as u64
+        } else {
+            q as u64
+        }
+    };
+
+    // Rounding up may carry into a new bit
+    if q >> (mant_bits + 1) != 0 {
+        q >>= 1;
+        unit_exp += 1;
     }
+
+    if q >> mant_bits == 0 {
+        // Subnormal, or zero
+        return Some(q);
+    }
+
+    let biased_exp = unit_exp + i64::from(mant_bits) + bias;
+    if biased_exp >= (1 << exp_bits) - 1 {
+        return None;
+    }
+
+    Some(((biased_exp as u64) << mant_bits) | (q & ((1 << mant_bits) - 1)))
 }
 
 fn is_id_char(c: u8) -> bool {
@@ -520,7 +690,7 @@ fn parse_id() {
 #[test]
 fn parse_string() {
     let mut lexer = Lexer::new("\"test\"".as_bytes());
-    assert_eq!(lexer.string().unwrap(), "test".to_string());
+    assert_eq!(lexer.string().unwrap(), b"test");
 }
 
 #[test]
@@ -543,3 +713,157 @@ fn parse_float_1() {
         other => panic!("{:?}", other),
     }
 }
+
+#[cfg(test)]
+fn lex_one(str: &str) -> Token {
+    let mut lexer = Lexer::new(str.as_bytes());
+    let token = lexer.next().unwrap().unwrap();
+    assert!(lexer.next().is_none());
+    token
+}
+
+#[cfg(test)]
+fn lex_float(str: &str) -> Float {
+    match lex_one(str) {
+        Token::Float(float) => float,
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn lex_integers() {
+    assert!(matches!(lex_one("123"), Token::Integer(Sign::Pos, 123)));
+    assert!(matches!(lex_one("-0xFF"), Token::Integer(Sign::Neg, 255)));
+    assert!(matches!(lex_one("+0x1_0"), Token::Integer(Sign::Pos, 16)));
+    assert!(matches!(
+        lex_one("1_000_000"),
+        Token::Integer(Sign::Pos, 1_000_000)
+    ));
+    assert!(matches!(
+        lex_one("0xffff_ffff_ffff_ffff"),
+        Token::Integer(Sign::Pos, u64::MAX)
+    ));
+    assert!(matches!(
+        Lexer::new("0x1_0000_0000_0000_0000".as_bytes()).next(),
+        Some(Err(LexerError::IntegerTooLarge))
+    ));
+}
+
+#[test]
+fn lex_decimal_floats() {
+    assert_eq!(lex_float("1.5").to_f64(), Some(1.5));
+    assert_eq!(lex_float("-1.5").to_f32(), Some(-1.5));
+    assert_eq!(lex_float("1e10").to_f64(), Some(1e10));
+    assert_eq!(lex_float("1.E-2").to_f64(), Some(0.01));
+    assert_eq!(lex_float("1_000.000_1").to_f64(), Some(1000.0001));
+    assert_eq!(lex_float("0.1").to_f32(), Some(0.1));
+    // Out of range
+    assert_eq!(lex_float("1e39").to_f32(), None);
+    assert_eq!(lex_float("1e39").to_f64(), Some(1e39));
+}
+
+#[test]
+fn lex_hex_floats() {
+    assert_eq!(lex_float("0x1.fffffep+127").to_f32(), Some(f32::MAX));
+    assert_eq!(lex_float("0x1p-149").to_f32(), Some(f32::from_bits(1)));
+    assert_eq!(lex_float("0x1p-150").to_f32(), Some(0.0));
+    assert_eq!(lex_float("0x1.8p-149").to_f32(), Some(f32::from_bits(2)));
+    assert_eq!(lex_float("-0x1.8p1").to_f64(), Some(-3.0));
+    assert_eq!(lex_float("0x1.fffffffffffffp1023").to_f64(), Some(f64::MAX));
+    // Ties round to even
+    assert_eq!(lex_float("0x1.000001p0").to_f32(), Some(1.0));
+    assert_eq!(
+        lex_float("0x1.000003p0").to_f32(),
+        Some(f32::from_bits(0x3F80_0002))
+    );
+    // Rounds up to the next power of two
+    assert_eq!(lex_float("0x1.ffffffp0").to_f32(), Some(2.0));
+    // Out of range
+    assert_eq!(lex_float("0x1p128").to_f32(), None);
+}
+
+#[test]
+fn lex_inf_nan() {
+    assert_eq!(lex_float("inf").to_f32(), Some(f32::INFINITY));
+    assert_eq!(lex_float("-inf").to_f64(), Some(f64::NEG_INFINITY));
+    assert_eq!(
+        lex_float("nan").to_f32().map(f32::to_bits),
+        Some(0x7FC0_0000)
+    );
+    assert_eq!(
+        lex_float("-nan").to_f64().map(f64::to_bits),
+        Some(0xFFF8_0000_0000_0000)
+    );
+    assert_eq!(
+        lex_float("nan:0x400000").to_f32().map(f32::to_bits),
+        Some(0x7FC0_0000)
+    );
+    assert_eq!(
+        lex_float("+nan:0x1").to_f32().map(f32::to_bits),
+        Some(0x7F80_0001)
+    );
+    assert_eq!(
+        lex_float("nan:0x4_0000").to_f64().map(f64::to_bits),
+        Some(0x7FF0_0000_0004_0000)
+    );
+    // Payload doesn't fit into f32
+    assert_eq!(lex_float("nan:0x800000").to_f32(), None);
+    assert!(matches!(
+        Lexer::new("nan:123".as_bytes()).next(),
+        Some(Err(LexerError::InvalidFloat))
+    ));
+    assert!(matches!(lex_one("nan:canonical"), Token::Reserved(kw) if kw == "nan:canonical"));
+}
+
+#[test]
+fn lex_string_escapes() {
+    let mut lexer = Lexer::new(r#""a\t\n\\\"\'\u{3bb}\00\ff\7F" "λ""#.as_bytes());
+    match lexer.next() {
+        Some(Ok(Token::String(bytes))) => assert_eq!(bytes, b"a\t\n\\\"'\xCE\xBB\x00\xFF\x7F"),
+        other => panic!("{:?}", other),
+    }
+    match lexer.next() {
+        Some(Ok(Token::String(bytes))) => assert_eq!(bytes, "λ".as_bytes()),
+        other => panic!("{:?}", other),
+    }
+    assert!(lexer.next().is_none());
+}
+
+#[test]
+fn lex_string_errors() {
+    assert!(matches!(
+        Lexer::new(r#""abc"#.as_bytes()).next(),
+        Some(Err(LexerError::NonTerminatedString))
+    ));
+    assert!(matches!(
+        Lexer::new(r#""abc\"#.as_bytes()).next(),
+        Some(Err(LexerError::NonTerminatedString))
+    ));
+    assert!(matches!(
+        Lexer::new(r#""\0g""#.as_bytes()).next(),
+        Some(Err(LexerError::InvalidEscapeSequence))
+    ));
+    assert!(matches!(
+        Lexer::new(r#""\u{d800}""#.as_bytes()).next(),
+        Some(Err(LexerError::InvalidUnicodeValue))
+    ));
+}
+
+#[test]
+fn lex_comments() {
+    let mut lexer =
+        Lexer::new("(;a (; nested ;) ;) b ;; line comment (; ;)\r\n(;;) c ;; last line".as_bytes());
+    assert!(matches!(lexer.next(), Some(Ok(Token::Reserved(kw))) if kw == "b"));
+    assert!(matches!(lexer.next(), Some(Ok(Token::Reserved(kw))) if kw == "c"));
+    assert!(lexer.next().is_none());
+}
+
+#[test]
+fn lex_unterminated_block_comment() {
+    // Closing the nested comment doesn't close the outer one
+    let mut lexer = Lexer::new("(; (; ;) a".as_bytes());
+    assert!(matches!(
+        lexer.next(),
+        Some(Err(LexerError::NonTerminatedComment))
+    ));
+}
@@ -1,12 +1,31 @@
 #![allow(dead_code)]
 
 use crate::parser::types::*;
-use crate::parser::wast::lexer::{Lexer, LexerError, Token};
+use crate::parser::wast::lexer::{Float, FloatKind, Lexer, LexerError, Sign, Token};
 
-pub struct Parser<'a> {
-    lexer: Lexer<'a>,
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// Parser for the text format. Works on all tokens of the input, so that identifiers can be
+/// resolved before their definitions.
+pub struct Parser {
+    tokens: Vec<Token>,
+    cursor: usize,
+
+    // State of the module being parsed
+    ids: Ids,
+    types: Vec<FuncType>,
+    n_imported_funs: u32,
+    n_imported_tables: u32,
+    n_imported_mems: u32,
+    n_imported_globals: u32,
+
+    // State of the function being parsed
+    locals: HashMap<String, LocalIdx>,
+    labels: Vec<Option<String>>,
 }
 
+#[derive(Debug)]
 pub enum ParseError {
     LexerError(LexerError),
     UnexpectedToken {
@@ -14,6 +33,22 @@ pub enum ParseError {
         found: String,
     },
     UnexpectedEOF,
+    /// Identifier is not defined in the index space
+    UnknownId(String),
+    /// Identifier is defined twice in the same index space
+    DuplicateId(String),
+    /// Type index of a type use is not defined
+    UnknownType(TypeIdx),
+    /// Type use with a type index and params or results that don't match the type
+    InlineTypeMismatch,
+    /// Number literal doesn't fit into its type
+    ConstantOutOfRange,
+    /// Import or export name is not valid UTF-8
+    Utf8Error {
+        error: ::std::str::Utf8Error,
+    },
+    /// `(module binary ...)` in a script is not a valid binary module
+    BinaryModule(crate::parser::ParseError),
 }
 
 pub type Result<A> = ::std::result::Result<A, ParseError>;
@@ -24,54 +59,1838 @@ impl From<LexerError> for ParseError {
     }
 }
 
-impl<'a> Parser<'a> {
-    pub fn new(lexer: Lexer<'a>) -> Self {
-        Parser { lexer }
+/// Lex the whole input
+pub fn tokenize(lexer: Lexer) -> Result<Vec<Token>> {
+    Ok(lexer.collect::<::std::result::Result<Vec<_>, _>>()?)
+}
+
+/// Parse a `(module ...)` form
+pub fn parse_module(tokens: Vec<Token>) -> Result<Module> {
+    let mut parser = Parser::new(tokens);
+    let module = parser.parse_module()?;
+    match parser.peek() {
+        None => Ok(module),
+        Some(other) => Err(ParseError::UnexpectedToken {
+            expected: "end of input",
+            found: format!("{:?}", other),
+        }),
+    }
+}
+
+/// Parse a `.wast` script: modules interleaved with actions and assertions
+pub fn parse_script(tokens: Vec<Token>) -> Result<Vec<Command>> {
+    let mut parser = Parser::new(tokens);
+    let mut commands = vec![];
+    while parser.peek().is_some() {
+        commands.push(parser.command()?);
+    }
+    Ok(commands)
+}
+
+/// A command in a script
+#[derive(Debug)]
+pub enum Command {
+    /// Define a module. Following actions without a module identifier use the last defined
+    /// module.
+    Module {
+        id: Option<String>,
+        module: Module,
+    },
+    Action(Action),
+    AssertReturn {
+        action: Action,
+        results: Vec<Expected>,
+    },
+    AssertTrap {
+        action: Action,
+        message: String,
+    },
+    AssertExhaustion {
+        action: Action,
+        message: String,
+    },
+    AssertInvalid {
+        module: Module,
+        message: String,
+    },
+    /// A command that is not supported, e.g. `assert_malformed` or `register`
+    Unsupported(String),
+}
+
+#[derive(Debug)]
+pub enum Action {
+    /// Call an exported function
+    Invoke {
+        module: Option<String>,
+        name: String,
+        args: Vec<Const>,
+    },
+    /// Read an exported global
+    Get {
+        module: Option<String>,
+        name: String,
+    },
+}
+
+/// Arguments of actions and expected results of assertions
+#[derive(Debug, Clone, Copy)]
+pub enum Const {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    RefNull(ValType),
+    RefExtern(u32),
+}
+
+/// Expected result in `assert_return`
+#[derive(Debug, Clone, Copy)]
+pub enum Expected {
+    Const(Const),
+    /// `nan:canonical` of the given float type
+    CanonicalNan(ValType),
+    /// `nan:arithmetic` of the given float type
+    ArithmeticNan(ValType),
+    /// Any non-null function reference
+    RefFunc,
+}
+
+// Index spaces of a module
+#[derive(Debug, Clone, Copy)]
+enum Space {
+    Type,
+    Func,
+    Table,
+    Mem,
+    Global,
+    Elem,
+    Data,
+}
+
+// Identifiers and number of entries in each index space of a module
+#[derive(Debug, Default)]
+struct Ids {
+    types: IndexSpace,
+    funcs: IndexSpace,
+    tables: IndexSpace,
+    mems: IndexSpace,
+    globals: IndexSpace,
+    elems: IndexSpace,
+    datas: IndexSpace,
+}
+
+#[derive(Debug, Default)]
+struct IndexSpace {
+    ids: HashMap<String, u32>,
+    len: u32,
+}
+
+impl Ids {
+    fn space(&mut self, space: Space) -> &mut IndexSpace {
+        match space {
+            Space::Type => &mut self.types,
+            Space::Func => &mut self.funcs,
+            Space::Table => &mut self.tables,
+            Space::Mem => &mut self.mems,
+            Space::Global => &mut self.globals,
+            Space::Elem => &mut self.elems,
+            Space::Data => &mut self.datas,
+        }
+    }
+}
+
+impl IndexSpace {
+    // Add an entry to the index space, with an optional identifier
+    fn define(&mut self, id: Option<String>) -> Result<()> {
+        if let Some(id) = id {
+            if self.ids.insert(id.clone(), self.len).is_some() {
+                return Err(ParseError::DuplicateId(id));
+            }
+        }
+        self.len += 1;
+        Ok(())
+    }
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Parser {
+            tokens,
+            cursor: 0,
+            ids: Default::default(),
+            types: vec![],
+            n_imported_funs: 0,
+            n_imported_tables: 0,
+            n_imported_mems: 0,
+            n_imported_globals: 0,
+            locals: Default::default(),
+            labels: vec![],
+        }
     }
 
     pub fn parse_module(&mut self) -> Result<Module> {
-        self.parens(&mut |parser| {
+        self.lparen()?;
+        self.kw("module")?;
+        let mod_name = self.id_opt();
+
+        self.ids = Default::default();
+        self.types = vec![];
+        self.n_imported_funs = 0;
+        self.n_imported_tables = 0;
+        self.n_imported_mems = 0;
+        self.n_imported_globals = 0;
+
+        self.collect_ids()?;
+
+        let mut module = Module::default();
+        module.names.mod_name = mod_name;
+
+        while !self.peek_rparen() {
+            self.module_field(&mut module)?;
+        }
+        self.rparen()?;
+
+        module.types = std::mem::take(&mut self.types);
+        // Text modules don't have sections, the binary format would have a datacount section for
+        // `memory.init` and `data.drop`
+        module.datacount = Some(module.data.len() as u32);
+        Ok(module)
+    }
+
+    /////////////
+    // Scripts //
+    /////////////
+
+    fn command(&mut self) -> Result<Command> {
+        if self.peek_field("module") {
+            return Ok(match self.script_module()? {
+                Some((id, module)) => Command::Module { id, module },
+                None => Command::Unsupported("module quote".to_owned()),
+            });
+        }
 
-            parser.kw("module")?;
+        let begin = self.cursor;
+        self.lparen()?;
+        let kw = self.keyword()?;
+        let command = match kw.as_str() {
+            "invoke" | "get" => {
+                self.cursor = begin;
+                return Ok(Command::Action(self.action()?));
+            }
+            "assert_return" => {
+                let action = self.action()?;
+                let mut results = vec![];
+                while !self.peek_rparen() {
+                    results.push(self.expected()?);
+                }
+                Command::AssertReturn { action, results }
+            }
+            "assert_trap" | "assert_exhaustion" if !self.peek_field("module") => {
+                let action = self.action()?;
+                let message = self.name()?;
+                if kw == "assert_trap" {
+                    Command::AssertTrap { action, message }
+                } else {
+                    Command::AssertExhaustion { action, message }
+                }
+            }
+            "assert_invalid" => match self.script_module() {
+                Ok(Some((_, module))) => {
+                    let message = self.name()?;
+                    Command::AssertInvalid { module, message }
+                }
+                // Invalid modules that we can't parse, e.g. because of unknown identifiers
+                _ => {
+                    self.cursor = begin;
+                    self.lparen()?;
+                    self.skip_rest()?;
+                    return Ok(Command::Unsupported(kw));
+                }
+            },
+            _ => {
+                self.skip_rest()?;
+                return Ok(Command::Unsupported(kw));
+            }
+        };
+        self.rparen()?;
+        Ok(command)
+    }
 
-            todo!()
+    // `(module ...)`, `(module binary ...)`, or `(module quote ...)`. Returns `None` for quoted
+    // modules, which are not supported.
+    fn script_module(&mut self) -> Result<Option<(Option<String>, Module)>> {
+        let begin = self.cursor;
+        self.lparen()?;
+        self.kw("module")?;
+        let id = self.id_opt();
+        match self.peek_kw() {
+            Some("binary") => {
+                self.next_token()?;
+                let bytes = self.strings()?;
+                self.rparen()?;
+                let module = crate::parser::parse(&bytes).map_err(ParseError::BinaryModule)?;
+                Ok(Some((id, module)))
+            }
+            Some("quote") => {
+                self.skip_rest()?;
+                Ok(None)
+            }
+            _ => {
+                self.cursor = begin;
+                let module = self.parse_module()?;
+                Ok(Some((id, module)))
+            }
+        }
+    }
+
+    fn action(&mut self) -> Result<Action> {
+        self.lparen()?;
+        let action = match self.keyword()?.as_str() {
+            "invoke" => {
+                let module = self.id_opt();
+                let name = self.name()?;
+                let mut args = vec![];
+                while !self.peek_rparen() {
+                    args.push(self.script_const()?);
+                }
+                Action::Invoke { module, name, args }
+            }
+            "get" => {
+                let module = self.id_opt();
+                let name = self.name()?;
+                Action::Get { module, name }
+            }
+            other => return self.unexpected("action", other),
+        };
+        self.rparen()?;
+        Ok(action)
+    }
+
+    fn script_const(&mut self) -> Result<Const> {
+        self.lparen()?;
+        let const_ = match self.keyword()?.as_str() {
+            "i32.const" => Const::I32(self.i32()?),
+            "i64.const" => Const::I64(self.i64()?),
+            "f32.const" => Const::F32(self.f32()?),
+            "f64.const" => Const::F64(self.f64()?),
+            "ref.null" => Const::RefNull(self.heap_type()?),
+            "ref.extern" => Const::RefExtern(self.u32()?),
+            other => return self.unexpected("constant", other),
+        };
+        self.rparen()?;
+        Ok(const_)
+    }
+
+    fn expected(&mut self) -> Result<Expected> {
+        let ty = match self.peek_kw_at(self.cursor + 1) {
+            Some("f32.const") => ValType::F32,
+            Some("f64.const") => ValType::F64,
+            Some("ref.func") => {
+                self.lparen()?;
+                self.kw("ref.func")?;
+                self.rparen()?;
+                return Ok(Expected::RefFunc);
+            }
+            _ => return Ok(Expected::Const(self.script_const()?)),
+        };
+        let expected = match self.peek_kw_at(self.cursor + 2) {
+            Some("nan:canonical") => Expected::CanonicalNan(ty),
+            Some("nan:arithmetic") => Expected::ArithmeticNan(ty),
+            _ => return Ok(Expected::Const(self.script_const()?)),
+        };
+        self.lparen()?;
+        self.keyword()?;
+        self.keyword()?;
+        self.rparen()?;
+        Ok(expected)
+    }
+
+    ////////////////////////////////
+    // Pass 1: collect identifiers //
+    ////////////////////////////////
+
+    // Assign indices to the identifiers of module fields, and parse type definitions so that
+    // implicit types of type uses are added after them. Does not move the cursor.
+    fn collect_ids(&mut self) -> Result<()> {
+        let begin = self.cursor;
+
+        while !self.peek_rparen() {
+            self.lparen()?;
+            let field = self.keyword()?;
+            match field.as_str() {
+                "type" => {
+                    let id = self.id_opt();
+                    self.ids.types.define(id)?;
+                    self.lparen()?;
+                    self.kw("func")?;
+                    let (ty, _) = self.params_results()?;
+                    self.rparen()?;
+                    self.types.push(ty);
+                }
+                "import" => {
+                    self.name()?;
+                    self.name()?;
+                    self.lparen()?;
+                    let space = match self.keyword()?.as_str() {
+                        "func" => Space::Func,
+                        "table" => Space::Table,
+                        "memory" => Space::Mem,
+                        "global" => Space::Global,
+                        other => return self.unexpected("import kind", other),
+                    };
+                    let id = self.id_opt();
+                    self.ids.space(space).define(id)?;
+                    // End of the import description
+                    self.skip_rest()?;
+                }
+                "func" | "table" | "memory" | "global" => {
+                    let space = match field.as_str() {
+                        "func" => Space::Func,
+                        "table" => Space::Table,
+                        "memory" => Space::Mem,
+                        _ => Space::Global,
+                    };
+                    let id = self.id_opt();
+                    self.ids.space(space).define(id)?;
+                    // Inline element and data segments
+                    if field == "table" && self.has_child("elem") {
+                        self.ids.elems.define(None)?;
+                    }
+                    if field == "memory" && self.has_child("data") {
+                        self.ids.datas.define(None)?;
+                    }
+                }
+                "elem" => {
+                    let id = self.id_opt();
+                    self.ids.elems.define(id)?;
+                }
+                "data" => {
+                    let id = self.id_opt();
+                    self.ids.datas.define(id)?;
+                }
+                _ => {}
+            }
+            self.skip_rest()?;
+        }
+
+        self.cursor = begin;
+        Ok(())
+    }
+
+    // Whether the current form has a child form starting with the keyword
+    fn has_child(&self, kw: &str) -> bool {
+        let mut depth = 0;
+        for (i, token) in self.tokens[self.cursor..].iter().enumerate() {
+            match token {
+                Token::LParen => {
+                    if depth == 0 && self.peek_kw_at(self.cursor + i + 1) == Some(kw) {
+                        return true;
+                    }
+                    depth += 1;
+                }
+                Token::RParen => {
+                    if depth == 0 {
+                        return false;
+                    }
+                    depth -= 1;
+                }
+                _ => {}
+            }
+        }
+        false
+    }
+
+    ////////////////////////////
+    // Pass 2: module fields //
+    ////////////////////////////
+
+    fn module_field(&mut self, module: &mut Module) -> Result<()> {
+        self.lparen()?;
+        match self.keyword()?.as_str() {
+            "type" => {
+                // Parsed in the first pass
+                self.skip_rest()?;
+                return Ok(());
+            }
+            "import" => self.import_field(module)?,
+            "func" => self.func_field(module)?,
+            "table" => self.table_field(module)?,
+            "memory" => self.memory_field(module)?,
+            "global" => self.global_field(module)?,
+            "export" => {
+                let nm = self.name()?;
+                self.lparen()?;
+                let desc = match self.keyword()?.as_str() {
+                    "func" => ExportDesc::Func(self.func_idx()?),
+                    "table" => ExportDesc::Table(self.index(Space::Table)?),
+                    "memory" => ExportDesc::Mem(self.index(Space::Mem)?),
+                    "global" => ExportDesc::Global(self.global_idx()?),
+                    other => return self.unexpected("export kind", other),
+                };
+                self.rparen()?;
+                module.exports.push(Export { nm, desc });
+            }
+            "start" => {
+                module.start = Some(self.func_idx()?);
+            }
+            "elem" => self.elem_field(module)?,
+            "data" => self.data_field(module)?,
+            other => return self.unexpected("module field", other),
+        }
+        self.rparen()
+    }
+
+    fn import_field(&mut self, module: &mut Module) -> Result<()> {
+        let module_name = self.name()?;
+        let name = self.name()?;
+        self.lparen()?;
+        let kind = self.keyword()?;
+        self.id_opt();
+        let desc = self.import_desc(&kind)?;
+        self.rparen()?;
+        module.imports.push(Import {
+            module: module_name,
+            name,
+            desc,
+        });
+        Ok(())
+    }
+
+    // Import description after the kind keyword and identifier
+    fn import_desc(&mut self, kind: &str) -> Result<ImportDesc> {
+        match kind {
+            "func" => {
+                self.n_imported_funs += 1;
+                let (type_idx, _) = self.type_use()?;
+                Ok(ImportDesc::Func(type_idx))
+            }
+            "table" => {
+                self.n_imported_tables += 1;
+                Ok(ImportDesc::Table(self.table_type()?))
+            }
+            "memory" => {
+                self.n_imported_mems += 1;
+                Ok(ImportDesc::MemType(self.mem_type()?))
+            }
+            "global" => {
+                self.n_imported_globals += 1;
+                Ok(ImportDesc::Global(self.global_type()?))
+            }
+            other => self.unexpected("import kind", other),
+        }
+    }
+
+    // Inline exports of a function, table, memory, or global
+    fn inline_exports<I: Copy>(
+        &mut self,
+        module: &mut Module,
+        desc: &dyn Fn(I) -> ExportDesc,
+        idx: I,
+    ) -> Result<()> {
+        while self.peek_field("export") {
+            self.lparen()?;
+            self.kw("export")?;
+            let nm = self.name()?;
+            self.rparen()?;
+            module.exports.push(Export {
+                nm,
+                desc: desc(idx),
+            });
+        }
+        Ok(())
+    }
+
+    // Inline import of a function, table, memory, or global. Returns whether the field was an
+    // import.
+    fn inline_import(&mut self, module: &mut Module, kind: &str) -> Result<bool> {
+        if !self.peek_field("import") {
+            return Ok(false);
+        }
+        self.lparen()?;
+        self.kw("import")?;
+        let module_name = self.name()?;
+        let name = self.name()?;
+        self.rparen()?;
+        let desc = self.import_desc(kind)?;
+        module.imports.push(Import {
+            module: module_name,
+            name,
+            desc,
+        });
+        Ok(true)
+    }
+
+    fn func_field(&mut self, module: &mut Module) -> Result<()> {
+        let id = self.id_opt();
+        let fun_idx = FuncIdx(self.n_imported_funs + module.funs.len() as u32);
+        self.inline_exports(module, &ExportDesc::Func, fun_idx)?;
+
+        if let Some(id) = id {
+            module.names.fun_names.insert(fun_idx, id);
+        }
+
+        if self.inline_import(module, "func")? {
+            return Ok(());
+        }
+
+        let (ty, param_ids) = self.type_use()?;
+
+        self.locals.clear();
+        let mut n_locals = 0;
+        for param_id in param_ids {
+            if let Some(id) = param_id {
+                self.define_local(id, LocalIdx(n_locals))?;
+            }
+            n_locals += 1;
+        }
+
+        let mut locals = vec![];
+        while self.peek_field("local") {
+            self.lparen()?;
+            self.kw("local")?;
+            if let Some(id) = self.id_opt() {
+                self.define_local(id, LocalIdx(n_locals))?;
+                locals.push(Local {
+                    n: 1,
+                    ty: self.valtype()?,
+                });
+                n_locals += 1;
+            } else {
+                while !self.peek_rparen() {
+                    locals.push(Local {
+                        n: 1,
+                        ty: self.valtype()?,
+                    });
+                    n_locals += 1;
+                }
+            }
+            self.rparen()?;
+        }
+
+        self.labels.clear();
+        let instrs = self.instrs()?;
+
+        module.funs.push(Fun {
+            ty,
+            locals,
+            expr: Expr {
+                instrs: instrs.into(),
+            },
+        });
+        Ok(())
+    }
+
+    fn define_local(&mut self, id: String, local_idx: LocalIdx) -> Result<()> {
+        if self.locals.insert(id.clone(), local_idx).is_some() {
+            return Err(ParseError::DuplicateId(id));
+        }
+        Ok(())
+    }
+
+    fn table_field(&mut self, module: &mut Module) -> Result<()> {
+        self.id_opt();
+        let table_idx = self.n_imported_tables + module.tables.len() as u32;
+        self.inline_exports(module, &ExportDesc::Table, table_idx)?;
+
+        if self.inline_import(module, "table")? {
+            return Ok(());
+        }
+
+        if self.peek_field("elem") || !matches!(self.peek(), Some(Token::Integer(_, _))) {
+            // Table with an inline element segment. The table has the size of the segment.
+            let elem_type = self.elem_type()?;
+            self.lparen()?;
+            self.kw("elem")?;
+            let mut init = vec![];
+            while !self.peek_rparen() {
+                init.push(self.func_idx()?);
+            }
+            self.rparen()?;
+            let n = init.len() as u64;
+            module.tables.push(Table {
+                limits: Limits {
+                    min: n,
+                    max: Some(n),
+                    memory64: false,
+                },
+                elem_type,
+            });
+            module.elems.push(Element {
+                mode: ElemMode::Active {
+                    table: table_idx,
+                    offset: Expr {
+                        instrs: vec![Instruction::I32Const(0)].into(),
+                    },
+                },
+                init,
+            });
+        } else {
+            let table = self.table_type()?;
+            module.tables.push(table);
+        }
+
+        Ok(())
+    }
+
+    fn memory_field(&mut self, module: &mut Module) -> Result<()> {
+        self.id_opt();
+        let mem_idx = self.n_imported_mems + module.mem_addrs.len() as u32;
+        self.inline_exports(module, &ExportDesc::Mem, mem_idx)?;
+
+        if self.inline_import(module, "memory")? {
+            return Ok(());
+        }
+
+        let memory64 = self.address_type()?;
+        if self.peek_field("data") {
+            // Memory with an inline data segment. The memory has the size of the segment.
+            self.lparen()?;
+            self.kw("data")?;
+            let init = self.strings()?;
+            self.rparen()?;
+            let n_pages = init.len().div_ceil(65536) as u64;
+            module.mem_addrs.push(Limits {
+                min: n_pages,
+                max: Some(n_pages),
+                memory64,
+            });
+            let offset = if memory64 {
+                Instruction::I64Const(0)
+            } else {
+                Instruction::I32Const(0)
+            };
+            module.data.push(Data {
+                mode: DataMode::Active {
+                    mem: mem_idx,
+                    offset: Expr {
+                        instrs: vec![offset].into(),
+                    },
+                },
+                init,
+            });
+        } else {
+            module.mem_addrs.push(self.limits(memory64)?);
+        }
+
+        Ok(())
+    }
+
+    fn global_field(&mut self, module: &mut Module) -> Result<()> {
+        self.id_opt();
+        let global_idx = GlobalIdx(self.n_imported_globals + module.globals.len() as u32);
+        self.inline_exports(module, &ExportDesc::Global, global_idx)?;
+
+        if self.inline_import(module, "global")? {
+            return Ok(());
+        }
+
+        let ty = self.global_type()?;
+        self.labels.clear();
+        let instrs = self.instrs()?;
+        module.globals.push(Global {
+            ty,
+            expr: Expr {
+                instrs: instrs.into(),
+            },
+        });
+        Ok(())
+    }
+
+    fn elem_field(&mut self, module: &mut Module) -> Result<()> {
+        self.id_opt();
+
+        let mode = if self.peek_kw() == Some("declare") {
+            self.next_token()?;
+            ElemMode::Declarative
+        } else if self.peek_field("table") {
+            self.lparen()?;
+            self.kw("table")?;
+            let table = self.index(Space::Table)?;
+            self.rparen()?;
+            let offset = self.offset_expr()?;
+            ElemMode::Active { table, offset }
+        } else if matches!(self.peek(), Some(Token::LParen)) {
+            let offset = self.offset_expr()?;
+            ElemMode::Active { table: 0, offset }
+        } else {
+            ElemMode::Passive
+        };
+
+        let mut init = vec![];
+        match self.peek_kw() {
+            Some("func") => {
+                self.next_token()?;
+                while !self.peek_rparen() {
+                    init.push(self.func_idx()?);
+                }
+            }
+            Some("funcref") => {
+                // Element expressions, only `ref.func` is supported
+                self.next_token()?;
+                while !self.peek_rparen() {
+                    self.lparen()?;
+                    let item = self.peek_kw() == Some("item");
+                    if item {
+                        self.next_token()?;
+                        self.lparen()?;
+                    }
+                    self.kw("ref.func")?;
+                    init.push(self.func_idx()?);
+                    self.rparen()?;
+                    if item {
+                        self.rparen()?;
+                    }
+                }
+            }
+            _ => {
+                while !self.peek_rparen() {
+                    init.push(self.func_idx()?);
+                }
+            }
+        }
+
+        module.elems.push(Element { mode, init });
+        Ok(())
+    }
+
+    fn data_field(&mut self, module: &mut Module) -> Result<()> {
+        self.id_opt();
+
+        let mode = if self.peek_field("memory") {
+            self.lparen()?;
+            self.kw("memory")?;
+            let mem = self.index(Space::Mem)?;
+            self.rparen()?;
+            let offset = self.offset_expr()?;
+            DataMode::Active { mem, offset }
+        } else if matches!(self.peek(), Some(Token::LParen)) {
+            let offset = self.offset_expr()?;
+            DataMode::Active { mem: 0, offset }
+        } else {
+            DataMode::Passive
+        };
+
+        let init = self.strings()?;
+        module.data.push(Data { mode, init });
+        Ok(())
+    }
+
+    // `(offset instr*)`, or a single folded instruction
+    fn offset_expr(&mut self) -> Result<Expr> {
+        self.labels.clear();
+        let instrs = if self.peek_field("offset") {
+            self.lparen()?;
+            self.kw("offset")?;
+            let instrs = self.instrs()?;
+            self.rparen()?;
+            instrs
+        } else {
+            let mut instrs = vec![];
+            self.folded_instr(&mut instrs)?;
+            instrs
+        };
+        Ok(Expr {
+            instrs: instrs.into(),
         })
     }
 
-    fn next_token(&mut self) -> Result<Token> {
-        match self.lexer.next() {
-            None => Err(ParseError::UnexpectedEOF),
-            Some(Err(err)) => Err(err.into()),
-            Some(Ok(token)) => Ok(token),
+    ///////////
+    // Types //
+    ///////////
+
+    fn valtype(&mut self) -> Result<ValType> {
+        match self.keyword()?.as_str() {
+            "i32" => Ok(ValType::I32),
+            "i64" => Ok(ValType::I64),
+            "f32" => Ok(ValType::F32),
+            "f64" => Ok(ValType::F64),
+            "funcref" => Ok(ValType::FuncRef),
+            "externref" => Ok(ValType::ExternRef),
+            other => self.unexpected("value type", other),
+        }
+    }
+
+    fn elem_type(&mut self) -> Result<ElemType> {
+        match self.keyword()?.as_str() {
+            "funcref" => Ok(ElemType::FuncRef),
+            "externref" => Ok(ElemType::ExternRef),
+            other => self.unexpected("reference type", other),
+        }
+    }
+
+    // `func` or `extern` in `ref.null`
+    fn heap_type(&mut self) -> Result<ValType> {
+        match self.keyword()?.as_str() {
+            "func" => Ok(ValType::FuncRef),
+            "extern" => Ok(ValType::ExternRef),
+            other => self.unexpected("reference type", other),
+        }
+    }
+
+    fn limits(&mut self, memory64: bool) -> Result<Limits> {
+        let min = self.limit(memory64)?;
+        let max = match self.peek() {
+            Some(Token::Integer(_, _)) => Some(self.limit(memory64)?),
+            _ => None,
+        };
+        Ok(Limits { min, max, memory64 })
+    }
+
+    // Limits of memory64 memories are 64-bit, other limits are 32-bit
+    fn limit(&mut self, memory64: bool) -> Result<u64> {
+        if memory64 {
+            self.u64()
+        } else {
+            self.u32().map(u64::from)
+        }
+    }
+
+    // `i32` or `i64` before memory limits, returns whether the memory is a memory64
+    fn address_type(&mut self) -> Result<bool> {
+        match self.peek_kw() {
+            Some("i64") => {
+                self.next_token()?;
+                Ok(true)
+            }
+            Some("i32") => {
+                self.next_token()?;
+                Ok(false)
+            }
+            _ => Ok(false),
         }
     }
 
-    fn parens<A>(&mut self, parse: &mut dyn FnMut(&mut Parser<'a>) -> Result<A>) -> Result<A> {
+    fn mem_type(&mut self) -> Result<Limits> {
+        let memory64 = self.address_type()?;
+        self.limits(memory64)
+    }
+
+    fn table_type(&mut self) -> Result<Table> {
+        let limits = self.limits(false)?;
+        let elem_type = self.elem_type()?;
+        Ok(Table { limits, elem_type })
+    }
+
+    fn global_type(&mut self) -> Result<GlobalType> {
+        if self.peek_field("mut") {
+            self.lparen()?;
+            self.kw("mut")?;
+            let ty = self.valtype()?;
+            self.rparen()?;
+            Ok(GlobalType {
+                ty,
+                mut_: Mutability::Var,
+            })
+        } else {
+            Ok(GlobalType {
+                ty: self.valtype()?,
+                mut_: Mutability::Const,
+            })
+        }
+    }
+
+    // `(param ...)* (result ...)*`. Returns the type and identifiers of the parameters.
+    fn params_results(&mut self) -> Result<(FuncType, Vec<Option<String>>)> {
+        let mut args = vec![];
+        let mut param_ids = vec![];
+        while self.peek_field("param") {
+            self.lparen()?;
+            self.kw("param")?;
+            if let Some(id) = self.id_opt() {
+                args.push(self.valtype()?);
+                param_ids.push(Some(id));
+            } else {
+                while !self.peek_rparen() {
+                    args.push(self.valtype()?);
+                    param_ids.push(None);
+                }
+            }
+            self.rparen()?;
+        }
+
+        let mut ret = vec![];
+        while self.peek_field("result") {
+            self.lparen()?;
+            self.kw("result")?;
+            while !self.peek_rparen() {
+                ret.push(self.valtype()?);
+            }
+            self.rparen()?;
+        }
+
+        Ok((FuncType { args, ret }, param_ids))
+    }
+
+    // `(type x)? (param ...)* (result ...)*`. Without a type index, the first type that matches
+    // the params and results is used, or a new type is added to the module.
+    fn type_use(&mut self) -> Result<(TypeIdx, Vec<Option<String>>)> {
+        let type_idx = if self.peek_field("type") {
+            self.lparen()?;
+            self.kw("type")?;
+            let type_idx = TypeIdx(self.index(Space::Type)?);
+            self.rparen()?;
+            Some(type_idx)
+        } else {
+            None
+        };
+
+        let (ty, mut param_ids) = self.params_results()?;
+
+        match type_idx {
+            Some(type_idx) => {
+                let defined_ty = match self.types.get(type_idx.0 as usize) {
+                    Some(defined_ty) => defined_ty,
+                    None => return Err(ParseError::UnknownType(type_idx)),
+                };
+                if ty.args.is_empty() && ty.ret.is_empty() {
+                    param_ids = vec![None; defined_ty.args.len()];
+                } else if &ty != defined_ty {
+                    return Err(ParseError::InlineTypeMismatch);
+                }
+                Ok((type_idx, param_ids))
+            }
+            None => Ok((self.implicit_type(ty), param_ids)),
+        }
+    }
+
+    // Table index and type use of `call_indirect` and `return_call_indirect`. Only table 0 is
+    // supported.
+    fn call_indirect_type(&mut self) -> Result<TypeIdx> {
+        if self.peek_index() {
+            let table_idx = self.index(Space::Table)?;
+            if table_idx != 0 {
+                return self.unexpected("table 0", &table_idx.to_string());
+            }
+        }
+        let (type_idx, _) = self.type_use()?;
+        Ok(type_idx)
+    }
+
+    fn implicit_type(&mut self, ty: FuncType) -> TypeIdx {
+        match self.types.iter().position(|ty_| ty_ == &ty) {
+            Some(type_idx) => TypeIdx(type_idx as u32),
+            None => {
+                self.types.push(ty);
+                TypeIdx(self.types.len() as u32 - 1)
+            }
+        }
+    }
+
+    fn block_type(&mut self) -> Result<BlockType> {
+        if self.peek_field("type") || self.peek_field("param") {
+            let (type_idx, _) = self.type_use()?;
+            return Ok(BlockType::TypeIdx(type_idx));
+        }
+        let (ty, _) = self.params_results()?;
+        match ty.ret.as_slice() {
+            [] => Ok(BlockType::Empty),
+            [ty] => Ok(BlockType::ValType(*ty)),
+            _ => Ok(BlockType::TypeIdx(self.implicit_type(ty))),
+        }
+    }
+
+    //////////////////
+    // Instructions //
+    //////////////////
+
+    // Plain and folded instructions until the end of the current form or block
+    fn instrs(&mut self) -> Result<Vec<Instruction>> {
+        let mut instrs = vec![];
+        loop {
+            match self.peek() {
+                None | Some(Token::RParen) => break,
+                Some(Token::LParen) => self.folded_instr(&mut instrs)?,
+                Some(Token::Reserved(kw)) if kw == "end" || kw == "else" => break,
+                Some(_) => instrs.push(self.instr()?),
+            }
+        }
+        Ok(instrs)
+    }
+
+    // A folded instruction. Operands of the instruction are added before the instruction.
+    fn folded_instr(&mut self, instrs: &mut Vec<Instruction>) -> Result<()> {
+        self.lparen()?;
+        match self.peek_kw() {
+            Some("block") | Some("loop") => {
+                let kw = self.keyword()?;
+                let label = self.id_opt();
+                let ty = self.block_type()?;
+                self.labels.push(label);
+                let body = self.instrs()?;
+                self.labels.pop();
+                let block = Block {
+                    ty,
+                    instrs: body.into(),
+                };
+                instrs.push(if kw == "block" {
+                    Instruction::Block(block)
+                } else {
+                    Instruction::Loop(block)
+                });
+            }
+            Some("if") => {
+                self.next_token()?;
+                let label = self.id_opt();
+                let ty = self.block_type()?;
+                // Condition
+                while !self.peek_field("then") {
+                    self.folded_instr(instrs)?;
+                }
+                self.labels.push(label);
+                self.lparen()?;
+                self.kw("then")?;
+                let then_instrs = self.instrs()?;
+                self.rparen()?;
+                let else_instrs = if self.peek_field("else") {
+                    self.lparen()?;
+                    self.kw("else")?;
+                    let else_instrs = self.instrs()?;
+                    self.rparen()?;
+                    else_instrs
+                } else {
+                    vec![]
+                };
+                self.labels.pop();
+                instrs.push(Instruction::If(If {
+                    ty,
+                    then_instrs: then_instrs.into(),
+                    else_instrs: else_instrs.into(),
+                }));
+            }
+            _ => {
+                let instr = self.plain_instr()?;
+                while !self.peek_rparen() {
+                    self.folded_instr(instrs)?;
+                }
+                instrs.push(instr);
+            }
+        }
+        self.rparen()
+    }
+
+    // A plain instruction, or a block instruction ending with `end`
+    fn instr(&mut self) -> Result<Instruction> {
+        match self.peek_kw() {
+            Some("block") | Some("loop") => {
+                let kw = self.keyword()?;
+                let label = self.id_opt();
+                let ty = self.block_type()?;
+                self.labels.push(label);
+                let body = self.instrs()?;
+                self.labels.pop();
+                self.kw("end")?;
+                self.id_opt();
+                let block = Block {
+                    ty,
+                    instrs: body.into(),
+                };
+                Ok(if kw == "block" {
+                    Instruction::Block(block)
+                } else {
+                    Instruction::Loop(block)
+                })
+            }
+            Some("if") => {
+                self.next_token()?;
+                let label = self.id_opt();
+                let ty = self.block_type()?;
+                self.labels.push(label);
+                let then_instrs = self.instrs()?;
+                let else_instrs = if self.peek_kw() == Some("else") {
+                    self.next_token()?;
+                    self.id_opt();
+                    self.instrs()?
+                } else {
+                    vec![]
+                };
+                self.labels.pop();
+                self.kw("end")?;
+                self.id_opt();
+                Ok(Instruction::If(If {
+                    ty,
+                    then_instrs: then_instrs.into(),
+                    else_instrs: else_instrs.into(),
+                }))
+            }
+            _ => self.plain_instr(),
+        }
+    }
+
+    fn plain_instr(&mut self) -> Result<Instruction> {
+        use Instruction::*;
+
+        let kw = self.keyword()?;
+        let instr = match kw.as_str() {
+            "unreachable" => Unreachable,
+            "nop" => Nop,
+            "return" => Return,
+            "drop" => Drop,
+            "br" => Br(self.label()?),
+            "br_if" => BrIf(self.label()?),
+            "br_table" => {
+                let mut tbl = vec![self.label()?];
+                while self.peek_index() {
+                    tbl.push(self.label()?);
+                }
+                let def = tbl.pop().unwrap();
+                BrTable(self::BrTable { tbl, def })
+            }
+            "call" => Call(self.func_idx()?),
+            "call_indirect" => CallIndirect(self.call_indirect_type()?),
+            "return_call" => ReturnCall(self.func_idx()?),
+            "return_call_indirect" => ReturnCallIndirect(self.call_indirect_type()?),
+            "select" => {
+                if self.peek_field("result") {
+                    let (ty, _) = self.params_results()?;
+                    SelectT(ty.ret)
+                } else {
+                    Select
+                }
+            }
+
+            "local.get" => LocalGet(self.local()?),
+            "local.set" => LocalSet(self.local()?),
+            "local.tee" => LocalTee(self.local()?),
+            "global.get" => GlobalGet(self.global_idx()?),
+            "global.set" => GlobalSet(self.global_idx()?),
+
+            "table.get" => TableGet(self.table_opt()?),
+            "table.set" => TableSet(self.table_opt()?),
+            "table.size" => TableSize(self.table_opt()?),
+            "table.grow" => TableGrow(self.table_opt()?),
+            "table.fill" => TableFill(self.table_opt()?),
+            "table.copy" => {
+                let dst = self.table_opt()?;
+                let src = self.table_opt()?;
+                TableCopy(dst, src)
+            }
+            "table.init" => {
+                // `table.init elem` or `table.init table elem`
+                let table = if self.peek_index_at(self.cursor + 1) {
+                    self.index(Space::Table)?
+                } else {
+                    0
+                };
+                TableInit(self.index(Space::Elem)?, table)
+            }
+            "elem.drop" => ElemDrop(self.index(Space::Elem)?),
+
+            "i32.load" => I32Load(self.memarg(2)?),
+            "i64.load" => I64Load(self.memarg(3)?),
+            "f32.load" => F32Load(self.memarg(2)?),
+            "f64.load" => F64Load(self.memarg(3)?),
+            "i32.load8_s" => I32Load8s(self.memarg(0)?),
+            "i32.load8_u" => I32Load8u(self.memarg(0)?),
+            "i32.load16_s" => I32Load16s(self.memarg(1)?),
+            "i32.load16_u" => I32Load16u(self.memarg(1)?),
+            "i64.load8_s" => I64Load8s(self.memarg(0)?),
+            "i64.load8_u" => I64Load8u(self.memarg(0)?),
+            "i64.load16_s" => I64Load16s(self.memarg(1)?),
+            "i64.load16_u" => I64Load16u(self.memarg(1)?),
+            "i64.load32_s" => I64Load32s(self.memarg(2)?),
+            "i64.load32_u" => I64Load32u(self.memarg(2)?),
+            "i32.store" => I32Store(self.memarg(2)?),
+            "i64.store" => I64Store(self.memarg(3)?),
+            "f32.store" => F32Store(self.memarg(2)?),
+            "f64.store" => F64Store(self.memarg(3)?),
+            "i32.store8" => I32Store8(self.memarg(0)?),
+            "i32.store16" => I32Store16(self.memarg(1)?),
+            "i64.store8" => I64Store8(self.memarg(0)?),
+            "i64.store16" => I64Store16(self.memarg(1)?),
+            "i64.store32" => I64Store32(self.memarg(2)?),
+            "memory.size" => MemorySize,
+            "memory.grow" => MemoryGrow,
+            "memory.copy" => MemoryCopy,
+            "memory.fill" => MemoryFill,
+            "memory.init" => MemoryInit(self.index(Space::Data)?),
+            "data.drop" => DataDrop(self.index(Space::Data)?),
+
+            "i32.const" => I32Const(self.i32()?),
+            "i64.const" => I64Const(self.i64()?),
+            "f32.const" => F32Const(self.f32()?),
+            "f64.const" => F64Const(self.f64()?),
+
+            "ref.null" => RefNull(self.heap_type()?),
+            "ref.func" => RefFunc(self.func_idx()?),
+            "ref.is_null" => RefIsNull,
+
+            other => match numeric_instr(other) {
+                Some(instr) => instr,
+                None => return self.unexpected("instruction", other),
+            },
+        };
+        Ok(instr)
+    }
+
+    // `memidx? offset=N? align=N?`. Alignment is a power of two in the text format, and its
+    // exponent in `MemArg`.
+    fn memarg(&mut self, natural_align: u32) -> Result<MemArg> {
+        let mem = if self.peek_index() {
+            self.index(Space::Mem)?
+        } else {
+            0
+        };
+        let mut memarg = MemArg {
+            align: natural_align,
+            offset: 0,
+            mem,
+        };
+        if let Some(offset) = self.peek_kw().and_then(|kw| kw.strip_prefix("offset=")) {
+            memarg.offset = parse_u64(offset).ok_or(ParseError::ConstantOutOfRange)?;
+            self.next_token()?;
+        }
+        if let Some(align) = self.peek_kw().and_then(|kw| kw.strip_prefix("align=")) {
+            let align = parse_u32(align).ok_or(ParseError::ConstantOutOfRange)?;
+            if !align.is_power_of_two() {
+                return Err(ParseError::ConstantOutOfRange);
+            }
+            memarg.align = align.trailing_zeros();
+            self.next_token()?;
+        }
+        Ok(memarg)
+    }
+
+    // Branch target. Labels are relative to the innermost block.
+    fn label(&mut self) -> Result<LabelIdx> {
+        match self.next_token()? {
+            Token::Id(id) => {
+                match self
+                    .labels
+                    .iter()
+                    .rev()
+                    .position(|label| label.as_ref() == Some(&id))
+                {
+                    Some(depth) => Ok(depth as u32),
+                    None => Err(ParseError::UnknownId(id)),
+                }
+            }
+            Token::Integer(Sign::Pos, n) if n <= u64::from(u32::MAX) => Ok(n as u32),
+            other => self.unexpected("label", &format!("{:?}", other)),
+        }
+    }
+
+    fn local(&mut self) -> Result<LocalIdx> {
+        match self.next_token()? {
+            Token::Id(id) => match self.locals.get(&id) {
+                Some(local_idx) => Ok(*local_idx),
+                None => Err(ParseError::UnknownId(id)),
+            },
+            Token::Integer(Sign::Pos, n) if n <= u64::from(u32::MAX) => Ok(LocalIdx(n as u32)),
+            other => self.unexpected("local", &format!("{:?}", other)),
+        }
+    }
+
+    fn func_idx(&mut self) -> Result<FuncIdx> {
+        Ok(FuncIdx(self.index(Space::Func)?))
+    }
+
+    fn global_idx(&mut self) -> Result<GlobalIdx> {
+        Ok(GlobalIdx(self.index(Space::Global)?))
+    }
+
+    // Optional table index, defaults to 0
+    fn table_opt(&mut self) -> Result<TableIdx> {
+        if self.peek_index() {
+            self.index(Space::Table)
+        } else {
+            Ok(0)
+        }
+    }
+
+    /////////////
+    // Numbers //
+    /////////////
+
+    fn u32(&mut self) -> Result<u32> {
+        match self.next_token()? {
+            Token::Integer(Sign::Pos, n) if n <= u64::from(u32::MAX) => Ok(n as u32),
+            Token::Integer(_, _) => Err(ParseError::ConstantOutOfRange),
+            other => self.unexpected("integer", &format!("{:?}", other)),
+        }
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        match self.next_token()? {
+            Token::Integer(Sign::Pos, n) => Ok(n),
+            Token::Integer(_, _) => Err(ParseError::ConstantOutOfRange),
+            other => self.unexpected("integer", &format!("{:?}", other)),
+        }
+    }
+
+    // Integers are accepted as signed or unsigned
+    fn i32(&mut self) -> Result<i32> {
+        match self.next_token()? {
+            Token::Integer(Sign::Pos, n) if n <= u64::from(u32::MAX) => Ok(n as u32 as i32),
+            Token::Integer(Sign::Neg, n) if n <= 1 << 31 => Ok((n as i64).wrapping_neg() as i32),
+            Token::Integer(_, _) => Err(ParseError::ConstantOutOfRange),
+            other => self.unexpected("integer", &format!("{:?}", other)),
+        }
+    }
+
+    fn i64(&mut self) -> Result<i64> {
         match self.next_token()? {
-            Token::LParen => {
-                let ret = parse(self)?;
-                match self.next_token()? {
-                    Token::RParen => Ok(ret),
-                    other => Err(ParseError::UnexpectedToken {
-                        expected: "right paren",
-                        found: format!("{:?}", other),
-                    }),
-                }
-            }
-            other => Err(ParseError::UnexpectedToken {
-                expected: "right paren",
-                found: format!("{:?}", other),
+            Token::Integer(Sign::Pos, n) => Ok(n as i64),
+            Token::Integer(Sign::Neg, n) if n <= 1 << 63 => Ok((n as i64).wrapping_neg()),
+            Token::Integer(_, _) => Err(ParseError::ConstantOutOfRange),
+            other => self.unexpected("integer", &format!("{:?}", other)),
+        }
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        self.float()?.to_f32().ok_or(ParseError::ConstantOutOfRange)
+    }
+
+    fn f64(&mut self) -> Result<f64> {
+        self.float()?.to_f64().ok_or(ParseError::ConstantOutOfRange)
+    }
+
+    // Float literal. Integer literals are also floats.
+    fn float(&mut self) -> Result<Float> {
+        match self.next_token()? {
+            Token::Float(float) => Ok(float),
+            Token::Integer(sign, n) => Ok(Float {
+                sign,
+                kind: FloatKind::Num {
+                    hex: false,
+                    integral: n.to_string(),
+                    frac: String::new(),
+                    exponent: 0,
+                },
             }),
+            other => self.unexpected("float", &format!("{:?}", other)),
         }
     }
 
-    fn kw(&mut self, kw: &'static str) -> Result<()> {
+    /////////////
+    // Helpers //
+    /////////////
+
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.cursor)
+    }
+
+    fn peek_kw(&self) -> Option<&str> {
+        self.peek_kw_at(self.cursor)
+    }
+
+    fn peek_kw_at(&self, idx: usize) -> Option<&str> {
+        match self.tokens.get(idx) {
+            Some(Token::Reserved(kw)) => Some(kw),
+            _ => None,
+        }
+    }
+
+    fn peek_rparen(&self) -> bool {
+        matches!(self.peek(), Some(Token::RParen) | None)
+    }
+
+    // Whether the next tokens are `(` and the keyword
+    pub fn peek_field(&self, kw: &str) -> bool {
+        matches!(self.peek(), Some(Token::LParen)) && self.peek_kw_at(self.cursor + 1) == Some(kw)
+    }
+
+    fn peek_index(&self) -> bool {
+        self.peek_index_at(self.cursor)
+    }
+
+    fn peek_index_at(&self, idx: usize) -> bool {
+        matches!(
+            self.tokens.get(idx),
+            Some(Token::Integer(Sign::Pos, _)) | Some(Token::Id(_))
+        )
+    }
+
+    pub fn next_token(&mut self) -> Result<Token> {
+        match self.tokens.get(self.cursor) {
+            None => Err(ParseError::UnexpectedEOF),
+            Some(token) => {
+                self.cursor += 1;
+                Ok(token.clone())
+            }
+        }
+    }
+
+    fn unexpected<A>(&self, expected: &'static str, found: &str) -> Result<A> {
+        Err(ParseError::UnexpectedToken {
+            expected,
+            found: found.to_owned(),
+        })
+    }
+
+    pub fn lparen(&mut self) -> Result<()> {
+        match self.next_token()? {
+            Token::LParen => Ok(()),
+            other => self.unexpected("left paren", &format!("{:?}", other)),
+        }
+    }
+
+    pub fn rparen(&mut self) -> Result<()> {
+        match self.next_token()? {
+            Token::RParen => Ok(()),
+            other => self.unexpected("right paren", &format!("{:?}", other)),
+        }
+    }
+
+    pub fn kw(&mut self, kw: &'static str) -> Result<()> {
         match self.next_token()? {
             Token::Reserved(reserved) if reserved == kw => Ok(()),
-            other => Err(ParseError::UnexpectedToken {
-                expected: kw,
-                found: format!("{:?}", other),
-            }),
+            other => self.unexpected(kw, &format!("{:?}", other)),
         }
     }
+
+    pub fn keyword(&mut self) -> Result<String> {
+        match self.next_token()? {
+            Token::Reserved(kw) => Ok(kw),
+            other => self.unexpected("keyword", &format!("{:?}", other)),
+        }
+    }
+
+    pub fn id_opt(&mut self) -> Option<String> {
+        match self.peek() {
+            Some(Token::Id(id)) => {
+                let id = id.clone();
+                self.cursor += 1;
+                Some(id)
+            }
+            _ => None,
+        }
+    }
+
+    fn index(&mut self, space: Space) -> Result<u32> {
+        match self.next_token()? {
+            Token::Id(id) => match self.ids.space(space).ids.get(&id) {
+                Some(idx) => Ok(*idx),
+                None => Err(ParseError::UnknownId(id)),
+            },
+            Token::Integer(Sign::Pos, n) if n <= u64::from(u32::MAX) => Ok(n as u32),
+            other => self.unexpected("index", &format!("{:?}", other)),
+        }
+    }
+
+    pub fn string(&mut self) -> Result<Vec<u8>> {
+        match self.next_token()? {
+            Token::String(bytes) => Ok(bytes),
+            other => self.unexpected("string", &format!("{:?}", other)),
+        }
+    }
+
+    // Concatenation of strings until the end of the current form
+    pub fn strings(&mut self) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        while !self.peek_rparen() {
+            bytes.extend(self.string()?);
+        }
+        Ok(bytes)
+    }
+
+    pub fn name(&mut self) -> Result<String> {
+        let bytes = self.string()?;
+        match ::std::str::from_utf8(&bytes) {
+            Ok(name) => Ok(name.to_owned()),
+            Err(error) => Err(ParseError::Utf8Error { error }),
+        }
+    }
+
+    // Skip to the end of the current form, consuming the right paren
+    pub fn skip_rest(&mut self) -> Result<()> {
+        let mut depth = 0;
+        loop {
+            match self.next_token()? {
+                Token::LParen => depth += 1,
+                Token::RParen if depth == 0 => return Ok(()),
+                Token::RParen => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+}
+
+// Parse a decimal or hex number in a keyword, e.g. the `16` in `offset=16`
+fn parse_u32(str: &str) -> Option<u32> {
+    parse_u64(str).and_then(|i| u32::try_from(i).ok())
+}
+
+fn parse_u64(str: &str) -> Option<u64> {
+    let str = str.replace('_', "");
+    match str.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => str.parse().ok(),
+    }
+}
+
+// Instructions without immediates, other than the ones handled in `Parser::plain_instr`
+fn numeric_instr(kw: &str) -> Option<Instruction> {
+    use Instruction::*;
+    Some(match kw {
+        "i32.eqz" => I32Eqz,
+        "i32.eq" => I32Eq,
+        "i32.ne" => I32Ne,
+        "i32.lt_s" => I32Lt_s,
+        "i32.lt_u" => I32Lt_u,
+        "i32.gt_s" => I32Gt_s,
+        "i32.gt_u" => I32Gt_u,
+        "i32.le_s" => I32Le_s,
+        "i32.le_u" => I32Le_u,
+        "i32.ge_s" => I32Ge_s,
+        "i32.ge_u" => I32Ge_u,
+        "i64.eqz" => I64Eqz,
+        "i64.eq" => I64Eq,
+        "i64.ne" => I64Ne,
+        "i64.lt_s" => I64Lt_s,
+        "i64.lt_u" => I64Lt_u,
+        "i64.gt_s" => I64Gt_s,
+        "i64.gt_u" => I64Gt_u,
+        "i64.le_s" => I64Le_s,
+        "i64.le_u" => I64Le_u,
+        "i64.ge_s" => I64Ge_s,
+        "i64.ge_u" => I64Ge_u,
+        "f32.eq" => F32Eq,
+        "f32.ne" => F32Ne,
+        "f32.lt" => F32Lt,
+        "f32.gt" => F32Gt,
+        "f32.le" => F32Le,
+        "f32.ge" => F32Ge,
+        "f64.eq" => F64Eq,
+        "f64.ne" => F64Ne,
+        "f64.lt" => F64Lt,
+        "f64.gt" => F64Gt,
+        "f64.le" => F64Le,
+        "f64.ge" => F64Ge,
+        "i32.clz" => I32Clz,
+        "i32.ctz" => I32Ctz,
+        "i32.popcnt" => I32Popcnt,
+        "i32.add" => I32Add,
+        "i32.sub" => I32Sub,
+        "i32.mul" => I32Mul,
+        "i32.div_s" => I32Div_s,
+        "i32.div_u" => I32Div_u,
+        "i32.rem_s" => I32Rem_s,
+        "i32.rem_u" => I32Rem_u,
+        "i32.and" => I32And,
+        "i32.or" => I32Or,
+        "i32.xor" => I32Xor,
+        "i32.shl" => I32Shl,
+        "i32.shr_s" => I32Shr_s,
+        "i32.shr_u" => I32Shr_u,
+        "i32.rotl" => I32Rotl,
+        "i32.rotr" => I32Rotr,
+        "i64.clz" => I64Clz,
+        "i64.ctz" => I64Ctz,
+        "i64.popcnt" => I64Popcnt,
+        "i64.add" => I64Add,
+        "i64.sub" => I64Sub,
+        "i64.mul" => I64Mul,
+        "i64.div_s" => I64Div_s,
+        "i64.div_u" => I64Div_u,
+        "i64.rem_s" => I64Rem_s,
+        "i64.rem_u" => I64Rem_u,
+        "i64.and" => I64And,
+        "i64.or" => I64Or,
+        "i64.xor" => I64Xor,
+        "i64.shl" => I64Shl,
+        "i64.shr_s" => I64Shr_s,
+        "i64.shr_u" => I64Shr_u,
+        "i64.rotl" => I64Rotl,
+        "i64.rotr" => I64Rotr,
+        "f32.abs" => F32Abs,
+        "f32.neg" => F32Neg,
+        "f32.ceil" => F32Ceil,
+        "f32.floor" => F32Floor,
+        "f32.trunc" => F32Trunc,
+        "f32.nearest" => F32Nearest,
+        "f32.sqrt" => F32Sqrt,
+        "f32.add" => F32Add,
+        "f32.sub" => F32Sub,
+        "f32.mul" => F32Mul,
+        "f32.div" => F32Div,
+        "f32.min" => F32Min,
+        "f32.max" => F32Max,
+        "f32.copysign" => F32Copysign,
+        "f64.abs" => F64Abs,
+        "f64.neg" => F64Neg,
+        "f64.ceil" => F64Ceil,
+        "f64.floor" => F64Floor,
+        "f64.trunc" => F64Trunc,
+        "f64.nearest" => F64Nearest,
+        "f64.sqrt" => F64Sqrt,
+        "f64.add" => F64Add,
+        "f64.sub" => F64Sub,
+        "f64.mul" => F64Mul,
+        "f64.div" => F64Div,
+        "f64.min" => F64Min,
+        "f64.max" => F64Max,
+        "f64.copysign" => F64Copysign,
+        "i32.wrap_i64" => I32Wrapi64,
+        "i32.trunc_f32_s" => I32Truncf32_s,
+        "i32.trunc_f32_u" => I32Truncf32_u,
+        "i32.trunc_f64_s" => I32Truncf64_s,
+        "i32.trunc_f64_u" => I32Truncf64_u,
+        "i64.extend_i32_s" => I64Extendi32_s,
+        "i64.extend_i32_u" => I64Extendi32_u,
+        "i64.trunc_f32_s" => I64Truncf32_s,
+        "i64.trunc_f32_u" => I64Truncf32_u,
+        "i64.trunc_f64_s" => I64Truncf64_s,
+        "i64.trunc_f64_u" => I64Truncf64_u,
+        "f32.convert_i32_s" => F32Converti32_s,
+        "f32.convert_i32_u" => F32Converti32_u,
+        "f32.convert_i64_s" => F32Converti64_s,
+        "f32.convert_i64_u" => F32Converti64_u,
+        "f32.demote_f64" => F32Demotef64,
+        "f64.convert_i32_s" => F64Converti32_s,
+        "f64.convert_i32_u" => F64Converti32_u,
+        "f64.convert_i64_s" => F64Converti64_s,
+        "f64.convert_i64_u" => F64Converti64_u,
+        "f64.promote_f32" => F64Promotef32,
+        "i32.reinterpret_f32" => I32Reinterpretf32,
+        "i64.reinterpret_f64" => I64Reinterpretf64,
+        "f32.reinterpret_i32" => F32Reinterpreti32,
+        "f64.reinterpret_i64" => F64Reinterpreti64,
+        "i32.extend8_s" => I32Extend8_s,
+        "i32.extend16_s" => I32Extend16_s,
+        "i64.extend8_s" => I64Extend8_s,
+        "i64.extend16_s" => I64Extend16_s,
+        "i64.extend32_s" => I64Extend32_s,
+        "i32.trunc_sat_f32_s" => I32TruncSatf32_s,
+        "i32.trunc_sat_f32_u" => I32TruncSatf32_u,
+        "i32.trunc_sat_f64_s" => I32TruncSatf64_s,
+        "i32.trunc_sat_f64_u" => I32TruncSatf64_u,
+        "i64.trunc_sat_f32_s" => I64TruncSatf32_s,
+        "i64.trunc_sat_f32_u" => I64TruncSatf32_u,
+        "i64.trunc_sat_f64_s" => I64TruncSatf64_s,
+        "i64.trunc_sat_f64_u" => I64TruncSatf64_u,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+fn parse_str(str: &str) -> Module {
+    let tokens = tokenize(Lexer::new(str.as_bytes())).unwrap();
+    parse_module(tokens).unwrap()
+}
+
+#[cfg(test)]
+fn run_str(str: &str, fn_name: &str, args: &[crate::exec::Value]) -> Vec<crate::exec::Value> {
+    use crate::exec::{allocate_module, Linker, Runtime};
+
+    let module = parse_str(str);
+    crate::validate::validate(&module).unwrap();
+    let mut rt = Runtime::default();
+    let module_idx = allocate_module(&mut rt, &Linker::default(), module).unwrap();
+    rt.invoke(module_idx, fn_name, args).unwrap()
+}
+
+#[test]
+fn parse_plain_instrs() {
+    use crate::exec::Value;
+
+    let results = run_str(
+        r#"(module (func (export "f") (result i32) i32.const 1 i32.const 2 i32.add))"#,
+        "f",
+        &[],
+    );
+    assert_eq!(results, vec![Value::I32(3)]);
+}
+
+#[test]
+fn parse_folded_instrs() {
+    use crate::exec::Value;
+
+    let module = r#"
+        (module
+          (func $max (export "max") (param $a i32) (param $b i32) (result i32)
+            (if (result i32) (i32.gt_s (local.get $a) (local.get $b))
+              (then (local.get $a))
+              (else (local.get $b))))
+          (func (export "sum") (param $n i32) (result i32) (local $acc i32)
+            (block $done
+              (loop $loop
+                (br_if $done (i32.eqz (local.get $n)))
+                (local.set $acc (i32.add (local.get $acc) (local.get $n)))
+                (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+                (br $loop)))
+            (local.get $acc)))
+    "#;
+    assert_eq!(
+        run_str(module, "max", &[Value::I32(3), Value::I32(-5)]),
+        vec![Value::I32(3)]
+    );
+    assert_eq!(
+        run_str(module, "sum", &[Value::I32(10)]),
+        vec![Value::I32(55)]
+    );
+}
+
+#[test]
+fn parse_ids() {
+    let module = parse_str(
+        r#"
+        (module $m
+          (type $t (func (param i32) (result i32)))
+          (import "env" "g" (global $g i32))
+          (import "env" "f" (func $imported (type $t)))
+          (memory $mem (export "mem") 1 2)
+          (func $f (type $t) (call $imported (global.get $g)))
+          (func $g (export "g") (param i64) (drop (i32.load offset=4 align=2 (i32.const 0))))
+          (start $g))
+        "#,
+    );
+
+    assert_eq!(module.names.mod_name.as_deref(), Some("m"));
+    assert_eq!(module.names.fun_name(FuncIdx(1)), Some("f"));
+    assert_eq!(module.names.fun_name(FuncIdx(2)), Some("g"));
+
+    assert_eq!(module.imports.len(), 2);
+    assert!(matches!(
+        module.imports[1].desc,
+        ImportDesc::Func(TypeIdx(0))
+    ));
+    assert_eq!(module.types.len(), 2);
+    assert_eq!(module.funs[0].ty, TypeIdx(0));
+    assert_eq!(module.funs[1].ty, TypeIdx(1));
+    assert_eq!(module.start, Some(FuncIdx(2)));
+
+    assert!(matches!(
+        module.funs[0].expr.instrs[..],
+        [
+            Instruction::GlobalGet(GlobalIdx(0)),
+            Instruction::Call(FuncIdx(0))
+        ]
+    ));
+    assert!(matches!(
+        module.funs[1].expr.instrs[1],
+        Instruction::I32Load(MemArg {
+            align: 1,
+            offset: 4,
+            mem: 0
+        })
+    ));
+
+    assert_eq!(module.exports.len(), 2);
+    assert!(matches!(module.exports[0].desc, ExportDesc::Mem(0)));
+    assert!(matches!(
+        module.exports[1].desc,
+        ExportDesc::Func(FuncIdx(2))
+    ));
+}
+
+#[test]
+fn parse_errors() {
+    let parse = |str: &str| parse_module(tokenize(Lexer::new(str.as_bytes())).unwrap());
+
+    assert!(matches!(
+        parse("(module (func (call $f)))"),
+        Err(ParseError::UnknownId(_))
+    ));
+    assert!(matches!(
+        parse("(module (func $f) (func $f))"),
+        Err(ParseError::DuplicateId(_))
+    ));
+    assert!(matches!(
+        parse("(module (func (drop (i32.const 0x1_0000_0000))))"),
+        Err(ParseError::ConstantOutOfRange)
+    ));
+    assert!(matches!(
+        parse("(module (type (func)) (func (type 0) (param i32)))"),
+        Err(ParseError::InlineTypeMismatch)
+    ));
 }
@@ -0,0 +1,335 @@
+//! Runs `.wast` spec test scripts against the interpreter
+
+use crate::exec::{self, allocate_module, Linker, ModuleIdx, Runtime, Trap, TrapKind, Value};
+use crate::parser::wast::parser::{
+    parse_script, tokenize, Action, Command, Const, Expected, ParseError,
+};
+use crate::parser::wast::Lexer;
+use crate::parser::{ElemType, Limits, Table, ValType};
+use crate::validate;
+
+use std::collections::HashMap;
+
+/// Results of running a script
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Number of assertions that passed
+    pub passed: usize,
+    /// Number of commands that are not supported by the parser or the runner
+    pub skipped: usize,
+    pub failures: Vec<Failure>,
+}
+
+/// A failed command
+#[derive(Debug)]
+pub struct Failure {
+    /// Index of the command in the script
+    pub command_idx: usize,
+    pub message: String,
+}
+
+/// Parse and run a script. Commands are run in order, failed commands don't stop the script.
+pub fn run_script(script: &[u8]) -> Result<Report, ParseError> {
+    let commands = parse_script(tokenize(Lexer::new(script))?)?;
+
+    let mut runner = Runner::new();
+    let mut report = Report::default();
+
+    for (command_idx, command) in commands.into_iter().enumerate() {
+        match runner.run_command(command) {
+            Ok(Outcome::Passed) => report.passed += 1,
+            Ok(Outcome::Done) => {}
+            Ok(Outcome::Skipped) => report.skipped += 1,
+            Err(message) => report.failures.push(Failure {
+                command_idx,
+                message,
+            }),
+        }
+    }
+
+    Ok(report)
+}
+
+enum Outcome {
+    /// Assertion passed
+    Passed,
+    /// Module or action command ran successfully
+    Done,
+    /// Command is not supported
+    Skipped,
+}
+
+// Result of an action, or `None` if the action is not supported
+type ActionResult = Option<Result<Vec<Value>, Trap>>;
+
+#[derive(Default)]
+struct Runner {
+    rt: Runtime,
+    linker: Linker,
+    // The last defined module
+    current: Option<ModuleIdx>,
+    // Modules with identifiers
+    named: HashMap<String, ModuleIdx>,
+}
+
+impl Runner {
+    // A runner with the `spectest` module that spec scripts import from
+    fn new() -> Runner {
+        let mut runner = Runner::default();
+        let Runner { rt, linker, .. } = &mut runner;
+        linker.global(rt, "spectest", "global_i32", Value::I32(666), false);
+        linker.global(rt, "spectest", "global_i64", Value::I64(666), false);
+        linker.global(rt, "spectest", "global_f32", Value::F32(666.6), false);
+        linker.global(rt, "spectest", "global_f64", Value::F64(666.6), false);
+        let limits = Limits {
+            min: 1,
+            max: Some(2),
+            memory64: false,
+        };
+        linker.memory(rt, "spectest", "memory", limits);
+        let table = Table {
+            limits: Limits {
+                min: 10,
+                max: Some(20),
+                memory64: false,
+            },
+            elem_type: ElemType::FuncRef,
+        };
+        linker.table(rt, "spectest", "table", table);
+        runner
+    }
+
+    fn run_command(&mut self, command: Command) -> Result<Outcome, String> {
+        match command {
+            Command::Module { id, module } => {
+                if let Err(err) = validate::validate(&module) {
+                    return Err(format!("invalid module: {:?}", err));
+                }
+                let module_idx = match allocate_module(&mut self.rt, &self.linker, module) {
+                    Ok(module_idx) => module_idx,
+                    Err(err) => return Err(format!("instantiation failed: {}", err)),
+                };
+                if let Some(start_idx) = self.rt.get_module_start(module_idx) {
+                    if let Err(trap) = exec::call(&mut self.rt, module_idx, start_idx) {
+                        return Err(format!("start function trapped: {:?}", trap.kind));
+                    }
+                }
+                self.current = Some(module_idx);
+                if let Some(id) = id {
+                    self.named.insert(id, module_idx);
+                }
+                Ok(Outcome::Done)
+            }
+
+            Command::Action(action) => match self.run_action(action)? {
+                None => Ok(Outcome::Skipped),
+                Some(Ok(_)) => Ok(Outcome::Done),
+                Some(Err(trap)) => Err(format!("unexpected trap: {:?}", trap.kind)),
+            },
+
+            Command::AssertReturn { action, results } => match self.run_action(action)? {
+                None => Ok(Outcome::Skipped),
+                Some(Ok(values)) => {
+                    if values.len() == results.len()
+                        && results
+                            .iter()
+                            .zip(values.iter())
+                            .all(|(expected, value)| result_matches(expected, value))
+                    {
+                        Ok(Outcome::Passed)
+                    } else {
+                        Err(format!("expected {:?}, found {:?}", results, values))
+                    }
+                }
+                Some(Err(trap)) => Err(format!("unexpected trap: {:?}", trap.kind)),
+            },
+
+            Command::AssertTrap { action, message }
+            | Command::AssertExhaustion { action, message } => match self.run_action(action)? {
+                None => Ok(Outcome::Skipped),
+                Some(Ok(values)) => Err(format!("expected trap {:?}, found {:?}", message, values)),
+                Some(Err(trap)) => {
+                    if trap_matches(&trap.kind, &message) {
+                        Ok(Outcome::Passed)
+                    } else {
+                        Err(format!(
+                            "expected trap {:?}, found {:?}",
+                            message, trap.kind
+                        ))
+                    }
+                }
+            },
+
+            Command::AssertInvalid { module, message } => match validate::validate(&module) {
+                Ok(()) => Err(format!("expected invalid module: {:?}", message)),
+                Err(_) => Ok(Outcome::Passed),
+            },
+
+            Command::Unsupported(_) => Ok(Outcome::Skipped),
+        }
+    }
+
+    fn run_action(&mut self, action: Action) -> Result<ActionResult, String> {
+        match action {
+            Action::Invoke { module, name, args } => {
+                let module_idx = self.module_idx(module)?;
+                let args = args.into_iter().map(const_value).collect::<Vec<_>>();
+                Ok(Some(self.rt.invoke(module_idx, &name, &args)))
+            }
+            Action::Get { module, name } => {
+                let module_idx = self.module_idx(module)?;
+                match self.rt.get_global(module_idx, &name) {
+                    Some(value) => Ok(Some(Ok(vec![value]))),
+                    None => Err(format!("unknown global {}", name)),
+                }
+            }
+        }
+    }
+
+    fn module_idx(&self, id: Option<String>) -> Result<ModuleIdx, String> {
+        match id {
+            None => self.current.ok_or_else(|| "no module defined".to_owned()),
+            Some(id) => match self.named.get(&id) {
+                Some(module_idx) => Ok(*module_idx),
+                None => Err(format!("unknown module {}", id)),
+            },
+        }
+    }
+}
+
+fn const_value(const_: Const) -> Value {
+    match const_ {
+        Const::I32(i) => Value::I32(i),
+        Const::I64(i) => Value::I64(i),
+        Const::F32(f) => Value::F32(f),
+        Const::F64(f) => Value::F64(f),
+        Const::RefNull(ValType::ExternRef) => Value::ExternRef(None),
+        Const::RefNull(_) => Value::FuncRef(None),
+        Const::RefExtern(extern_) => Value::ExternRef(Some(extern_)),
+    }
+}
+
+// Floats are compared bitwise, so NaNs in results have to match exactly
+fn result_matches(expected: &Expected, value: &Value) -> bool {
+    match (expected, value) {
+        (Expected::Const(Const::F32(f1)), Value::F32(f2)) => f1.to_bits() == f2.to_bits(),
+        (Expected::Const(Const::F64(f1)), Value::F64(f2)) => f1.to_bits() == f2.to_bits(),
+        (Expected::Const(const_), value) => const_value(*const_) == *value,
+        (Expected::CanonicalNan(ValType::F32), Value::F32(f)) => {
+            f.to_bits() & 0x7FFF_FFFF == 0x7FC0_0000
+        }
+        (Expected::CanonicalNan(ValType::F64), Value::F64(f)) => {
+            f.to_bits() & 0x7FFF_FFFF_FFFF_FFFF == 0x7FF8_0000_0000_0000
+        }
+        (Expected::ArithmeticNan(ValType::F32), Value::F32(f)) => {
+            f.to_bits() & 0x7FC0_0000 == 0x7FC0_0000
+        }
+        (Expected::ArithmeticNan(ValType::F64), Value::F64(f)) => {
+            f.to_bits() & 0x7FF8_0000_0000_0000 == 0x7FF8_0000_0000_0000
+        }
+        (Expected::RefFunc, Value::FuncRef(Some(_))) => true,
+        _ => false,
+    }
+}
+
+// Spec tests only check the prefix of trap messages
+fn trap_matches(kind: &TrapKind, message: &str) -> bool {
+    match kind {
+        TrapKind::Unreachable
+        | TrapKind::MemoryOutOfBounds
+        | TrapKind::IntegerOverflow
+        | TrapKind::IntegerDivideByZero
+        | TrapKind::InvalidConversionToInteger
+        | TrapKind::TableOutOfBounds
+        | TrapKind::UndefinedElement
+        | TrapKind::UninitializedElement
+        | TrapKind::IndirectCallTypeMismatch
+        | TrapKind::CallStackExhausted => message.starts_with(&kind.to_string()),
+        _ => false,
+    }
+}
+
+#[test]
+fn run_spec_fixture() {
+    let report = run_script(include_bytes!("../tests/spec/arith.wast")).unwrap();
+    assert!(report.failures.is_empty(), "{:#?}", report.failures);
+    assert_eq!(report.skipped, 0);
+    assert!(report.passed > 0);
+}
+
+#[test]
+fn report_failures() {
+    let script = r#"
+        (module (func (export "one") (result i32) (i32.const 1)))
+        (assert_return (invoke "one") (i32.const 1))
+        (assert_return (invoke "one") (i32.const 2))
+        (assert_trap (invoke "one") "unreachable")
+        (assert_malformed (module quote "(func") "unexpected end")
+    "#;
+    let report = run_script(script.as_bytes()).unwrap();
+    assert_eq!(report.passed, 1);
+    assert_eq!(report.skipped, 1);
+    assert_eq!(
+        report
+            .failures
+            .iter()
+            .map(|failure| (failure.command_idx, failure.message.as_str()))
+            .collect::<Vec<_>>(),
+        vec![
+            (2, "expected [Const(I32(2))], found [I32(1)]"),
+            (3, "expected trap \"unreachable\", found [I32(1)]")
+        ]
+    );
+}
+
+#[test]
+fn spectest_globals() {
+    let script = r#"
+        (module
+          (import "spectest" "global_i32" (global i32))
+          (import "spectest" "global_f64" (global f64))
+          (func (export "get") (result i32 f64) (global.get 0) (global.get 1)))
+        (assert_return (invoke "get") (i32.const 666) (f64.const 666.6))
+    "#;
+    let report = run_script(script.as_bytes()).unwrap();
+    assert!(report.failures.is_empty(), "{:#?}", report.failures);
+    assert_eq!(report.passed, 1);
+}
+
+#[test]
+fn spectest_memory() {
+    // Modules importing the memory share it
+    let script = r#"
+        (module $A
+          (import "spectest" "memory" (memory 1 2))
+          (func (export "store") (param i32) (i32.store (i32.const 8) (local.get 0))))
+        (module
+          (import "spectest" "memory" (memory 1))
+          (func (export "load") (result i32) (i32.load (i32.const 8))))
+        (invoke $A "store" (i32.const 42))
+        (assert_return (invoke "load") (i32.const 42))
+    "#;
+    let report = run_script(script.as_bytes()).unwrap();
+    assert!(report.failures.is_empty(), "{:#?}", report.failures);
+    assert_eq!(report.passed, 1);
+}
+
+#[test]
+fn spectest_table() {
+    let script = r#"
+        (module $A
+          (import "spectest" "table" (table 10 funcref))
+          (func $f (result i32) (i32.const 42))
+          (elem (i32.const 3) $f))
+        (module
+          (import "spectest" "table" (table 10 20 funcref))
+          (type $t (func (result i32)))
+          (func (export "size") (result i32) (table.size))
+          (func (export "call") (result i32) (call_indirect (type $t) (i32.const 3))))
+        (assert_return (invoke "size") (i32.const 10))
+        (assert_return (invoke "call") (i32.const 42))
+    "#;
+    let report = run_script(script.as_bytes()).unwrap();
+    assert!(report.failures.is_empty(), "{:#?}", report.failures);
+    assert_eq!(report.passed, 2);
+}
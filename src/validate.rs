@@ -0,0 +1,1266 @@
+// Module validation
+//
+// https://webassembly.github.io/spec/core/valid/index.html
+//
+// Function bodies are type checked with the algorithm described in the spec appendix:
+// https://webassembly.github.io/spec/core/appendix/algorithm.html
+
+use crate::parser::types::*;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ValidationError {
+    /// Index of the function with the invalid body, `None` for errors outside of function bodies
+    pub fun_idx: Option<FuncIdx>,
+    pub kind: ValidationErrorKind,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ValidationErrorKind {
+    /// An instruction expected an operand of type `expected` (`None` for any type), but the
+    /// operand has type `found` (`None` when the operand stack is empty).
+    TypeMismatch {
+        expected: Option<ValType>,
+        found: Option<ValType>,
+    },
+    /// Labels of a `br_table` have different arities
+    BrTableArityMismatch,
+    /// A block left more values on the operand stack than its result type
+    ExtraValues,
+    UnknownType(TypeIdx),
+    UnknownFunction(FuncIdx),
+    UnknownTable(TableIdx),
+    UnknownMemory(MemIdx),
+    UnknownGlobal(GlobalIdx),
+    UnknownLocal(LocalIdx),
+    UnknownLabel(LabelIdx),
+    UnknownData(DataIdx),
+    UnknownElem(ElemIdx),
+    /// `call_indirect` or `return_call_indirect` through a table with elements other than funcref
+    IndirectCallTableType(TableIdx),
+    /// `global.set` on an immutable global
+    ImmutableGlobal(GlobalIdx),
+    /// `return_call` or `return_call_indirect` of a function with results different from the
+    /// current function's results
+    TailCallTypeMismatch,
+    /// `select` with a number of result types other than one
+    SelectArity(usize),
+    /// `memory.init` or `data.drop` in a module without a datacount section
+    MissingDataCount,
+    /// Minimum or maximum size of a memory is larger than `MAX_PAGES`, or `MAX_PAGES_64` for
+    /// memory64 memories
+    MemorySizeTooLarge,
+    /// Minimum or maximum size of a table doesn't fit into 32 bits
+    TableSizeTooLarge,
+    /// Minimum size of a memory or table is larger than its maximum
+    LimitsMinGreaterThanMax,
+}
+
+// Max. number of pages of 32-bit and memory64 memories
+const MAX_PAGES: u64 = 1 << 16;
+const MAX_PAGES_64: u64 = 1 << 48;
+
+/// Validate limits of memories and tables, and function bodies of the module.
+pub fn validate(module: &Module) -> Result<(), ValidationError> {
+    let mut ctx = Context {
+        types: &module.types,
+        funs: vec![],
+        tables: module.tables.iter().map(|table| table.elem_type).collect(),
+        mem_addr_types: module.mem_addrs.iter().map(mem_addr_type).collect(),
+        globals: vec![],
+        // Data segments are declared by the datacount section, which comes before the code
+        // section in binary modules. Instructions that refer to data segments require the section.
+        n_datas: module.datacount.map(|datacount| datacount as usize),
+        n_elems: module.elems.len(),
+    };
+
+    let module_error = |kind| ValidationError {
+        fun_idx: None,
+        kind,
+    };
+    for import in &module.imports {
+        match &import.desc {
+            ImportDesc::Table(table) => validate_table_limits(&table.limits),
+            ImportDesc::MemType(limits) => validate_mem_limits(limits),
+            ImportDesc::Func(_) | ImportDesc::Global(_) => Ok(()),
+        }
+        .map_err(module_error)?;
+    }
+    for table in &module.tables {
+        validate_table_limits(&table.limits).map_err(module_error)?;
+    }
+    for limits in &module.mem_addrs {
+        validate_mem_limits(limits).map_err(module_error)?;
+    }
+
+    for import in &module.imports {
+        match &import.desc {
+            ImportDesc::Func(type_idx) => ctx.funs.push(*type_idx),
+            ImportDesc::Table(table) => ctx.tables.push(table.elem_type),
+            ImportDesc::MemType(limits) => ctx.mem_addr_types.push(mem_addr_type(limits)),
+            ImportDesc::Global(global_ty) => ctx.globals.push(global_ty),
+        }
+    }
+    ctx.funs.extend(module.funs.iter().map(|fun| fun.ty));
+    ctx.globals
+        .extend(module.globals.iter().map(|global| &global.ty));
+
+    let n_imported_funs = ctx.funs.len() - module.funs.len();
+    for (i, fun) in module.funs.iter().enumerate() {
+        let fun_idx = FuncIdx((n_imported_funs + i) as u32);
+        validate_fun(&ctx, fun).map_err(|kind| ValidationError {
+            fun_idx: Some(fun_idx),
+            kind,
+        })?;
+    }
+
+    Ok(())
+}
+
+fn validate_mem_limits(limits: &Limits) -> Result<(), ValidationErrorKind> {
+    let max_pages = if limits.memory64 {
+        MAX_PAGES_64
+    } else {
+        MAX_PAGES
+    };
+    validate_limits(limits, max_pages, ValidationErrorKind::MemorySizeTooLarge)
+}
+
+fn validate_table_limits(limits: &Limits) -> Result<(), ValidationErrorKind> {
+    validate_limits(
+        limits,
+        u64::from(u32::MAX),
+        ValidationErrorKind::TableSizeTooLarge,
+    )
+}
+
+// Check that the limits are in range `0..=max_size`, and the minimum is not larger than the maximum
+fn validate_limits(
+    limits: &Limits,
+    max_size: u64,
+    too_large: ValidationErrorKind,
+) -> Result<(), ValidationErrorKind> {
+    if limits.min > max_size || limits.max.unwrap_or(0) > max_size {
+        return Err(too_large);
+    }
+    match limits.max {
+        Some(max) if limits.min > max => Err(ValidationErrorKind::LimitsMinGreaterThanMax),
+        _ => Ok(()),
+    }
+}
+
+fn mem_addr_type(limits: &Limits) -> ValType {
+    if limits.memory64 {
+        ValType::I64
+    } else {
+        ValType::I32
+    }
+}
+
+// Types and sizes of things in the module, including imports
+struct Context<'a> {
+    types: &'a [FuncType],
+    // Type indices of functions
+    funs: Vec<TypeIdx>,
+    // Element types of tables
+    tables: Vec<ElemType>,
+    // Types of memory addresses, `I64` for memory64 memories
+    mem_addr_types: Vec<ValType>,
+    globals: Vec<&'a GlobalType>,
+    n_datas: Option<usize>,
+    n_elems: usize,
+}
+
+impl<'a> Context<'a> {
+    fn get_type(&self, type_idx: TypeIdx) -> Result<&'a FuncType, ValidationErrorKind> {
+        self.types
+            .get(type_idx.0 as usize)
+            .ok_or(ValidationErrorKind::UnknownType(type_idx))
+    }
+
+    fn get_global(&self, global_idx: GlobalIdx) -> Result<&'a GlobalType, ValidationErrorKind> {
+        self.globals
+            .get(global_idx.0 as usize)
+            .copied()
+            .ok_or(ValidationErrorKind::UnknownGlobal(global_idx))
+    }
+
+    // Value type of the table's elements
+    fn check_table(&self, table_idx: TableIdx) -> Result<ValType, ValidationErrorKind> {
+        self.tables
+            .get(table_idx as usize)
+            .map(|elem_type| elem_type.val_type())
+            .ok_or(ValidationErrorKind::UnknownTable(table_idx))
+    }
+
+    // Check the table of `call_indirect` and `return_call_indirect`
+    fn check_indirect_call_table(&self) -> Result<(), ValidationErrorKind> {
+        match self.check_table(0)? {
+            ValType::FuncRef => Ok(()),
+            _ => Err(ValidationErrorKind::IndirectCallTableType(0)),
+        }
+    }
+
+    // Address type of the memory
+    fn check_mem(&self, mem_idx: MemIdx) -> Result<ValType, ValidationErrorKind> {
+        self.mem_addr_types
+            .get(mem_idx as usize)
+            .copied()
+            .ok_or(ValidationErrorKind::UnknownMemory(mem_idx))
+    }
+
+    fn check_data(&self, data_idx: DataIdx) -> Result<(), ValidationErrorKind> {
+        match self.n_datas {
+            None => Err(ValidationErrorKind::MissingDataCount),
+            Some(n_datas) if data_idx as usize >= n_datas => {
+                Err(ValidationErrorKind::UnknownData(data_idx))
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
+    // Parameter and result types of a block
+    fn block_type(
+        &self,
+        ty: &BlockType,
+    ) -> Result<(Vec<ValType>, Vec<ValType>), ValidationErrorKind> {
+        match ty {
+            BlockType::Empty => Ok((vec![], vec![])),
+            BlockType::ValType(ty) => Ok((vec![], vec![*ty])),
+            BlockType::TypeIdx(type_idx) => {
+                let ty = self.get_type(*type_idx)?;
+                Ok((ty.args.clone(), ty.ret.clone()))
+            }
+        }
+    }
+}
+
+fn validate_fun(ctx: &Context, fun: &Fun) -> Result<(), ValidationErrorKind> {
+    let fun_ty = ctx.get_type(fun.ty)?;
+
+    let mut locals = fun_ty.args.clone();
+    for local in &fun.locals {
+        for _ in 0..local.n {
+            locals.push(local.ty);
+        }
+    }
+
+    let mut validator = Validator {
+        ctx,
+        locals,
+        ret: fun_ty.ret.clone(),
+        vals: vec![],
+        ctrls: vec![],
+    };
+
+    validator.push_ctrl(fun_ty.ret.clone(), fun_ty.ret.clone());
+    validator.validate_instrs(&fun.expr.instrs)?;
+    validator.pop_ctrl()?;
+    Ok(())
+}
+
+struct Validator<'a> {
+    ctx: &'a Context<'a>,
+    locals: Vec<ValType>,
+    // Result type of the function
+    ret: Vec<ValType>,
+    // Operand stack. `None` is an unknown type, pushed when popping from the stack of an
+    // unreachable block.
+    vals: Vec<Option<ValType>>,
+    // Control stack
+    ctrls: Vec<Ctrl>,
+}
+
+struct Ctrl {
+    // Types of values expected by branches to the block
+    label_types: Vec<ValType>,
+    // Types of values at the end of the block
+    end_types: Vec<ValType>,
+    // Height of the operand stack at the beginning of the block
+    height: usize,
+    // Whether rest of the block is unreachable (after `br`, `return` etc.)
+    unreachable: bool,
+}
+
+impl<'a> Validator<'a> {
+    fn push_val(&mut self, ty: Option<ValType>) {
+        self.vals.push(ty);
+    }
+
+    fn push_vals(&mut self, tys: &[ValType]) {
+        for ty in tys {
+            self.push_val(Some(*ty));
+        }
+    }
+
+    fn pop_val(&mut self) -> Result<Option<ValType>, ValidationErrorKind> {
+        let ctrl = self.ctrls.last().unwrap();
+        if self.vals.len() == ctrl.height {
+            if ctrl.unreachable {
+                return Ok(None);
+            }
+            return Err(ValidationErrorKind::TypeMismatch {
+                expected: None,
+                found: None,
+            });
+        }
+        Ok(self.vals.pop().unwrap())
+    }
+
+    fn pop_expect(&mut self, expected: ValType) -> Result<Option<ValType>, ValidationErrorKind> {
+        let ctrl = self.ctrls.last().unwrap();
+        if self.vals.len() == ctrl.height && !ctrl.unreachable {
+            return Err(ValidationErrorKind::TypeMismatch {
+                expected: Some(expected),
+                found: None,
+            });
+        }
+        match self.pop_val()? {
+            Some(found) if found != expected => Err(ValidationErrorKind::TypeMismatch {
+                expected: Some(expected),
+                found: Some(found),
+            }),
+            found => Ok(found),
+        }
+    }
+
+    fn pop_vals(&mut self, tys: &[ValType]) -> Result<(), ValidationErrorKind> {
+        for ty in tys.iter().rev() {
+            self.pop_expect(*ty)?;
+        }
+        Ok(())
+    }
+
+    // Pop operands of an instruction and push its results
+    fn op(&mut self, args: &[ValType], ret: &[ValType]) -> Result<(), ValidationErrorKind> {
+        self.pop_vals(args)?;
+        self.push_vals(ret);
+        Ok(())
+    }
+
+    // A call that returns the callee's results from the current function
+    fn tail_call(&mut self, args: &[ValType], ret: &[ValType]) -> Result<(), ValidationErrorKind> {
+        if ret != self.ret.as_slice() {
+            return Err(ValidationErrorKind::TailCallTypeMismatch);
+        }
+        self.pop_vals(args)?;
+        self.unreachable();
+        Ok(())
+    }
+
+    fn push_ctrl(&mut self, label_types: Vec<ValType>, end_types: Vec<ValType>) {
+        self.ctrls.push(Ctrl {
+            label_types,
+            end_types,
+            height: self.vals.len(),
+            unreachable: false,
+        });
+    }
+
+    fn pop_ctrl(&mut self) -> Result<(), ValidationErrorKind> {
+        let end_types = self.ctrls.last().unwrap().end_types.clone();
+        self.pop_vals(&end_types)?;
+        if self.vals.len() != self.ctrls.last().unwrap().height {
+            return Err(ValidationErrorKind::ExtraValues);
+        }
+        self.ctrls.pop();
+        Ok(())
+    }
+
+    fn label_types(&self, label_idx: LabelIdx) -> Result<Vec<ValType>, ValidationErrorKind> {
+        if label_idx as usize >= self.ctrls.len() {
+            return Err(ValidationErrorKind::UnknownLabel(label_idx));
+        }
+        Ok(self.ctrls[self.ctrls.len() - 1 - label_idx as usize]
+            .label_types
+            .clone())
+    }
+
+    fn unreachable(&mut self) {
+        let ctrl = self.ctrls.last_mut().unwrap();
+        self.vals.truncate(ctrl.height);
+        ctrl.unreachable = true;
+    }
+
+    fn get_local(&self, local_idx: LocalIdx) -> Result<ValType, ValidationErrorKind> {
+        self.locals
+            .get(local_idx.0 as usize)
+            .copied()
+            .ok_or(ValidationErrorKind::UnknownLocal(local_idx))
+    }
+
+    fn validate_block(
+        &mut self,
+        params: &[ValType],
+        label_types: Vec<ValType>,
+        results: Vec<ValType>,
+        instrs: &[Instruction],
+    ) -> Result<(), ValidationErrorKind> {
+        self.push_ctrl(label_types, results);
+        self.push_vals(params);
+        self.validate_instrs(instrs)?;
+        self.pop_ctrl()?;
+        Ok(())
+    }
+
+    fn validate_instrs(&mut self, instrs: &[Instruction]) -> Result<(), ValidationErrorKind> {
+        for instr in instrs {
+            self.validate_instr(instr)?;
+        }
+        Ok(())
+    }
+
+    fn validate_instr(&mut self, instr: &Instruction) -> Result<(), ValidationErrorKind> {
+        use Instruction::*;
+        use ValType::{ExternRef, FuncRef, F32, F64, I32, I64};
+
+        match instr {
+            //
+            // Control instructions
+            //
+            Unreachable => {
+                self.unreachable();
+            }
+
+            Nop => {}
+
+            Block(block) => {
+                let (params, results) = self.ctx.block_type(&block.ty)?;
+                self.pop_vals(&params)?;
+                self.validate_block(&params, results.clone(), results.clone(), &block.instrs)?;
+                self.push_vals(&results);
+            }
+
+            Loop(block) => {
+                let (params, results) = self.ctx.block_type(&block.ty)?;
+                self.pop_vals(&params)?;
+                self.validate_block(&params, params.clone(), results.clone(), &block.instrs)?;
+                self.push_vals(&results);
+            }
+
+            If(if_) => {
+                let (params, results) = self.ctx.block_type(&if_.ty)?;
+                self.pop_expect(I32)?;
+                self.pop_vals(&params)?;
+                self.validate_block(&params, results.clone(), results.clone(), &if_.then_instrs)?;
+                self.validate_block(&params, results.clone(), results.clone(), &if_.else_instrs)?;
+                self.push_vals(&results);
+            }
+
+            Br(label_idx) => {
+                let label_types = self.label_types(*label_idx)?;
+                self.pop_vals(&label_types)?;
+                self.unreachable();
+            }
+
+            BrIf(label_idx) => {
+                self.pop_expect(I32)?;
+                let label_types = self.label_types(*label_idx)?;
+                self.pop_vals(&label_types)?;
+                self.push_vals(&label_types);
+            }
+
+            BrTable(br_table) => {
+                self.pop_expect(I32)?;
+                let default_types = self.label_types(br_table.def)?;
+                for label_idx in &br_table.tbl {
+                    let label_types = self.label_types(*label_idx)?;
+                    if label_types.len() != default_types.len() {
+                        return Err(ValidationErrorKind::BrTableArityMismatch);
+                    }
+                    self.pop_vals(&label_types)?;
+                    self.push_vals(&label_types);
+                }
+                self.pop_vals(&default_types)?;
+                self.unreachable();
+            }
+
+            Return => {
+                let ret = self.ret.clone();
+                self.pop_vals(&ret)?;
+                self.unreachable();
+            }
+
+            Call(fun_idx) => {
+                let type_idx = *self
+                    .ctx
+                    .funs
+                    .get(fun_idx.0 as usize)
+                    .ok_or(ValidationErrorKind::UnknownFunction(*fun_idx))?;
+                let fun_ty = self.ctx.get_type(type_idx)?;
+                self.op(&fun_ty.args, &fun_ty.ret)?;
+            }
+
+            CallIndirect(type_idx) => {
+                self.ctx.check_indirect_call_table()?;
+                let fun_ty = self.ctx.get_type(*type_idx)?;
+                self.pop_expect(I32)?;
+                self.op(&fun_ty.args, &fun_ty.ret)?;
+            }
+
+            ReturnCall(fun_idx) => {
+                let type_idx = *self
+                    .ctx
+                    .funs
+                    .get(fun_idx.0 as usize)
+                    .ok_or(ValidationErrorKind::UnknownFunction(*fun_idx))?;
+                let fun_ty = self.ctx.get_type(type_idx)?;
+                self.tail_call(&fun_ty.args, &fun_ty.ret)?;
+            }
+
+            ReturnCallIndirect(type_idx) => {
+                self.ctx.check_indirect_call_table()?;
+                let fun_ty = self.ctx.get_type(*type_idx)?;
+                self.pop_expect(I32)?;
+                self.tail_call(&fun_ty.args, &fun_ty.ret)?;
+            }
+
+            //
+            // Parametric instructions
+            //
+            Drop => {
+                self.pop_val()?;
+            }
+
+            Select => {
+                self.pop_expect(I32)?;
+                let ty1 = self.pop_val()?;
+                let ty2 = self.pop_val()?;
+                match (ty1, ty2) {
+                    (Some(ty1), Some(ty2)) if ty1 != ty2 => {
+                        return Err(ValidationErrorKind::TypeMismatch {
+                            expected: Some(ty1),
+                            found: Some(ty2),
+                        });
+                    }
+                    _ => {}
+                }
+                self.push_val(ty1.or(ty2));
+            }
+
+            SelectT(tys) => {
+                // Only one type is allowed currently
+                let ty = match tys.as_slice() {
+                    [ty] => *ty,
+                    _ => return Err(ValidationErrorKind::SelectArity(tys.len())),
+                };
+                self.op(&[ty, ty, I32], &[ty])?;
+            }
+
+            //
+            // Variable instructions
+            //
+            LocalGet(local_idx) => {
+                let ty = self.get_local(*local_idx)?;
+                self.push_val(Some(ty));
+            }
+
+            LocalSet(local_idx) => {
+                let ty = self.get_local(*local_idx)?;
+                self.pop_expect(ty)?;
+            }
+
+            LocalTee(local_idx) => {
+                let ty = self.get_local(*local_idx)?;
+                self.op(&[ty], &[ty])?;
+            }
+
+            GlobalGet(global_idx) => {
+                let global_ty = self.ctx.get_global(*global_idx)?;
+                self.push_val(Some(global_ty.ty));
+            }
+
+            GlobalSet(global_idx) => {
+                let global_ty = self.ctx.get_global(*global_idx)?;
+                if global_ty.mut_ != Mutability::Var {
+                    return Err(ValidationErrorKind::ImmutableGlobal(*global_idx));
+                }
+                self.pop_expect(global_ty.ty)?;
+            }
+
+            //
+            // Table instructions
+            //
+            TableGet(table_idx) => {
+                let elem_ty = self.ctx.check_table(*table_idx)?;
+                self.op(&[I32], &[elem_ty])?;
+            }
+
+            TableSet(table_idx) => {
+                let elem_ty = self.ctx.check_table(*table_idx)?;
+                self.op(&[I32, elem_ty], &[])?;
+            }
+
+            TableInit(elem_idx, table_idx) => {
+                self.ctx.check_table(*table_idx)?;
+                if *elem_idx as usize >= self.ctx.n_elems {
+                    return Err(ValidationErrorKind::UnknownElem(*elem_idx));
+                }
+                self.op(&[I32, I32, I32], &[])?;
+            }
+
+            ElemDrop(elem_idx) => {
+                if *elem_idx as usize >= self.ctx.n_elems {
+                    return Err(ValidationErrorKind::UnknownElem(*elem_idx));
+                }
+            }
+
+            TableCopy(dst, src) => {
+                self.ctx.check_table(*dst)?;
+                self.ctx.check_table(*src)?;
+                self.op(&[I32, I32, I32], &[])?;
+            }
+
+            TableGrow(table_idx) => {
+                let elem_ty = self.ctx.check_table(*table_idx)?;
+                self.op(&[elem_ty, I32], &[I32])?;
+            }
+
+            TableSize(table_idx) => {
+                self.ctx.check_table(*table_idx)?;
+                self.op(&[], &[I32])?;
+            }
+
+            TableFill(table_idx) => {
+                let elem_ty = self.ctx.check_table(*table_idx)?;
+                self.op(&[I32, elem_ty, I32], &[])?;
+            }
+
+            //
+            // Reference instructions
+            //
+            RefNull(ty) => {
+                self.push_val(Some(*ty));
+            }
+
+            RefIsNull => {
+                match self.pop_val()? {
+                    None | Some(FuncRef | ExternRef) => {}
+                    Some(ty) => {
+                        return Err(ValidationErrorKind::TypeMismatch {
+                            expected: Some(FuncRef),
+                            found: Some(ty),
+                        });
+                    }
+                }
+                self.push_val(Some(I32));
+            }
+
+            RefFunc(fun_idx) => {
+                if fun_idx.0 as usize >= self.ctx.funs.len() {
+                    return Err(ValidationErrorKind::UnknownFunction(*fun_idx));
+                }
+                self.push_val(Some(FuncRef));
+            }
+
+            //
+            // Memory instructions
+            //
+            I32Load(memarg) | I32Load8s(memarg) | I32Load8u(memarg) | I32Load16s(memarg)
+            | I32Load16u(memarg) => {
+                let addr_ty = self.ctx.check_mem(memarg.mem)?;
+                self.op(&[addr_ty], &[I32])?;
+            }
+
+            I64Load(memarg) | I64Load8s(memarg) | I64Load8u(memarg) | I64Load16s(memarg)
+            | I64Load16u(memarg) | I64Load32s(memarg) | I64Load32u(memarg) => {
+                let addr_ty = self.ctx.check_mem(memarg.mem)?;
+                self.op(&[addr_ty], &[I64])?;
+            }
+
+            F32Load(memarg) => {
+                let addr_ty = self.ctx.check_mem(memarg.mem)?;
+                self.op(&[addr_ty], &[F32])?;
+            }
+
+            F64Load(memarg) => {
+                let addr_ty = self.ctx.check_mem(memarg.mem)?;
+                self.op(&[addr_ty], &[F64])?;
+            }
+
+            I32Store(memarg) | I32Store8(memarg) | I32Store16(memarg) => {
+                let addr_ty = self.ctx.check_mem(memarg.mem)?;
+                self.op(&[addr_ty, I32], &[])?;
+            }
+
+            I64Store(memarg) | I64Store8(memarg) | I64Store16(memarg) | I64Store32(memarg) => {
+                let addr_ty = self.ctx.check_mem(memarg.mem)?;
+                self.op(&[addr_ty, I64], &[])?;
+            }
+
+            F32Store(memarg) => {
+                let addr_ty = self.ctx.check_mem(memarg.mem)?;
+                self.op(&[addr_ty, F32], &[])?;
+            }
+
+            F64Store(memarg) => {
+                let addr_ty = self.ctx.check_mem(memarg.mem)?;
+                self.op(&[addr_ty, F64], &[])?;
+            }
+
+            MemorySize => {
+                let addr_ty = self.ctx.check_mem(0)?;
+                self.op(&[], &[addr_ty])?;
+            }
+
+            MemoryGrow => {
+                let addr_ty = self.ctx.check_mem(0)?;
+                self.op(&[addr_ty], &[addr_ty])?;
+            }
+
+            MemoryFill => {
+                let addr_ty = self.ctx.check_mem(0)?;
+                self.op(&[addr_ty, I32, addr_ty], &[])?;
+            }
+
+            MemoryCopy => {
+                let addr_ty = self.ctx.check_mem(0)?;
+                self.op(&[addr_ty, addr_ty, addr_ty], &[])?;
+            }
+
+            MemoryInit(data_idx) => {
+                let addr_ty = self.ctx.check_mem(0)?;
+                self.ctx.check_data(*data_idx)?;
+                self.op(&[addr_ty, I32, I32], &[])?;
+            }
+
+            DataDrop(data_idx) => {
+                self.ctx.check_data(*data_idx)?;
+            }
+
+            //
+            // Numeric instructions
+            //
+            I32Const(_) => self.op(&[], &[I32])?,
+            I64Const(_) => self.op(&[], &[I64])?,
+            F32Const(_) => self.op(&[], &[F32])?,
+            F64Const(_) => self.op(&[], &[F64])?,
+
+            I32Eqz | I32Clz | I32Ctz | I32Popcnt | I32Extend8_s | I32Extend16_s => {
+                self.op(&[I32], &[I32])?
+            }
+
+            I32Eq | I32Ne | I32Lt_s | I32Lt_u | I32Gt_s | I32Gt_u | I32Le_s | I32Le_u | I32Ge_s
+            | I32Ge_u | I32Add | I32Sub | I32Mul | I32Div_s | I32Div_u | I32Rem_s | I32Rem_u
+            | I32And | I32Or | I32Xor | I32Shl | I32Shr_s | I32Shr_u | I32Rotl | I32Rotr => {
+                self.op(&[I32, I32], &[I32])?
+            }
+
+            I64Eqz => self.op(&[I64], &[I32])?,
+
+            I64Eq | I64Ne | I64Lt_s | I64Lt_u | I64Gt_s | I64Gt_u | I64Le_s | I64Le_u | I64Ge_s
+            | I64Ge_u => self.op(&[I64, I64], &[I32])?,
+
+            I64Clz | I64Ctz | I64Popcnt | I64Extend8_s | I64Extend16_s | I64Extend32_s => {
+                self.op(&[I64], &[I64])?
+            }
+
+            I64Add | I64Sub | I64Mul | I64Div_s | I64Div_u | I64Rem_s | I64Rem_u | I64And
+            | I64Or | I64Xor | I64Shl | I64Shr_s | I64Shr_u | I64Rotl | I64Rotr => {
+                self.op(&[I64, I64], &[I64])?
+            }
+
+            F32Eq | F32Ne | F32Lt | F32Gt | F32Le | F32Ge => self.op(&[F32, F32], &[I32])?,
+
+            F64Eq | F64Ne | F64Lt | F64Gt | F64Le | F64Ge => self.op(&[F64, F64], &[I32])?,
+
+            F32Abs | F32Neg | F32Ceil | F32Floor | F32Trunc | F32Nearest | F32Sqrt => {
+                self.op(&[F32], &[F32])?
+            }
+
+            F32Add | F32Sub | F32Mul | F32Div | F32Min | F32Max | F32Copysign => {
+                self.op(&[F32, F32], &[F32])?
+            }
+
+            F64Abs | F64Neg | F64Ceil | F64Floor | F64Trunc | F64Nearest | F64Sqrt => {
+                self.op(&[F64], &[F64])?
+            }
+
+            F64Add | F64Sub | F64Mul | F64Div | F64Min | F64Max | F64Copysign => {
+                self.op(&[F64, F64], &[F64])?
+            }
+
+            I32Wrapi64 => self.op(&[I64], &[I32])?,
+
+            I32Truncf32_s | I32Truncf32_u | I32TruncSatf32_s | I32TruncSatf32_u
+            | I32Reinterpretf32 => self.op(&[F32], &[I32])?,
+
+            I32Truncf64_s | I32Truncf64_u | I32TruncSatf64_s | I32TruncSatf64_u => {
+                self.op(&[F64], &[I32])?
+            }
+
+            I64Extendi32_s | I64Extendi32_u => self.op(&[I32], &[I64])?,
+
+            I64Truncf32_s | I64Truncf32_u | I64TruncSatf32_s | I64TruncSatf32_u => {
+                self.op(&[F32], &[I64])?
+            }
+
+            I64Truncf64_s | I64Truncf64_u | I64TruncSatf64_s | I64TruncSatf64_u
+            | I64Reinterpretf64 => self.op(&[F64], &[I64])?,
+
+            F32Converti32_s | F32Converti32_u | F32Reinterpreti32 => self.op(&[I32], &[F32])?,
+
+            F32Converti64_s | F32Converti64_u => self.op(&[I64], &[F32])?,
+
+            F32Demotef64 => self.op(&[F64], &[F32])?,
+
+            F64Converti32_s | F64Converti32_u => self.op(&[I32], &[F64])?,
+
+            F64Converti64_s | F64Converti64_u | F64Reinterpreti64 => self.op(&[I64], &[F64])?,
+
+            F64Promotef32 => self.op(&[F32], &[F64])?,
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn test_module(ty: FuncType, locals: Vec<Local>, instrs: Vec<Instruction>) -> Module {
+    Module {
+        types: vec![ty],
+        funs: vec![Fun {
+            ty: TypeIdx(0),
+            locals,
+            expr: Expr {
+                instrs: instrs.into(),
+            },
+        }],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn validate_ok() {
+    use Instruction::*;
+    let module = test_module(
+        FuncType {
+            args: vec![ValType::I32],
+            ret: vec![ValType::I64],
+        },
+        vec![Local {
+            n: 1,
+            ty: ValType::I64,
+        }],
+        vec![
+            Block(self::Block {
+                ty: BlockType::ValType(ValType::I64),
+                instrs: vec![
+                    LocalGet(LocalIdx(1)),
+                    LocalGet(LocalIdx(0)),
+                    BrIf(0),
+                    Drop,
+                    I64Const(1),
+                ]
+                .into(),
+            }),
+            Return,
+        ],
+    );
+    assert_eq!(validate(&module), Ok(()));
+}
+
+#[test]
+fn validate_type_mismatch() {
+    use Instruction::*;
+    let module = test_module(
+        FuncType {
+            args: vec![],
+            ret: vec![ValType::I32],
+        },
+        vec![],
+        vec![I32Const(1), I64Const(2), I32Add],
+    );
+    assert_eq!(
+        validate(&module),
+        Err(ValidationError {
+            fun_idx: Some(FuncIdx(0)),
+            kind: ValidationErrorKind::TypeMismatch {
+                expected: Some(ValType::I32),
+                found: Some(ValType::I64)
+            }
+        })
+    );
+}
+
+#[test]
+fn validate_unknown_local() {
+    use Instruction::*;
+    let module = test_module(
+        FuncType {
+            args: vec![ValType::I32],
+            ret: vec![],
+        },
+        vec![],
+        vec![LocalGet(LocalIdx(1)), Drop],
+    );
+    assert_eq!(
+        validate(&module),
+        Err(ValidationError {
+            fun_idx: Some(FuncIdx(0)),
+            kind: ValidationErrorKind::UnknownLocal(LocalIdx(1))
+        })
+    );
+}
+
+#[test]
+fn validate_immutable_global() {
+    use Instruction::*;
+    let module = Module {
+        globals: vec![Global {
+            ty: GlobalType {
+                ty: ValType::I32,
+                mut_: Mutability::Const,
+            },
+            expr: Expr {
+                instrs: vec![I32Const(0)].into(),
+            },
+        }],
+        ..test_module(
+            FuncType {
+                args: vec![],
+                ret: vec![],
+            },
+            vec![],
+            vec![I32Const(1), GlobalSet(GlobalIdx(0))],
+        )
+    };
+    assert_eq!(
+        validate(&module),
+        Err(ValidationError {
+            fun_idx: Some(FuncIdx(0)),
+            kind: ValidationErrorKind::ImmutableGlobal(GlobalIdx(0))
+        })
+    );
+}
+
+#[test]
+fn validate_unknown_table() {
+    use Instruction::*;
+    let module = test_module(
+        FuncType {
+            args: vec![],
+            ret: vec![],
+        },
+        vec![],
+        vec![I32Const(0), TableGet(0), Drop],
+    );
+    assert_eq!(
+        validate(&module),
+        Err(ValidationError {
+            fun_idx: Some(FuncIdx(0)),
+            kind: ValidationErrorKind::UnknownTable(0)
+        })
+    );
+}
+
+#[test]
+fn validate_unknown_memory() {
+    use Instruction::*;
+    let module = Module {
+        mem_addrs: vec![Limits {
+            min: 1,
+            max: None,
+            memory64: false,
+        }],
+        ..test_module(
+            FuncType {
+                args: vec![],
+                ret: vec![ValType::I32],
+            },
+            vec![],
+            vec![
+                I32Const(0),
+                I32Load(MemArg {
+                    align: 2,
+                    offset: 0,
+                    mem: 1,
+                }),
+            ],
+        )
+    };
+    assert_eq!(
+        validate(&module),
+        Err(ValidationError {
+            fun_idx: Some(FuncIdx(0)),
+            kind: ValidationErrorKind::UnknownMemory(1)
+        })
+    );
+}
+
+#[test]
+fn validate_ref_is_null_type_mismatch() {
+    use Instruction::*;
+    let module = test_module(
+        FuncType {
+            args: vec![],
+            ret: vec![ValType::I32],
+        },
+        vec![],
+        vec![I32Const(0), RefIsNull],
+    );
+    assert_eq!(
+        validate(&module),
+        Err(ValidationError {
+            fun_idx: Some(FuncIdx(0)),
+            kind: ValidationErrorKind::TypeMismatch {
+                expected: Some(ValType::FuncRef),
+                found: Some(ValType::I32)
+            }
+        })
+    );
+}
+
+#[test]
+fn validate_tail_call_type_mismatch() {
+    use Instruction::*;
+    let ty = FuncType {
+        args: vec![],
+        ret: vec![ValType::I32],
+    };
+    let module = Module {
+        types: vec![
+            ty.clone(),
+            FuncType {
+                args: vec![],
+                ret: vec![ValType::I64],
+            },
+        ],
+        funs: vec![
+            Fun {
+                ty: TypeIdx(0),
+                locals: vec![],
+                expr: Expr {
+                    instrs: vec![ReturnCall(FuncIdx(1))].into(),
+                },
+            },
+            Fun {
+                ty: TypeIdx(1),
+                locals: vec![],
+                expr: Expr {
+                    instrs: vec![I64Const(1)].into(),
+                },
+            },
+        ],
+        ..test_module(ty, vec![], vec![])
+    };
+    assert_eq!(
+        validate(&module),
+        Err(ValidationError {
+            fun_idx: Some(FuncIdx(0)),
+            kind: ValidationErrorKind::TailCallTypeMismatch
+        })
+    );
+}
+
+#[test]
+fn validate_memory64_address_type() {
+    use Instruction::*;
+    let module = Module {
+        mem_addrs: vec![Limits {
+            min: 1,
+            max: None,
+            memory64: true,
+        }],
+        ..test_module(
+            FuncType {
+                args: vec![],
+                ret: vec![ValType::I32],
+            },
+            vec![],
+            vec![
+                I32Const(0),
+                I32Load(MemArg {
+                    align: 2,
+                    offset: 0,
+                    mem: 0,
+                }),
+            ],
+        )
+    };
+    assert_eq!(
+        validate(&module),
+        Err(ValidationError {
+            fun_idx: Some(FuncIdx(0)),
+            kind: ValidationErrorKind::TypeMismatch {
+                expected: Some(ValType::I64),
+                found: Some(ValType::I32)
+            }
+        })
+    );
+}
+
+#[test]
+fn validate_call_indirect_externref_table() {
+    use Instruction::*;
+    let module = Module {
+        tables: vec![Table {
+            limits: Limits {
+                min: 1,
+                max: None,
+                memory64: false,
+            },
+            elem_type: ElemType::ExternRef,
+        }],
+        ..test_module(
+            FuncType {
+                args: vec![],
+                ret: vec![],
+            },
+            vec![],
+            vec![I32Const(0), CallIndirect(TypeIdx(0))],
+        )
+    };
+    assert_eq!(
+        validate(&module),
+        Err(ValidationError {
+            fun_idx: Some(FuncIdx(0)),
+            kind: ValidationErrorKind::IndirectCallTableType(0)
+        })
+    );
+}
+
+#[test]
+fn validate_datacount() {
+    use Instruction::*;
+    // Data indices are checked against the datacount section
+    let module = Module {
+        datacount: Some(0),
+        data: vec![Data {
+            mode: DataMode::Passive,
+            init: vec![],
+        }],
+        ..test_module(
+            FuncType {
+                args: vec![],
+                ret: vec![],
+            },
+            vec![],
+            vec![DataDrop(0)],
+        )
+    };
+    assert_eq!(
+        validate(&module),
+        Err(ValidationError {
+            fun_idx: Some(FuncIdx(0)),
+            kind: ValidationErrorKind::UnknownData(0)
+        })
+    );
+
+    let module = Module {
+        datacount: Some(1),
+        ..module
+    };
+    assert_eq!(validate(&module), Ok(()));
+
+    // Without a datacount section instructions can't refer to data segments
+    let module = Module {
+        datacount: None,
+        ..module
+    };
+    assert_eq!(
+        validate(&module),
+        Err(ValidationError {
+            fun_idx: Some(FuncIdx(0)),
+            kind: ValidationErrorKind::MissingDataCount
+        })
+    );
+}
+
+#[test]
+fn validate_select_arity() {
+    use Instruction::*;
+    let ty = FuncType {
+        args: vec![],
+        ret: vec![],
+    };
+    for tys in &[vec![], vec![ValType::I32, ValType::I32]] {
+        let n_tys = tys.len();
+        let module = test_module(
+            ty.clone(),
+            vec![],
+            vec![
+                I32Const(1),
+                I32Const(2),
+                I32Const(0),
+                SelectT(tys.clone()),
+                Drop,
+            ],
+        );
+        assert_eq!(
+            validate(&module),
+            Err(ValidationError {
+                fun_idx: Some(FuncIdx(0)),
+                kind: ValidationErrorKind::SelectArity(n_tys)
+            })
+        );
+    }
+}
+
+#[test]
+fn validate_mem_and_table_limits() {
+    let limits = |min, max, memory64| Limits { min, max, memory64 };
+    let mem_module = |limits| Module {
+        mem_addrs: vec![limits],
+        ..Default::default()
+    };
+    let error = |kind| {
+        Err(ValidationError {
+            fun_idx: None,
+            kind,
+        })
+    };
+
+    assert_eq!(validate(&mem_module(limits(1 << 16, None, false))), Ok(()));
+    assert_eq!(
+        validate(&mem_module(limits(0xFFFF_FFFF, None, false))),
+        error(ValidationErrorKind::MemorySizeTooLarge)
+    );
+    assert_eq!(
+        validate(&mem_module(limits(1, Some((1 << 16) + 1), false))),
+        error(ValidationErrorKind::MemorySizeTooLarge)
+    );
+    assert_eq!(validate(&mem_module(limits(1 << 32, None, true))), Ok(()));
+    assert_eq!(
+        validate(&mem_module(limits((1 << 48) + 1, None, true))),
+        error(ValidationErrorKind::MemorySizeTooLarge)
+    );
+    assert_eq!(
+        validate(&mem_module(limits(2, Some(1), false))),
+        error(ValidationErrorKind::LimitsMinGreaterThanMax)
+    );
+
+    let table = |limits| Table {
+        limits,
+        elem_type: ElemType::FuncRef,
+    };
+    let module = Module {
+        tables: vec![table(limits(1 << 32, None, false))],
+        ..Default::default()
+    };
+    assert_eq!(
+        validate(&module),
+        error(ValidationErrorKind::TableSizeTooLarge)
+    );
+
+    // Imported memories and tables
+    let import = |desc| Module {
+        imports: vec![Import {
+            module: "env".to_owned(),
+            name: "x".to_owned(),
+            desc,
+        }],
+        ..Default::default()
+    };
+    assert_eq!(
+        validate(&import(ImportDesc::MemType(limits(
+            0xFFFF_FFFF,
+            None,
+            false
+        )))),
+        error(ValidationErrorKind::MemorySizeTooLarge)
+    );
+    assert_eq!(
+        validate(&import(ImportDesc::Table(table(limits(3, Some(2), false))))),
+        error(ValidationErrorKind::LimitsMinGreaterThanMax)
+    );
+}
@@ -0,0 +1,62 @@
+;; Arithmetic assertions run by `spec::run_spec_fixture`
+
+(module
+  (func (export "add") (param $a i32) (param $b i32) (result i32)
+    (i32.add (local.get $a) (local.get $b)))
+  (func (export "sub") (param $a i64) (param $b i64) (result i64)
+    (i64.sub (local.get $a) (local.get $b)))
+  (func (export "mul") (param $a f32) (param $b f32) (result f32)
+    (f32.mul (local.get $a) (local.get $b)))
+  (func (export "div") (param $a f64) (param $b f64) (result f64)
+    (f64.div (local.get $a) (local.get $b)))
+  (func (export "fac") (param $n i64) (result i64)
+    (if (result i64) (i64.eqz (local.get $n))
+      (then (i64.const 1))
+      (else (i64.mul (local.get $n) (call 4 (i64.sub (local.get $n) (i64.const 1)))))))
+  (func (export "trunc") (param $a f32) (result i32)
+    (i32.trunc_f32_s (local.get $a)))
+  (func (export "unreachable") unreachable)
+  (func (export "div_s") (param $a i32) (param $b i32) (result i32)
+    (i32.div_s (local.get $a) (local.get $b)))
+  (func (export "rem_s") (param $a i32) (param $b i32) (result i32)
+    (i32.rem_s (local.get $a) (local.get $b)))
+  (func $countdown (export "countdown") (param $n i64) (result i64)
+    (if (result i64) (i64.eqz (local.get $n))
+      (then (i64.const 42))
+      (else (return_call $countdown (i64.sub (local.get $n) (i64.const 1))))))
+  (global (export "answer") i64 (i64.const 42)))
+
+(assert_return (invoke "add" (i32.const 1) (i32.const 2)) (i32.const 3))
+(assert_return (invoke "add" (i32.const 0x7fffffff) (i32.const 1)) (i32.const 0x80000000))
+(assert_return (invoke "add" (i32.const -1) (i32.const -1)) (i32.const -2))
+
+(assert_return (invoke "sub" (i64.const 0) (i64.const 1)) (i64.const -1))
+(assert_return (invoke "sub" (i64.const 0x8000000000000000) (i64.const 1))
+               (i64.const 0x7fffffffffffffff))
+
+(assert_return (invoke "mul" (f32.const 1.5) (f32.const -2)) (f32.const -3))
+(assert_return (invoke "mul" (f32.const inf) (f32.const 0x1p-1)) (f32.const inf))
+
+(assert_return (invoke "div" (f64.const 1) (f64.const 4)) (f64.const 0.25))
+(assert_return (invoke "div" (f64.const -1) (f64.const 0)) (f64.const -inf))
+(assert_return (invoke "div" (f64.const 0) (f64.const 0)) (f64.const nan:canonical))
+(assert_return (invoke "div" (f64.const nan:0x4) (f64.const 1)) (f64.const nan:arithmetic))
+(assert_return (invoke "mul" (f32.const nan) (f32.const 1)) (f32.const nan:canonical))
+
+(assert_return (invoke "fac" (i64.const 20)) (i64.const 2432902008176640000))
+(assert_return (invoke "countdown" (i64.const 10000)) (i64.const 42))
+
+(invoke "add" (i32.const 1) (i32.const 1))
+(assert_return (get "answer") (i64.const 42))
+
+(assert_trap (invoke "trunc" (f32.const nan)) "invalid conversion to integer")
+(assert_trap (invoke "trunc" (f32.const 0x1p31)) "integer overflow")
+(assert_trap (invoke "unreachable") "unreachable")
+(assert_trap (invoke "div_s" (i32.const 1) (i32.const 0)) "integer divide by zero")
+(assert_trap (invoke "div_s" (i32.const 0x80000000) (i32.const -1)) "integer overflow")
+(assert_return (invoke "rem_s" (i32.const 0x80000000) (i32.const -1)) (i32.const 0))
+(assert_trap (invoke "rem_s" (i32.const 1) (i32.const 0)) "integer divide by zero")
+
+(assert_invalid
+  (module (func (result i32) (i64.const 0)))
+  "type mismatch")